@@ -331,6 +331,8 @@ async fn main() -> anyhow::Result<()> {
             // We're just a test helper: no graceful shutdown.
             std::process::exit(0);
         }
+        // Not delivered by `ShutdownSignals::handle`.
+        Signal::Hangup => Ok(()),
     })?;
 
     Ok(())
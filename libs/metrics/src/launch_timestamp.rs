@@ -11,6 +11,11 @@ impl LaunchTimestamp {
     pub fn generate() -> Self {
         LaunchTimestamp(Utc::now())
     }
+
+    /// Time since the process launched.
+    pub fn elapsed(&self) -> std::time::Duration {
+        (Utc::now() - self.0).to_std().unwrap_or_default()
+    }
 }
 
 impl Display for LaunchTimestamp {
@@ -4,7 +4,7 @@ use std::{
     collections::HashMap,
     io::{BufRead, Read},
     num::{NonZeroU64, NonZeroUsize},
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 
 use byteorder::{BigEndian, ReadBytesExt};
@@ -176,6 +176,29 @@ pub enum TimelineState {
     Broken { reason: String, backtrace: String },
 }
 
+/// Value of the `state` query parameter of `GET /v1/tenant/:tenant_shard_id/timeline`, which
+/// lists only the timelines in the given [`TimelineState`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum_macros::Display, strum_macros::EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum TimelineStateFilter {
+    Loading,
+    Active,
+    Stopping,
+    Broken,
+}
+
+impl TimelineStateFilter {
+    pub fn matches(&self, state: &TimelineState) -> bool {
+        matches!(
+            (self, state),
+            (TimelineStateFilter::Loading, TimelineState::Loading)
+                | (TimelineStateFilter::Active, TimelineState::Active)
+                | (TimelineStateFilter::Stopping, TimelineState::Stopping)
+                | (TimelineStateFilter::Broken, TimelineState::Broken { .. })
+        )
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct TimelineCreateRequest {
     pub new_timeline_id: TimelineId,
@@ -302,6 +325,19 @@ pub struct TenantLocationConfigRequest {
     pub config: LocationConfig, // as we have a flattened field, we should reject all unknown fields in it
 }
 
+/// Request of `/v1/tenant/:tenant_id/shard_split`: split all the shards of a tenant so that
+/// it ends up with `new_shard_count` shards.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TenantShardSplitRequest {
+    pub new_shard_count: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TenantShardSplitResponse {
+    /// The shards that the tenant was split into.
+    pub new_shards: Vec<TenantShardId>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct TenantConfigRequest {
@@ -310,6 +346,19 @@ pub struct TenantConfigRequest {
     pub config: TenantConfig, // as we have a flattened field, we should reject all unknown fields in it
 }
 
+/// Request body of `PUT /v1/tenant/:tenant_shard_id/time_travel_remote_storage`.
+///
+/// Both timestamps are RFC 3339 strings.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TenantTimeTravelRequest {
+    /// Restore the tenant's remote storage objects to their versions at this point in time.
+    pub travel_to: String,
+    /// If all objects were last modified before this point in time, the restore has already
+    /// been done and there is nothing to do.
+    pub done_if_after: String,
+}
+
 impl std::ops::Deref for TenantConfigRequest {
     type Target = TenantConfig;
 
@@ -564,6 +613,109 @@ pub struct TimelineGcRequest {
     pub gc_horizon: Option<u64>,
 }
 
+/// Response of `/v1/tenant/:tenant_shard_id/timeline/:timeline_id/do_gc`: the statistics of the
+/// GC run, as the pageserver's `GcResult` serializes them.
+#[serde_as]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GcResult {
+    pub layers_total: u64,
+    pub layers_needed_by_cutoff: u64,
+    pub layers_needed_by_pitr: u64,
+    pub layers_needed_by_branches: u64,
+    pub layers_not_updated: u64,
+    pub layers_removed: u64,
+    #[serde_as(as = "serde_with::DurationMilliSeconds")]
+    pub elapsed: std::time::Duration,
+}
+
+/// Version of the pagestream protocol, agreed on with a [`PagestreamHandshakeRequest`] as the
+/// first message after entering pagestream mode. Clients that don't send a handshake speak
+/// [`PagestreamProtocolVersion::V1`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum PagestreamProtocolVersion {
+    /// The original protocol. GetPage responses only carry the effective LSN if the client
+    /// asked for it with the `pagestream_effective_lsn=true` startup option, and DbSize
+    /// requests may omit the tablespace.
+    V1 = 1,
+    /// GetPage responses always carry the effective LSN, and DbSize requests always carry
+    /// the tablespace.
+    V2 = 2,
+    /// Like V2, but the server may ask the client to reconnect with a
+    /// [`PagestreamBeMessage::GoAway`].
+    V3 = 3,
+    /// Like V3, but requests may carry a deadline, see [`PagestreamGetPageRequest::deadline`].
+    V4 = 4,
+}
+
+impl PagestreamProtocolVersion {
+    /// All versions, oldest first.
+    pub const ALL: [PagestreamProtocolVersion; 4] = [Self::V1, Self::V2, Self::V3, Self::V4];
+
+    /// The bit of this version in [`PagestreamHandshakeRequest::supported_versions`].
+    pub fn bit(self) -> u32 {
+        1 << (self as u8)
+    }
+
+    /// Choose the newest version that is set in the `supported_versions` bitmap.
+    pub fn negotiate(supported_versions: u32) -> Option<PagestreamProtocolVersion> {
+        Self::ALL
+            .into_iter()
+            .rev()
+            .find(|version| supported_versions & version.bit() != 0)
+    }
+}
+
+impl TryFrom<u8> for PagestreamProtocolVersion {
+    type Error = u8;
+    fn try_from(value: u8) -> Result<Self, u8> {
+        match value {
+            1 => Ok(PagestreamProtocolVersion::V1),
+            2 => Ok(PagestreamProtocolVersion::V2),
+            3 => Ok(PagestreamProtocolVersion::V3),
+            4 => Ok(PagestreamProtocolVersion::V4),
+            _ => Err(value),
+        }
+    }
+}
+
+/// First message of a client that wants to negotiate the pagestream protocol version. The
+/// server replies with a [`PagestreamBeMessage::Handshake`] that names the chosen version.
+///
+/// Wrapped in libpq CopyData, like [`PagestreamFeMessage`], with a tag that no request uses.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PagestreamHandshakeRequest {
+    /// Bitmap of the [`PagestreamProtocolVersion::bit`]s of the versions the client speaks.
+    pub supported_versions: u32,
+}
+
+impl PagestreamHandshakeRequest {
+    // Keep in sync with `pagestore_client.h`
+    const TAG: u8 = 10;
+
+    /// Does this CopyData body contain a handshake, rather than a [`PagestreamFeMessage`]?
+    pub fn is_handshake(body: &[u8]) -> bool {
+        body.first() == Some(&Self::TAG)
+    }
+
+    pub fn serialize(&self) -> Bytes {
+        let mut bytes = BytesMut::new();
+        bytes.put_u8(Self::TAG);
+        bytes.put_u32(self.supported_versions);
+        bytes.into()
+    }
+
+    pub fn parse<R: std::io::Read>(body: &mut R) -> anyhow::Result<PagestreamHandshakeRequest> {
+        let msg_tag = body.read_u8()?;
+        if msg_tag != Self::TAG {
+            bail!("not a handshake message, tag: {msg_tag}");
+        }
+        Ok(PagestreamHandshakeRequest {
+            supported_versions: body.read_u32::<BigEndian>()?,
+        })
+    }
+}
+
 // Wrapped in libpq CopyData
 #[derive(PartialEq, Eq, Debug)]
 pub enum PagestreamFeMessage {
@@ -571,6 +723,7 @@ pub enum PagestreamFeMessage {
     Nblocks(PagestreamNblocksRequest),
     GetPage(PagestreamGetPageRequest),
     DbSize(PagestreamDbSizeRequest),
+    GetPageLastModified(PagestreamGetPageLastModifiedRequest),
 }
 
 // Wrapped in libpq CopyData
@@ -581,6 +734,9 @@ pub enum PagestreamBeMessage {
     GetPage(PagestreamGetPageResponse),
     Error(PagestreamErrorResponse),
     DbSize(PagestreamDbSizeResponse),
+    Handshake(PagestreamHandshakeResponse),
+    GoAway(PagestreamGoAwayResponse),
+    GetPageLastModified(PagestreamGetPageLastModifiedResponse),
 }
 
 // Keep in sync with `pagestore_client.h`
@@ -591,6 +747,9 @@ enum PagestreamBeMessageTag {
     GetPage = 102,
     Error = 103,
     DbSize = 104,
+    Handshake = 105,
+    GoAway = 106,
+    GetPageLastModified = 107,
 }
 impl TryFrom<u8> for PagestreamBeMessageTag {
     type Error = u8;
@@ -601,6 +760,9 @@ impl TryFrom<u8> for PagestreamBeMessageTag {
             102 => Ok(PagestreamBeMessageTag::GetPage),
             103 => Ok(PagestreamBeMessageTag::Error),
             104 => Ok(PagestreamBeMessageTag::DbSize),
+            105 => Ok(PagestreamBeMessageTag::Handshake),
+            106 => Ok(PagestreamBeMessageTag::GoAway),
+            107 => Ok(PagestreamBeMessageTag::GetPageLastModified),
             _ => Err(value),
         }
     }
@@ -611,6 +773,8 @@ pub struct PagestreamExistsRequest {
     pub latest: bool,
     pub lsn: Lsn,
     pub rel: RelTag,
+    /// See [`PagestreamGetPageRequest::deadline`].
+    pub deadline: Option<Duration>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -618,6 +782,8 @@ pub struct PagestreamNblocksRequest {
     pub latest: bool,
     pub lsn: Lsn,
     pub rel: RelTag,
+    /// See [`PagestreamGetPageRequest::deadline`].
+    pub deadline: Option<Duration>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -626,6 +792,24 @@ pub struct PagestreamGetPageRequest {
     pub lsn: Lsn,
     pub rel: RelTag,
     pub blkno: u32,
+    /// How long the client is willing to wait for the response, counted from when the server
+    /// receives the request. If the server can't answer in time, e.g. because it is behind
+    /// the requested LSN, it responds with an error instead. `None` means no deadline.
+    ///
+    /// Only sent to servers that speak [`PagestreamProtocolVersion::V4`], in whole
+    /// milliseconds after the other fields.
+    pub deadline: Option<Duration>,
+}
+
+/// Asks for the LSN at which a page was last modified, instead of the page itself.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PagestreamGetPageLastModifiedRequest {
+    pub latest: bool,
+    pub lsn: Lsn,
+    pub rel: RelTag,
+    pub blkno: u32,
+    /// See [`PagestreamGetPageRequest::deadline`].
+    pub deadline: Option<Duration>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -633,6 +817,17 @@ pub struct PagestreamDbSizeRequest {
     pub latest: bool,
     pub lsn: Lsn,
     pub dbnode: u32,
+    /// The tablespace of the database to size. Clients that predate this field don't send it,
+    /// which means the default tablespace. Use [`Self::ALL_TABLESPACES`] to get the total size
+    /// of the database across all tablespaces.
+    pub spcnode: u32,
+    /// See [`PagestreamGetPageRequest::deadline`].
+    pub deadline: Option<Duration>,
+}
+
+impl PagestreamDbSizeRequest {
+    /// `spcnode` value to request the size of a database summed over all its tablespaces.
+    pub const ALL_TABLESPACES: u32 = 0;
 }
 
 #[derive(Debug)]
@@ -648,6 +843,10 @@ pub struct PagestreamNblocksResponse {
 #[derive(Debug)]
 pub struct PagestreamGetPageResponse {
     pub page: Bytes,
+    /// The LSN at which the page was read, which differs from the request LSN for requests
+    /// of the latest page version. Only sent to clients that asked for it at connection
+    /// startup, and appended after the page so that older clients are not confused.
+    pub effective_lsn: Option<Lsn>,
 }
 
 #[derive(Debug)]
@@ -660,6 +859,26 @@ pub struct PagestreamDbSizeResponse {
     pub db_size: i64,
 }
 
+#[derive(Debug)]
+pub struct PagestreamHandshakeResponse {
+    pub version: PagestreamProtocolVersion,
+}
+
+#[derive(Debug)]
+pub struct PagestreamGetPageLastModifiedResponse {
+    /// LSN of the newest version of the page at or below the request LSN, or
+    /// [`Lsn::INVALID`] if the page is beyond the end of the relation.
+    pub last_modified_lsn: Lsn,
+}
+
+/// Sent by the server as the last message of a connection, to ask the client to reconnect,
+/// e.g. because the tenant is about to move to another pageserver. It is not an error: the
+/// server has answered all requests that it received before.
+#[derive(Debug)]
+pub struct PagestreamGoAwayResponse {
+    pub reason: String,
+}
+
 impl PagestreamFeMessage {
     pub fn serialize(&self) -> Bytes {
         let mut bytes = BytesMut::new();
@@ -701,13 +920,68 @@ impl PagestreamFeMessage {
                 bytes.put_u8(u8::from(req.latest));
                 bytes.put_u64(req.lsn.0);
                 bytes.put_u32(req.dbnode);
+                bytes.put_u32(req.spcnode);
+            }
+
+            Self::GetPageLastModified(req) => {
+                bytes.put_u8(4);
+                bytes.put_u8(u8::from(req.latest));
+                bytes.put_u64(req.lsn.0);
+                bytes.put_u32(req.rel.spcnode);
+                bytes.put_u32(req.rel.dbnode);
+                bytes.put_u32(req.rel.relnode);
+                bytes.put_u8(req.rel.forknum);
+                bytes.put_u32(req.blkno);
             }
         }
 
+        if let Some(deadline) = self.deadline() {
+            // Zero means no deadline, so round a deadline that short up.
+            let millis = u32::try_from(deadline.as_millis()).unwrap_or(u32::MAX);
+            bytes.put_u32(millis.max(1));
+        }
+
         bytes.into()
     }
 
+    /// The deadline of the request, see [`PagestreamGetPageRequest::deadline`].
+    pub fn deadline(&self) -> Option<Duration> {
+        match self {
+            Self::Exists(req) => req.deadline,
+            Self::Nblocks(req) => req.deadline,
+            Self::GetPage(req) => req.deadline,
+            Self::DbSize(req) => req.deadline,
+            Self::GetPageLastModified(req) => req.deadline,
+        }
+    }
+
+    /// Read the deadline that ends a request of a client that speaks `version`.
+    fn parse_deadline<R: std::io::Read>(
+        body: &mut R,
+        version: PagestreamProtocolVersion,
+    ) -> anyhow::Result<Option<Duration>> {
+        if version < PagestreamProtocolVersion::V4 {
+            return Ok(None);
+        }
+        // Clients may leave it out if there is no deadline.
+        match body.read_u32::<BigEndian>() {
+            Ok(0) => Ok(None),
+            Ok(millis) => Ok(Some(Duration::from_millis(u64::from(millis)))),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Parse a request of a client that speaks [`PagestreamProtocolVersion::V1`].
     pub fn parse<R: std::io::Read>(body: &mut R) -> anyhow::Result<PagestreamFeMessage> {
+        Self::parse_versioned(body, PagestreamProtocolVersion::V1)
+    }
+
+    /// Parse a request of a client that speaks the given protocol version.
+    pub fn parse_versioned<R: std::io::Read>(
+        body: &mut R,
+        version: PagestreamProtocolVersion,
+    ) -> anyhow::Result<PagestreamFeMessage> {
         // TODO these gets can fail
 
         // these correspond to the NeonMessageTag enum in pagestore_client.h
@@ -725,6 +999,7 @@ impl PagestreamFeMessage {
                     relnode: body.read_u32::<BigEndian>()?,
                     forknum: body.read_u8()?,
                 },
+                deadline: Self::parse_deadline(body, version)?,
             })),
             1 => Ok(PagestreamFeMessage::Nblocks(PagestreamNblocksRequest {
                 latest: body.read_u8()? != 0,
@@ -735,6 +1010,7 @@ impl PagestreamFeMessage {
                     relnode: body.read_u32::<BigEndian>()?,
                     forknum: body.read_u8()?,
                 },
+                deadline: Self::parse_deadline(body, version)?,
             })),
             2 => Ok(PagestreamFeMessage::GetPage(PagestreamGetPageRequest {
                 latest: body.read_u8()? != 0,
@@ -746,12 +1022,39 @@ impl PagestreamFeMessage {
                     forknum: body.read_u8()?,
                 },
                 blkno: body.read_u32::<BigEndian>()?,
+                deadline: Self::parse_deadline(body, version)?,
             })),
             3 => Ok(PagestreamFeMessage::DbSize(PagestreamDbSizeRequest {
                 latest: body.read_u8()? != 0,
                 lsn: Lsn::from(body.read_u64::<BigEndian>()?),
                 dbnode: body.read_u32::<BigEndian>()?,
+                // Older clients don't send the tablespace: it's the default one then.
+                spcnode: match body.read_u32::<BigEndian>() {
+                    Ok(spcnode) => spcnode,
+                    Err(e)
+                        if e.kind() == std::io::ErrorKind::UnexpectedEof
+                            && version == PagestreamProtocolVersion::V1 =>
+                    {
+                        postgres_ffi::pg_constants::DEFAULTTABLESPACE_OID
+                    }
+                    Err(e) => return Err(e.into()),
+                },
+                deadline: Self::parse_deadline(body, version)?,
             })),
+            4 => Ok(PagestreamFeMessage::GetPageLastModified(
+                PagestreamGetPageLastModifiedRequest {
+                    latest: body.read_u8()? != 0,
+                    lsn: Lsn::from(body.read_u64::<BigEndian>()?),
+                    rel: RelTag {
+                        spcnode: body.read_u32::<BigEndian>()?,
+                        dbnode: body.read_u32::<BigEndian>()?,
+                        relnode: body.read_u32::<BigEndian>()?,
+                        forknum: body.read_u8()?,
+                    },
+                    blkno: body.read_u32::<BigEndian>()?,
+                    deadline: Self::parse_deadline(body, version)?,
+                },
+            )),
             _ => bail!("unknown smgr message tag: {:?}", msg_tag),
         }
     }
@@ -776,6 +1079,9 @@ impl PagestreamBeMessage {
             Self::GetPage(resp) => {
                 bytes.put_u8(Tag::GetPage as u8);
                 bytes.put(&resp.page[..]);
+                if let Some(effective_lsn) = resp.effective_lsn {
+                    bytes.put_u64(effective_lsn.0);
+                }
             }
 
             Self::Error(resp) => {
@@ -787,47 +1093,127 @@ impl PagestreamBeMessage {
                 bytes.put_u8(Tag::DbSize as u8);
                 bytes.put_i64(resp.db_size);
             }
+            Self::Handshake(resp) => {
+                bytes.put_u8(Tag::Handshake as u8);
+                bytes.put_u8(resp.version as u8);
+            }
+            Self::GoAway(resp) => {
+                bytes.put_u8(Tag::GoAway as u8);
+                bytes.put(resp.reason.as_bytes());
+                bytes.put_u8(0); // null terminator
+            }
+            Self::GetPageLastModified(resp) => {
+                bytes.put_u8(Tag::GetPageLastModified as u8);
+                bytes.put_u64(resp.last_modified_lsn.0);
+            }
         }
 
         bytes.into()
     }
 
+    /// Whether this is the response to a request, as opposed to a message that the server
+    /// sends on its own, like [`Self::Handshake`] and [`Self::GoAway`].
+    pub fn is_request_response(&self) -> bool {
+        !matches!(self, Self::Handshake(_) | Self::GoAway(_))
+    }
+
+    /// Serialize a response to a request followed by the correlation id that the server assigned
+    /// to the request, for clients that asked for it with the `pagestream_correlation_ids=true`
+    /// startup option. Other messages never carry a correlation id.
+    pub fn serialize_with_correlation_id(&self, correlation_id: u64) -> Bytes {
+        debug_assert!(
+            self.is_request_response(),
+            "{} has no correlation id",
+            self.kind()
+        );
+        let mut bytes = BytesMut::from(&self.serialize()[..]);
+        bytes.put_u64(correlation_id);
+        bytes.into()
+    }
+
+    /// Counterpart of [`Self::serialize_with_correlation_id`]. Fails for messages that aren't
+    /// responses to requests, which are sent without a correlation id: use [`Self::deserialize`]
+    /// for those.
+    pub fn deserialize_with_correlation_id(mut buf: Bytes) -> anyhow::Result<(Self, u64)> {
+        use PagestreamBeMessageTag as Tag;
+        match buf.first().map(|tag| Tag::try_from(*tag)) {
+            Some(Ok(Tag::Handshake | Tag::GoAway)) => {
+                anyhow::bail!("message with tag={} has no correlation id", buf[0])
+            }
+            _ => {}
+        }
+        anyhow::ensure!(buf.len() > 8, "message too short to carry a correlation id");
+        let correlation_id = buf.split_off(buf.len() - 8).get_u64();
+        let msg = Self::deserialize(buf)?;
+        Ok((msg, correlation_id))
+    }
+
     pub fn deserialize(buf: Bytes) -> anyhow::Result<Self> {
         let mut buf = buf.reader();
         let msg_tag = buf.read_u8()?;
 
         use PagestreamBeMessageTag as Tag;
-        let ok =
-            match Tag::try_from(msg_tag).map_err(|tag: u8| anyhow::anyhow!("invalid tag {tag}"))? {
-                Tag::Exists => {
-                    let exists = buf.read_u8()?;
-                    Self::Exists(PagestreamExistsResponse {
-                        exists: exists != 0,
-                    })
-                }
-                Tag::Nblocks => {
-                    let n_blocks = buf.read_u32::<BigEndian>()?;
-                    Self::Nblocks(PagestreamNblocksResponse { n_blocks })
-                }
-                Tag::GetPage => {
-                    let mut page = vec![0; 8192]; // TODO: use MaybeUninit
-                    buf.read_exact(&mut page)?;
-                    PagestreamBeMessage::GetPage(PagestreamGetPageResponse { page: page.into() })
-                }
-                Tag::Error => {
-                    let mut msg = Vec::new();
-                    buf.read_until(0, &mut msg)?;
-                    let cstring = std::ffi::CString::from_vec_with_nul(msg)?;
-                    let rust_str = cstring.to_str()?;
-                    PagestreamBeMessage::Error(PagestreamErrorResponse {
-                        message: rust_str.to_owned(),
-                    })
-                }
-                Tag::DbSize => {
-                    let db_size = buf.read_i64::<BigEndian>()?;
-                    Self::DbSize(PagestreamDbSizeResponse { db_size })
-                }
-            };
+        let ok = match Tag::try_from(msg_tag)
+            .map_err(|tag: u8| anyhow::anyhow!("invalid tag {tag}"))?
+        {
+            Tag::Exists => {
+                let exists = buf.read_u8()?;
+                Self::Exists(PagestreamExistsResponse {
+                    exists: exists != 0,
+                })
+            }
+            Tag::Nblocks => {
+                let n_blocks = buf.read_u32::<BigEndian>()?;
+                Self::Nblocks(PagestreamNblocksResponse { n_blocks })
+            }
+            Tag::GetPage => {
+                let mut page = vec![0; 8192]; // TODO: use MaybeUninit
+                buf.read_exact(&mut page)?;
+                let effective_lsn = if buf.get_ref().has_remaining() {
+                    Some(Lsn(buf.read_u64::<BigEndian>()?))
+                } else {
+                    None
+                };
+                PagestreamBeMessage::GetPage(PagestreamGetPageResponse {
+                    page: page.into(),
+                    effective_lsn,
+                })
+            }
+            Tag::Error => {
+                let mut msg = Vec::new();
+                buf.read_until(0, &mut msg)?;
+                let cstring = std::ffi::CString::from_vec_with_nul(msg)?;
+                let rust_str = cstring.to_str()?;
+                PagestreamBeMessage::Error(PagestreamErrorResponse {
+                    message: rust_str.to_owned(),
+                })
+            }
+            Tag::DbSize => {
+                let db_size = buf.read_i64::<BigEndian>()?;
+                Self::DbSize(PagestreamDbSizeResponse { db_size })
+            }
+            Tag::Handshake => {
+                let version = buf.read_u8()?;
+                let version = PagestreamProtocolVersion::try_from(version)
+                    .map_err(|version: u8| anyhow::anyhow!("invalid protocol version {version}"))?;
+                Self::Handshake(PagestreamHandshakeResponse { version })
+            }
+            Tag::GoAway => {
+                let mut msg = Vec::new();
+                buf.read_until(0, &mut msg)?;
+                let cstring = std::ffi::CString::from_vec_with_nul(msg)?;
+                let rust_str = cstring.to_str()?;
+                PagestreamBeMessage::GoAway(PagestreamGoAwayResponse {
+                    reason: rust_str.to_owned(),
+                })
+            }
+            Tag::GetPageLastModified => {
+                let last_modified_lsn = Lsn(buf.read_u64::<BigEndian>()?);
+                Self::GetPageLastModified(PagestreamGetPageLastModifiedResponse {
+                    last_modified_lsn,
+                })
+            }
+        };
         let remaining = buf.into_inner();
         if !remaining.is_empty() {
             anyhow::bail!(
@@ -845,6 +1231,9 @@ impl PagestreamBeMessage {
             Self::GetPage(_) => "GetPage",
             Self::Error(_) => "Error",
             Self::DbSize(_) => "DbSize",
+            Self::Handshake(_) => "Handshake",
+            Self::GoAway(_) => "GoAway",
+            Self::GetPageLastModified(_) => "GetPageLastModified",
         }
     }
 }
@@ -856,6 +1245,33 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_gc_result() {
+        let original = json!({
+            "layers_total": 10,
+            "layers_needed_by_cutoff": 4,
+            "layers_needed_by_pitr": 3,
+            "layers_needed_by_branches": 1,
+            "layers_not_updated": 0,
+            "layers_removed": 2,
+            "elapsed": 1500,
+        });
+        let expected = GcResult {
+            layers_total: 10,
+            layers_needed_by_cutoff: 4,
+            layers_needed_by_pitr: 3,
+            layers_needed_by_branches: 1,
+            layers_not_updated: 0,
+            layers_removed: 2,
+            elapsed: std::time::Duration::from_millis(1500),
+        };
+        assert_eq!(
+            serde_json::from_value::<GcResult>(original.clone()).unwrap(),
+            expected
+        );
+        assert_eq!(serde_json::to_value(&expected).unwrap(), original);
+    }
+
     #[test]
     fn test_pagestream() {
         // Test serialization/deserialization of PagestreamFeMessage
@@ -869,6 +1285,7 @@ mod tests {
                     dbnode: 3,
                     relnode: 4,
                 },
+                deadline: None,
             }),
             PagestreamFeMessage::Nblocks(PagestreamNblocksRequest {
                 latest: false,
@@ -879,6 +1296,7 @@ mod tests {
                     dbnode: 3,
                     relnode: 4,
                 },
+                deadline: None,
             }),
             PagestreamFeMessage::GetPage(PagestreamGetPageRequest {
                 latest: true,
@@ -890,11 +1308,33 @@ mod tests {
                     relnode: 4,
                 },
                 blkno: 7,
+                deadline: None,
             }),
             PagestreamFeMessage::DbSize(PagestreamDbSizeRequest {
                 latest: true,
                 lsn: Lsn(4),
                 dbnode: 7,
+                spcnode: 1663,
+                deadline: None,
+            }),
+            PagestreamFeMessage::DbSize(PagestreamDbSizeRequest {
+                latest: false,
+                lsn: Lsn(4),
+                dbnode: 7,
+                spcnode: PagestreamDbSizeRequest::ALL_TABLESPACES,
+                deadline: None,
+            }),
+            PagestreamFeMessage::GetPageLastModified(PagestreamGetPageLastModifiedRequest {
+                latest: false,
+                lsn: Lsn(4),
+                rel: RelTag {
+                    forknum: 1,
+                    spcnode: 2,
+                    dbnode: 3,
+                    relnode: 4,
+                },
+                blkno: 7,
+                deadline: None,
             }),
         ];
         for msg in messages {
@@ -904,6 +1344,232 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_pagestream_db_size_without_spcnode() {
+        // Clients that predate the spcnode field end the message after the dbnode.
+        let mut bytes = BytesMut::new();
+        bytes.put_u8(3);
+        bytes.put_u8(1);
+        bytes.put_u64(4);
+        bytes.put_u32(7);
+
+        let parsed = PagestreamFeMessage::parse(&mut bytes.freeze().reader()).unwrap();
+        assert_eq!(
+            parsed,
+            PagestreamFeMessage::DbSize(PagestreamDbSizeRequest {
+                latest: true,
+                lsn: Lsn(4),
+                dbnode: 7,
+                spcnode: postgres_ffi::pg_constants::DEFAULTTABLESPACE_OID,
+                deadline: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_pagestream_deadline() {
+        let msg = PagestreamFeMessage::GetPage(PagestreamGetPageRequest {
+            latest: false,
+            lsn: Lsn(4),
+            rel: RelTag {
+                forknum: 0,
+                spcnode: 1663,
+                dbnode: 5,
+                relnode: 16384,
+            },
+            blkno: 7,
+            deadline: Some(Duration::from_millis(500)),
+        });
+        let bytes = msg.serialize();
+        let parsed = PagestreamFeMessage::parse_versioned(
+            &mut bytes.reader(),
+            PagestreamProtocolVersion::V4,
+        )
+        .unwrap();
+        assert_eq!(parsed, msg);
+
+        // Older versions don't know about deadlines
+        let parsed = PagestreamFeMessage::parse_versioned(
+            &mut bytes.reader(),
+            PagestreamProtocolVersion::V3,
+        )
+        .unwrap();
+        assert_eq!(parsed.deadline(), None);
+
+        // V4 clients may leave out the deadline if there is none
+        let msg = PagestreamFeMessage::DbSize(PagestreamDbSizeRequest {
+            latest: true,
+            lsn: Lsn(4),
+            dbnode: 5,
+            spcnode: 1663,
+            deadline: None,
+        });
+        let parsed = PagestreamFeMessage::parse_versioned(
+            &mut msg.serialize().reader(),
+            PagestreamProtocolVersion::V4,
+        )
+        .unwrap();
+        assert_eq!(parsed, msg);
+    }
+
+    #[test]
+    fn test_pagestream_handshake() {
+        let req = PagestreamHandshakeRequest {
+            supported_versions: PagestreamProtocolVersion::V1.bit()
+                | PagestreamProtocolVersion::V2.bit(),
+        };
+        let bytes = req.serialize();
+        assert!(PagestreamHandshakeRequest::is_handshake(&bytes));
+        assert_eq!(
+            PagestreamHandshakeRequest::parse(&mut bytes.reader()).unwrap(),
+            req
+        );
+        // No request can be mistaken for a handshake
+        let getpage = PagestreamFeMessage::GetPage(PagestreamGetPageRequest {
+            latest: true,
+            lsn: Lsn(4),
+            rel: RelTag {
+                forknum: 1,
+                spcnode: 2,
+                dbnode: 3,
+                relnode: 4,
+            },
+            blkno: 7,
+            deadline: None,
+        });
+        assert!(!PagestreamHandshakeRequest::is_handshake(
+            &getpage.serialize()
+        ));
+
+        assert_eq!(
+            PagestreamProtocolVersion::negotiate(req.supported_versions),
+            Some(PagestreamProtocolVersion::V2)
+        );
+        assert_eq!(
+            PagestreamProtocolVersion::negotiate(PagestreamProtocolVersion::V1.bit() | 1 << 31),
+            Some(PagestreamProtocolVersion::V1)
+        );
+        assert_eq!(PagestreamProtocolVersion::negotiate(1 << 31), None);
+
+        let resp = PagestreamBeMessage::Handshake(PagestreamHandshakeResponse {
+            version: PagestreamProtocolVersion::V2,
+        });
+        let PagestreamBeMessage::Handshake(resp) =
+            PagestreamBeMessage::deserialize(resp.serialize()).unwrap()
+        else {
+            panic!("expected a handshake response");
+        };
+        assert_eq!(resp.version, PagestreamProtocolVersion::V2);
+
+        // Version 2 clients must send the tablespace of DbSize requests
+        let mut bytes = BytesMut::new();
+        bytes.put_u8(3);
+        bytes.put_u8(1);
+        bytes.put_u64(4);
+        bytes.put_u32(7);
+        PagestreamFeMessage::parse_versioned(
+            &mut bytes.freeze().reader(),
+            PagestreamProtocolVersion::V2,
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_pagestream_go_away() {
+        let msg = PagestreamBeMessage::GoAway(PagestreamGoAwayResponse {
+            reason: "tenant is moving".to_owned(),
+        });
+        let PagestreamBeMessage::GoAway(resp) =
+            PagestreamBeMessage::deserialize(msg.serialize()).unwrap()
+        else {
+            panic!("expected a go away message");
+        };
+        assert_eq!(resp.reason, "tenant is moving");
+        assert_eq!(
+            PagestreamProtocolVersion::try_from(3),
+            Ok(PagestreamProtocolVersion::V3)
+        );
+    }
+
+    #[test]
+    fn test_pagestream_correlation_id() {
+        // The correlation id goes after a GetPage response's optional effective LSN
+        let msg = PagestreamBeMessage::GetPage(PagestreamGetPageResponse {
+            page: Bytes::from_static(&[7; 8192]),
+            effective_lsn: Some(Lsn(0x1234)),
+        });
+        let (PagestreamBeMessage::GetPage(resp), correlation_id) =
+            PagestreamBeMessage::deserialize_with_correlation_id(
+                msg.serialize_with_correlation_id(42),
+            )
+            .unwrap()
+        else {
+            panic!("expected a get page response");
+        };
+        assert_eq!(correlation_id, 42);
+        assert_eq!(resp.effective_lsn, Some(Lsn(0x1234)));
+
+        let msg = PagestreamBeMessage::Error(PagestreamErrorResponse {
+            message: "no such relation".to_string(),
+        });
+        let (PagestreamBeMessage::Error(resp), correlation_id) =
+            PagestreamBeMessage::deserialize_with_correlation_id(
+                msg.serialize_with_correlation_id(43),
+            )
+            .unwrap()
+        else {
+            panic!("expected an error response");
+        };
+        assert_eq!(correlation_id, 43);
+        assert_eq!(resp.message, "no such relation");
+
+        PagestreamBeMessage::deserialize_with_correlation_id(Bytes::from_static(&[0; 8]))
+            .unwrap_err();
+
+        // Messages that the server sends on its own are never followed by a correlation id,
+        // even on connections that asked for them.
+        for msg in [
+            PagestreamBeMessage::Handshake(PagestreamHandshakeResponse {
+                version: PagestreamProtocolVersion::V2,
+            }),
+            PagestreamBeMessage::GoAway(PagestreamGoAwayResponse {
+                reason: "tenant is moving".to_string(),
+            }),
+        ] {
+            assert!(!msg.is_request_response());
+            PagestreamBeMessage::deserialize_with_correlation_id(msg.serialize()).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_pagestream_get_page_last_modified_response() {
+        let msg = PagestreamBeMessage::GetPageLastModified(PagestreamGetPageLastModifiedResponse {
+            last_modified_lsn: Lsn(0x1234),
+        });
+        let PagestreamBeMessage::GetPageLastModified(resp) =
+            PagestreamBeMessage::deserialize(msg.serialize()).unwrap()
+        else {
+            panic!("expected a last modified response");
+        };
+        assert_eq!(resp.last_modified_lsn, Lsn(0x1234));
+    }
+
+    #[test]
+    fn test_pagestream_get_page_response() {
+        for effective_lsn in [None, Some(Lsn(0x1234))] {
+            let msg = PagestreamBeMessage::GetPage(PagestreamGetPageResponse {
+                page: Bytes::from(vec![7u8; 8192]),
+                effective_lsn,
+            });
+            let reconstructed = PagestreamBeMessage::deserialize(msg.serialize()).unwrap();
+            let PagestreamBeMessage::GetPage(resp) = reconstructed else {
+                panic!("unexpected message {}", reconstructed.kind());
+            };
+            assert_eq!(resp.page, vec![7u8; 8192]);
+            assert_eq!(resp.effective_lsn, effective_lsn);
+        }
+    }
+
     #[test]
     fn test_tenantinfo_serde() {
         // Test serialization/deserialization of TenantInfo
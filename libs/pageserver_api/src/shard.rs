@@ -1,4 +1,7 @@
-use std::{ops::RangeInclusive, str::FromStr};
+use std::{
+    ops::{Range, RangeInclusive},
+    str::FromStr,
+};
 
 use crate::key::{is_rel_block_key, Key};
 use hex::FromHex;
@@ -422,6 +425,39 @@ impl ShardIdentity {
         }
     }
 
+    /// Return true if all the keys in `range` should be ingested by this shard, like
+    /// [`Self::is_key_local`] for each of them. `range` must be the keys of contiguous blocks
+    /// of one relation, end exclusive.
+    ///
+    /// The blocks of a relation are distributed in stripes of `stripe_size` blocks, so this
+    /// only looks at one key per stripe instead of at every key.
+    pub fn is_key_range_local(&self, range: &Range<Key>) -> bool {
+        assert!(!self.is_broken());
+        if self.count < ShardCount(2) || range.is_empty() {
+            return true;
+        }
+        debug_assert!(is_rel_block_key(&range.start));
+        debug_assert_eq!(
+            (range.start.field1, range.start.field2, range.start.field3),
+            (range.end.field1, range.end.field2, range.end.field3)
+        );
+        debug_assert_eq!(
+            (range.start.field4, range.start.field5),
+            (range.end.field4, range.end.field5)
+        );
+
+        let stripe_size = self.stripe_size.0;
+        let first_stripe = range.start.field6 / stripe_size;
+        let last_stripe = (range.end.field6 - 1) / stripe_size;
+        (first_stripe..=last_stripe).all(|stripe| {
+            let key = Key {
+                field6: stripe * stripe_size,
+                ..range.start
+            };
+            key_to_shard_number(self.count, self.stripe_size, &key) == self.number
+        })
+    }
+
     /// Return true if the key should be discarded if found in this shard's
     /// data store, e.g. during compaction after a split
     pub fn is_key_disposable(&self, key: &Key) -> bool {
@@ -773,4 +809,33 @@ mod tests {
         let shard = key_to_shard_number(ShardCount(10), DEFAULT_STRIPE_SIZE, &key);
         assert_eq!(shard, ShardNumber(8));
     }
+
+    #[test]
+    fn key_range_locality() -> Result<(), ShardConfigError> {
+        let key = |blkno| Key {
+            field1: 0x00,
+            field2: 0x67f,
+            field3: 0x5,
+            field4: 0x400c,
+            field5: 0x00,
+            field6: blkno,
+        };
+        let stripe_size = ShardStripeSize(8);
+
+        for number in 0..4 {
+            let shard = ShardIdentity::new(ShardNumber(number), ShardCount(4), stripe_size)?;
+            for (start, end) in [(0, 0), (0, 1), (0, 8), (3, 5), (7, 9), (8, 16), (5, 40)] {
+                let expected = (start..end).all(|blkno| shard.is_key_local(&key(blkno)));
+                assert_eq!(
+                    shard.is_key_range_local(&(key(start)..key(end))),
+                    expected,
+                    "shard {number}, blocks {start}..{end}"
+                );
+            }
+        }
+
+        let unsharded = ShardIdentity::unsharded();
+        assert!(unsharded.is_key_range_local(&(key(0)..key(1000))));
+        Ok(())
+    }
 }
@@ -21,7 +21,7 @@ use tracing::{debug, error, info, trace, warn};
 
 use pq_proto::framed::{ConnectionError, Framed, FramedReader, FramedWriter};
 use pq_proto::{
-    BeMessage, FeMessage, FeStartupPacket, ProtocolError, SQLSTATE_ADMIN_SHUTDOWN,
+    BeMessage, CancelKeyData, FeMessage, FeStartupPacket, ProtocolError, SQLSTATE_ADMIN_SHUTDOWN,
     SQLSTATE_INTERNAL_ERROR, SQLSTATE_SUCCESSFUL_COMPLETION,
 };
 
@@ -112,6 +112,28 @@ pub trait Handler<IO> {
     ) -> Result<(), QueryError> {
         Err(QueryError::Other(anyhow::anyhow!("JWT auth failed")))
     }
+
+    /// Key data to send to the client in BackendKeyData once it's authenticated. A client
+    /// that wants to cancel what this connection is doing opens another connection and sends
+    /// the same key data in a CancelRequest, see [`Handler::process_cancel_request`].
+    ///
+    /// `None`, the default, means that the handler doesn't support cancellation, and no
+    /// BackendKeyData is sent.
+    fn backend_key_data(&mut self) -> Option<CancelKeyData> {
+        None
+    }
+
+    /// Called when the client sends a CancelRequest instead of a startup message, with the
+    /// key data of the connection to cancel. Like in Postgres, nothing is sent back, and the
+    /// connection is closed afterwards.
+    ///
+    /// The request is unauthenticated: the key data is the only proof that it comes from the
+    /// client of the connection to cancel, so it must be hard to guess.
+    fn process_cancel_request(&mut self, _key: &CancelKeyData) -> Result<(), QueryError> {
+        Err(QueryError::Other(anyhow::anyhow!(
+            "Unexpected CancelRequest message during handshake"
+        )))
+    }
 }
 
 /// PostgresBackend protocol state.
@@ -634,9 +656,11 @@ impl<IO: AsyncRead + AsyncWrite + Unpin> PostgresBackend<IO> {
                     }
 
                     self.write_message_noflush(&BeMessage::AuthenticationOk)?
-                        .write_message_noflush(&BeMessage::CLIENT_ENCODING)?
-                        .write_message(&BeMessage::ReadyForQuery)
-                        .await?;
+                        .write_message_noflush(&BeMessage::CLIENT_ENCODING)?;
+                    if let Some(key_data) = handler.backend_key_data() {
+                        self.write_message_noflush(&BeMessage::BackendKeyData(key_data))?;
+                    }
+                    self.write_message(&BeMessage::ReadyForQuery).await?;
                     self.state = ProtoState::Established;
                 }
                 Some(m) => {
@@ -666,6 +690,7 @@ impl<IO: AsyncRead + AsyncWrite + Unpin> PostgresBackend<IO> {
     /// - transition to Authentication if auth type is NeonJWT.
     /// - or perform TLS handshake -- then need to call this again to receive
     ///   actual startup packet.
+    /// - or pass a CancelRequest to the handler and transition to Closed.
     async fn process_startup_message(
         &mut self,
         handler: &mut impl Handler<IO>,
@@ -709,9 +734,11 @@ impl<IO: AsyncRead + AsyncWrite + Unpin> PostgresBackend<IO> {
                             .write_message_noflush(&BeMessage::CLIENT_ENCODING)?
                             .write_message_noflush(&BeMessage::INTEGER_DATETIMES)?
                             // The async python driver requires a valid server_version
-                            .write_message_noflush(&BeMessage::server_version("14.1"))?
-                            .write_message(&BeMessage::ReadyForQuery)
-                            .await?;
+                            .write_message_noflush(&BeMessage::server_version("14.1"))?;
+                        if let Some(key_data) = handler.backend_key_data() {
+                            self.write_message_noflush(&BeMessage::BackendKeyData(key_data))?;
+                        }
+                        self.write_message(&BeMessage::ReadyForQuery).await?;
                         self.state = ProtoState::Established;
                     }
                     AuthType::NeonJWT => {
@@ -721,10 +748,10 @@ impl<IO: AsyncRead + AsyncWrite + Unpin> PostgresBackend<IO> {
                     }
                 }
             }
-            FeStartupPacket::CancelRequest { .. } => {
-                return Err(QueryError::Other(anyhow::anyhow!(
-                    "Unexpected CancelRequest message during handshake"
-                )));
+            FeStartupPacket::CancelRequest(key) => {
+                handler.process_cancel_request(&key)?;
+                // The connection was only for the CancelRequest.
+                self.state = ProtoState::Closed;
             }
         }
         Ok(())
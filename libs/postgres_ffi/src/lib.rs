@@ -137,6 +137,7 @@ pub use v14::xlog_utils::from_pg_timestamp;
 pub use v14::xlog_utils::get_current_timestamp;
 pub use v14::xlog_utils::to_pg_timestamp;
 pub use v14::xlog_utils::XLogFileName;
+pub use v14::xlog_utils::{IsXLogFileName, XLogFromFileName};
 
 pub use v14::bindings::DBState_DB_SHUTDOWNED;
 
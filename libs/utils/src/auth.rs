@@ -11,7 +11,10 @@ use jsonwebtoken::{
 };
 use serde::{Deserialize, Serialize};
 
-use crate::{http::error::ApiError, id::TenantId};
+use crate::{
+    http::error::ApiError,
+    id::{TenantId, TimelineId},
+};
 
 /// Algorithm to use. We require EdDSA.
 const STORAGE_TOKEN_ALGORITHM: Algorithm = Algorithm::EdDSA;
@@ -22,6 +25,9 @@ pub enum Scope {
     // Provides access to all data for a specific tenant (specified in `struct Claims` below)
     // TODO: join these two?
     Tenant,
+    // Provides access to the data of a single timeline of a specific tenant (both specified
+    // in `struct Claims` below), but not to the tenant's other timelines.
+    Timeline,
     // Provides blanket access to all tenants on the pageserver plus pageserver-wide APIs.
     // Should only be used e.g. for status check/tenant creation/list.
     PageServerApi,
@@ -36,12 +42,26 @@ pub enum Scope {
 pub struct Claims {
     #[serde(default)]
     pub tenant_id: Option<TenantId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeline_id: Option<TimelineId>,
     pub scope: Scope,
 }
 
 impl Claims {
     pub fn new(tenant_id: Option<TenantId>, scope: Scope) -> Self {
-        Self { tenant_id, scope }
+        Self {
+            tenant_id,
+            timeline_id: None,
+            scope,
+        }
+    }
+
+    pub fn new_for_timeline(tenant_id: TenantId, timeline_id: TimelineId) -> Self {
+        Self {
+            tenant_id: Some(tenant_id),
+            timeline_id: Some(timeline_id),
+            scope: Scope::Timeline,
+        }
     }
 }
 
@@ -187,6 +207,7 @@ MC4CAQAwBQYDK2VwBCIEID/Drmc1AA6U/znNRWpF3zEGegOATQxfkdWxitcOMsIH
     fn test_decode() {
         let expected_claims = Claims {
             tenant_id: Some(TenantId::from_str("3d1f7595b468230304e0b73cecbcb081").unwrap()),
+            timeline_id: None,
             scope: Scope::Tenant,
         };
 
@@ -214,6 +235,7 @@ MC4CAQAwBQYDK2VwBCIEID/Drmc1AA6U/znNRWpF3zEGegOATQxfkdWxitcOMsIH
     fn test_encode() {
         let claims = Claims {
             tenant_id: Some(TenantId::from_str("3d1f7595b468230304e0b73cecbcb081").unwrap()),
+            timeline_id: None,
             scope: Scope::Tenant,
         };
 
@@ -225,4 +247,20 @@ MC4CAQAwBQYDK2VwBCIEID/Drmc1AA6U/znNRWpF3zEGegOATQxfkdWxitcOMsIH
 
         assert_eq!(decoded.claims, claims);
     }
+
+    #[test]
+    fn test_encode_timeline_scope() {
+        let claims = Claims::new_for_timeline(
+            TenantId::from_str("3d1f7595b468230304e0b73cecbcb081").unwrap(),
+            TimelineId::from_str("de200bd42b49cc1814412c7e592dd6e9").unwrap(),
+        );
+
+        let encoded = encode_from_key_file(&claims, TEST_PRIV_KEY_ED25519).unwrap();
+
+        let auth = JwtAuth::new(vec![DecodingKey::from_ed_pem(TEST_PUB_KEY_ED25519).unwrap()]);
+        let decoded = auth.decode(&encoded).unwrap();
+
+        assert_eq!(decoded.claims, claims);
+        assert_eq!(decoded.claims.scope, Scope::Timeline);
+    }
 }
@@ -312,6 +312,17 @@ pub struct ConnectionId(Id);
 
 id_newtype!(ConnectionId);
 
+/// Neon Request Id identifies a single request within a long-lived connection (for example,
+/// one GetPage request on a pagestream connection). Is used for better logging and tracing
+///
+/// NOTE: It (de)serializes as an array of hex bytes, so the string representation would look
+/// like `[173,80,132,115,129,226,72,254,170,201,135,108,199,26,228,24]`.
+/// See [`Id`] for alternative ways to serialize it.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, PartialOrd, Ord)]
+pub struct RequestId(Id);
+
+id_newtype!(RequestId);
+
 // A pair uniquely identifying Neon instance.
 #[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct TenantTimelineId {
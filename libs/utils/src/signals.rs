@@ -1,3 +1,5 @@
+use std::ffi::c_int;
+
 use signal_hook::iterator::Signals;
 
 pub use signal_hook::consts::{signal::*, TERM_SIGNALS};
@@ -6,6 +8,8 @@ pub enum Signal {
     Quit,
     Interrupt,
     Terminate,
+    /// Only delivered by [`ShutdownSignals::handle_with_reload`].
+    Hangup,
 }
 
 impl Signal {
@@ -14,6 +18,7 @@ impl Signal {
             Signal::Quit => "SIGQUIT",
             Signal::Interrupt => "SIGINT",
             Signal::Terminate => "SIGTERM",
+            Signal::Hangup => "SIGHUP",
         }
     }
 }
@@ -21,12 +26,30 @@ impl Signal {
 pub struct ShutdownSignals;
 
 impl ShutdownSignals {
-    pub fn handle(mut handler: impl FnMut(Signal) -> anyhow::Result<()>) -> anyhow::Result<()> {
-        for raw_signal in Signals::new(TERM_SIGNALS)?.into_iter() {
+    pub fn handle(handler: impl FnMut(Signal) -> anyhow::Result<()>) -> anyhow::Result<()> {
+        Self::handle_signals(TERM_SIGNALS, handler)
+    }
+
+    /// Like [`Self::handle`], but also passes SIGHUP to the handler, which by convention asks
+    /// a daemon to reload its configuration.
+    pub fn handle_with_reload(
+        handler: impl FnMut(Signal) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let mut signals = TERM_SIGNALS.to_vec();
+        signals.push(SIGHUP);
+        Self::handle_signals(&signals, handler)
+    }
+
+    fn handle_signals(
+        signals: &[c_int],
+        mut handler: impl FnMut(Signal) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        for raw_signal in Signals::new(signals)?.into_iter() {
             let signal = match raw_signal {
                 SIGINT => Signal::Interrupt,
                 SIGTERM => Signal::Terminate,
                 SIGQUIT => Signal::Quit,
+                SIGHUP => Signal::Hangup,
                 other => panic!("unknown signal: {}", other),
             };
 
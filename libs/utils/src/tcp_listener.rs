@@ -4,7 +4,7 @@ use std::{
     os::unix::prelude::AsRawFd,
 };
 
-use nix::sys::socket::{setsockopt, sockopt::ReuseAddr};
+use nix::sys::socket::{listen, setsockopt, sockopt::ReuseAddr};
 
 /// Bind a [`TcpListener`] to addr with `SO_REUSEADDR` set to true.
 pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<TcpListener> {
@@ -14,3 +14,46 @@ pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<TcpListener> {
 
     Ok(listener)
 }
+
+/// Like [`bind`], but with room for `backlog` established connections that were not accepted
+/// yet, instead of the standard library's default of 128.
+pub fn bind_with_backlog<A: ToSocketAddrs>(addr: A, backlog: usize) -> io::Result<TcpListener> {
+    let listener = bind(addr)?;
+
+    // The socket is already listening: calling listen() again only changes the backlog.
+    listen(listener.as_raw_fd(), backlog)?;
+
+    Ok(listener)
+}
+
+/// Number of established connections waiting in the accept queue of a listening socket.
+#[cfg(target_os = "linux")]
+pub fn accept_queue_len<L: AsRawFd>(listener: &L) -> io::Result<u32> {
+    use nix::libc;
+
+    // SAFETY: tcp_info is a plain C struct, for which all zeroes is a valid value.
+    let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+    // SAFETY: we pass a pointer to a tcp_info and its size, which the kernel fills in.
+    let ret = unsafe {
+        libc::getsockopt(
+            listener.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut info as *mut libc::tcp_info as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // For a listening socket, the kernel reports the length of the accept queue as the number
+    // of unacknowledged segments.
+    Ok(info.tcpi_unacked)
+}
+
+/// Number of established connections waiting in the accept queue of a listening socket.
+#[cfg(not(target_os = "linux"))]
+pub fn accept_queue_len<L: AsRawFd>(_listener: &L) -> io::Result<u32> {
+    Err(io::ErrorKind::Unsupported.into())
+}
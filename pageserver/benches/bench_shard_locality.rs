@@ -0,0 +1,50 @@
+//! Checking whether the pages of a hot relation are local to a shard, key by key versus a
+//! whole range of contiguous blocks at once.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use pageserver_api::key::Key;
+use pageserver_api::shard::{ShardCount, ShardIdentity, ShardNumber, ShardStripeSize};
+
+/// Block key of `blkno` in the main fork of a relation.
+fn rel_block_key(blkno: u32) -> Key {
+    Key {
+        field1: 0x00,
+        field2: 0x67f,
+        field3: 0x5,
+        field4: 0x400c,
+        field5: 0x00,
+        field6: blkno,
+    }
+}
+
+fn bench_hot_relation(c: &mut Criterion) {
+    let shard = ShardIdentity::new(ShardNumber(0), ShardCount(8), ShardStripeSize(32768)).unwrap();
+
+    // Batches of 32 consecutive blocks, at the start of every stripe of shard 0 in the
+    // first 10000 stripes, like a sequential scan of the shard's part of the relation.
+    let batches: Vec<(u32, u32)> = (0..10_000u32)
+        .map(|stripe| stripe * 32768)
+        .filter(|start| shard.is_key_local(&rel_block_key(*start)))
+        .map(|start| (start, start + 32))
+        .collect();
+
+    let mut group = c.benchmark_group("hot_relation");
+    group.bench_function("is_key_local", |b| {
+        b.iter(|| {
+            for (start, end) in &batches {
+                black_box((*start..*end).all(|blkno| shard.is_key_local(&rel_block_key(blkno))));
+            }
+        });
+    });
+    group.bench_function("is_key_range_local", |b| {
+        b.iter(|| {
+            for (start, end) in &batches {
+                black_box(shard.is_key_range_local(&(rel_block_key(*start)..rel_block_key(*end))));
+            }
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_hot_relation);
+criterion_main!(benches);
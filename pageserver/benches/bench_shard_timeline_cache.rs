@@ -0,0 +1,159 @@
+//! Finding the Timeline of another shard of the tenant for a GetPage request, by resolving the
+//! tenant again versus looking in the per-connection cache of co-located shards.
+//!
+//! The two lookups are modelled on the structures that page_service uses: the tenant map is a
+//! `BTreeMap` of shards behind a `RwLock`, with the timelines of each shard in a
+//! `Mutex<HashMap>`, and the connection's cache is a `Mutex<HashMap>` of `Weak` references by
+//! shard number.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use pageserver_api::key::Key;
+use pageserver_api::shard::{
+    ShardCount, ShardIdentity, ShardNumber, ShardStripeSize, TenantShardId,
+};
+use tokio_util::sync::CancellationToken;
+use utils::id::{TenantId, TimelineId};
+
+/// Stand-in for a Timeline, with the fields that the cache checks.
+struct Timeline {
+    tenant_shard_id: TenantShardId,
+    timeline_id: TimelineId,
+    shard_identity: ShardIdentity,
+    cancel: CancellationToken,
+}
+
+/// Stand-in for a Tenant shard in the tenant map.
+struct Tenant {
+    shard_identity: ShardIdentity,
+    timelines: Mutex<HashMap<TimelineId, Arc<Timeline>>>,
+}
+
+/// Block key of `blkno` in the main fork of a relation.
+fn rel_block_key(blkno: u32) -> Key {
+    Key {
+        field1: 0x00,
+        field2: 0x67f,
+        field3: 0x5,
+        field4: 0x400c,
+        field5: 0x00,
+        field6: blkno,
+    }
+}
+
+/// Resolve the shard that holds `key` like `get_active_tenant_timeline` does, with
+/// `ShardSelector::Page`.
+fn resolve(
+    tenants: &RwLock<BTreeMap<TenantShardId, Arc<Tenant>>>,
+    tenant_id: TenantId,
+    timeline_id: TimelineId,
+    key: &Key,
+) -> Option<Arc<Timeline>> {
+    let tenant = {
+        let tenants = tenants.read().unwrap();
+        let mut want_shard = None;
+        tenants
+            .range(TenantShardId::tenant_range(tenant_id))
+            .find(|(_, tenant)| {
+                let want_shard =
+                    *want_shard.get_or_insert_with(|| tenant.shard_identity.get_shard_number(key));
+                tenant.shard_identity.number == want_shard
+            })
+            .map(|(_, tenant)| Arc::clone(tenant))?
+    };
+    let timelines = tenant.timelines.lock().unwrap();
+    timelines.get(&timeline_id).cloned()
+}
+
+/// Look up the shard that holds `key` like `PageServerHandler::cached_shard_timeline` does.
+fn cached(
+    cache: &Mutex<HashMap<ShardNumber, Weak<Timeline>>>,
+    connection_timeline: &Timeline,
+    key: &Key,
+) -> Option<Arc<Timeline>> {
+    let shard_number = connection_timeline.shard_identity.get_shard_number(key);
+    let cache = cache.lock().unwrap();
+    let timeline = cache.get(&shard_number)?.upgrade()?;
+    (!timeline.cancel.is_cancelled()
+        && timeline.timeline_id == connection_timeline.timeline_id
+        && timeline.tenant_shard_id.tenant_id == connection_timeline.tenant_shard_id.tenant_id
+        && timeline.shard_identity.is_key_local(key))
+    .then_some(timeline)
+}
+
+fn bench_cross_shard_lookup(c: &mut Criterion) {
+    const SHARD_COUNT: ShardCount = ShardCount(8);
+    const STRIPE_SIZE: ShardStripeSize = ShardStripeSize(8);
+
+    // A pageserver with a few hundred unsharded tenants, and all the shards of one more tenant.
+    let mut tenant_map = BTreeMap::new();
+    for _ in 0..500 {
+        let tenant_shard_id = TenantShardId::unsharded(TenantId::generate());
+        let tenant = Tenant {
+            shard_identity: ShardIdentity::unsharded(),
+            timelines: Mutex::new(HashMap::new()),
+        };
+        tenant_map.insert(tenant_shard_id, Arc::new(tenant));
+    }
+    let tenant_id = TenantId::generate();
+    let timeline_id = TimelineId::generate();
+    let mut timelines = Vec::new();
+    for number in 0..SHARD_COUNT.0 {
+        let tenant_shard_id = TenantShardId {
+            tenant_id,
+            shard_number: ShardNumber(number),
+            shard_count: SHARD_COUNT,
+        };
+        let shard_identity =
+            ShardIdentity::new(ShardNumber(number), SHARD_COUNT, STRIPE_SIZE).unwrap();
+        let timeline = Arc::new(Timeline {
+            tenant_shard_id,
+            timeline_id,
+            shard_identity,
+            cancel: CancellationToken::new(),
+        });
+        let tenant = Tenant {
+            shard_identity,
+            timelines: Mutex::new(HashMap::from([(timeline_id, Arc::clone(&timeline))])),
+        };
+        tenant_map.insert(tenant_shard_id, Arc::new(tenant));
+        timelines.push(timeline);
+    }
+    let tenants = RwLock::new(tenant_map);
+
+    // The connection is to shard 0, and all other shards are in its cache already.
+    let connection_timeline = &timelines[0];
+    let cache = Mutex::new(HashMap::from_iter(
+        timelines[1..]
+            .iter()
+            .map(|t| (t.shard_identity.number, Arc::downgrade(t))),
+    ));
+
+    // Requests for the blocks of a relation that shard 0 doesn't hold.
+    let keys: Vec<Key> = (0..4096)
+        .map(rel_block_key)
+        .filter(|key| !connection_timeline.shard_identity.is_key_local(key))
+        .collect();
+
+    let mut group = c.benchmark_group("cross_shard_lookup");
+    group.bench_function("resolve", |b| {
+        b.iter(|| {
+            for key in &keys {
+                black_box(resolve(&tenants, tenant_id, timeline_id, key).unwrap());
+            }
+        });
+    });
+    group.bench_function("cached", |b| {
+        b.iter(|| {
+            for key in &keys {
+                black_box(cached(&cache, connection_timeline, key).unwrap());
+            }
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_cross_shard_lookup);
+criterion_main!(benches);
@@ -0,0 +1,160 @@
+//! Compares positional page-sized I/O through `VirtualFile` on disk with the in-memory backend
+//! that tests can use instead, to show how much of a test's runtime is spent on the disk.
+//!
+//! Also compares small sequential reads straight from a `VirtualFile` with reads through a
+//! `VirtualFileBufReader`.
+//!
+//! Needs the `testing` feature for the in-memory backend:
+//! `cargo bench --features testing --bench bench_virtual_file`
+
+use camino::Utf8PathBuf;
+use pageserver::virtual_file::{self, in_memory, VirtualFile, VirtualFileBufReader};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const PAGE_SZ: usize = 8192;
+const PAGES: u64 = 128;
+
+fn bench_page_io(c: &mut Criterion) {
+    virtual_file::init(100);
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let tmpdir = camino_tempfile::tempdir_in(env!("CARGO_TARGET_TMPDIR")).unwrap();
+    let path: Utf8PathBuf = tmpdir.path().join("pages");
+
+    let page = [0xADu8; PAGE_SZ];
+    let mut buf = [0u8; PAGE_SZ];
+
+    let mut group = c.benchmark_group("page_io");
+
+    let vfile = rt.block_on(VirtualFile::create(&path)).unwrap();
+    group.bench_function(BenchmarkId::new("write", "virtual_file"), |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                for blkno in 0..PAGES {
+                    vfile
+                        .write_all_at(&page, blkno * PAGE_SZ as u64)
+                        .await
+                        .unwrap();
+                }
+            })
+        })
+    });
+    drop(vfile);
+
+    let vfile = rt.block_on(VirtualFile::open(&path)).unwrap();
+    group.bench_function(BenchmarkId::new("read", "virtual_file"), |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                for blkno in 0..PAGES {
+                    vfile
+                        .read_exact_at(&mut buf, blkno * PAGE_SZ as u64)
+                        .await
+                        .unwrap();
+                    black_box(&buf);
+                }
+            })
+        })
+    });
+    drop(vfile);
+
+    let mfile = rt.block_on(VirtualFile::create_in_memory(&path)).unwrap();
+    group.bench_function(BenchmarkId::new("write", "in_memory"), |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                for blkno in 0..PAGES {
+                    mfile
+                        .write_all_at(&page, blkno * PAGE_SZ as u64)
+                        .await
+                        .unwrap();
+                }
+            })
+        })
+    });
+    rt.block_on(mfile.remove()).unwrap();
+
+    let mut mfile = rt.block_on(VirtualFile::create_in_memory(&path)).unwrap();
+    rt.block_on(async {
+        for _ in 0..PAGES {
+            mfile.write_all(&page).await.unwrap();
+        }
+    });
+    drop(mfile);
+    let mfile = rt
+        .block_on(VirtualFile::open_in_memory(
+            &path,
+            in_memory::OpenOptions::new().read(true),
+        ))
+        .unwrap();
+    group.bench_function(BenchmarkId::new("read", "in_memory"), |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                for blkno in 0..PAGES {
+                    mfile
+                        .read_exact_at(&mut buf, blkno * PAGE_SZ as u64)
+                        .await
+                        .unwrap();
+                    black_box(&buf);
+                }
+            })
+        })
+    });
+    rt.block_on(mfile.remove()).unwrap();
+
+    group.finish();
+}
+
+/// Size of the records of the sequential scan, like small values in a layer file.
+const RECORD_SZ: usize = 64;
+
+fn bench_sequential_read(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let tmpdir = camino_tempfile::tempdir_in(env!("CARGO_TARGET_TMPDIR")).unwrap();
+    let path: Utf8PathBuf = tmpdir.path().join("records");
+    let file_size = PAGES as usize * PAGE_SZ;
+    std::fs::write(&path, vec![0xADu8; file_size]).unwrap();
+
+    let mut record = [0u8; RECORD_SZ];
+
+    let mut group = c.benchmark_group("sequential_read");
+
+    let vfile = rt.block_on(VirtualFile::open(&path)).unwrap();
+    group.bench_function("unbuffered", |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                for offset in (0..file_size).step_by(RECORD_SZ) {
+                    vfile
+                        .read_exact_at(&mut record, offset as u64)
+                        .await
+                        .unwrap();
+                    black_box(&record);
+                }
+            })
+        })
+    });
+    group.bench_function("buffered", |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                let mut reader = VirtualFileBufReader::new(&vfile);
+                for _ in (0..file_size).step_by(RECORD_SZ) {
+                    reader.read_exact(&mut record).await.unwrap();
+                    black_box(&record);
+                }
+            })
+        })
+    });
+    drop(vfile);
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_page_io, bench_sequential_read);
+criterion_main!(benches);
@@ -1,17 +1,32 @@
+use futures::{Stream, StreamExt};
 use pageserver_api::{models::*, shard::TenantShardId};
-use reqwest::{IntoUrl, Method};
+use reqwest::{
+    header::{HeaderMap, HeaderValue, ACCEPT, CONTENT_TYPE, IF_MATCH},
+    IntoUrl, Method, StatusCode,
+};
 use utils::{
     http::error::HttpErrorBody,
     id::{TenantId, TimelineId},
 };
 
+pub mod client_metrics;
+#[cfg(feature = "parse-metrics")]
+pub mod metrics;
+#[cfg(all(unix, feature = "unix-socket"))]
+mod unix;
 pub mod util;
 
+use client_metrics::ClientMetrics;
+
 #[derive(Debug)]
 pub struct Client {
     mgmt_api_endpoint: String,
     authorization_header: Option<String>,
     client: reqwest::Client,
+    metrics: Option<ClientMetrics>,
+    /// Send the requests over this Unix domain socket instead of TCP, see [`Client::new_unix`].
+    #[cfg(all(unix, feature = "unix-socket"))]
+    unix_socket: Option<std::path::PathBuf>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -22,8 +37,47 @@ pub enum Error {
     #[error("receive error body: {0}")]
     ReceiveErrorBody(String),
 
+    /// The response body is not the expected JSON. `body` is the start of it, to tell what the
+    /// server sent instead, e.g. an error page of a proxy.
+    #[error("decode body: {error}, body: {body:?}")]
+    DecodeBody {
+        error: serde_json::Error,
+        body: String,
+    },
+
     #[error("pageserver API: {0}")]
     ApiError(String),
+
+    /// The request conflicts with the state of the tenant, e.g. the tenant is already split into
+    /// the requested number of shards. Only returned by [`Client::tenant_shard_split`], other
+    /// methods report conflicts as [`Error::ApiError`].
+    #[error("pageserver API conflict: {0}")]
+    Conflict(String),
+
+    /// The resource changed since the caller last read it: its entity tag no longer matches the
+    /// one that the request was conditional on. Only returned by [`Client::tenant_config_cas`].
+    #[error("pageserver API precondition failed: {0}")]
+    PreconditionFailed(String),
+
+    /// The timeline has no layer with the requested name. Only returned by the methods that
+    /// operate on a single layer, like [`Client::timeline_layer_download`].
+    #[error("layer not found: {0}")]
+    LayerNotFound(String),
+
+    #[cfg(feature = "parse-metrics")]
+    #[error("parse metrics: {0}")]
+    ParseMetrics(std::io::Error),
+
+    /// The request couldn't be sent, or the response not received, over the Unix domain socket
+    /// of a client created with [`Client::new_unix`].
+    #[cfg(all(unix, feature = "unix-socket"))]
+    #[error("unix socket: {0:#}")]
+    UnixSocket(anyhow::Error),
+
+    /// The operation polled by [`Client::await_operation`] didn't complete within the given
+    /// timeout.
+    #[error("operation did not complete within {0:?}")]
+    Timeout(std::time::Duration),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -49,48 +103,295 @@ impl ResponseErrorMessageExt for reqwest::Response {
     }
 }
 
+/// Append `segment` to `url`, with all characters but the unreserved ones of RFC 3986
+/// percent-encoded, so that it can't change the structure of the URL.
+fn push_percent_encoded(url: &mut String, segment: &str) {
+    for b in segment.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            url.push(b as char);
+        } else {
+            url.push_str(&format!("%{b:02X}"));
+        }
+    }
+}
+
+/// The error of a failed request for a single layer. The pageserver reports a layer that isn't
+/// in the layer map as `400 Bad Request`, with just the message as a JSON string instead of an
+/// [`HttpErrorBody`], which tells it apart from other bad requests.
+async fn layer_error(response: reqwest::Response) -> Error {
+    let status = response.status();
+    let url = response.url().to_owned();
+    let body = match response.bytes().await {
+        Ok(body) => body,
+        Err(e) => return Error::ReceiveBody(e),
+    };
+    if status == StatusCode::BAD_REQUEST {
+        if let Ok(msg) = serde_json::from_slice::<String>(&body) {
+            return Error::LayerNotFound(msg);
+        }
+    }
+    match serde_json::from_slice::<HttpErrorBody>(&body) {
+        Ok(HttpErrorBody { msg }) => Error::ApiError(msg),
+        Err(_) => Error::ReceiveErrorBody(format!("Http error ({}) at {}.", status.as_u16(), url)),
+    }
+}
+
+/// How much of a response body that can't be decoded to include in [`Error::DecodeBody`].
+const DECODE_ERROR_BODY_SNIPPET_LEN: usize = 512;
+
+pub(crate) trait ResponseJsonExt: Sized {
+    async fn json_body<T: serde::de::DeserializeOwned>(self) -> Result<T>;
+}
+
+impl ResponseJsonExt for reqwest::Response {
+    /// Like [`reqwest::Response::json`], but with the start of the body in the error if it
+    /// can't be decoded.
+    async fn json_body<T: serde::de::DeserializeOwned>(self) -> Result<T> {
+        let body = self.bytes().await.map_err(Error::ReceiveBody)?;
+        decode_json(&body)
+    }
+}
+
+/// Decode `body` as JSON, with the start of it in the error if it can't be decoded.
+fn decode_json<T: serde::de::DeserializeOwned>(body: &[u8]) -> Result<T> {
+    serde_json::from_slice(body).map_err(|error| {
+        let snippet = &body[..body.len().min(DECODE_ERROR_BODY_SNIPPET_LEN)];
+        let mut body_snippet = String::from_utf8_lossy(snippet).into_owned();
+        if snippet.len() < body.len() {
+            body_snippet.push_str("...");
+        }
+        Error::DecodeBody {
+            error,
+            body: body_snippet,
+        }
+    })
+}
+
+/// The content type of newline-delimited JSON: one JSON value per line.
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// How long [`Client::await_operation`] waits before polling again. The delay doubles after
+/// each poll, up to the maximum.
+const AWAIT_OPERATION_MIN_BACKOFF: std::time::Duration = std::time::Duration::from_millis(100);
+const AWAIT_OPERATION_MAX_BACKOFF: std::time::Duration = std::time::Duration::from_secs(5);
+
+fn is_ndjson(response: &reqwest::Response) -> bool {
+    response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with(NDJSON_CONTENT_TYPE))
+}
+
+/// Decode the newline-delimited JSON body of `response` into a stream of values as it arrives,
+/// holding at most one line of it in memory. A line that can't be decoded is an
+/// [`Error::DecodeBody`] item and the stream goes on with the next line, but the stream ends
+/// after an error receiving the body.
+fn ndjson_stream<T: serde::de::DeserializeOwned>(
+    response: reqwest::Response,
+) -> impl Stream<Item = Result<T>> {
+    futures::stream::unfold(
+        (Some(response), Vec::new()),
+        |(mut response, mut buf)| async move {
+            loop {
+                if let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+                    let line: Vec<u8> = buf.drain(..=pos).collect();
+                    if line.iter().all(u8::is_ascii_whitespace) {
+                        continue;
+                    }
+                    return Some((decode_json(&line), (response, buf)));
+                }
+                let Some(body) = response.as_mut() else {
+                    // The last line doesn't need to end with a newline.
+                    let line = std::mem::take(&mut buf);
+                    if line.iter().all(u8::is_ascii_whitespace) {
+                        return None;
+                    }
+                    return Some((decode_json(&line), (response, buf)));
+                };
+                match body.chunk().await {
+                    Ok(Some(chunk)) => buf.extend_from_slice(&chunk),
+                    Ok(None) => response = None,
+                    Err(e) => return Some((Err(Error::ReceiveBody(e)), (None, Vec::new()))),
+                }
+            }
+        },
+    )
+}
+
 pub enum ForceAwaitLogicalSize {
     Yes,
     No,
 }
 
+/// Options of [`Client::list_timelines_query`].
+pub struct ListTimelinesQuery {
+    pub force_await_logical_size: ForceAwaitLogicalSize,
+    /// Compute the logical size of each timeline from scratch, which is expensive.
+    pub include_non_incremental_logical_size: bool,
+    /// Only list the timelines in this state.
+    pub state: Option<TimelineStateFilter>,
+}
+
+impl Default for ListTimelinesQuery {
+    fn default() -> Self {
+        Self {
+            force_await_logical_size: ForceAwaitLogicalSize::No,
+            include_non_incremental_logical_size: false,
+            state: None,
+        }
+    }
+}
+
+impl ListTimelinesQuery {
+    /// The query part of the URL, including the leading `?`, or an empty string if all
+    /// options are at their defaults.
+    fn to_query_string(&self) -> String {
+        let mut params = Vec::new();
+        if let ForceAwaitLogicalSize::Yes = self.force_await_logical_size {
+            params.push("force-await-initial-logical-size=true".to_owned());
+        }
+        if self.include_non_incremental_logical_size {
+            params.push("include-non-incremental-logical-size=true".to_owned());
+        }
+        if let Some(state) = self.state {
+            params.push(format!("state={state}"));
+        }
+        if params.is_empty() {
+            String::new()
+        } else {
+            format!("?{}", params.join("&"))
+        }
+    }
+}
+
+impl ForceAwaitLogicalSize {
+    /// Add the corresponding query parameter to `uri`, which must not have a query yet.
+    fn apply(&self, uri: String) -> String {
+        match self {
+            ForceAwaitLogicalSize::Yes => {
+                format!("{}?force-await-initial-logical-size={}", uri, true)
+            }
+            ForceAwaitLogicalSize::No => uri,
+        }
+    }
+}
+
 impl Client {
     pub fn new(mgmt_api_endpoint: String, jwt: Option<&str>) -> Self {
         Self {
             mgmt_api_endpoint,
             authorization_header: jwt.map(|jwt| format!("Bearer {jwt}")),
             client: reqwest::Client::new(),
+            metrics: None,
+            #[cfg(all(unix, feature = "unix-socket"))]
+            unix_socket: None,
         }
     }
 
+    /// Like [`Self::new`], but connects to the management API on the Unix domain socket at
+    /// `socket_path` instead of over TCP, which keeps the API off the network in development
+    /// setups. Only available on Unix platforms, with the `unix-socket` feature.
+    ///
+    /// Every request opens a new connection to the socket.
+    #[cfg(all(unix, feature = "unix-socket"))]
+    pub fn new_unix(socket_path: impl Into<std::path::PathBuf>, jwt: Option<&str>) -> Self {
+        Self {
+            // Only the path and the query of the URLs are sent, the host is a placeholder.
+            mgmt_api_endpoint: "http://localhost".to_owned(),
+            unix_socket: Some(socket_path.into()),
+            ..Self::new(String::new(), jwt)
+        }
+    }
+
+    /// Record the requests of this client in `metrics`.
+    pub fn with_metrics(mut self, metrics: ClientMetrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
     pub async fn list_tenants(&self) -> Result<Vec<pageserver_api::models::TenantInfo>> {
-        let uri = format!("{}/v1/tenant", self.mgmt_api_endpoint);
+        let uri = self.url(&["v1", "tenant"]);
         let resp = self.get(&uri).await?;
-        resp.json().await.map_err(Error::ReceiveBody)
+        resp.json_body().await
+    }
+
+    /// Like [`Self::list_tenants`], but decodes the tenants one by one as they arrive instead
+    /// of buffering the whole list, for pageservers with very many tenants.
+    ///
+    /// The list is requested as newline-delimited JSON, with `Accept: application/x-ndjson`,
+    /// and the pageserver is expected to answer with that content type and one [`TenantInfo`]
+    /// per line. A pageserver that doesn't support it answers with the usual JSON array, which
+    /// is then decoded as a whole. A line that can't be decoded is an error item of the stream,
+    /// and the stream goes on with the next line.
+    pub fn stream_tenants(&self) -> impl Stream<Item = Result<TenantInfo>> + '_ {
+        let response = async move {
+            let uri = self.url(&["v1", "tenant"]);
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT, HeaderValue::from_static(NDJSON_CONTENT_TYPE));
+            self.request_with_headers_noerror(Method::GET, &uri, (), headers)
+                .await?
+                .error_from_body()
+                .await
+        };
+        futures::stream::once(response).flat_map(|response| match response {
+            Ok(response) if is_ndjson(&response) => ndjson_stream(response).left_stream(),
+            response => futures::stream::once(async move {
+                // A pageserver without support for NDJSON sends the whole list at once.
+                response?.json_body::<Vec<TenantInfo>>().await
+            })
+            .flat_map(|tenants| {
+                futures::stream::iter(match tenants {
+                    Ok(tenants) => tenants.into_iter().map(Ok).collect(),
+                    Err(e) => vec![Err(e)],
+                })
+            })
+            .right_stream(),
+        })
     }
 
     pub async fn tenant_details(
         &self,
         tenant_id: TenantId,
     ) -> Result<pageserver_api::models::TenantDetails> {
-        let uri = format!("{}/v1/tenant/{tenant_id}", self.mgmt_api_endpoint);
-        self.get(uri)
-            .await?
-            .json()
-            .await
-            .map_err(Error::ReceiveBody)
+        let uri = self.url(&["v1", "tenant", &tenant_id.to_string()]);
+        self.get(uri).await?.json_body().await
     }
 
     pub async fn list_timelines(
         &self,
         tenant_id: TenantId,
     ) -> Result<Vec<pageserver_api::models::TimelineInfo>> {
-        let uri = format!("{}/v1/tenant/{tenant_id}/timeline", self.mgmt_api_endpoint);
-        self.get(&uri)
-            .await?
-            .json()
+        self.list_timelines_with(tenant_id, ForceAwaitLogicalSize::No)
             .await
-            .map_err(Error::ReceiveBody)
+    }
+
+    /// Like [`Self::list_timelines`], but with control over waiting for the logical sizes of
+    /// the timelines, like [`Self::timeline_info`].
+    pub async fn list_timelines_with(
+        &self,
+        tenant_id: TenantId,
+        force_await_logical_size: ForceAwaitLogicalSize,
+    ) -> Result<Vec<pageserver_api::models::TimelineInfo>> {
+        let query = ListTimelinesQuery {
+            force_await_logical_size,
+            ..Default::default()
+        };
+        self.list_timelines_query(tenant_id, &query).await
+    }
+
+    /// Like [`Self::list_timelines`], with all the options of the endpoint.
+    pub async fn list_timelines_query(
+        &self,
+        tenant_id: TenantId,
+        query: &ListTimelinesQuery,
+    ) -> Result<Vec<pageserver_api::models::TimelineInfo>> {
+        let uri = format!(
+            "{}{}",
+            self.url(&["v1", "tenant", &tenant_id.to_string(), "timeline"]),
+            query.to_query_string()
+        );
+        self.get(&uri).await?.json_body().await
     }
 
     pub async fn timeline_info(
@@ -99,21 +400,17 @@ impl Client {
         timeline_id: TimelineId,
         force_await_logical_size: ForceAwaitLogicalSize,
     ) -> Result<pageserver_api::models::TimelineInfo> {
-        let uri = format!(
-            "{}/v1/tenant/{tenant_id}/timeline/{timeline_id}",
-            self.mgmt_api_endpoint
-        );
+        let uri = self.url(&[
+            "v1",
+            "tenant",
+            &tenant_id.to_string(),
+            "timeline",
+            &timeline_id.to_string(),
+        ]);
 
-        let uri = match force_await_logical_size {
-            ForceAwaitLogicalSize::Yes => format!("{}?force-await-logical-size={}", uri, true),
-            ForceAwaitLogicalSize::No => uri,
-        };
+        let uri = force_await_logical_size.apply(uri);
 
-        self.get(&uri)
-            .await?
-            .json()
-            .await
-            .map_err(Error::ReceiveBody)
+        self.get(&uri).await?.json_body().await
     }
 
     pub async fn keyspace(
@@ -121,15 +418,26 @@ impl Client {
         tenant_id: TenantId,
         timeline_id: TimelineId,
     ) -> Result<pageserver_api::models::partitioning::Partitioning> {
-        let uri = format!(
-            "{}/v1/tenant/{tenant_id}/timeline/{timeline_id}/keyspace",
-            self.mgmt_api_endpoint
-        );
-        self.get(&uri)
-            .await?
-            .json()
-            .await
-            .map_err(Error::ReceiveBody)
+        let uri = self.url(&[
+            "v1",
+            "tenant",
+            &tenant_id.to_string(),
+            "timeline",
+            &timeline_id.to_string(),
+            "keyspace",
+        ]);
+        self.get(&uri).await?.json_body().await
+    }
+
+    /// The URL of an endpoint of the management API: `segments` are percent-encoded and joined
+    /// into a path under the base URL that the client was created with.
+    fn url(&self, segments: &[&str]) -> String {
+        let mut url = self.mgmt_api_endpoint.clone();
+        for segment in segments {
+            url.push('/');
+            push_percent_encoded(&mut url, segment);
+        }
+        url
     }
 
     async fn get<U: IntoUrl>(&self, uri: U) -> Result<reqwest::Response> {
@@ -142,43 +450,157 @@ impl Client {
         uri: U,
         body: B,
     ) -> Result<reqwest::Response> {
-        let req = self.client.request(method, uri);
+        let res = self.request_noerror(method, uri, body).await?;
+        let response = res.error_from_body().await?;
+        Ok(response)
+    }
+
+    /// Like [`Self::request`], but returns error responses as they are.
+    async fn request_noerror<B: serde::Serialize, U: reqwest::IntoUrl>(
+        &self,
+        method: Method,
+        uri: U,
+        body: B,
+    ) -> Result<reqwest::Response> {
+        self.request_with_headers_noerror(method, uri, body, HeaderMap::new())
+            .await
+    }
+
+    /// Like [`Self::request_noerror`], with additional request headers.
+    async fn request_with_headers_noerror<B: serde::Serialize, U: reqwest::IntoUrl>(
+        &self,
+        method: Method,
+        uri: U,
+        body: B,
+        headers: HeaderMap,
+    ) -> Result<reqwest::Response> {
+        let in_flight = self
+            .metrics
+            .as_ref()
+            .map(|metrics| metrics.start_request(&self.mgmt_api_endpoint, &method));
+        let req = self.client.request(method, uri).headers(headers);
         let req = if let Some(value) = &self.authorization_header {
             req.header(reqwest::header::AUTHORIZATION, value)
         } else {
             req
         };
-        let res = req.json(&body).send().await.map_err(Error::ReceiveBody)?;
-        let response = res.error_from_body().await?;
-        Ok(response)
+        let req = req.json(&body);
+        #[cfg(all(unix, feature = "unix-socket"))]
+        let res = match &self.unix_socket {
+            Some(socket_path) => unix::send(socket_path, req).await,
+            None => req.send().await.map_err(Error::ReceiveBody),
+        };
+        #[cfg(not(all(unix, feature = "unix-socket")))]
+        let res = req.send().await.map_err(Error::ReceiveBody);
+        if let Some(in_flight) = in_flight {
+            in_flight.finish(res.as_ref().ok().map(|res| res.status()));
+        }
+        res
     }
 
     pub async fn status(&self) -> Result<()> {
-        let uri = format!("{}/v1/status", self.mgmt_api_endpoint);
+        let uri = self.url(&["v1", "status"]);
         self.get(&uri).await?;
         Ok(())
     }
 
+    /// The pageserver's metrics, in the Prometheus text exposition format.
+    pub async fn metrics_raw(&self) -> Result<String> {
+        let uri = self.url(&["metrics"]);
+        self.get(&uri)
+            .await?
+            .text()
+            .await
+            .map_err(Error::ReceiveBody)
+    }
+
+    /// The pageserver's metrics, grouped into families. See [`metrics::parse`].
+    #[cfg(feature = "parse-metrics")]
+    pub async fn metrics_parsed(&self) -> Result<Vec<metrics::MetricFamily>> {
+        let text = self.metrics_raw().await?;
+        metrics::parse(&text).map_err(Error::ParseMetrics)
+    }
+
     pub async fn tenant_create(&self, req: &TenantCreateRequest) -> Result<TenantId> {
-        let uri = format!("{}/v1/tenant", self.mgmt_api_endpoint);
+        let uri = self.url(&["v1", "tenant"]);
         self.request(Method::POST, &uri, req)
             .await?
-            .json()
+            .json_body()
             .await
-            .map_err(Error::ReceiveBody)
     }
 
     pub async fn tenant_config(&self, req: &TenantConfigRequest) -> Result<()> {
-        let uri = format!("{}/v1/tenant/config", self.mgmt_api_endpoint);
+        let uri = self.url(&["v1", "tenant", "config"]);
         self.request(Method::PUT, &uri, req).await?;
         Ok(())
     }
 
-    pub async fn tenant_secondary_download(&self, tenant_id: TenantShardId) -> Result<()> {
-        let uri = format!(
-            "{}/v1/tenant/{}/secondary/download",
-            self.mgmt_api_endpoint, tenant_id
+    /// Like [`Self::tenant_config`], but only if the tenant's config hasn't changed since the
+    /// caller read it, for read-modify-write cycles that don't clobber concurrent updates.
+    ///
+    /// `expected_etag` is the `ETag` header of the response that returned the config that `req`
+    /// is derived from, quotes included. The request is sent with it in an `If-Match` header,
+    /// and the pageserver is expected to reject it with `412 Precondition Failed` if the
+    /// current config has a different tag, which is returned as [`Error::PreconditionFailed`].
+    /// The caller should then read the config again and retry. A pageserver that doesn't
+    /// support entity tags on the tenant config ignores the header and applies the update.
+    pub async fn tenant_config_cas(
+        &self,
+        req: &TenantConfigRequest,
+        expected_etag: &str,
+    ) -> Result<()> {
+        let uri = self.url(&["v1", "tenant", "config"]);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            IF_MATCH,
+            HeaderValue::from_str(expected_etag)
+                .map_err(|e| Error::ApiError(format!("invalid entity tag: {e}")))?,
         );
+        let response = self
+            .request_with_headers_noerror(Method::PUT, &uri, req, headers)
+            .await?;
+        let status = response.status();
+        match response.error_from_body().await {
+            Ok(_) => Ok(()),
+            Err(Error::ApiError(msg) | Error::ReceiveErrorBody(msg))
+                if status == StatusCode::PRECONDITION_FAILED =>
+            {
+                Err(Error::PreconditionFailed(msg))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Restore the remote storage of a tenant shard to its state at `timestamp`, see
+    /// [`TenantTimeTravelRequest`]. The tenant must not be attached anywhere while this runs.
+    pub async fn tenant_time_travel_remote_storage(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timestamp: &str,
+        done_if_after: &str,
+    ) -> Result<()> {
+        let uri = self.url(&[
+            "v1",
+            "tenant",
+            &tenant_shard_id.to_string(),
+            "time_travel_remote_storage",
+        ]);
+        let req = TenantTimeTravelRequest {
+            travel_to: timestamp.to_owned(),
+            done_if_after: done_if_after.to_owned(),
+        };
+        self.request(Method::PUT, &uri, &req).await?;
+        Ok(())
+    }
+
+    pub async fn tenant_secondary_download(&self, tenant_id: TenantShardId) -> Result<()> {
+        let uri = self.url(&[
+            "v1",
+            "tenant",
+            &tenant_id.to_string(),
+            "secondary",
+            "download",
+        ]);
         self.request(Method::POST, &uri, ())
             .await?
             .error_for_status()
@@ -193,10 +615,7 @@ impl Client {
         flush_ms: Option<std::time::Duration>,
     ) -> Result<()> {
         let req_body = TenantLocationConfigRequest { tenant_id, config };
-        let path = format!(
-            "{}/v1/tenant/{}/location_config",
-            self.mgmt_api_endpoint, tenant_id
-        );
+        let path = self.url(&["v1", "tenant", &tenant_id.to_string(), "location_config"]);
         let path = if let Some(flush_ms) = flush_ms {
             format!("{}?flush_ms={}", path, flush_ms.as_millis())
         } else {
@@ -211,26 +630,846 @@ impl Client {
         tenant_id: TenantId,
         req: &TimelineCreateRequest,
     ) -> Result<TimelineInfo> {
-        let uri = format!(
-            "{}/v1/tenant/{}/timeline",
-            self.mgmt_api_endpoint, tenant_id
-        );
+        let uri = self.url(&["v1", "tenant", &tenant_id.to_string(), "timeline"]);
         self.request(Method::POST, &uri, req)
             .await?
-            .json()
+            .json_body()
             .await
-            .map_err(Error::ReceiveBody)
+    }
+
+    /// Compact a timeline right away. With `force`, the keyspace is repartitioned even if it
+    /// hasn't changed much.
+    ///
+    /// The pageserver answers once the compaction is done, so there is no need to poll
+    /// [`Self::timeline_info`] afterwards. The endpoint is only available in pageservers
+    /// that have the testing API enabled.
+    pub async fn timeline_compact(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+        force: bool,
+    ) -> Result<()> {
+        let uri = self.url(&[
+            "v1",
+            "tenant",
+            &tenant_shard_id.to_string(),
+            "timeline",
+            &timeline_id.to_string(),
+            "compact",
+        ]);
+        let uri = if force {
+            format!("{uri}?force_repartition=true")
+        } else {
+            uri
+        };
+        self.request(Method::PUT, &uri, ()).await?;
+        Ok(())
+    }
+
+    /// Run GC on a timeline right away and wait for it to finish. `gc_horizon` overrides the
+    /// tenant's GC horizon for this run.
+    pub async fn timeline_gc(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+        gc_horizon: Option<u64>,
+    ) -> Result<GcResult> {
+        let uri = self.url(&[
+            "v1",
+            "tenant",
+            &tenant_shard_id.to_string(),
+            "timeline",
+            &timeline_id.to_string(),
+            "do_gc",
+        ]);
+        let req = TimelineGcRequest { gc_horizon };
+        self.request(Method::PUT, &uri, &req)
+            .await?
+            .json_body()
+            .await
+    }
+
+    /// Split the shards of a tenant so that it has `new_shard_count` of them, and return the
+    /// ids of the new shards. Fails with [`Error::Conflict`] if the tenant can't be split that
+    /// way, e.g. because it already has that many shards.
+    pub async fn tenant_shard_split(
+        &self,
+        tenant_id: TenantId,
+        new_shard_count: u8,
+    ) -> Result<TenantShardSplitResponse> {
+        let uri = self.url(&["v1", "tenant", &tenant_id.to_string(), "shard_split"]);
+        let req = TenantShardSplitRequest { new_shard_count };
+        let response = self.request_noerror(Method::PUT, &uri, &req).await?;
+        let status = response.status();
+        match response.error_from_body().await {
+            Ok(response) => response.json_body().await,
+            Err(Error::ApiError(msg)) if status == StatusCode::CONFLICT => {
+                Err(Error::Conflict(msg))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Make the pageserver download a layer of a timeline from remote storage to its local
+    /// disk, if it isn't there already. Returns whether the layer had to be downloaded.
+    ///
+    /// The pageserver doesn't send the contents of the layer, only whether it is resident now.
+    /// Fails with [`Error::LayerNotFound`] if the timeline has no layer `layer_file_name`.
+    pub async fn timeline_layer_download(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+        layer_file_name: &str,
+    ) -> Result<bool> {
+        let uri = self.url(&[
+            "v1",
+            "tenant",
+            &tenant_shard_id.to_string(),
+            "timeline",
+            &timeline_id.to_string(),
+            "layer",
+            layer_file_name,
+        ]);
+        let response = self.request_noerror(Method::GET, &uri, ()).await?;
+        match response.status() {
+            StatusCode::NOT_MODIFIED => Ok(false),
+            status if status.is_success() => Ok(true),
+            _ => Err(layer_error(response).await),
+        }
+    }
+
+    /// Evict a layer of a timeline from the pageserver's local disk, so that the next read
+    /// of it downloads it again. A layer that is already evicted is not an error.
+    ///
+    /// Fails with [`Error::LayerNotFound`] if the timeline has no layer `layer_file_name`.
+    pub async fn timeline_layer_evict(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+        layer_file_name: &str,
+    ) -> Result<()> {
+        let uri = self.url(&[
+            "v1",
+            "tenant",
+            &tenant_shard_id.to_string(),
+            "timeline",
+            &timeline_id.to_string(),
+            "layer",
+            layer_file_name,
+        ]);
+        let response = self.request_noerror(Method::DELETE, &uri, ()).await?;
+        match response.status() {
+            // Not resident, nothing to evict.
+            StatusCode::NOT_MODIFIED => Ok(()),
+            status if status.is_client_error() || status.is_server_error() => {
+                Err(layer_error(response).await)
+            }
+            _ => response
+                .error_for_status()
+                .map(|_| ())
+                .map_err(|e| Error::ApiError(format!("{}", e))),
+        }
     }
 
     pub async fn tenant_reset(&self, tenant_shard_id: TenantShardId) -> Result<()> {
-        let uri = format!(
-            "{}/v1/tenant/{}/reset",
-            self.mgmt_api_endpoint, tenant_shard_id
-        );
+        let uri = self.url(&["v1", "tenant", &tenant_shard_id.to_string(), "reset"]);
         self.request(Method::POST, &uri, ())
             .await?
-            .json()
+            .json_body()
             .await
-            .map_err(Error::ReceiveBody)
+    }
+
+    /// Call `poll` until it returns `Some`, for operations that the pageserver completes in
+    /// the background after the request that started them returned. The delay between polls
+    /// grows exponentially, up to a few seconds.
+    ///
+    /// Errors returned by `poll` end the wait. Fails with [`Error::Timeout`] if the operation
+    /// didn't complete within `timeout`.
+    pub async fn await_operation<F, Fut, T>(
+        &self,
+        mut poll: F,
+        timeout: std::time::Duration,
+    ) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<Option<T>>>,
+    {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut backoff = AWAIT_OPERATION_MIN_BACKOFF;
+        loop {
+            if let Some(result) = poll().await? {
+                return Ok(result);
+            }
+            let now = tokio::time::Instant::now();
+            if now >= deadline {
+                return Err(Error::Timeout(timeout));
+            }
+            tokio::time::sleep(backoff.min(deadline - now)).await;
+            backoff = (backoff * 2).min(AWAIT_OPERATION_MAX_BACKOFF);
+        }
+    }
+
+    /// Wait until a tenant is active, e.g. after [`Self::location_config`],
+    /// [`Self::tenant_reset`] or [`Self::tenant_shard_split`], which return while the tenant
+    /// is still loading. Fails with [`Error::ApiError`] if the tenant is broken or shutting
+    /// down.
+    pub async fn tenant_await_active(
+        &self,
+        tenant_id: TenantId,
+        timeout: std::time::Duration,
+    ) -> Result<TenantInfo> {
+        self.await_operation(
+            move || async move {
+                let tenant_info = self.tenant_details(tenant_id).await?.tenant_info;
+                match &tenant_info.state {
+                    TenantState::Active => Ok(Some(tenant_info)),
+                    TenantState::Broken { reason, .. } => Err(Error::ApiError(format!(
+                        "tenant {tenant_id} is broken: {reason}"
+                    ))),
+                    TenantState::Stopping { .. } => {
+                        Err(Error::ApiError(format!("tenant {tenant_id} is stopping")))
+                    }
+                    TenantState::Loading | TenantState::Attaching | TenantState::Activating(_) => {
+                        Ok(None)
+                    }
+                }
+            },
+            timeout,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use futures::StreamExt;
+    use pageserver_api::models::{TenantConfigRequest, TimelineStateFilter};
+    use pageserver_api::shard::TenantShardId;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use utils::id::{TenantId, TimelineId};
+
+    use super::client_metrics::ClientMetrics;
+    use super::{Client, Error, ForceAwaitLogicalSize, ListTimelinesQuery};
+
+    /// Accept a single HTTP request, answer it with `response` and return the request line
+    /// and the body of the request.
+    async fn mock_one_request(listener: TcpListener, response: String) -> (String, String) {
+        let (headers, body) = mock_one_request_with_headers(listener, response).await;
+        let request_line = headers.lines().next().unwrap().to_owned();
+        (request_line, body)
+    }
+
+    /// Like [`mock_one_request`], but returns the whole head of the request, headers included,
+    /// instead of just the request line.
+    async fn mock_one_request_with_headers(
+        listener: TcpListener,
+        response: String,
+    ) -> (String, String) {
+        let (socket, _) = listener.accept().await.unwrap();
+        serve_one_request(socket, response).await
+    }
+
+    /// Read a single HTTP request from `socket` and answer it with `response`, like
+    /// [`mock_one_request_with_headers`].
+    async fn serve_one_request<S>(mut socket: S, response: String) -> (String, String)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut buf = Vec::new();
+        let header_end = loop {
+            let mut chunk = [0u8; 1024];
+            let n = socket.read(&mut chunk).await.unwrap();
+            assert!(n > 0, "connection closed before the end of the headers");
+            buf.extend_from_slice(&chunk[..n]);
+            if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+        };
+        let headers = String::from_utf8(buf[..header_end].to_vec()).unwrap();
+        let content_length = headers
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+            .map(|(_, value)| value.trim().parse::<usize>().unwrap())
+            .unwrap_or(0);
+        while buf.len() < header_end + content_length {
+            let mut chunk = [0u8; 1024];
+            let n = socket.read(&mut chunk).await.unwrap();
+            assert!(n > 0, "connection closed before the end of the body");
+            buf.extend_from_slice(&chunk[..n]);
+        }
+
+        socket.write_all(response.as_bytes()).await.unwrap();
+        socket.shutdown().await.unwrap();
+
+        let body = String::from_utf8(buf[header_end..].to_vec()).unwrap();
+        (headers, body)
+    }
+
+    #[test]
+    fn url_percent_encoding() {
+        let client = Client::new("http://localhost:9898".to_owned(), None);
+        assert_eq!(
+            client.url(&["v1", "tenant", "1f359dd625e519a1a4e8d7509690f6fc-0002"]),
+            "http://localhost:9898/v1/tenant/1f359dd625e519a1a4e8d7509690f6fc-0002"
+        );
+        assert_eq!(
+            client.url(&["v1", "tenant", "a/b?c#d e%", "ü~"]),
+            "http://localhost:9898/v1/tenant/a%2Fb%3Fc%23d%20e%25/%C3%BC~"
+        );
+    }
+
+    #[tokio::test]
+    async fn tenant_time_travel_remote_storage() {
+        let tenant_shard_id =
+            TenantShardId::from_str("1f359dd625e519a1a4e8d7509690f6fc-0002").unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = Client::new(format!("http://{}", listener.local_addr().unwrap()), None);
+        let server = tokio::spawn(mock_one_request(
+            listener,
+            "HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n".to_owned(),
+        ));
+        client
+            .tenant_time_travel_remote_storage(
+                tenant_shard_id,
+                "2024-01-01T00:00:00Z",
+                "2024-02-01T00:00:00Z",
+            )
+            .await
+            .unwrap();
+        let (request_line, body) = server.await.unwrap();
+        assert_eq!(
+            request_line,
+            format!("PUT /v1/tenant/{tenant_shard_id}/time_travel_remote_storage HTTP/1.1")
+        );
+        assert_eq!(
+            body,
+            r#"{"travel_to":"2024-01-01T00:00:00Z","done_if_after":"2024-02-01T00:00:00Z"}"#
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = Client::new(format!("http://{}", listener.local_addr().unwrap()), None);
+        let error_body = r#"{"msg":"tenant is attached"}"#;
+        let response = format!(
+            "HTTP/1.1 409 Conflict\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{error_body}",
+            error_body.len()
+        );
+        let server = tokio::spawn(mock_one_request(listener, response));
+        let err = client
+            .tenant_time_travel_remote_storage(
+                tenant_shard_id,
+                "2024-01-01T00:00:00Z",
+                "2024-02-01T00:00:00Z",
+            )
+            .await
+            .unwrap_err();
+        server.await.unwrap();
+        assert!(
+            matches!(&err, Error::ApiError(msg) if msg == "tenant is attached"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn timeline_compact() {
+        let tenant_shard_id =
+            TenantShardId::from_str("1f359dd625e519a1a4e8d7509690f6fc-0002").unwrap();
+        let timeline_id = TimelineId::from_str("de200bd42b49cc1814412c7e592dd6e9").unwrap();
+
+        for (force, query) in [(false, ""), (true, "?force_repartition=true")] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let client = Client::new(format!("http://{}", listener.local_addr().unwrap()), None);
+            let server = tokio::spawn(mock_one_request(
+                listener,
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 4\r\n\r\nnull"
+                    .to_owned(),
+            ));
+            client
+                .timeline_compact(tenant_shard_id, timeline_id, force)
+                .await
+                .unwrap();
+            let (request_line, body) = server.await.unwrap();
+            assert_eq!(
+                request_line,
+                format!(
+                    "PUT /v1/tenant/{tenant_shard_id}/timeline/{timeline_id}/compact{query} HTTP/1.1"
+                )
+            );
+            assert_eq!(body, "null");
+        }
+    }
+
+    #[tokio::test]
+    async fn timeline_gc() {
+        let tenant_shard_id =
+            TenantShardId::from_str("1f359dd625e519a1a4e8d7509690f6fc-0002").unwrap();
+        let timeline_id = TimelineId::from_str("de200bd42b49cc1814412c7e592dd6e9").unwrap();
+
+        let gc_result = r#"{"layers_total":10,"layers_needed_by_cutoff":4,"layers_needed_by_pitr":3,"layers_needed_by_branches":1,"layers_not_updated":0,"layers_removed":2,"elapsed":1500}"#;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = Client::new(format!("http://{}", listener.local_addr().unwrap()), None);
+        let server = tokio::spawn(mock_one_request(
+            listener,
+            format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{gc_result}",
+                gc_result.len()
+            ),
+        ));
+        let result = client
+            .timeline_gc(tenant_shard_id, timeline_id, Some(0))
+            .await
+            .unwrap();
+        let (request_line, body) = server.await.unwrap();
+        assert_eq!(
+            request_line,
+            format!("PUT /v1/tenant/{tenant_shard_id}/timeline/{timeline_id}/do_gc HTTP/1.1")
+        );
+        assert_eq!(body, r#"{"gc_horizon":0}"#);
+        assert_eq!(result.layers_removed, 2);
+        assert_eq!(result.elapsed, std::time::Duration::from_millis(1500));
+    }
+
+    #[tokio::test]
+    async fn tenant_shard_split() {
+        let tenant_id = TenantId::from_str("1f359dd625e519a1a4e8d7509690f6fc").unwrap();
+
+        let split_response = r#"{"new_shards":["1f359dd625e519a1a4e8d7509690f6fc-0002","1f359dd625e519a1a4e8d7509690f6fc-0102"]}"#;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = Client::new(format!("http://{}", listener.local_addr().unwrap()), None);
+        let server = tokio::spawn(mock_one_request(
+            listener,
+            format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{split_response}",
+                split_response.len()
+            ),
+        ));
+        let response = client.tenant_shard_split(tenant_id, 2).await.unwrap();
+        let (request_line, body) = server.await.unwrap();
+        assert_eq!(
+            request_line,
+            format!("PUT /v1/tenant/{tenant_id}/shard_split HTTP/1.1")
+        );
+        assert_eq!(body, r#"{"new_shard_count":2}"#);
+        assert_eq!(
+            response.new_shards,
+            [
+                TenantShardId::from_str("1f359dd625e519a1a4e8d7509690f6fc-0002").unwrap(),
+                TenantShardId::from_str("1f359dd625e519a1a4e8d7509690f6fc-0102").unwrap(),
+            ]
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = Client::new(format!("http://{}", listener.local_addr().unwrap()), None);
+        let error_body = r#"{"msg":"tenant is already split"}"#;
+        let response = format!(
+            "HTTP/1.1 409 Conflict\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{error_body}",
+            error_body.len()
+        );
+        let server = tokio::spawn(mock_one_request(listener, response));
+        let err = client.tenant_shard_split(tenant_id, 2).await.unwrap_err();
+        server.await.unwrap();
+        assert!(
+            matches!(&err, Error::Conflict(msg) if msg == "tenant is already split"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn decode_error_includes_body() {
+        let tenant_id = TenantId::from_str("1f359dd625e519a1a4e8d7509690f6fc").unwrap();
+
+        let html = "<html><body>502 Bad Gateway</body></html>";
+        let long_html = format!("<html>{}</html>", "x".repeat(1000));
+        for (body, expected) in [
+            (html.to_owned(), html.to_owned()),
+            (long_html.clone(), format!("{}...", &long_html[..512])),
+        ] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let client = Client::new(format!("http://{}", listener.local_addr().unwrap()), None);
+            let server = tokio::spawn(mock_one_request(
+                listener,
+                format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\ncontent-length: {}\r\n\r\n{body}",
+                    body.len()
+                ),
+            ));
+            let err = client.tenant_details(tenant_id).await.unwrap_err();
+            server.await.unwrap();
+            assert!(
+                matches!(&err, Error::DecodeBody { body, .. } if *body == expected),
+                "{err}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn client_metrics() {
+        let registry = metrics::Registry::new();
+        let client_metrics = ClientMetrics::register(&registry).unwrap();
+        assert!(
+            ClientMetrics::register(&registry).is_err(),
+            "metrics can only be registered once"
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let client = Client::new(endpoint.clone(), None).with_metrics(client_metrics);
+        let server = tokio::spawn(mock_one_request(
+            listener,
+            "HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n".to_owned(),
+        ));
+        client.status().await.unwrap();
+        server.await.unwrap();
+
+        let families = registry.gather();
+        let family = |name: &str| {
+            families
+                .iter()
+                .find(|family| family.get_name() == name)
+                .unwrap_or_else(|| panic!("no metric {name}"))
+        };
+        let labels = |metric: &metrics::proto::Metric| {
+            metric
+                .get_label()
+                .iter()
+                .map(|label| (label.get_name().to_owned(), label.get_value().to_owned()))
+                .collect::<Vec<_>>()
+        };
+
+        let requests = family("pageserver_client_requests_total").get_metric();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            labels(&requests[0]),
+            [
+                ("endpoint".to_owned(), endpoint.clone()),
+                ("method".to_owned(), "GET".to_owned()),
+                ("status".to_owned(), "200".to_owned()),
+            ]
+        );
+        assert_eq!(requests[0].get_counter().get_value(), 1.0);
+
+        let durations = family("pageserver_client_request_seconds").get_metric();
+        assert_eq!(durations[0].get_histogram().get_sample_count(), 1);
+
+        let in_flight = family("pageserver_client_requests_in_flight").get_metric();
+        assert_eq!(in_flight[0].get_gauge().get_value(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn tenant_config_cas() {
+        let tenant_id = TenantId::from_str("1f359dd625e519a1a4e8d7509690f6fc").unwrap();
+        let req = TenantConfigRequest::new(tenant_id);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = Client::new(format!("http://{}", listener.local_addr().unwrap()), None);
+        let server = tokio::spawn(mock_one_request_with_headers(
+            listener,
+            "HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n".to_owned(),
+        ));
+        client.tenant_config_cas(&req, "\"v1\"").await.unwrap();
+        let (headers, _) = server.await.unwrap();
+        assert!(headers.starts_with("PUT /v1/tenant/config HTTP/1.1\r\n"));
+        assert!(
+            headers
+                .lines()
+                .any(|line| line.eq_ignore_ascii_case("if-match: \"v1\"")),
+            "{headers}"
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = Client::new(format!("http://{}", listener.local_addr().unwrap()), None);
+        let error_body = r#"{"msg":"tenant config has changed"}"#;
+        let response = format!(
+            "HTTP/1.1 412 Precondition Failed\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{error_body}",
+            error_body.len()
+        );
+        let server = tokio::spawn(mock_one_request(listener, response));
+        let err = client.tenant_config_cas(&req, "\"v1\"").await.unwrap_err();
+        server.await.unwrap();
+        assert!(
+            matches!(&err, Error::PreconditionFailed(msg) if msg == "tenant config has changed"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn list_timelines_force_await_logical_size() {
+        let tenant_id = TenantId::from_str("1f359dd625e519a1a4e8d7509690f6fc").unwrap();
+
+        for (force, query) in [
+            (ForceAwaitLogicalSize::No, ""),
+            (
+                ForceAwaitLogicalSize::Yes,
+                "?force-await-initial-logical-size=true",
+            ),
+        ] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let client = Client::new(format!("http://{}", listener.local_addr().unwrap()), None);
+            let server = tokio::spawn(mock_one_request(
+                listener,
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 2\r\n\r\n[]"
+                    .to_owned(),
+            ));
+            let timelines = client.list_timelines_with(tenant_id, force).await.unwrap();
+            assert!(timelines.is_empty());
+            let (request_line, _) = server.await.unwrap();
+            assert_eq!(
+                request_line,
+                format!("GET /v1/tenant/{tenant_id}/timeline{query} HTTP/1.1")
+            );
+        }
+    }
+
+    #[test]
+    fn list_timelines_query_string() {
+        assert_eq!(ListTimelinesQuery::default().to_query_string(), "");
+        let query = ListTimelinesQuery {
+            force_await_logical_size: ForceAwaitLogicalSize::Yes,
+            include_non_incremental_logical_size: true,
+            state: Some(TimelineStateFilter::Broken),
+        };
+        assert_eq!(
+            query.to_query_string(),
+            "?force-await-initial-logical-size=true&include-non-incremental-logical-size=true&state=broken"
+        );
+    }
+
+    #[tokio::test]
+    async fn timeline_layer_download() {
+        let tenant_shard_id =
+            TenantShardId::from_str("1f359dd625e519a1a4e8d7509690f6fc-0002").unwrap();
+        let timeline_id = TimelineId::from_str("de200bd42b49cc1814412c7e592dd6e9").unwrap();
+        let layer_file_name = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51";
+
+        for (response, downloaded) in [
+            ("HTTP/1.1 200 OK\r\ncontent-length: 4\r\n\r\nnull", true),
+            ("HTTP/1.1 304 Not Modified\r\n\r\n", false),
+        ] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let client = Client::new(format!("http://{}", listener.local_addr().unwrap()), None);
+            let server = tokio::spawn(mock_one_request(listener, response.to_owned()));
+            let result = client
+                .timeline_layer_download(tenant_shard_id, timeline_id, layer_file_name)
+                .await
+                .unwrap();
+            let (request_line, _) = server.await.unwrap();
+            assert_eq!(
+                request_line,
+                format!(
+                    "GET /v1/tenant/{tenant_shard_id}/timeline/{timeline_id}/layer/{layer_file_name} HTTP/1.1"
+                )
+            );
+            assert_eq!(result, downloaded);
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = Client::new(format!("http://{}", listener.local_addr().unwrap()), None);
+        let error_body = r#""Layer not found""#;
+        let response = format!(
+            "HTTP/1.1 400 Bad Request\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{error_body}",
+            error_body.len()
+        );
+        let server = tokio::spawn(mock_one_request(listener, response));
+        let err = client
+            .timeline_layer_download(tenant_shard_id, timeline_id, layer_file_name)
+            .await
+            .unwrap_err();
+        server.await.unwrap();
+        assert!(
+            matches!(&err, Error::LayerNotFound(msg) if msg == "Layer not found"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn timeline_layer_evict() {
+        let tenant_shard_id =
+            TenantShardId::from_str("1f359dd625e519a1a4e8d7509690f6fc-0002").unwrap();
+        let timeline_id = TimelineId::from_str("de200bd42b49cc1814412c7e592dd6e9").unwrap();
+        let layer_file_name = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51";
+
+        for response in [
+            "HTTP/1.1 200 OK\r\ncontent-length: 4\r\n\r\nnull",
+            "HTTP/1.1 304 Not Modified\r\n\r\n",
+        ] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let client = Client::new(format!("http://{}", listener.local_addr().unwrap()), None);
+            let server = tokio::spawn(mock_one_request(listener, response.to_owned()));
+            client
+                .timeline_layer_evict(tenant_shard_id, timeline_id, layer_file_name)
+                .await
+                .unwrap();
+            let (request_line, _) = server.await.unwrap();
+            assert_eq!(
+                request_line,
+                format!(
+                    "DELETE /v1/tenant/{tenant_shard_id}/timeline/{timeline_id}/layer/{layer_file_name} HTTP/1.1"
+                )
+            );
+        }
+    }
+
+    #[cfg(all(unix, feature = "unix-socket"))]
+    #[tokio::test]
+    async fn unix_socket() {
+        let socket_path =
+            std::env::temp_dir().join(format!("mgmt_api_test_{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = tokio::net::UnixListener::bind(&socket_path).unwrap();
+
+        let client = Client::new_unix(&socket_path, Some("token"));
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            serve_one_request(
+                socket,
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 2\r\n\r\n[]"
+                    .to_owned(),
+            )
+            .await
+        });
+        let tenants = client.list_tenants().await.unwrap();
+        assert!(tenants.is_empty());
+        let (headers, _) = server.await.unwrap();
+        std::fs::remove_file(&socket_path).unwrap();
+        assert!(
+            headers.starts_with("GET /v1/tenant HTTP/1.1\r\n"),
+            "{headers}"
+        );
+        assert!(
+            headers
+                .lines()
+                .any(|line| line.eq_ignore_ascii_case("authorization: Bearer token")),
+            "{headers}"
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_tenants() {
+        let tenant_shard_ids = [
+            "1f359dd625e519a1a4e8d7509690f6fc-0002",
+            "1f359dd625e519a1a4e8d7509690f6fc-0102",
+        ];
+        let body = format!(
+            "{{\"id\":\"{}\",\"state\":{{\"slug\":\"Active\"}},\"current_physical_size\":null,\"attachment_status\":{{\"slug\":\"attached\"}}}}\n\
+             {{\"id\":\n\
+             \n\
+             {{\"id\":\"{}\",\"state\":{{\"slug\":\"Active\"}},\"current_physical_size\":null,\"attachment_status\":{{\"slug\":\"attached\"}}}}",
+            tenant_shard_ids[0], tenant_shard_ids[1]
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = Client::new(format!("http://{}", listener.local_addr().unwrap()), None);
+        let server = tokio::spawn(mock_one_request_with_headers(
+            listener,
+            format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/x-ndjson\r\ncontent-length: {}\r\n\r\n{body}",
+                body.len()
+            ),
+        ));
+        let tenants = client.stream_tenants().collect::<Vec<_>>().await;
+        let (headers, _) = server.await.unwrap();
+        assert!(
+            headers.starts_with("GET /v1/tenant HTTP/1.1\r\n"),
+            "{headers}"
+        );
+        assert!(
+            headers
+                .lines()
+                .any(|line| line.eq_ignore_ascii_case("accept: application/x-ndjson")),
+            "{headers}"
+        );
+
+        assert_eq!(tenants.len(), 3);
+        assert_eq!(
+            tenants[0].as_ref().unwrap().id,
+            TenantShardId::from_str(tenant_shard_ids[0]).unwrap()
+        );
+        assert!(
+            matches!(&tenants[1], Err(Error::DecodeBody { body, .. }) if body == "{\"id\":\n"),
+            "{:?}",
+            tenants[1].as_ref().err()
+        );
+        assert_eq!(
+            tenants[2].as_ref().unwrap().id,
+            TenantShardId::from_str(tenant_shard_ids[1]).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn metrics_raw() {
+        let metrics = "# HELP pageserver_ready Whether the pageserver is ready\n\
+                       # TYPE pageserver_ready gauge\n\
+                       pageserver_ready 1\n";
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = Client::new(
+            format!("http://{}", listener.local_addr().unwrap()),
+            Some("token"),
+        );
+        let server = tokio::spawn(mock_one_request(
+            listener,
+            format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\ncontent-length: {}\r\n\r\n{metrics}",
+                metrics.len()
+            ),
+        ));
+        assert_eq!(client.metrics_raw().await.unwrap(), metrics);
+        let (request_line, _) = server.await.unwrap();
+        assert_eq!(request_line, "GET /metrics HTTP/1.1");
+    }
+
+    #[tokio::test]
+    async fn tenant_await_active() {
+        let tenant_id = TenantId::from_str("1f359dd625e519a1a4e8d7509690f6fc").unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = Client::new(format!("http://{}", listener.local_addr().unwrap()), None);
+        let server = tokio::spawn(async move {
+            let mut request_lines = Vec::new();
+            for state in ["Attaching", "Loading", "Active"] {
+                let body = format!(
+                    "{{\"id\":\"{tenant_id}\",\"state\":{{\"slug\":\"{state}\"}},\"current_physical_size\":null,\"attachment_status\":{{\"slug\":\"attached\"}},\"timelines\":[]}}"
+                );
+                // Close the connection after each response, so that every poll is a new one
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{body}",
+                    body.len()
+                );
+                let (socket, _) = listener.accept().await.unwrap();
+                let (headers, _) = serve_one_request(socket, response).await;
+                request_lines.push(headers.lines().next().unwrap().to_owned());
+            }
+            request_lines
+        });
+
+        let tenant_info = client
+            .tenant_await_active(tenant_id, std::time::Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(tenant_info.id.tenant_id, tenant_id);
+        let request_lines = server.await.unwrap();
+        assert_eq!(
+            request_lines,
+            vec![format!("GET /v1/tenant/{tenant_id} HTTP/1.1"); 3]
+        );
+
+        // An operation that never completes times out
+        let polls = std::sync::atomic::AtomicUsize::new(0);
+        let result = client
+            .await_operation(
+                || {
+                    polls.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    async { Ok(None::<()>) }
+                },
+                std::time::Duration::from_millis(250),
+            )
+            .await;
+        assert!(matches!(result, Err(Error::Timeout(_))), "{result:?}");
+        // Polled right away, after 100ms and at the deadline
+        assert_eq!(polls.load(std::sync::atomic::Ordering::Relaxed), 3);
     }
 }
@@ -0,0 +1,109 @@
+//! Metrics of the requests that a [`Client`](super::Client) makes, for callers that talk to many
+//! pageservers and want to see how the requests to each of them perform. Clients don't record
+//! any metrics unless they are given a [`ClientMetrics`] with
+//! [`Client::with_metrics`](super::Client::with_metrics).
+
+use std::time::Instant;
+
+use metrics::{opts, HistogramVec, IntCounterVec, IntGaugeVec, Registry};
+use reqwest::{Method, StatusCode};
+
+/// The metrics of the requests of any number of clients, labelled by the base URL of the
+/// pageserver's management API that each client was created with.
+#[derive(Clone)]
+pub struct ClientMetrics {
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    requests_in_flight: IntGaugeVec,
+}
+
+impl std::fmt::Debug for ClientMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientMetrics").finish_non_exhaustive()
+    }
+}
+
+impl ClientMetrics {
+    /// Create the metrics and register them in `registry`. This fails if they are already
+    /// registered there: clients that share a registry must share the [`ClientMetrics`] too.
+    pub fn register(registry: &Registry) -> Result<Self, metrics::Error> {
+        let requests = IntCounterVec::new(
+            opts!(
+                "pageserver_client_requests_total",
+                "Number of finished requests to the pageserver management API, by response status, or 'error' if there was no response",
+            ),
+            &["endpoint", "method", "status"],
+        )?;
+        let request_duration = HistogramVec::new(
+            opts!(
+                "pageserver_client_request_seconds",
+                "Time until the response headers of a request to the pageserver management API were received",
+            )
+            .into(),
+            &["endpoint", "method"],
+        )?;
+        let requests_in_flight = IntGaugeVec::new(
+            opts!(
+                "pageserver_client_requests_in_flight",
+                "Number of requests to the pageserver management API waiting for a response",
+            ),
+            &["endpoint"],
+        )?;
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(request_duration.clone()))?;
+        registry.register(Box::new(requests_in_flight.clone()))?;
+        Ok(ClientMetrics {
+            requests,
+            request_duration,
+            requests_in_flight,
+        })
+    }
+
+    pub(super) fn start_request<'a>(
+        &'a self,
+        endpoint: &'a str,
+        method: &Method,
+    ) -> InFlightRequest<'a> {
+        self.requests_in_flight.with_label_values(&[endpoint]).inc();
+        InFlightRequest {
+            metrics: self,
+            endpoint,
+            method: method.clone(),
+            start: Instant::now(),
+        }
+    }
+}
+
+/// A request that is counted in the in-flight gauge until this is dropped.
+pub(super) struct InFlightRequest<'a> {
+    metrics: &'a ClientMetrics,
+    endpoint: &'a str,
+    method: Method,
+    start: Instant,
+}
+
+impl InFlightRequest<'_> {
+    /// Record the outcome of the request: the status of the response, or `None` if there was no
+    /// response.
+    pub(super) fn finish(self, status: Option<StatusCode>) {
+        let method = self.method.as_str();
+        self.metrics
+            .request_duration
+            .with_label_values(&[self.endpoint, method])
+            .observe(self.start.elapsed().as_secs_f64());
+        let status = status.map(|status| status.as_u16().to_string());
+        self.metrics
+            .requests
+            .with_label_values(&[self.endpoint, method, status.as_deref().unwrap_or("error")])
+            .inc();
+    }
+}
+
+impl Drop for InFlightRequest<'_> {
+    fn drop(&mut self) {
+        self.metrics
+            .requests_in_flight
+            .with_label_values(&[self.endpoint])
+            .dec();
+    }
+}
@@ -0,0 +1,71 @@
+//! Parsing of the pageserver's `/metrics` output, see [`Client::metrics_parsed`].
+//!
+//! [`Client::metrics_parsed`]: super::Client::metrics_parsed
+
+use std::collections::HashMap;
+
+pub use prometheus_parse::{Sample, Value};
+
+/// All samples of one metric, with its help text.
+#[derive(Debug)]
+pub struct MetricFamily {
+    pub name: String,
+    pub help: Option<String>,
+    pub samples: Vec<Sample>,
+}
+
+/// Parse metrics in the Prometheus text exposition format into families, in the order in
+/// which they first appear.
+///
+/// Histograms and summaries are collected into a single sample per label set, named after the
+/// family rather than its `_bucket`/`_sum`/`_count` series.
+pub fn parse(text: &str) -> std::io::Result<Vec<MetricFamily>> {
+    let lines = text.lines().map(|line| Ok(line.to_owned()));
+    let mut scrape = prometheus_parse::Scrape::parse(lines)?;
+
+    let mut families: Vec<MetricFamily> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for sample in scrape.samples {
+        let i = *index.entry(sample.metric.clone()).or_insert_with(|| {
+            families.push(MetricFamily {
+                help: scrape.docs.remove(&sample.metric),
+                name: sample.metric.clone(),
+                samples: Vec::new(),
+            });
+            families.len() - 1
+        });
+        families[i].samples.push(sample);
+    }
+    Ok(families)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Value};
+
+    #[test]
+    fn parse_families() {
+        let text = "# HELP pageserver_ready Whether the pageserver is ready\n\
+                    # TYPE pageserver_ready gauge\n\
+                    pageserver_ready 1\n\
+                    # HELP pageserver_requests_total Requests\n\
+                    # TYPE pageserver_requests_total counter\n\
+                    pageserver_requests_total{kind=\"get\"} 3\n\
+                    pageserver_requests_total{kind=\"put\"} 4\n";
+
+        let families = parse(text).unwrap();
+        assert_eq!(families.len(), 2);
+
+        assert_eq!(families[0].name, "pageserver_ready");
+        assert_eq!(
+            families[0].help.as_deref(),
+            Some("Whether the pageserver is ready")
+        );
+        assert!(matches!(families[0].samples[0].value, Value::Gauge(v) if v == 1.0));
+
+        assert_eq!(families[1].name, "pageserver_requests_total");
+        assert_eq!(families[1].samples.len(), 2);
+        assert_eq!(families[1].samples[1].labels.get("kind"), Some("put"));
+        assert!(matches!(families[1].samples[1].value, Value::Counter(v) if v == 4.0));
+    }
+}
@@ -0,0 +1,60 @@
+//! Sending the requests of a [`Client`](super::Client) over a Unix domain socket, for clients
+//! created with [`Client::new_unix`](super::Client::new_unix).
+//!
+//! reqwest can only connect over TCP, so the request is built with it as usual, sent with hyper
+//! on a new connection to the socket, and the response is converted back into a
+//! [`reqwest::Response`] so that the methods of the client handle it like any other.
+
+use std::path::Path;
+
+use anyhow::Context;
+use tokio::net::UnixStream;
+
+use super::{Error, Result};
+
+pub(super) async fn send(
+    socket_path: &Path,
+    req: reqwest::RequestBuilder,
+) -> Result<reqwest::Response> {
+    let req = req.build().map_err(Error::ReceiveBody)?;
+    send_request(socket_path, req)
+        .await
+        .map_err(Error::UnixSocket)
+}
+
+async fn send_request(
+    socket_path: &Path,
+    req: reqwest::Request,
+) -> anyhow::Result<reqwest::Response> {
+    let url = req.url();
+    let path_and_query = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_owned(),
+    };
+    let mut builder = hyper::Request::builder()
+        .method(req.method().clone())
+        .uri(path_and_query)
+        .header(hyper::header::HOST, "localhost");
+    for (name, value) in req.headers() {
+        builder = builder.header(name, value);
+    }
+    // The bodies of the management API requests are always JSON, never streams.
+    let body = match req.body().and_then(|body| body.as_bytes()) {
+        Some(bytes) => hyper::Body::from(bytes.to_vec()),
+        None => hyper::Body::empty(),
+    };
+    let request = builder.body(body).context("build request")?;
+
+    let stream = UnixStream::connect(socket_path)
+        .await
+        .with_context(|| format!("connect to {}", socket_path.display()))?;
+    let (mut sender, connection) = hyper::client::conn::handshake(stream)
+        .await
+        .context("handshake")?;
+    // Errors of the connection surface in the response, or when reading its body.
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+    let response = sender.send_request(request).await.context("send request")?;
+    Ok(reqwest::Response::from(response))
+}
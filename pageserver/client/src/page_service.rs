@@ -4,7 +4,7 @@ use futures::SinkExt;
 use pageserver_api::{
     models::{
         PagestreamBeMessage, PagestreamFeMessage, PagestreamGetPageRequest,
-        PagestreamGetPageResponse,
+        PagestreamGetPageResponse, PagestreamHandshakeRequest, PagestreamProtocolVersion,
     },
     reltag::RelTag,
 };
@@ -113,6 +113,34 @@ impl PagestreamClient {
         self.conn_task.await.unwrap();
     }
 
+    /// Negotiate the protocol version. Must be the first request on the connection, if any.
+    pub async fn handshake(
+        &mut self,
+        supported_versions: &[PagestreamProtocolVersion],
+    ) -> anyhow::Result<PagestreamProtocolVersion> {
+        let req = PagestreamHandshakeRequest {
+            supported_versions: supported_versions
+                .iter()
+                .fold(0, |bitmap, version| bitmap | version.bit()),
+        };
+        let mut req = tokio_stream::once(Ok(req.serialize()));
+
+        self.copy_both.send_all(&mut req).await?;
+
+        let next: Option<Result<bytes::Bytes, _>> = self.copy_both.next().await;
+        let next: bytes::Bytes = next.unwrap()?;
+
+        let msg = PagestreamBeMessage::deserialize(next)?;
+        match msg {
+            PagestreamBeMessage::Handshake(h) => Ok(h.version),
+            PagestreamBeMessage::Error(e) => anyhow::bail!("Error: {:?}", e),
+            _ => anyhow::bail!(
+                "unexpected be message kind in response to handshake: {}",
+                msg.kind()
+            ),
+        }
+    }
+
     pub async fn getpage(
         &mut self,
         req: PagestreamGetPageRequest,
@@ -131,9 +159,14 @@ impl PagestreamClient {
         match msg {
             PagestreamBeMessage::GetPage(p) => Ok(p),
             PagestreamBeMessage::Error(e) => anyhow::bail!("Error: {:?}", e),
+            PagestreamBeMessage::GoAway(g) => {
+                anyhow::bail!("server asked to reconnect: {}", g.reason)
+            }
             PagestreamBeMessage::Exists(_)
             | PagestreamBeMessage::Nblocks(_)
-            | PagestreamBeMessage::DbSize(_) => {
+            | PagestreamBeMessage::DbSize(_)
+            | PagestreamBeMessage::Handshake(_)
+            | PagestreamBeMessage::GetPageLastModified(_) => {
                 anyhow::bail!(
                     "unexpected be message kind in response to getpage request: {}",
                     msg.kind()
@@ -277,6 +277,7 @@ async fn main_impl(
                                 lsn: r.timeline_lsn,
                                 rel: rel_tag,
                                 blkno: block_no,
+                                deadline: None,
                             },
                         )
                     };
@@ -326,6 +327,7 @@ async fn main_impl(
                                     lsn: r.timeline_lsn,
                                     rel: rel_tag,
                                     blkno: block_no,
+                                    deadline: None,
                                 }
                             };
                             if sender.send(req).await.is_err() {
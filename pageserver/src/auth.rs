@@ -1,5 +1,5 @@
 use utils::auth::{AuthError, Claims, Scope};
-use utils::id::TenantId;
+use utils::id::{TenantId, TimelineId};
 
 pub fn check_permission(claims: &Claims, tenant_id: Option<TenantId>) -> Result<(), AuthError> {
     match (&claims.scope, tenant_id) {
@@ -12,6 +12,10 @@ pub fn check_permission(claims: &Claims, tenant_id: Option<TenantId>) -> Result<
             }
             Ok(())
         }
+        (Scope::Timeline, _) => Err(AuthError(
+            "Attempt to access tenant or management api with timeline scope. Permission denied"
+                .into(),
+        )),
         (Scope::PageServerApi, None) => Ok(()), // access to management api for PageServerApi scope
         (Scope::PageServerApi, Some(_)) => Ok(()), // access to tenant api using PageServerApi scope
         (Scope::SafekeeperData, _) => Err(AuthError(
@@ -19,3 +23,25 @@ pub fn check_permission(claims: &Claims, tenant_id: Option<TenantId>) -> Result<
         )),
     }
 }
+
+/// Like [`check_permission`], for requests that only access a single timeline of a tenant.
+/// Besides the scopes that give access to the whole tenant, these also accept the Timeline
+/// scope for that very timeline.
+pub fn check_timeline_permission(
+    claims: &Claims,
+    tenant_id: TenantId,
+    timeline_id: TimelineId,
+) -> Result<(), AuthError> {
+    match claims.scope {
+        Scope::Timeline => {
+            if claims.tenant_id != Some(tenant_id) {
+                return Err(AuthError("Tenant id mismatch. Permission denied".into()));
+            }
+            if claims.timeline_id != Some(timeline_id) {
+                return Err(AuthError("Timeline id mismatch. Permission denied".into()));
+            }
+            Ok(())
+        }
+        _ => check_permission(claims, Some(tenant_id)),
+    }
+}
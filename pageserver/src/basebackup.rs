@@ -12,12 +12,19 @@
 //!
 use anyhow::{anyhow, bail, ensure, Context};
 use bytes::{BufMut, BytesMut};
+use camino::Utf8Path;
 use fail::fail_point;
 use postgres_ffi::pg_constants;
+use serde::{Deserialize, Serialize};
 use std::fmt::Write as FmtWrite;
-use std::time::SystemTime;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{ready, Context as TaskContext, Poll};
+use std::time::{Duration, SystemTime};
 use tokio::io;
-use tokio::io::AsyncWrite;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
 use tracing::*;
 
 use tokio_tar::{Builder, EntryType, Header};
@@ -25,6 +32,7 @@ use tokio_tar::{Builder, EntryType, Header};
 use crate::context::RequestContext;
 use crate::pgdatadir_mapping::Version;
 use crate::tenant::Timeline;
+use crate::virtual_file::VirtualFile;
 use pageserver_api::reltag::{RelTag, SlruKind};
 
 use postgres_ffi::dispatch_pgversion;
@@ -37,20 +45,48 @@ use postgres_ffi::PG_TLI;
 use postgres_ffi::{BLCKSZ, RELSEG_SIZE, WAL_SEGMENT_SIZE};
 use utils::lsn::Lsn;
 
+/// Size of the buffer between the tarball generation and the file, in
+/// [`write_basebackup_tarball_to_file`].
+const BUFFER_SIZE: usize = 64 * 1024;
+
 /// Create basebackup with non-rel data in it.
 /// Only include relational data if 'full_backup' is true.
 ///
+/// Replication slot files (`pg_replslot/*`), and the `restart.lsn` file derived from them,
+/// are only included if 'include_slots' is true. This is independent of the LSN and the
+/// prev_lsn that the backup is taken at: slot state is read at the backup LSN either way.
+///
+/// If 'manifest' is true, a [`MANIFEST_FILE_NAME`] file is added at the end of the tarball,
+/// listing the other files in it with their sizes and checksums, see [`BasebackupManifest`].
+///
+/// If 'rel_filter' is set, only the databases with these OIDs are included, with the contents
+/// of all their relations as in a full backup, along with the shared catalogs of the global
+/// tablespace and the non-relational files. The other databases are left out altogether. An
+/// OID that isn't a database at the backup LSN is an error. Restoring such a backup gives a
+/// partial cluster for extracting data from, not a runnable one: the catalogs still list the
+/// databases that were left out.
+///
+/// If 'reproducible' is true, and both 'req_lsn' and 'prev_lsn' are given, taking the same
+/// backup again gives the same bytes, so that an interrupted transfer can be resumed with
+/// [`SkipPrefix`]. The files in such a backup have a modification time of zero rather than
+/// the current time.
+///
 /// Currently we use empty 'req_lsn' in two cases:
 ///  * During the basebackup right after timeline creation
 ///  * When working without safekeepers. In this situation it is important to match the lsn
 ///    we are taking basebackup on with the lsn that is used in pageserver's walreceiver
 ///    to start the replication.
+#[allow(clippy::too_many_arguments)]
 pub async fn send_basebackup_tarball<'a, W>(
     write: &'a mut W,
     timeline: &'a Timeline,
     req_lsn: Option<Lsn>,
     prev_lsn: Option<Lsn>,
     full_backup: bool,
+    include_slots: bool,
+    manifest: bool,
+    rel_filter: Option<&'a [u32]>,
+    reproducible: bool,
     ctx: &'a RequestContext,
 ) -> anyhow::Result<()>
 where
@@ -89,6 +125,19 @@ where
         (end_of_timeline.prev, end_of_timeline.last)
     };
 
+    // A reproducible backup at an explicitly requested lsn and prev_lsn comes out
+    // byte-for-byte the same every time it's taken, which is what resuming an interrupted
+    // transfer relies on, see [`SkipPrefix`]. That requires a fixed modification time for the
+    // files in it.
+    let mtime = if reproducible && req_lsn.is_some() && prev_lsn.is_some() {
+        0
+    } else {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    };
+
     // Consolidate the derived and the provided prev_lsn values
     let prev_lsn = if let Some(provided_prev_lsn) = prev_lsn {
         if backup_prev != Lsn(0) {
@@ -99,9 +148,15 @@ where
         backup_prev
     };
 
+    if let Some(dbnodes) = rel_filter {
+        // Check before anything is sent, so that the caller gets a clear error instead of a
+        // truncated tarball.
+        check_rel_filter(timeline, backup_lsn, dbnodes, ctx).await?;
+    }
+
     info!(
-        "taking basebackup lsn={}, prev_lsn={} (full_backup={})",
-        backup_lsn, prev_lsn, full_backup
+        "taking basebackup lsn={}, prev_lsn={} (full_backup={}, rel_filter={:?})",
+        backup_lsn, prev_lsn, full_backup, rel_filter
     );
 
     let basebackup = Basebackup {
@@ -110,6 +165,10 @@ where
         lsn: backup_lsn,
         prev_record_lsn: prev_lsn,
         full_backup,
+        include_slots,
+        manifest: manifest.then(Vec::new),
+        rel_filter,
+        mtime,
         ctx,
     };
     basebackup
@@ -118,6 +177,412 @@ where
         .await
 }
 
+/// Check that all the OIDs of a `rel_filter` for [`send_basebackup_tarball`] are databases at
+/// `lsn`.
+pub async fn check_rel_filter(
+    timeline: &Timeline,
+    lsn: Lsn,
+    dbnodes: &[u32],
+    ctx: &RequestContext,
+) -> anyhow::Result<()> {
+    let dbdirs = timeline.list_dbdirs(lsn, ctx).await?;
+    for &dbnode in dbnodes {
+        ensure!(
+            dbnode != 0 && dbdirs.keys().any(|&(_, db)| db == dbnode),
+            "database with OID {dbnode} does not exist at {lsn}"
+        );
+    }
+    Ok(())
+}
+
+/// Like [`send_basebackup_tarball`], but write the tarball into a new file at `path` on the
+/// pageserver's local disk. Returns the number of bytes written.
+///
+/// The file must not exist yet. If taking the backup fails, or the returned future is dropped
+/// before it completes, the partially written file is removed.
+pub async fn write_basebackup_tarball_to_file(
+    path: &Utf8Path,
+    timeline: &Timeline,
+    req_lsn: Option<Lsn>,
+    prev_lsn: Option<Lsn>,
+    full_backup: bool,
+    include_slots: bool,
+    ctx: &RequestContext,
+) -> anyhow::Result<u64> {
+    let mut file = VirtualFile::open_with_options(
+        path,
+        std::fs::OpenOptions::new().write(true).create_new(true),
+    )
+    .await
+    .with_context(|| format!("create basebackup file {path}"))?;
+    // Errors remove the file below. If the future is dropped instead, there is nothing left to
+    // await on, so hand the removal to the blocking pool rather than block the executor.
+    let remove_dropped_file = scopeguard::guard(path.to_owned(), |path| {
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = std::fs::remove_file(&path) {
+                warn!("failed to remove partial basebackup file {path}: {e}");
+            }
+        });
+    });
+
+    // VirtualFile is not an AsyncWrite, so pipe the tarball through a buffer.
+    let (mut writer, mut reader) = io::duplex(BUFFER_SIZE);
+    let produce = async move {
+        send_basebackup_tarball(
+            &mut writer,
+            timeline,
+            req_lsn,
+            prev_lsn,
+            full_backup,
+            include_slots,
+            false,
+            None,
+            false,
+            ctx,
+        )
+        .await?;
+        writer.shutdown().await?;
+        anyhow::Ok(())
+    };
+    let consume = async {
+        let mut buf = vec![0u8; BUFFER_SIZE];
+        let mut written = 0u64;
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            file.write_all(&buf[..n])
+                .await
+                .with_context(|| format!("write basebackup file {path}"))?;
+            written += n as u64;
+        }
+        file.sync_all()
+            .await
+            .with_context(|| format!("fsync basebackup file {path}"))?;
+        anyhow::Ok(written)
+    };
+
+    let res = tokio::try_join!(produce, consume);
+    scopeguard::ScopeGuard::into_inner(remove_dropped_file);
+    match res {
+        Ok(((), written)) => Ok(written),
+        Err(e) => {
+            if let Err(e) = tokio::fs::remove_file(path).await {
+                warn!("failed to remove partial basebackup file {path}: {e}");
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Estimate the size of the tarball that [`send_basebackup_tarball`] would send for a backup
+/// at `lsn`, without generating it.
+///
+/// This is the size of the uncompressed tarball. It is computed from relation and SLRU sizes
+/// and the sizes of the other files, without reconstructing any pages, so it's cheap compared
+/// to taking the backup. It's an estimate because paths that need a GNU long name entry in
+/// the tarball are not accounted for.
+pub async fn estimate_basebackup_tarball_size(
+    timeline: &Timeline,
+    lsn: Lsn,
+    full_backup: bool,
+    include_slots: bool,
+    ctx: &RequestContext,
+) -> anyhow::Result<u64> {
+    let mut size = 0;
+
+    // pgdata subdirs and config files
+    size += PGDATA_SUBDIRS.len() as u64 * tar_entry_size(0);
+    for filepath in PGDATA_SPECIAL_FILES.iter() {
+        if *filepath == "pg_hba.conf" {
+            size += tar_entry_size(PG_HBA.len() as u64);
+        } else {
+            size += tar_entry_size(0);
+        }
+    }
+
+    // SLRU segments
+    for kind in [
+        SlruKind::Clog,
+        SlruKind::MultiXactOffsets,
+        SlruKind::MultiXactMembers,
+    ] {
+        for segno in timeline
+            .list_slru_segments(kind, Version::Lsn(lsn), ctx)
+            .await?
+        {
+            let nblocks = timeline
+                .get_slru_segment_size(kind, segno, Version::Lsn(lsn), ctx)
+                .await?;
+            size += tar_entry_size(nblocks as u64 * BLCKSZ as u64);
+        }
+    }
+
+    let relmap_file_size =
+        dispatch_pgversion!(timeline.pg_version, pgv::bindings::SIZEOF_RELMAPFILE as u64);
+    let pg_version_file_size = match timeline.pg_version {
+        14 | 15 => timeline.pg_version.to_string().len() as u64,
+        ver => format!("{ver}\x0A").len() as u64,
+    };
+    let mut has_slots = false;
+    for ((spcnode, dbnode), has_relmap_file) in timeline.list_dbdirs(lsn, ctx).await? {
+        let rels = timeline
+            .list_rels(spcnode, dbnode, Version::Lsn(lsn), ctx)
+            .await?;
+
+        // Database directory, as in Basebackup::add_dbdir
+        if spcnode == GLOBALTABLESPACE_OID {
+            size += tar_entry_size(pg_version_file_size);
+            if has_relmap_file {
+                size += tar_entry_size(relmap_file_size);
+            }
+        } else if has_relmap_file || !rels.is_empty() {
+            size += tar_entry_size(0);
+            if has_relmap_file {
+                size += tar_entry_size(pg_version_file_size) + tar_entry_size(relmap_file_size);
+            }
+        }
+
+        // Relation files, as in Basebackup::send_tarball
+        for &rel in rels.iter() {
+            if rel.forknum == INIT_FORKNUM {
+                // The init fork is sent twice, as itself and as the main fork
+                size += 2 * rel_files_size(timeline, rel, lsn, ctx).await?;
+                continue;
+            }
+            if full_backup {
+                if rel.forknum == MAIN_FORKNUM && rels.contains(&rel.with_forknum(INIT_FORKNUM)) {
+                    continue;
+                }
+                size += rel_files_size(timeline, rel, lsn, ctx).await?;
+            }
+        }
+
+        for (path, content) in timeline.list_aux_files(lsn, ctx).await? {
+            if path.starts_with("pg_replslot") {
+                if !include_slots {
+                    continue;
+                }
+                has_slots = true;
+            }
+            size += tar_entry_size(content.len() as u64);
+        }
+    }
+    if has_slots {
+        // restart.lsn
+        size += tar_entry_size(std::mem::size_of::<u64>() as u64);
+    }
+
+    for xid in timeline.list_twophase_files(lsn, ctx).await? {
+        let img = timeline.get_twophase_file(xid, lsn, ctx).await?;
+        // The file is followed by its CRC
+        size += tar_entry_size(img.len() as u64 + 4);
+    }
+
+    // zenith.signal, which is shorter than a tar block, pg_control and the WAL segment
+    size += tar_entry_size(1);
+    size += tar_entry_size(dispatch_pgversion!(
+        timeline.pg_version,
+        pgv::bindings::PG_CONTROL_FILE_SIZE as u64
+    ));
+    size += tar_entry_size(WAL_SEGMENT_SIZE as u64);
+
+    // The end of the archive: two zero blocks
+    size += 1024;
+
+    Ok(size)
+}
+
+/// Size of the files that [`Basebackup::add_rel`] sends for a relation, in the tarball.
+async fn rel_files_size(
+    timeline: &Timeline,
+    rel: RelTag,
+    lsn: Lsn,
+    ctx: &RequestContext,
+) -> anyhow::Result<u64> {
+    let nblocks = timeline
+        .get_rel_size(rel, Version::Lsn(lsn), false, ctx)
+        .await?;
+    if nblocks == 0 {
+        return Ok(tar_entry_size(0));
+    }
+    // One file per segment of RELSEG_SIZE blocks
+    let full_segments = (nblocks / RELSEG_SIZE) as u64;
+    let mut size = full_segments * tar_entry_size(RELSEG_SIZE as u64 * BLCKSZ as u64);
+    let rest = nblocks % RELSEG_SIZE;
+    if rest > 0 {
+        size += tar_entry_size(rest as u64 * BLCKSZ as u64);
+    }
+    Ok(size)
+}
+
+/// Writer that drops the first `skip` bytes written to it and passes the rest through to `inner`.
+///
+/// Used to send a byte range of a basebackup, so that a client can resume an interrupted
+/// transfer. That only works if the output is byte-for-byte the same as in the interrupted
+/// transfer, i.e. the backup is taken at the same, explicitly requested `lsn` and `prev_lsn`,
+/// see [`send_basebackup_tarball`].
+pub struct SkipPrefix<W> {
+    inner: W,
+    skip: u64,
+}
+
+impl<W> SkipPrefix<W> {
+    pub fn new(inner: W, skip: u64) -> Self {
+        SkipPrefix { inner, skip }
+    }
+
+    /// Number of bytes that are still to be skipped. Non-zero after the whole output has
+    /// been written means that the output was shorter than the requested prefix.
+    pub fn remaining_skip(&self) -> u64 {
+        self.skip
+    }
+}
+
+impl<W> AsyncWrite for SkipPrefix<W>
+where
+    W: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.skip > 0 && !buf.is_empty() {
+            let skipped = std::cmp::min(self.skip, buf.len() as u64);
+            self.skip -= skipped;
+            return Poll::Ready(Ok(skipped as usize));
+        }
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Writer that passes everything through to `inner`, but no faster than `bytes_per_sec` on
+/// average, 0 meaning unlimited. Writes are paused as needed. Writing fails once `cancel`
+/// has fired, which is noticed at the end of a pause at the latest. Each write is at most one
+/// second worth of bytes, so pauses are short.
+///
+/// Time in which nothing was written, e.g. because generating the output was slow, is made up
+/// for by writing faster afterwards, but at most one second worth of it.
+pub struct RateLimited<W> {
+    inner: W,
+    bytes_per_sec: u64,
+    cancel: CancellationToken,
+    /// Bytes written since `since`. Writing them took at least `written / bytes_per_sec`.
+    since: Instant,
+    written: u64,
+    /// The pause that the next write has to wait for, and when it started. A plain `Sleep`
+    /// rather than a boxed future that also waits for `cancel`, to keep the writer `Sync`, as
+    /// [`send_basebackup_tarball`] requires.
+    pause: Option<(Pin<Box<tokio::time::Sleep>>, Instant)>,
+}
+
+impl<W> RateLimited<W> {
+    pub fn new(inner: W, bytes_per_sec: u64, cancel: CancellationToken) -> Self {
+        RateLimited {
+            inner,
+            bytes_per_sec,
+            cancel,
+            since: Instant::now(),
+            written: 0,
+            pause: None,
+        }
+    }
+
+    /// Start a pause if the bytes written so far are ahead of the rate.
+    fn maybe_pause(&mut self) {
+        let now = Instant::now();
+        let due =
+            self.since + Duration::from_secs_f64(self.written as f64 / self.bytes_per_sec as f64);
+        if due > now {
+            self.pause = Some((Box::pin(tokio::time::sleep_until(due)), now));
+        } else if now - due > Duration::from_secs(1) {
+            self.since = now - Duration::from_secs(1);
+            self.written = 0;
+        }
+    }
+}
+
+impl<W> AsyncWrite for RateLimited<W>
+where
+    W: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        if this.bytes_per_sec == 0 {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+
+        let cancelled = || {
+            Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::Other,
+                "cancelled while throttled",
+            )))
+        };
+        if this.cancel.is_cancelled() {
+            return cancelled();
+        }
+        if this.pause.is_none() {
+            this.maybe_pause();
+        }
+        if let Some((pause, started)) = &mut this.pause {
+            ready!(pause.as_mut().poll(cx));
+            crate::metrics::BASEBACKUP_THROTTLED_SECONDS.inc_by(started.elapsed().as_secs_f64());
+            this.pause = None;
+            if this.cancel.is_cancelled() {
+                return cancelled();
+            }
+        }
+
+        // Write at most one second worth at a time, so that the pauses stay short.
+        let max_len = usize::try_from(this.bytes_per_sec).unwrap_or(usize::MAX);
+        let buf = &buf[..std::cmp::min(buf.len(), max_len)];
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        this.written += n as u64;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Name of the manifest file that [`send_basebackup_tarball`] adds on request.
+pub const MANIFEST_FILE_NAME: &str = "neon_manifest.json";
+
+/// Contents of [`MANIFEST_FILE_NAME`]: the regular files in the tarball, in the order in which
+/// they were added. Directories and the manifest itself are not listed.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BasebackupManifest {
+    pub lsn: Lsn,
+    pub files: Vec<BasebackupManifestFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BasebackupManifestFile {
+    pub path: String,
+    pub size: u64,
+    /// CRC-32C of the file contents, like the default checksum of Postgres' backup_manifest.
+    pub crc32c: u32,
+}
+
 /// This is short-living object only for the time of tarball creation,
 /// created mostly to avoid passing a lot of parameters between various functions
 /// used for constructing tarball.
@@ -130,6 +595,13 @@ where
     lsn: Lsn,
     prev_record_lsn: Lsn,
     full_backup: bool,
+    include_slots: bool,
+    /// The files added so far, if a manifest was requested.
+    manifest: Option<Vec<BasebackupManifestFile>>,
+    /// OIDs of the databases to include, see [`send_basebackup_tarball`].
+    rel_filter: Option<&'a [u32]>,
+    /// Modification time of the files, in seconds since the epoch.
+    mtime: u64,
     ctx: &'a RequestContext,
 }
 
@@ -142,7 +614,7 @@ where
 
         // Create pgdata subdirs structure
         for dir in PGDATA_SUBDIRS.iter() {
-            let header = new_tar_header_dir(dir)?;
+            let header = new_tar_header_dir(dir, self.mtime)?;
             self.ar
                 .append(&header, &mut io::empty())
                 .await
@@ -151,74 +623,93 @@ where
 
         // Send config files.
         for filepath in PGDATA_SPECIAL_FILES.iter() {
-            if *filepath == "pg_hba.conf" {
-                let data = PG_HBA.as_bytes();
-                let header = new_tar_header(filepath, data.len() as u64)?;
-                self.ar
-                    .append(&header, data)
-                    .await
-                    .context("could not add config file to basebackup tarball")?;
+            let data = if *filepath == "pg_hba.conf" {
+                PG_HBA.as_bytes()
             } else {
-                let header = new_tar_header(filepath, 0)?;
-                self.ar
-                    .append(&header, &mut io::empty())
-                    .await
-                    .context("could not add config file to basebackup tarball")?;
-            }
+                &[]
+            };
+            self.add_file(filepath, data)
+                .await
+                .context("could not add config file to basebackup tarball")?;
         }
 
+        // The directory listings below come unordered. Sort them, so that the same backup
+        // always comes out the same.
+
         // Gather non-relational files from object storage pages.
         for kind in [
             SlruKind::Clog,
             SlruKind::MultiXactOffsets,
             SlruKind::MultiXactMembers,
         ] {
-            for segno in self
-                .timeline
-                .list_slru_segments(kind, Version::Lsn(self.lsn), self.ctx)
-                .await?
-            {
+            let mut segnos = Vec::from_iter(
+                self.timeline
+                    .list_slru_segments(kind, Version::Lsn(self.lsn), self.ctx)
+                    .await?,
+            );
+            segnos.sort_unstable();
+            for segno in segnos {
                 self.add_slru_segment(kind, segno).await?;
             }
         }
 
         let mut min_restart_lsn: Lsn = Lsn::MAX;
         // Create tablespace directories
-        for ((spcnode, dbnode), has_relmap_file) in
-            self.timeline.list_dbdirs(self.lsn, self.ctx).await?
-        {
-            self.add_dbdir(spcnode, dbnode, has_relmap_file).await?;
-
-            // If full backup is requested, include all relation files.
-            // Otherwise only include init forks of unlogged relations.
-            let rels = self
-                .timeline
-                .list_rels(spcnode, dbnode, Version::Lsn(self.lsn), self.ctx)
-                .await?;
-            for &rel in rels.iter() {
-                // Send init fork as main fork to provide well formed empty
-                // contents of UNLOGGED relations. Postgres copies it in
-                // `reinit.c` during recovery.
-                if rel.forknum == INIT_FORKNUM {
-                    // I doubt we need _init fork itself, but having it at least
-                    // serves as a marker relation is unlogged.
-                    self.add_rel(rel, rel).await?;
-                    self.add_rel(rel, rel.with_forknum(MAIN_FORKNUM)).await?;
-                    continue;
+        let mut dbdirs = Vec::from_iter(self.timeline.list_dbdirs(self.lsn, self.ctx).await?);
+        dbdirs.sort_unstable();
+        for ((spcnode, dbnode), has_relmap_file) in dbdirs {
+            // With a filter, the selected databases and the shared catalogs are sent with all
+            // their relation files, and the other databases not at all.
+            let (include_dbdir, all_rels) = match self.rel_filter {
+                None => (true, self.full_backup),
+                Some(dbnodes) => {
+                    let selected = spcnode == GLOBALTABLESPACE_OID || dbnodes.contains(&dbnode);
+                    (selected, selected)
                 }
+            };
+            if include_dbdir {
+                self.add_dbdir(spcnode, dbnode, has_relmap_file).await?;
 
-                if self.full_backup {
-                    if rel.forknum == MAIN_FORKNUM && rels.contains(&rel.with_forknum(INIT_FORKNUM))
-                    {
-                        // skip this, will include it when we reach the init fork
+                // If full backup is requested, include all relation files.
+                // Otherwise only include init forks of unlogged relations.
+                let rels = self
+                    .timeline
+                    .list_rels(spcnode, dbnode, Version::Lsn(self.lsn), self.ctx)
+                    .await?;
+                let mut sorted_rels = Vec::from_iter(rels.iter().copied());
+                sorted_rels.sort_unstable();
+                for rel in sorted_rels {
+                    // Send init fork as main fork to provide well formed empty
+                    // contents of UNLOGGED relations. Postgres copies it in
+                    // `reinit.c` during recovery.
+                    if rel.forknum == INIT_FORKNUM {
+                        // I doubt we need _init fork itself, but having it at least
+                        // serves as a marker relation is unlogged.
+                        self.add_rel(rel, rel).await?;
+                        self.add_rel(rel, rel.with_forknum(MAIN_FORKNUM)).await?;
                         continue;
                     }
-                    self.add_rel(rel, rel).await?;
+
+                    if all_rels {
+                        if rel.forknum == MAIN_FORKNUM
+                            && rels.contains(&rel.with_forknum(INIT_FORKNUM))
+                        {
+                            // skip this, will include it when we reach the init fork
+                            continue;
+                        }
+                        self.add_rel(rel, rel).await?;
+                    }
                 }
             }
 
-            for (path, content) in self.timeline.list_aux_files(self.lsn, self.ctx).await? {
+            let mut aux_files =
+                Vec::from_iter(self.timeline.list_aux_files(self.lsn, self.ctx).await?);
+            aux_files.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+            for (path, content) in aux_files {
                 if path.starts_with("pg_replslot") {
+                    if !self.include_slots {
+                        continue;
+                    }
                     let offs = pg_constants::REPL_SLOT_ON_DISK_OFFSETOF_RESTART_LSN;
                     let restart_lsn = Lsn(u64::from_le_bytes(
                         content[offs..offs + 8].try_into().unwrap(),
@@ -226,9 +717,7 @@ where
                     info!("Replication slot {} restart LSN={}", path, restart_lsn);
                     min_restart_lsn = Lsn::min(min_restart_lsn, restart_lsn);
                 }
-                let header = new_tar_header(&path, content.len() as u64)?;
-                self.ar
-                    .append(&header, &*content)
+                self.add_file(&path, &content)
                     .await
                     .context("could not add aux file to basebackup tarball")?;
             }
@@ -239,17 +728,17 @@ where
                 min_restart_lsn
             );
             let data = min_restart_lsn.0.to_le_bytes();
-            let header = new_tar_header("restart.lsn", data.len() as u64)?;
-            self.ar
-                .append(&header, &data[..])
+            self.add_file("restart.lsn", &data)
                 .await
                 .context("could not add restart.lsn file to basebackup tarball")?;
         }
-        for xid in self
-            .timeline
-            .list_twophase_files(self.lsn, self.ctx)
-            .await?
-        {
+        let mut xids = Vec::from_iter(
+            self.timeline
+                .list_twophase_files(self.lsn, self.ctx)
+                .await?,
+        );
+        xids.sort_unstable();
+        for xid in xids {
             self.add_twophase_file(xid).await?;
         }
 
@@ -259,11 +748,39 @@ where
 
         // Generate pg_control and bootstrap WAL segment.
         self.add_pgcontrol_file().await?;
+
+        if let Some(files) = self.manifest.take() {
+            let manifest = BasebackupManifest {
+                lsn: self.lsn,
+                files,
+            };
+            let data = serde_json::to_vec(&manifest)?;
+            let header = new_tar_header(MANIFEST_FILE_NAME, data.len() as u64, self.mtime)?;
+            self.ar
+                .append(&header, &data[..])
+                .await
+                .context("could not add manifest to basebackup tarball")?;
+        }
+
         self.ar.finish().await?;
         debug!("all tarred up!");
         Ok(())
     }
 
+    /// Add a regular file to the tarball, and to the manifest if one was requested.
+    async fn add_file(&mut self, path: &str, data: &[u8]) -> anyhow::Result<()> {
+        let header = new_tar_header(path, data.len() as u64, self.mtime)?;
+        self.ar.append(&header, data).await?;
+        if let Some(manifest) = &mut self.manifest {
+            manifest.push(BasebackupManifestFile {
+                path: path.to_owned(),
+                size: data.len() as u64,
+                crc32c: crc32c::crc32c(data),
+            });
+        }
+        Ok(())
+    }
+
     /// Add contents of relfilenode `src`, naming it as `dst`.
     async fn add_rel(&mut self, src: RelTag, dst: RelTag) -> anyhow::Result<()> {
         let nblocks = self
@@ -274,8 +791,7 @@ where
         // If the relation is empty, create an empty file
         if nblocks == 0 {
             let file_name = dst.to_segfile_name(0);
-            let header = new_tar_header(&file_name, 0)?;
-            self.ar.append(&header, &mut io::empty()).await?;
+            self.add_file(&file_name, &[]).await?;
             return Ok(());
         }
 
@@ -295,8 +811,7 @@ where
             }
 
             let file_name = dst.to_segfile_name(seg as u32);
-            let header = new_tar_header(&file_name, segment_data.len() as u64)?;
-            self.ar.append(&header, segment_data.as_slice()).await?;
+            self.add_file(&file_name, &segment_data).await?;
 
             seg += 1;
             startblk = endblk;
@@ -331,8 +846,7 @@ where
         }
 
         let segname = format!("{}/{:>04X}", slru.to_str(), segno);
-        let header = new_tar_header(&segname, slru_buf.len() as u64)?;
-        self.ar.append(&header, slru_buf.as_slice()).await?;
+        self.add_file(&segname, &slru_buf).await?;
 
         trace!("Added to basebackup slru {} relsize {}", segname, nblocks);
         Ok(())
@@ -374,15 +888,14 @@ where
                 14 | 15 => self.timeline.pg_version.to_string(),
                 ver => format!("{ver}\x0A"),
             };
-            let header = new_tar_header("PG_VERSION", pg_version_str.len() as u64)?;
-            self.ar.append(&header, pg_version_str.as_bytes()).await?;
+            self.add_file("PG_VERSION", pg_version_str.as_bytes())
+                .await?;
 
             info!("timeline.pg_version {}", self.timeline.pg_version);
 
             if let Some(img) = relmap_img {
                 // filenode map for global tablespace
-                let header = new_tar_header("global/pg_filenode.map", img.len() as u64)?;
-                self.ar.append(&header, &img[..]).await?;
+                self.add_file("global/pg_filenode.map", &img).await?;
             } else {
                 warn!("global/pg_filenode.map is missing");
             }
@@ -411,7 +924,7 @@ where
 
             // Append dir path for each database
             let path = format!("base/{}", dbnode);
-            let header = new_tar_header_dir(&path)?;
+            let header = new_tar_header_dir(&path, self.mtime)?;
             self.ar.append(&header, &mut io::empty()).await?;
 
             if let Some(img) = relmap_img {
@@ -421,12 +934,10 @@ where
                     14 | 15 => self.timeline.pg_version.to_string(),
                     ver => format!("{ver}\x0A"),
                 };
-                let header = new_tar_header(&dst_path, pg_version_str.len() as u64)?;
-                self.ar.append(&header, pg_version_str.as_bytes()).await?;
+                self.add_file(&dst_path, pg_version_str.as_bytes()).await?;
 
                 let relmap_path = format!("base/{}/pg_filenode.map", dbnode);
-                let header = new_tar_header(&relmap_path, img.len() as u64)?;
-                self.ar.append(&header, &img[..]).await?;
+                self.add_file(&relmap_path, &img).await?;
             }
         };
         Ok(())
@@ -446,8 +957,7 @@ where
         let crc = crc32c::crc32c(&img[..]);
         buf.put_u32_le(crc);
         let path = format!("pg_twophase/{:>08X}", xid);
-        let header = new_tar_header(&path, buf.len() as u64)?;
-        self.ar.append(&header, &buf[..]).await?;
+        self.add_file(&path, &buf).await?;
 
         Ok(())
     }
@@ -468,11 +978,7 @@ where
         } else {
             write!(zenith_signal, "PREV LSN: {}", self.prev_record_lsn)?;
         }
-        self.ar
-            .append(
-                &new_tar_header("zenith.signal", zenith_signal.len() as u64)?,
-                zenith_signal.as_bytes(),
-            )
+        self.add_file("zenith.signal", zenith_signal.as_bytes())
             .await?;
 
         let checkpoint_bytes = self
@@ -494,14 +1000,13 @@ where
         )?;
 
         //send pg_control
-        let header = new_tar_header("global/pg_control", pg_control_bytes.len() as u64)?;
-        self.ar.append(&header, &pg_control_bytes[..]).await?;
+        self.add_file("global/pg_control", &pg_control_bytes)
+            .await?;
 
         //send wal segment
         let segno = self.lsn.segment_number(WAL_SEGMENT_SIZE);
         let wal_file_name = XLogFileName(PG_TLI, segno, WAL_SEGMENT_SIZE);
         let wal_file_path = format!("pg_wal/{}", wal_file_name);
-        let header = new_tar_header(&wal_file_path, WAL_SEGMENT_SIZE as u64)?;
 
         let wal_seg = postgres_ffi::generate_wal_segment(
             segno,
@@ -511,43 +1016,380 @@ where
         )
         .map_err(|e| anyhow!(e).context("Failed generating wal segment"))?;
         ensure!(wal_seg.len() == WAL_SEGMENT_SIZE);
-        self.ar.append(&header, &wal_seg[..]).await?;
+        self.add_file(&wal_file_path, &wal_seg).await?;
         Ok(())
     }
 }
 
+/// Size of a tarball entry with `len` bytes of data: a header block, followed by the data
+/// padded to whole blocks.
+fn tar_entry_size(len: u64) -> u64 {
+    const BLOCK_SIZE: u64 = 512;
+    BLOCK_SIZE + len.div_ceil(BLOCK_SIZE) * BLOCK_SIZE
+}
+
 //
 // Create new tarball entry header
 //
-fn new_tar_header(path: &str, size: u64) -> anyhow::Result<Header> {
+fn new_tar_header(path: &str, size: u64, mtime: u64) -> anyhow::Result<Header> {
     let mut header = Header::new_gnu();
     header.set_size(size);
     header.set_path(path)?;
     header.set_mode(0b110000000); // -rw-------
-    header.set_mtime(
-        // use currenttime as last modified time
-        SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs(),
-    );
+    header.set_mtime(mtime);
     header.set_cksum();
     Ok(header)
 }
 
-fn new_tar_header_dir(path: &str) -> anyhow::Result<Header> {
+fn new_tar_header_dir(path: &str, mtime: u64) -> anyhow::Result<Header> {
     let mut header = Header::new_gnu();
     header.set_size(0);
     header.set_path(path)?;
     header.set_mode(0o755); // -rw-------
     header.set_entry_type(EntryType::dir());
-    header.set_mtime(
-        // use currenttime as last modified time
-        SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs(),
-    );
+    header.set_mtime(mtime);
     header.set_cksum();
     Ok(header)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tenant::harness::{TenantHarness, TEST_IMG, TIMELINE_ID};
+    use crate::tenant::Tenant;
+    use crate::DEFAULT_PG_VERSION;
+    use bytes::Bytes;
+    use futures::StreamExt;
+    use postgres_ffi::v15::{CheckPoint, ControlFileData};
+    use std::sync::Arc;
+
+    /// Databases in the timeline created by [`create_timeline_with_databases`].
+    const TEST_DBNODES: [u32; 3] = [16384, 16385, 16386];
+
+    /// Create a timeline with the shared catalogs and a few databases with a few small relations
+    /// each, enough to take a basebackup of. The pages are full-sized, like the ones Postgres
+    /// writes.
+    async fn create_timeline_with_databases(
+        test_name: &'static str,
+    ) -> anyhow::Result<(Arc<Tenant>, Arc<Timeline>, RequestContext)> {
+        let (tenant, ctx) = TenantHarness::create(test_name)?.load().await;
+        let tline = tenant
+            .create_test_timeline(TIMELINE_ID, Lsn(0x10), DEFAULT_PG_VERSION, &ctx)
+            .await?;
+
+        let relmap = Bytes::from(vec![0u8; postgres_ffi::v15::bindings::SIZEOF_RELMAPFILE]);
+        let mut m = tline.begin_modification(Lsn(0x20));
+        m.put_control_file(ControlFileData::default().encode())?;
+        m.put_checkpoint(CheckPoint::default().encode()?)?;
+        m.put_relmap_file(GLOBALTABLESPACE_OID, 0, relmap.clone(), &ctx)
+            .await?;
+        for dbnode in TEST_DBNODES {
+            m.put_relmap_file(DEFAULTTABLESPACE_OID, dbnode, relmap.clone(), &ctx)
+                .await?;
+            for relnode in 1000..1010 {
+                let rel = RelTag {
+                    spcnode: DEFAULTTABLESPACE_OID,
+                    dbnode,
+                    relnode,
+                    forknum: MAIN_FORKNUM,
+                };
+                m.put_rel_creation(rel, 1, &ctx).await?;
+                let mut img = BytesMut::from(&TEST_IMG(&format!("{rel} blk 0"))[..]);
+                img.resize(BLCKSZ as usize, 0);
+                m.put_rel_page_image(rel, 0, img.freeze())?;
+            }
+        }
+        m.commit(&ctx).await?;
+
+        Ok((tenant, tline, ctx))
+    }
+
+    #[tokio::test]
+    async fn resumed_basebackup_is_identical() -> anyhow::Result<()> {
+        let (_tenant, tline, ctx) =
+            create_timeline_with_databases("resumed_basebackup_is_identical").await?;
+        let end = tline.get_last_record_rlsn();
+
+        let mut full = Vec::new();
+        send_basebackup_tarball(
+            &mut full,
+            &tline,
+            Some(end.last),
+            Some(end.prev),
+            true,
+            true,
+            false,
+            None,
+            true,
+            &ctx,
+        )
+        .await?;
+
+        // Let the clock move on, as it would before a client retries.
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        // Resume from the middle, and stitch the two parts together.
+        let offset = full.len() / 2 + 123;
+        let mut rest = SkipPrefix::new(Vec::new(), offset as u64);
+        send_basebackup_tarball(
+            &mut rest,
+            &tline,
+            Some(end.last),
+            Some(end.prev),
+            true,
+            true,
+            false,
+            None,
+            true,
+            &ctx,
+        )
+        .await?;
+        assert_eq!(rest.remaining_skip(), 0);
+
+        let mut stitched = full[..offset].to_vec();
+        stitched.extend_from_slice(&rest.inner);
+        assert!(
+            stitched == full,
+            "stitched basebackup differs from a single one"
+        );
+        Ok(())
+    }
+
+    /// Paths of the entries of a tarball, in order.
+    async fn tarball_paths(tarball: &[u8]) -> anyhow::Result<Vec<String>> {
+        let mut paths = Vec::new();
+        let mut entries = tokio_tar::Archive::new(tarball).entries()?;
+        while let Some(entry) = entries.next().await {
+            paths.push(entry?.path()?.to_string_lossy().into_owned());
+        }
+        Ok(paths)
+    }
+
+    #[tokio::test]
+    async fn rel_filter_selects_databases() -> anyhow::Result<()> {
+        let (_tenant, tline, ctx) =
+            create_timeline_with_databases("rel_filter_selects_databases").await?;
+        let end = tline.get_last_record_rlsn();
+        let selected = TEST_DBNODES[1];
+
+        let mut tarball = Vec::new();
+        send_basebackup_tarball(
+            &mut tarball,
+            &tline,
+            Some(end.last),
+            Some(end.prev),
+            false,
+            true,
+            false,
+            Some(&[selected]),
+            false,
+            &ctx,
+        )
+        .await?;
+        let paths = tarball_paths(&tarball).await?;
+
+        // All relations of the selected database, and none of the others
+        for relnode in 1000..1010 {
+            let path = format!("base/{selected}/{relnode}");
+            assert!(paths.contains(&path), "{path} is missing");
+        }
+        for path in &paths {
+            if let Some(rest) = path.strip_prefix("base/") {
+                let dbnode = rest.split('/').next().unwrap();
+                assert!(
+                    dbnode.is_empty() || dbnode == selected.to_string(),
+                    "{path} is not in the selected database"
+                );
+            }
+        }
+        // The shared catalogs and the control file are always there
+        assert!(paths.iter().any(|path| path == "global/pg_filenode.map"));
+        assert!(paths.iter().any(|path| path == "global/pg_control"));
+
+        // An OID that isn't a database fails the backup before anything is written
+        let mut tarball = Vec::new();
+        let err = send_basebackup_tarball(
+            &mut tarball,
+            &tline,
+            Some(end.last),
+            Some(end.prev),
+            false,
+            true,
+            false,
+            Some(&[selected, 99999]),
+            false,
+            &ctx,
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("database with OID 99999 does not exist"),
+            "{err:#}"
+        );
+        assert!(tarball.is_empty());
+        check_rel_filter(&tline, end.last, &[selected], &ctx).await?;
+        check_rel_filter(&tline, end.last, &[0], &ctx)
+            .await
+            .unwrap_err();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn manifest_lists_all_files() -> anyhow::Result<()> {
+        let (_tenant, tline, ctx) =
+            create_timeline_with_databases("manifest_lists_all_files").await?;
+        let end = tline.get_last_record_rlsn();
+
+        let mut tarball = Vec::new();
+        send_basebackup_tarball(
+            &mut tarball,
+            &tline,
+            Some(end.last),
+            Some(end.prev),
+            true,
+            true,
+            true,
+            None,
+            false,
+            &ctx,
+        )
+        .await?;
+
+        // The regular files in the tarball, and the manifest, which is the last one
+        let mut files = Vec::new();
+        let mut manifest = None;
+        let mut entries = tokio_tar::Archive::new(&tarball[..]).entries()?;
+        while let Some(entry) = entries.next().await {
+            let mut entry = entry?;
+            assert!(manifest.is_none(), "manifest is not the last file");
+            if entry.header().entry_type() != EntryType::Regular {
+                continue;
+            }
+            let path = entry.path()?.to_string_lossy().into_owned();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).await?;
+            if path == MANIFEST_FILE_NAME {
+                manifest = Some(serde_json::from_slice::<BasebackupManifest>(&data)?);
+            } else {
+                files.push(BasebackupManifestFile {
+                    path,
+                    size: data.len() as u64,
+                    crc32c: crc32c::crc32c(&data),
+                });
+            }
+        }
+
+        let manifest = manifest.expect("tarball has a manifest");
+        assert_eq!(manifest.lsn, end.last);
+        assert!(files.len() > 30, "only {} files", files.len());
+        assert_eq!(manifest.files, files);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn estimated_size_matches_tarball() -> anyhow::Result<()> {
+        let (_tenant, tline, ctx) =
+            create_timeline_with_databases("estimated_size_matches_tarball").await?;
+        let end = tline.get_last_record_rlsn();
+
+        for full_backup in [false, true] {
+            let mut tarball = Vec::new();
+            send_basebackup_tarball(
+                &mut tarball,
+                &tline,
+                Some(end.last),
+                Some(end.prev),
+                full_backup,
+                true,
+                false,
+                None,
+                false,
+                &ctx,
+            )
+            .await?;
+            let estimate =
+                estimate_basebackup_tarball_size(&tline, end.last, full_backup, true, &ctx).await?;
+            assert_eq!(estimate, tarball.len() as u64, "full_backup: {full_backup}");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn basebackup_file_is_removed_when_cancelled() -> anyhow::Result<()> {
+        let (_tenant, tline, ctx) =
+            create_timeline_with_databases("basebackup_file_is_removed_when_cancelled").await?;
+        let end = tline.get_last_record_rlsn();
+        let dir = camino_tempfile::tempdir()?;
+        let path = dir.path().join("base.tar");
+
+        // Stop polling the backup part way through: the partial file is removed in the background.
+        let mut write = Box::pin(write_basebackup_tarball_to_file(
+            &path,
+            &tline,
+            Some(end.last),
+            Some(end.prev),
+            true,
+            true,
+            &ctx,
+        ));
+        while !path.exists() {
+            assert!(futures::poll!(write.as_mut()).is_pending());
+            tokio::task::yield_now().await;
+        }
+        assert!(futures::poll!(write.as_mut()).is_pending());
+        drop(write);
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while path.exists() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
+
+        let written = write_basebackup_tarball_to_file(
+            &path,
+            &tline,
+            Some(end.last),
+            Some(end.prev),
+            true,
+            true,
+            &ctx,
+        )
+        .await?;
+        assert_eq!(std::fs::metadata(&path)?.len(), written);
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limited() {
+        let cancel = CancellationToken::new();
+        let mut writer = RateLimited::new(Vec::new(), 1000, cancel.clone());
+
+        let started = Instant::now();
+        writer.write_all(&[0; 2500]).await.unwrap();
+        // The first second worth goes out right away, the rest at 1000 bytes per second.
+        assert_eq!(started.elapsed(), Duration::from_millis(2000));
+        assert_eq!(writer.inner.len(), 2500);
+
+        // Writing fails once the writer is cancelled, at the end of the pause at the latest.
+        let write = tokio::spawn(async move { writer.write_all(&[0; 1000]).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        cancel.cancel();
+        write.await.unwrap().unwrap_err();
+    }
+
+    #[test]
+    fn rate_limited_is_send_and_sync() {
+        // send_basebackup_tarball requires its writer to be both
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SkipPrefix<RateLimited<Vec<u8>>>>();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limited_unlimited() {
+        let mut writer = RateLimited::new(Vec::new(), 0, CancellationToken::new());
+        let started = Instant::now();
+        writer.write_all(&[0; 1_000_000]).await.unwrap();
+        assert_eq!(started.elapsed(), Duration::ZERO);
+    }
+}
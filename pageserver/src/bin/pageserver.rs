@@ -21,7 +21,7 @@ use tracing::*;
 
 use metrics::set_build_info_metric;
 use pageserver::{
-    config::{defaults::*, PageServerConf},
+    config::{defaults::*, PageServerConf, PageServiceTunables},
     context::{DownloadBehavior, RequestContext},
     deletion_queue::DeletionQueue,
     http, page_cache, page_service, task_mgr,
@@ -80,6 +80,12 @@ fn main() -> anyhow::Result<()> {
 
     let cfg_file_path = workdir.join("pageserver.toml");
 
+    // Kept to apply them again when the config file is reloaded.
+    let config_overrides: Vec<String> = arg_matches
+        .get_many::<String>("config-override")
+        .map(|values| values.cloned().collect())
+        .unwrap_or_default();
+
     // Set CWD to workdir for non-daemon modes
     env::set_current_dir(&workdir)
         .with_context(|| format!("Failed to set application's current dir to '{workdir}'"))?;
@@ -131,9 +137,16 @@ fn main() -> anyhow::Result<()> {
 
     // Basic initialization of things that don't change after startup
     virtual_file::init(conf.max_file_descriptors);
+    virtual_file::set_sweep_rounds(conf.virtual_file_sweep_rounds);
+    // The log goes to stdout: make sure that the line about a fatal I/O error is out before
+    // the process aborts.
+    virtual_file::register_fatal_io_error_hook(|| {
+        let _ = std::io::Write::flush(&mut std::io::stdout());
+    });
     page_cache::init(conf.page_cache_size);
 
-    start_pageserver(launch_ts, conf).context("Failed to start pageserver")?;
+    start_pageserver(launch_ts, conf, &cfg_file_path, &config_overrides)
+        .context("Failed to start pageserver")?;
 
     scenario.teardown();
     Ok(())
@@ -258,6 +271,8 @@ fn startup_checkpoint(started_at: Instant, phase: &str, human_phase: &str) {
 fn start_pageserver(
     launch_ts: &'static LaunchTimestamp,
     conf: &'static PageServerConf,
+    cfg_file_path: &Utf8Path,
+    config_overrides: &[String],
 ) -> anyhow::Result<()> {
     // Monotonic time for later calculating startup duration
     let started_startup_at = Instant::now();
@@ -309,7 +324,8 @@ fn start_pageserver(
 
     let pg_addr = &conf.listen_pg_addr;
     info!("Starting pageserver pg protocol handler on {pg_addr}");
-    let pageserver_listener = tcp_listener::bind(pg_addr)?;
+    let pageserver_listener =
+        tcp_listener::bind_with_backlog(pg_addr, conf.page_service_listen_backlog)?;
 
     // Launch broker client
     // The storage_broker::connect call needs to happen inside a tokio runtime thread.
@@ -351,6 +367,16 @@ fn start_pageserver(
     info!("Using auth for http API: {:#?}", conf.http_auth_type);
     info!("Using auth for pg connections: {:#?}", conf.pg_auth_type);
 
+    let pg_tls_config = conf.load_pg_tls_config()?;
+    info!(
+        "Using TLS for pg connections: {}",
+        if pg_tls_config.is_some() {
+            "enabled"
+        } else {
+            "disabled"
+        }
+    );
+
     match var("NEON_AUTH_TOKEN") {
         Ok(v) => {
             info!("Loaded JWT token for authentication with Safekeeper");
@@ -539,7 +565,7 @@ fn start_pageserver(
         let router_state = Arc::new(
             http::routes::State::new(
                 conf,
-                tenant_manager,
+                tenant_manager.clone(),
                 http_auth.clone(),
                 remote_storage.clone(),
                 broker_client.clone(),
@@ -640,10 +666,12 @@ fn start_pageserver(
             async move {
                 page_service::libpq_listener_main(
                     conf,
+                    launch_ts,
                     broker_client,
                     pg_auth,
                     pageserver_listener,
                     conf.pg_auth_type,
+                    pg_tls_config,
                     libpq_ctx,
                     task_mgr::shutdown_token(),
                 )
@@ -654,8 +682,16 @@ fn start_pageserver(
 
     let mut shutdown_pageserver = Some(shutdown_pageserver.drop_guard());
 
-    // All started up! Now just sit and wait for shutdown signal.
-    ShutdownSignals::handle(|signal| match signal {
+    // All started up! Now just sit and wait for shutdown signal, or SIGHUP to reload the config.
+    ShutdownSignals::handle_with_reload(|signal| match signal {
+        Signal::Hangup => {
+            info!("Got {}. Reloading configuration", signal.name());
+            if let Err(e) = reload_config(conf, cfg_file_path, config_overrides, &tenant_manager) {
+                error!("Failed to reload configuration: {e:#}");
+            }
+            Ok(())
+        }
+
         Signal::Quit => {
             info!(
                 "Got {}. Terminating in immediate shutdown mode",
@@ -685,6 +721,41 @@ fn start_pageserver(
     })
 }
 
+/// Read the config file again, with the same overrides as at startup, and apply the settings
+/// that can be changed at runtime, see [`PageServiceTunables`]. Changes to the other settings
+/// are ignored until the next restart.
+fn reload_config(
+    conf: &'static PageServerConf,
+    cfg_file_path: &Utf8Path,
+    config_overrides: &[String],
+    tenant_manager: &mgr::TenantManager,
+) -> anyhow::Result<()> {
+    let cfg_file_contents = std::fs::read_to_string(cfg_file_path)
+        .with_context(|| format!("Failed to read pageserver config at '{cfg_file_path}'"))?;
+    let mut toml = cfg_file_contents
+        .parse::<toml_edit::Document>()
+        .with_context(|| format!("Failed to parse '{cfg_file_path}' as pageserver config"))?;
+    for option_line in config_overrides {
+        let doc = toml_edit::Document::from_str(option_line).with_context(|| {
+            format!("Option '{option_line}' could not be parsed as a toml document")
+        })?;
+        for (key, item) in doc.iter() {
+            toml.insert(key, item.clone());
+        }
+    }
+
+    let new_conf = PageServerConf::parse_and_validate(&toml, &conf.workdir)
+        .context("Failed to parse pageserver configuration")?;
+    match PageServiceTunables::reload(conf, &new_conf) {
+        Some(tunables) => {
+            info!("Reloaded page_service settings: {tunables:?}");
+            tenant_manager.apply_page_service_tunables(&tunables);
+        }
+        None => info!("page_service settings are unchanged"),
+    }
+    Ok(())
+}
+
 fn create_remote_storage_client(
     conf: &'static PageServerConf,
 ) -> anyhow::Result<Option<GenericRemoteStorage>> {
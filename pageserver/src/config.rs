@@ -5,6 +5,7 @@
 //! See also `settings.md` for better description on every parameter.
 
 use anyhow::{anyhow, bail, ensure, Context, Result};
+use arc_swap::ArcSwapOption;
 use pageserver_api::shard::TenantShardId;
 use remote_storage::{RemotePath, RemoteStorageConfig};
 use serde::de::IntoDeserializer;
@@ -60,6 +61,7 @@ pub mod defaults {
 
     pub const DEFAULT_PAGE_CACHE_SIZE: usize = 8192;
     pub const DEFAULT_MAX_FILE_DESCRIPTORS: usize = 100;
+    pub const DEFAULT_VIRTUAL_FILE_SWEEP_ROUNDS: usize = 2;
 
     pub const DEFAULT_LOG_FORMAT: &str = "plain";
 
@@ -79,6 +81,19 @@ pub mod defaults {
 
     pub const DEFAULT_INGEST_BATCH_SIZE: u64 = 100;
 
+    pub const DEFAULT_PAGE_SERVICE_TENANT_RATE_LIMIT: u64 = 0;
+    pub const DEFAULT_PAGE_SERVICE_TENANT_MAX_CONNECTIONS: usize = 0;
+
+    pub const DEFAULT_CONCURRENT_BASEBACKUPS: usize = 64;
+
+    pub const DEFAULT_PAGE_SERVICE_LISTEN_BACKLOG: usize = 128;
+    pub const DEFAULT_PAGE_SERVICE_ACCEPT_BATCH_SIZE: usize = 16;
+    pub const DEFAULT_PAGE_SERVICE_SLO_THRESHOLD: &str = "1 h";
+    pub const DEFAULT_PAGE_SERVICE_SLOW_GETPAGE_THRESHOLD: &str = "1 h";
+    pub const DEFAULT_PAGE_SERVICE_CONNECTION_TIMEOUT: &str = "10 min";
+    pub const DEFAULT_PAGE_SERVICE_ACTIVE_TENANT_TIMEOUT: &str = "30 s";
+    pub const DEFAULT_BASEBACKUP_BANDWIDTH_LIMIT: u64 = 0;
+
     ///
     /// Default built-in configuration file.
     ///
@@ -93,6 +108,7 @@ pub mod defaults {
 
 #page_cache_size = {DEFAULT_PAGE_CACHE_SIZE}
 #max_file_descriptors = {DEFAULT_MAX_FILE_DESCRIPTORS}
+#virtual_file_sweep_rounds = {DEFAULT_VIRTUAL_FILE_SWEEP_ROUNDS}
 
 # initial superuser role name to use when creating a new tenant
 #initial_superuser_name = '{DEFAULT_SUPERUSER}'
@@ -114,6 +130,19 @@ pub mod defaults {
 
 #ingest_batch_size = {DEFAULT_INGEST_BATCH_SIZE}
 
+#page_service_tenant_rate_limit = {DEFAULT_PAGE_SERVICE_TENANT_RATE_LIMIT}
+#page_service_tenant_max_connections = {DEFAULT_PAGE_SERVICE_TENANT_MAX_CONNECTIONS}
+
+#concurrent_basebackups = '{DEFAULT_CONCURRENT_BASEBACKUPS}'
+
+#page_service_listen_backlog = {DEFAULT_PAGE_SERVICE_LISTEN_BACKLOG}
+#page_service_accept_batch_size = {DEFAULT_PAGE_SERVICE_ACCEPT_BATCH_SIZE}
+#page_service_slo_threshold = '{DEFAULT_PAGE_SERVICE_SLO_THRESHOLD}'
+#page_service_slow_getpage_threshold = '{DEFAULT_PAGE_SERVICE_SLOW_GETPAGE_THRESHOLD}'
+#page_service_connection_timeout = '{DEFAULT_PAGE_SERVICE_CONNECTION_TIMEOUT}'
+#page_service_active_tenant_timeout = '{DEFAULT_PAGE_SERVICE_ACTIVE_TENANT_TIMEOUT}'
+#basebackup_bandwidth_limit = {DEFAULT_BASEBACKUP_BANDWIDTH_LIMIT}
+
 [tenant_config]
 #checkpoint_distance = {DEFAULT_CHECKPOINT_DISTANCE} # in bytes
 #checkpoint_timeout = {DEFAULT_CHECKPOINT_TIMEOUT}
@@ -162,6 +191,10 @@ pub struct PageServerConf {
 
     pub page_cache_size: usize,
     pub max_file_descriptors: usize,
+    /// How many times the clock sweep may go around the file descriptor cache looking for a
+    /// slot that's neither recently used nor locked, before it waits for the lock of the next
+    /// slot. See [`crate::virtual_file::set_sweep_rounds`].
+    pub virtual_file_sweep_rounds: NonZeroUsize,
 
     // Repository directory, relative to current working directory.
     // Normally, the page server changes the current working directory
@@ -247,6 +280,70 @@ pub struct PageServerConf {
 
     /// Maximum number of WAL records to be ingested and committed at the same time
     pub ingest_batch_size: u64,
+
+    /// Maximum number of page_service requests per second that a tenant may issue, 0 means
+    /// unlimited. Requests that exceed the rate are delayed, not rejected.
+    pub page_service_tenant_rate_limit: u64,
+
+    /// Maximum number of concurrent pagestream connections to a tenant, 0 means unlimited.
+    /// Connections beyond that are rejected before they start streaming. Connections are
+    /// counted by the first shard of the tenant that is attached to this pageserver, which all
+    /// pagestream connections to the tenant start at, so the limit applies to all the tenant's
+    /// shards on this pageserver together.
+    pub page_service_tenant_max_connections: usize,
+
+    /// Path to a PEM file with the private key for TLS on the libpq listener. TLS is only
+    /// enabled if both this and [`Self::pg_tls_cert_path`] are set.
+    pub pg_tls_key_path: Option<Utf8PathBuf>,
+
+    /// Path to a PEM file with the certificate chain for TLS on the libpq listener.
+    pub pg_tls_cert_path: Option<Utf8PathBuf>,
+
+    /// Number of basebackups that may be taken concurrently, the rest are queued. Limits the disk
+    /// I/O and page reconstruction load when many computes start at once.
+    pub concurrent_basebackups: ConfigurableSemaphore,
+
+    /// How many connections to the libpq listener may wait to be accepted, see listen(2). The
+    /// kernel caps it at `net.core.somaxconn`.
+    pub page_service_listen_backlog: usize,
+
+    /// How many waiting connections the libpq listener accepts in one go, before checking for
+    /// shutdown and letting other tasks run. Higher values drain the backlog faster after a
+    /// mass reconnect, e.g. when the pageserver restarts.
+    pub page_service_accept_batch_size: NonZeroUsize,
+
+    /// How long flushing responses to a libpq client may take before the connection is torn
+    /// down. Without it, a stalled client that stops reading pins its connection task until
+    /// the kernel gives up on the socket, which can take very long. Off by default.
+    pub page_service_flush_timeout: Option<Duration>,
+
+    /// Pagestream requests that take longer than this count as breaches of the latency SLO,
+    /// see `pageserver_smgr_slo_breaches_total`. The default is high enough that nothing
+    /// counts.
+    pub page_service_slo_threshold: Duration,
+
+    /// A getpage request that takes longer than this is logged with a warning that includes
+    /// the effective LSN, the shard and how the time was split between waiting for the LSN and
+    /// reconstructing the page. The default is high enough that nothing gets logged.
+    pub page_service_slow_getpage_threshold: Duration,
+
+    /// How long a libpq connection may stay idle before it is closed. Long enough for most
+    /// valid compute connections, but finite so that we don't leak connections to computes
+    /// that are long gone.
+    pub page_service_connection_timeout: Duration,
+
+    /// How long a page_service request waits for its tenant to become active, e.g. while
+    /// it is still loading or being attached, before it fails.
+    pub page_service_active_tenant_timeout: Duration,
+
+    /// Maximum number of bytes per second that a basebackup is sent at, 0 means unlimited.
+    /// Keeps large basebackups from saturating the network, at the expense of interactive
+    /// traffic. The limit applies to each basebackup separately.
+    pub basebackup_bandwidth_limit: u64,
+
+    /// The page_service settings last reloaded from the config file, see
+    /// [`PageServiceTunables`].
+    pub page_service_tunables: ReloadedTunables,
 }
 
 /// We do not want to store this in a PageServerConf because the latter may be logged
@@ -257,6 +354,96 @@ pub struct PageServerConf {
 /// startup code to the connection code through a dozen layers.
 pub static SAFEKEEPER_AUTH_TOKEN: OnceCell<Arc<String>> = OnceCell::new();
 
+/// The page_service settings that can be changed without restarting the pageserver: edit
+/// `pageserver.toml` and send SIGHUP. The other fields of [`PageServerConf`] are read once at
+/// startup, and changing them in the file has no effect until the next restart.
+///
+/// The hot-reloadable settings are:
+/// - `page_service_connection_timeout`
+/// - `page_service_active_tenant_timeout`
+/// - `page_service_tenant_rate_limit`
+/// - `page_service_tenant_max_connections`
+/// - `page_service_flush_timeout`
+/// - `page_service_slo_threshold`
+/// - `page_service_slow_getpage_threshold`
+/// - `basebackup_bandwidth_limit`
+///
+/// A connection captures the values that are current when it is accepted, and keeps using
+/// them until it ends. The exception is the rate limit, which is shared by all connections to
+/// a tenant and so is applied to all tenants as soon as it is reloaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageServiceTunables {
+    pub connection_timeout: Duration,
+    pub active_tenant_timeout: Duration,
+    pub tenant_rate_limit: u64,
+    pub tenant_max_connections: usize,
+    pub flush_timeout: Option<Duration>,
+    pub slo_threshold: Duration,
+    pub slow_getpage_threshold: Duration,
+    pub basebackup_bandwidth_limit: u64,
+}
+
+impl PageServiceTunables {
+    fn from_conf(conf: &PageServerConf) -> Self {
+        PageServiceTunables {
+            connection_timeout: conf.page_service_connection_timeout,
+            active_tenant_timeout: conf.page_service_active_tenant_timeout,
+            tenant_rate_limit: conf.page_service_tenant_rate_limit,
+            tenant_max_connections: conf.page_service_tenant_max_connections,
+            flush_timeout: conf.page_service_flush_timeout,
+            slo_threshold: conf.page_service_slo_threshold,
+            slow_getpage_threshold: conf.page_service_slow_getpage_threshold,
+            basebackup_bandwidth_limit: conf.basebackup_bandwidth_limit,
+        }
+    }
+
+    /// The current values of `conf`: the last reloaded ones, or those it was started with if
+    /// there was no reload yet.
+    pub fn current(conf: &PageServerConf) -> Arc<Self> {
+        conf.page_service_tunables
+            .0
+            .load_full()
+            .unwrap_or_else(|| Arc::new(Self::from_conf(conf)))
+    }
+
+    /// Make the tunables of a freshly parsed `new_conf` the current ones of `conf`. Returns the
+    /// new values, or `None` if they are the same as before.
+    pub fn reload(conf: &PageServerConf, new_conf: &PageServerConf) -> Option<Arc<Self>> {
+        let new = Arc::new(Self::from_conf(new_conf));
+        let old = conf.page_service_tunables.0.swap(Some(Arc::clone(&new)));
+        let unchanged = match old {
+            Some(old) => old == new,
+            None => Self::from_conf(conf) == *new,
+        };
+        (!unchanged).then_some(new)
+    }
+}
+
+/// The values last loaded with [`PageServiceTunables::reload`], `None` until the first reload,
+/// in which case the values that the [`PageServerConf`] was started with apply.
+#[derive(Debug)]
+pub struct ReloadedTunables(ArcSwapOption<PageServiceTunables>);
+
+impl Default for ReloadedTunables {
+    fn default() -> Self {
+        ReloadedTunables(ArcSwapOption::const_empty())
+    }
+}
+
+impl Clone for ReloadedTunables {
+    fn clone(&self) -> Self {
+        ReloadedTunables(ArcSwapOption::new(self.0.load_full()))
+    }
+}
+
+impl PartialEq for ReloadedTunables {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.load_full() == other.0.load_full()
+    }
+}
+
+impl Eq for ReloadedTunables {}
+
 // use dedicated enum for builder to better indicate the intention
 // and avoid possible confusion with nested options
 pub enum BuilderValue<T> {
@@ -288,6 +475,7 @@ struct PageServerConfigBuilder {
 
     page_cache_size: BuilderValue<usize>,
     max_file_descriptors: BuilderValue<usize>,
+    virtual_file_sweep_rounds: BuilderValue<NonZeroUsize>,
 
     workdir: BuilderValue<Utf8PathBuf>,
 
@@ -331,6 +519,32 @@ struct PageServerConfigBuilder {
     secondary_download_concurrency: BuilderValue<usize>,
 
     ingest_batch_size: BuilderValue<u64>,
+
+    page_service_tenant_rate_limit: BuilderValue<u64>,
+
+    page_service_tenant_max_connections: BuilderValue<usize>,
+
+    pg_tls_key_path: BuilderValue<Option<Utf8PathBuf>>,
+
+    pg_tls_cert_path: BuilderValue<Option<Utf8PathBuf>>,
+
+    concurrent_basebackups: BuilderValue<NonZeroUsize>,
+
+    page_service_listen_backlog: BuilderValue<usize>,
+
+    page_service_accept_batch_size: BuilderValue<NonZeroUsize>,
+
+    page_service_flush_timeout: BuilderValue<Option<Duration>>,
+
+    page_service_slo_threshold: BuilderValue<Duration>,
+
+    page_service_slow_getpage_threshold: BuilderValue<Duration>,
+
+    page_service_connection_timeout: BuilderValue<Duration>,
+
+    page_service_active_tenant_timeout: BuilderValue<Duration>,
+
+    basebackup_bandwidth_limit: BuilderValue<u64>,
 }
 
 impl Default for PageServerConfigBuilder {
@@ -348,6 +562,8 @@ impl Default for PageServerConfigBuilder {
             superuser: Set(DEFAULT_SUPERUSER.to_string()),
             page_cache_size: Set(DEFAULT_PAGE_CACHE_SIZE),
             max_file_descriptors: Set(DEFAULT_MAX_FILE_DESCRIPTORS),
+            virtual_file_sweep_rounds: Set(NonZeroUsize::new(DEFAULT_VIRTUAL_FILE_SWEEP_ROUNDS)
+                .expect("Invalid default constant")),
             workdir: Set(Utf8PathBuf::new()),
             pg_distrib_dir: Set(Utf8PathBuf::from_path_buf(
                 env::current_dir().expect("cannot access current directory"),
@@ -406,6 +622,48 @@ impl Default for PageServerConfigBuilder {
             secondary_download_concurrency: Set(DEFAULT_SECONDARY_DOWNLOAD_CONCURRENCY),
 
             ingest_batch_size: Set(DEFAULT_INGEST_BATCH_SIZE),
+
+            page_service_tenant_rate_limit: Set(DEFAULT_PAGE_SERVICE_TENANT_RATE_LIMIT),
+
+            page_service_tenant_max_connections: Set(DEFAULT_PAGE_SERVICE_TENANT_MAX_CONNECTIONS),
+
+            pg_tls_key_path: Set(None),
+
+            pg_tls_cert_path: Set(None),
+
+            concurrent_basebackups: Set(NonZeroUsize::new(DEFAULT_CONCURRENT_BASEBACKUPS)
+                .expect("Invalid default constant")),
+
+            page_service_listen_backlog: Set(DEFAULT_PAGE_SERVICE_LISTEN_BACKLOG),
+
+            page_service_accept_batch_size: Set(NonZeroUsize::new(
+                DEFAULT_PAGE_SERVICE_ACCEPT_BATCH_SIZE,
+            )
+            .expect("Invalid default constant")),
+
+            page_service_flush_timeout: Set(None),
+
+            page_service_slo_threshold: Set(humantime::parse_duration(
+                DEFAULT_PAGE_SERVICE_SLO_THRESHOLD,
+            )
+            .expect("cannot parse default page service slo threshold")),
+
+            page_service_slow_getpage_threshold: Set(humantime::parse_duration(
+                DEFAULT_PAGE_SERVICE_SLOW_GETPAGE_THRESHOLD,
+            )
+            .expect("cannot parse default page service slow getpage threshold")),
+
+            page_service_connection_timeout: Set(humantime::parse_duration(
+                DEFAULT_PAGE_SERVICE_CONNECTION_TIMEOUT,
+            )
+            .expect("cannot parse default page service connection timeout")),
+
+            page_service_active_tenant_timeout: Set(humantime::parse_duration(
+                DEFAULT_PAGE_SERVICE_ACTIVE_TENANT_TIMEOUT,
+            )
+            .expect("cannot parse default page service active tenant timeout")),
+
+            basebackup_bandwidth_limit: Set(DEFAULT_BASEBACKUP_BANDWIDTH_LIMIT),
         }
     }
 }
@@ -443,6 +701,10 @@ impl PageServerConfigBuilder {
         self.max_file_descriptors = BuilderValue::Set(max_file_descriptors)
     }
 
+    pub fn virtual_file_sweep_rounds(&mut self, virtual_file_sweep_rounds: NonZeroUsize) {
+        self.virtual_file_sweep_rounds = BuilderValue::Set(virtual_file_sweep_rounds)
+    }
+
     pub fn workdir(&mut self, workdir: Utf8PathBuf) {
         self.workdir = BuilderValue::Set(workdir)
     }
@@ -562,6 +824,70 @@ impl PageServerConfigBuilder {
         self.ingest_batch_size = BuilderValue::Set(ingest_batch_size)
     }
 
+    pub fn page_service_tenant_rate_limit(&mut self, page_service_tenant_rate_limit: u64) {
+        self.page_service_tenant_rate_limit = BuilderValue::Set(page_service_tenant_rate_limit)
+    }
+
+    pub fn page_service_tenant_max_connections(
+        &mut self,
+        page_service_tenant_max_connections: usize,
+    ) {
+        self.page_service_tenant_max_connections =
+            BuilderValue::Set(page_service_tenant_max_connections)
+    }
+
+    pub fn pg_tls_key_path(&mut self, pg_tls_key_path: Option<Utf8PathBuf>) {
+        self.pg_tls_key_path = BuilderValue::Set(pg_tls_key_path)
+    }
+
+    pub fn pg_tls_cert_path(&mut self, pg_tls_cert_path: Option<Utf8PathBuf>) {
+        self.pg_tls_cert_path = BuilderValue::Set(pg_tls_cert_path)
+    }
+
+    pub fn concurrent_basebackups(&mut self, concurrent_basebackups: NonZeroUsize) {
+        self.concurrent_basebackups = BuilderValue::Set(concurrent_basebackups)
+    }
+
+    pub fn page_service_listen_backlog(&mut self, page_service_listen_backlog: usize) {
+        self.page_service_listen_backlog = BuilderValue::Set(page_service_listen_backlog)
+    }
+
+    pub fn page_service_accept_batch_size(&mut self, page_service_accept_batch_size: NonZeroUsize) {
+        self.page_service_accept_batch_size = BuilderValue::Set(page_service_accept_batch_size)
+    }
+
+    pub fn page_service_flush_timeout(&mut self, page_service_flush_timeout: Option<Duration>) {
+        self.page_service_flush_timeout = BuilderValue::Set(page_service_flush_timeout)
+    }
+
+    pub fn page_service_slo_threshold(&mut self, page_service_slo_threshold: Duration) {
+        self.page_service_slo_threshold = BuilderValue::Set(page_service_slo_threshold)
+    }
+
+    pub fn page_service_slow_getpage_threshold(
+        &mut self,
+        page_service_slow_getpage_threshold: Duration,
+    ) {
+        self.page_service_slow_getpage_threshold =
+            BuilderValue::Set(page_service_slow_getpage_threshold)
+    }
+
+    pub fn page_service_connection_timeout(&mut self, page_service_connection_timeout: Duration) {
+        self.page_service_connection_timeout = BuilderValue::Set(page_service_connection_timeout)
+    }
+
+    pub fn page_service_active_tenant_timeout(
+        &mut self,
+        page_service_active_tenant_timeout: Duration,
+    ) {
+        self.page_service_active_tenant_timeout =
+            BuilderValue::Set(page_service_active_tenant_timeout)
+    }
+
+    pub fn basebackup_bandwidth_limit(&mut self, basebackup_bandwidth_limit: u64) {
+        self.basebackup_bandwidth_limit = BuilderValue::Set(basebackup_bandwidth_limit)
+    }
+
     pub fn build(self) -> anyhow::Result<PageServerConf> {
         let concurrent_tenant_warmup = self
             .concurrent_tenant_warmup
@@ -594,6 +920,9 @@ impl PageServerConfigBuilder {
             max_file_descriptors: self
                 .max_file_descriptors
                 .ok_or(anyhow!("missing max_file_descriptors"))?,
+            virtual_file_sweep_rounds: self
+                .virtual_file_sweep_rounds
+                .ok_or(anyhow!("missing virtual_file_sweep_rounds"))?,
             workdir: self.workdir.ok_or(anyhow!("missing workdir"))?,
             pg_distrib_dir: self
                 .pg_distrib_dir
@@ -669,6 +998,47 @@ impl PageServerConfigBuilder {
             ingest_batch_size: self
                 .ingest_batch_size
                 .ok_or(anyhow!("missing ingest_batch_size"))?,
+            page_service_tenant_rate_limit: self
+                .page_service_tenant_rate_limit
+                .ok_or(anyhow!("missing page_service_tenant_rate_limit"))?,
+            page_service_tenant_max_connections: self
+                .page_service_tenant_max_connections
+                .ok_or(anyhow!("missing page_service_tenant_max_connections"))?,
+            pg_tls_key_path: self
+                .pg_tls_key_path
+                .ok_or(anyhow!("missing pg_tls_key_path"))?,
+            pg_tls_cert_path: self
+                .pg_tls_cert_path
+                .ok_or(anyhow!("missing pg_tls_cert_path"))?,
+            concurrent_basebackups: ConfigurableSemaphore::new(
+                self.concurrent_basebackups
+                    .ok_or(anyhow!("missing concurrent_basebackups"))?,
+            ),
+            page_service_listen_backlog: self
+                .page_service_listen_backlog
+                .ok_or(anyhow!("missing page_service_listen_backlog"))?,
+            page_service_accept_batch_size: self
+                .page_service_accept_batch_size
+                .ok_or(anyhow!("missing page_service_accept_batch_size"))?,
+            page_service_flush_timeout: self
+                .page_service_flush_timeout
+                .ok_or(anyhow!("missing page_service_flush_timeout"))?,
+            page_service_slo_threshold: self
+                .page_service_slo_threshold
+                .ok_or(anyhow!("missing page_service_slo_threshold"))?,
+            page_service_slow_getpage_threshold: self
+                .page_service_slow_getpage_threshold
+                .ok_or(anyhow!("missing page_service_slow_getpage_threshold"))?,
+            page_service_connection_timeout: self
+                .page_service_connection_timeout
+                .ok_or(anyhow!("missing page_service_connection_timeout"))?,
+            page_service_active_tenant_timeout: self
+                .page_service_active_tenant_timeout
+                .ok_or(anyhow!("missing page_service_active_tenant_timeout"))?,
+            basebackup_bandwidth_limit: self
+                .basebackup_bandwidth_limit
+                .ok_or(anyhow!("missing basebackup_bandwidth_limit"))?,
+            page_service_tunables: ReloadedTunables::default(),
         })
     }
 }
@@ -686,6 +1056,11 @@ impl PageServerConf {
         self.workdir.join("deletion")
     }
 
+    /// Directory that the `basebackup_to_file` command writes into.
+    pub fn basebackups_path(&self) -> Utf8PathBuf {
+        self.workdir.join("basebackups")
+    }
+
     pub fn deletion_list_path(&self, sequence: u64) -> Utf8PathBuf {
         // Encode a version in the filename, so that if we ever switch away from JSON we can
         // increment this.
@@ -846,6 +1221,12 @@ impl PageServerConf {
                 "max_file_descriptors" => {
                     builder.max_file_descriptors(parse_toml_u64(key, item)? as usize)
                 }
+                "virtual_file_sweep_rounds" => {
+                    builder.virtual_file_sweep_rounds(
+                        NonZeroUsize::new(parse_toml_u64(key, item)? as usize)
+                            .context("virtual_file_sweep_rounds must be greater than zero")?,
+                    )
+                }
                 "pg_distrib_dir" => {
                     builder.pg_distrib_dir(Utf8PathBuf::from(parse_toml_string(key, item)?))
                 }
@@ -920,6 +1301,48 @@ impl PageServerConf {
                     builder.secondary_download_concurrency(parse_toml_u64(key, item)? as usize)
                 },
                 "ingest_batch_size" => builder.ingest_batch_size(parse_toml_u64(key, item)?),
+                "page_service_tenant_rate_limit" => {
+                    builder.page_service_tenant_rate_limit(parse_toml_u64(key, item)?)
+                }
+                "page_service_tenant_max_connections" => {
+                    builder.page_service_tenant_max_connections(parse_toml_u64(key, item)? as usize)
+                },
+                "pg_tls_key_path" => {
+                    builder.pg_tls_key_path(Some(Utf8PathBuf::from(parse_toml_string(key, item)?)))
+                },
+                "pg_tls_cert_path" => {
+                    builder.pg_tls_cert_path(Some(Utf8PathBuf::from(parse_toml_string(key, item)?)))
+                },
+                "concurrent_basebackups" => builder.concurrent_basebackups({
+                    let input = parse_toml_string(key, item)?;
+                    let permits = input.parse::<usize>().with_context(|| format!("expected a number of initial permits, not {input:?}"))?;
+                    NonZeroUsize::new(permits).context("initial semaphore permits out of range: 0, use other configuration to disable a feature")?
+                }),
+                "page_service_listen_backlog" => {
+                    builder.page_service_listen_backlog(parse_toml_u64(key, item)? as usize)
+                },
+                "page_service_accept_batch_size" => builder.page_service_accept_batch_size(
+                    NonZeroUsize::new(parse_toml_u64(key, item)? as usize)
+                        .context("page_service_accept_batch_size must be greater than zero")?
+                ),
+                "page_service_flush_timeout" => {
+                    builder.page_service_flush_timeout(Some(parse_toml_duration(key, item)?))
+                },
+                "page_service_slo_threshold" => {
+                    builder.page_service_slo_threshold(parse_toml_duration(key, item)?)
+                },
+                "page_service_slow_getpage_threshold" => {
+                    builder.page_service_slow_getpage_threshold(parse_toml_duration(key, item)?)
+                },
+                "page_service_connection_timeout" => {
+                    builder.page_service_connection_timeout(parse_toml_duration(key, item)?)
+                },
+                "page_service_active_tenant_timeout" => {
+                    builder.page_service_active_tenant_timeout(parse_toml_duration(key, item)?)
+                },
+                "basebackup_bandwidth_limit" => {
+                    builder.basebackup_bandwidth_limit(parse_toml_u64(key, item)?)
+                },
                 _ => bail!("unrecognized pageserver option '{key}'"),
             }
         }
@@ -938,11 +1361,48 @@ impl PageServerConf {
             );
         }
 
+        ensure!(
+            conf.pg_tls_key_path.is_some() == conf.pg_tls_cert_path.is_some(),
+            "pg_tls_key_path and pg_tls_cert_path must be set together"
+        );
+
         conf.default_tenant_conf = t_conf.merge(TenantConf::default());
 
         Ok(conf)
     }
 
+    /// Load the TLS configuration for the libpq listener, if TLS is enabled.
+    pub fn load_pg_tls_config(&self) -> anyhow::Result<Option<Arc<rustls::ServerConfig>>> {
+        let (Some(key_path), Some(cert_path)) = (&self.pg_tls_key_path, &self.pg_tls_cert_path)
+        else {
+            return Ok(None);
+        };
+
+        let key = {
+            let key_bytes = std::fs::read(key_path)
+                .with_context(|| format!("Failed to read TLS key file at '{key_path}'"))?;
+            let mut keys = rustls_pemfile::pkcs8_private_keys(&mut &key_bytes[..])
+                .with_context(|| format!("Failed to read TLS keys at '{key_path}'"))?;
+            ensure!(keys.len() == 1, "keys.len() = {} (should be 1)", keys.len());
+            rustls::PrivateKey(keys.pop().unwrap())
+        };
+
+        let cert_chain_bytes = std::fs::read(cert_path)
+            .with_context(|| format!("Failed to read TLS cert file at '{cert_path}'"))?;
+        let cert_chain = rustls_pemfile::certs(&mut &cert_chain_bytes[..])
+            .with_context(|| format!("Failed to read TLS certificate chain at '{cert_path}'"))?
+            .into_iter()
+            .map(rustls::Certificate)
+            .collect::<Vec<_>>();
+
+        let tls_config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(cert_chain, key)?;
+
+        Ok(Some(Arc::new(tls_config)))
+    }
+
     #[cfg(test)]
     pub fn test_repo_dir(test_name: &str) -> Utf8PathBuf {
         let test_output_dir = std::env::var("TEST_OUTPUT").unwrap_or("../tmp_check".into());
@@ -958,6 +1418,10 @@ impl PageServerConf {
             wal_redo_timeout: Duration::from_secs(60),
             page_cache_size: defaults::DEFAULT_PAGE_CACHE_SIZE,
             max_file_descriptors: defaults::DEFAULT_MAX_FILE_DESCRIPTORS,
+            virtual_file_sweep_rounds: NonZeroUsize::new(
+                defaults::DEFAULT_VIRTUAL_FILE_SWEEP_ROUNDS,
+            )
+            .expect("Invalid default constant"),
             listen_pg_addr: defaults::DEFAULT_PG_LISTEN_ADDR.to_string(),
             listen_http_addr: defaults::DEFAULT_HTTP_LISTEN_ADDR.to_string(),
             availability_zone: None,
@@ -993,6 +1457,39 @@ impl PageServerConf {
             heatmap_upload_concurrency: defaults::DEFAULT_HEATMAP_UPLOAD_CONCURRENCY,
             secondary_download_concurrency: defaults::DEFAULT_SECONDARY_DOWNLOAD_CONCURRENCY,
             ingest_batch_size: defaults::DEFAULT_INGEST_BATCH_SIZE,
+            page_service_tenant_rate_limit: defaults::DEFAULT_PAGE_SERVICE_TENANT_RATE_LIMIT,
+            page_service_tenant_max_connections:
+                defaults::DEFAULT_PAGE_SERVICE_TENANT_MAX_CONNECTIONS,
+            pg_tls_key_path: None,
+            pg_tls_cert_path: None,
+            concurrent_basebackups: ConfigurableSemaphore::new(
+                NonZeroUsize::new(defaults::DEFAULT_CONCURRENT_BASEBACKUPS)
+                    .expect("Invalid default constant"),
+            ),
+            page_service_listen_backlog: defaults::DEFAULT_PAGE_SERVICE_LISTEN_BACKLOG,
+            page_service_accept_batch_size: NonZeroUsize::new(
+                defaults::DEFAULT_PAGE_SERVICE_ACCEPT_BATCH_SIZE,
+            )
+            .expect("Invalid default constant"),
+            page_service_flush_timeout: None,
+            page_service_slo_threshold: humantime::parse_duration(
+                defaults::DEFAULT_PAGE_SERVICE_SLO_THRESHOLD,
+            )
+            .unwrap(),
+            page_service_slow_getpage_threshold: humantime::parse_duration(
+                defaults::DEFAULT_PAGE_SERVICE_SLOW_GETPAGE_THRESHOLD,
+            )
+            .unwrap(),
+            page_service_connection_timeout: humantime::parse_duration(
+                defaults::DEFAULT_PAGE_SERVICE_CONNECTION_TIMEOUT,
+            )
+            .unwrap(),
+            page_service_active_tenant_timeout: humantime::parse_duration(
+                defaults::DEFAULT_PAGE_SERVICE_ACTIVE_TENANT_TIMEOUT,
+            )
+            .unwrap(),
+            basebackup_bandwidth_limit: defaults::DEFAULT_BASEBACKUP_BANDWIDTH_LIMIT,
+            page_service_tunables: ReloadedTunables::default(),
         }
     }
 }
@@ -1143,6 +1640,7 @@ wal_redo_timeout = '111 s'
 
 page_cache_size = 444
 max_file_descriptors = 333
+virtual_file_sweep_rounds = 3
 
 # initial superuser role name to use when creating a new tenant
 initial_superuser_name = 'zzzz'
@@ -1184,6 +1682,10 @@ background_task_maximum_delay = '334 s'
                 superuser: defaults::DEFAULT_SUPERUSER.to_string(),
                 page_cache_size: defaults::DEFAULT_PAGE_CACHE_SIZE,
                 max_file_descriptors: defaults::DEFAULT_MAX_FILE_DESCRIPTORS,
+                virtual_file_sweep_rounds: NonZeroUsize::new(
+                    defaults::DEFAULT_VIRTUAL_FILE_SWEEP_ROUNDS
+                )
+                .unwrap(),
                 workdir,
                 pg_distrib_dir,
                 http_auth_type: AuthType::Trust,
@@ -1224,6 +1726,34 @@ background_task_maximum_delay = '334 s'
                 heatmap_upload_concurrency: defaults::DEFAULT_HEATMAP_UPLOAD_CONCURRENCY,
                 secondary_download_concurrency: defaults::DEFAULT_SECONDARY_DOWNLOAD_CONCURRENCY,
                 ingest_batch_size: defaults::DEFAULT_INGEST_BATCH_SIZE,
+                page_service_tenant_rate_limit: defaults::DEFAULT_PAGE_SERVICE_TENANT_RATE_LIMIT,
+                page_service_tenant_max_connections:
+                    defaults::DEFAULT_PAGE_SERVICE_TENANT_MAX_CONNECTIONS,
+                pg_tls_key_path: None,
+                pg_tls_cert_path: None,
+                concurrent_basebackups: ConfigurableSemaphore::new(
+                    NonZeroUsize::new(defaults::DEFAULT_CONCURRENT_BASEBACKUPS).unwrap()
+                ),
+                page_service_listen_backlog: defaults::DEFAULT_PAGE_SERVICE_LISTEN_BACKLOG,
+                page_service_accept_batch_size: NonZeroUsize::new(
+                    defaults::DEFAULT_PAGE_SERVICE_ACCEPT_BATCH_SIZE
+                )
+                .unwrap(),
+                page_service_flush_timeout: None,
+                page_service_slo_threshold: humantime::parse_duration(
+                    defaults::DEFAULT_PAGE_SERVICE_SLO_THRESHOLD
+                )?,
+                page_service_slow_getpage_threshold: humantime::parse_duration(
+                    defaults::DEFAULT_PAGE_SERVICE_SLOW_GETPAGE_THRESHOLD
+                )?,
+                page_service_connection_timeout: humantime::parse_duration(
+                    defaults::DEFAULT_PAGE_SERVICE_CONNECTION_TIMEOUT
+                )?,
+                page_service_active_tenant_timeout: humantime::parse_duration(
+                    defaults::DEFAULT_PAGE_SERVICE_ACTIVE_TENANT_TIMEOUT
+                )?,
+                basebackup_bandwidth_limit: defaults::DEFAULT_BASEBACKUP_BANDWIDTH_LIMIT,
+                page_service_tunables: ReloadedTunables::default(),
             },
             "Correct defaults should be used when no config values are provided"
         );
@@ -1257,6 +1787,7 @@ background_task_maximum_delay = '334 s'
                 superuser: "zzzz".to_string(),
                 page_cache_size: 444,
                 max_file_descriptors: 333,
+                virtual_file_sweep_rounds: NonZeroUsize::new(3).unwrap(),
                 workdir,
                 pg_distrib_dir,
                 http_auth_type: AuthType::Trust,
@@ -1287,6 +1818,34 @@ background_task_maximum_delay = '334 s'
                 heatmap_upload_concurrency: defaults::DEFAULT_HEATMAP_UPLOAD_CONCURRENCY,
                 secondary_download_concurrency: defaults::DEFAULT_SECONDARY_DOWNLOAD_CONCURRENCY,
                 ingest_batch_size: 100,
+                page_service_tenant_rate_limit: defaults::DEFAULT_PAGE_SERVICE_TENANT_RATE_LIMIT,
+                page_service_tenant_max_connections:
+                    defaults::DEFAULT_PAGE_SERVICE_TENANT_MAX_CONNECTIONS,
+                pg_tls_key_path: None,
+                pg_tls_cert_path: None,
+                concurrent_basebackups: ConfigurableSemaphore::new(
+                    NonZeroUsize::new(defaults::DEFAULT_CONCURRENT_BASEBACKUPS).unwrap()
+                ),
+                page_service_listen_backlog: defaults::DEFAULT_PAGE_SERVICE_LISTEN_BACKLOG,
+                page_service_accept_batch_size: NonZeroUsize::new(
+                    defaults::DEFAULT_PAGE_SERVICE_ACCEPT_BATCH_SIZE
+                )
+                .unwrap(),
+                page_service_flush_timeout: None,
+                page_service_slo_threshold: humantime::parse_duration(
+                    defaults::DEFAULT_PAGE_SERVICE_SLO_THRESHOLD
+                )?,
+                page_service_slow_getpage_threshold: humantime::parse_duration(
+                    defaults::DEFAULT_PAGE_SERVICE_SLOW_GETPAGE_THRESHOLD
+                )?,
+                page_service_connection_timeout: humantime::parse_duration(
+                    defaults::DEFAULT_PAGE_SERVICE_CONNECTION_TIMEOUT
+                )?,
+                page_service_active_tenant_timeout: humantime::parse_duration(
+                    defaults::DEFAULT_PAGE_SERVICE_ACTIVE_TENANT_TIMEOUT
+                )?,
+                basebackup_bandwidth_limit: defaults::DEFAULT_BASEBACKUP_BANDWIDTH_LIMIT,
+                page_service_tunables: ReloadedTunables::default(),
             },
             "Should be able to parse all basic config values correctly"
         );
@@ -1434,6 +1993,54 @@ trace_read_requests = {trace_read_requests}"#,
         Ok(())
     }
 
+    #[test]
+    fn parse_page_service_flush_timeout() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let (workdir, pg_distrib_dir) = prepare_fs(&tempdir)?;
+
+        let config_string = format!(
+            r#"{ALL_BASE_VALUES_TOML}
+pg_distrib_dir='{pg_distrib_dir}'
+broker_endpoint = 'http://127.0.0.1:7777'"#,
+        );
+        let conf = PageServerConf::parse_and_validate(&config_string.parse()?, &workdir)?;
+        assert_eq!(
+            conf.page_service_flush_timeout, None,
+            "flush timeout should be off by default"
+        );
+
+        let config_string = format!("{config_string}\npage_service_flush_timeout = '60 s'");
+        let conf = PageServerConf::parse_and_validate(&config_string.parse()?, &workdir)?;
+        assert_eq!(
+            conf.page_service_flush_timeout,
+            Some(Duration::from_secs(60))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_zero_virtual_file_sweep_rounds() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let (workdir, pg_distrib_dir) = prepare_fs(&tempdir)?;
+        let broker_endpoint = storage_broker::DEFAULT_ENDPOINT;
+
+        let config_string = format!(
+            r#"pg_distrib_dir='{pg_distrib_dir}'
+id=10
+broker_endpoint = '{broker_endpoint}'
+virtual_file_sweep_rounds = 0"#,
+        );
+        let error = PageServerConf::parse_and_validate(&config_string.parse()?, &workdir)
+            .expect_err("zero sweep rounds should be rejected");
+        assert!(
+            format!("{error:#}").contains("virtual_file_sweep_rounds must be greater than zero"),
+            "unexpected error: {error:#}"
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn parse_incorrect_tenant_config() -> anyhow::Result<()> {
         let config_string = r#"
@@ -1452,6 +2059,30 @@ trace_read_requests = {trace_read_requests}"#,
         Ok(())
     }
 
+    #[test]
+    fn page_service_tunables_reload() {
+        let conf = PageServerConf::dummy_conf(Utf8PathBuf::from("/nonexistent"));
+        let startup_conf = conf.clone();
+        let mut new_conf = conf.clone();
+        new_conf.page_service_slow_getpage_threshold = Duration::from_secs(7200);
+
+        assert_eq!(PageServiceTunables::reload(&conf, &startup_conf), None);
+
+        let reloaded = PageServiceTunables::reload(&conf, &new_conf).expect("changed");
+        assert_eq!(reloaded.slow_getpage_threshold, Duration::from_secs(7200));
+        assert_eq!(PageServiceTunables::current(&conf), reloaded);
+        assert_eq!(PageServiceTunables::reload(&conf, &new_conf), None);
+
+        // Other configurations are not affected
+        assert_eq!(
+            *PageServiceTunables::current(&startup_conf),
+            PageServiceTunables::from_conf(&startup_conf)
+        );
+
+        let restored = PageServiceTunables::reload(&conf, &startup_conf).expect("changed");
+        assert_eq!(*restored, PageServiceTunables::from_conf(&startup_conf));
+    }
+
     #[test]
     fn parse_override_tenant_config() -> anyhow::Result<()> {
         let config_string = r#"tenant_config={ min_resident_size_override =  400 }"#.to_string();
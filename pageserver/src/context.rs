@@ -86,6 +86,9 @@
 //! [`RequestContext`] argument. Functions in the middle of the call chain
 //! only need to pass it on.
 
+use tokio_util::sync::CancellationToken;
+use utils::id::RequestId;
+
 use crate::task_mgr::TaskKind;
 
 // The main structure of this module, see module-level comment.
@@ -95,6 +98,8 @@ pub struct RequestContext {
     download_behavior: DownloadBehavior,
     access_stats_behavior: AccessStatsBehavior,
     page_content_kind: PageContentKind,
+    request_id: Option<RequestId>,
+    cancel: Option<CancellationToken>,
 }
 
 /// The kind of access to the page cache.
@@ -150,6 +155,8 @@ impl RequestContextBuilder {
                 download_behavior: DownloadBehavior::Download,
                 access_stats_behavior: AccessStatsBehavior::Update,
                 page_content_kind: PageContentKind::Unknown,
+                request_id: None,
+                cancel: None,
             },
         }
     }
@@ -163,6 +170,8 @@ impl RequestContextBuilder {
                 download_behavior: original.download_behavior,
                 access_stats_behavior: original.access_stats_behavior,
                 page_content_kind: original.page_content_kind,
+                request_id: original.request_id,
+                cancel: original.cancel.clone(),
             },
         }
     }
@@ -186,6 +195,12 @@ impl RequestContextBuilder {
         self
     }
 
+    /// Make the context cancellable through `cancel`, see [`RequestContext::cancelled`].
+    pub fn cancel(mut self, cancel: CancellationToken) -> Self {
+        self.inner.cancel = Some(cancel);
+        self
+    }
+
     pub fn build(self) -> RequestContext {
         self.inner
     }
@@ -235,9 +250,9 @@ impl RequestContext {
     ///
     /// Use this when fanning-out work to other async tasks.
     ///
-    /// # Future: Cancellation
+    /// # Cancellation
     ///
-    /// Cancelling a context will propagate to its attached children.
+    /// Cancelling a context propagates to its attached children.
     ///
     /// # Future: Structured Concurrency
     ///
@@ -252,7 +267,20 @@ impl RequestContext {
     /// The method to wait for child tasks would return an error, indicating
     /// that the child task was not started because the context was canceled.
     pub fn attached_child(&self) -> Self {
-        self.child_impl(self.task_kind(), self.download_behavior())
+        let mut child = self.child_impl(self.task_kind(), self.download_behavior());
+        child.cancel = self.cancel.as_ref().map(CancellationToken::child_token);
+        child
+    }
+
+    /// Create an attached child of context `self` for serving a single request on
+    /// a long-lived connection, tagged with a freshly generated [`RequestId`].
+    ///
+    /// The request id can be put in tracing spans, so that everything that happens
+    /// on behalf of the request, e.g. on-demand downloads, can be attributed to it.
+    pub fn attached_request_child(&self) -> Self {
+        let mut child = self.attached_child();
+        child.request_id = Some(RequestId::generate());
+        child
     }
 
     /// Use this function when you should be creating a child context using
@@ -286,4 +314,20 @@ impl RequestContext {
     pub(crate) fn page_content_kind(&self) -> PageContentKind {
         self.page_content_kind
     }
+
+    pub fn request_id(&self) -> Option<RequestId> {
+        self.request_id
+    }
+
+    /// Completes when the context is cancelled. Contexts that were not made cancellable
+    /// with [`RequestContextBuilder::cancel`], or inherited that from the context they are
+    /// an attached child of, are never cancelled.
+    ///
+    /// So far, only the waits for an LSN of pagestream requests are cut short by this.
+    pub async fn cancelled(&self) {
+        match &self.cancel {
+            Some(cancel) => cancel.cancelled().await,
+            None => std::future::pending().await,
+        }
+    }
 }
@@ -119,6 +119,9 @@ impl EvictionOrder {
 pub struct State {
     /// Exclude http requests and background task from running at the same time.
     mutex: tokio::sync::Mutex<()>,
+
+    /// Wakes up the background task before its period has passed.
+    wakeup: tokio::sync::Notify,
 }
 
 pub fn launch_disk_usage_global_eviction_task(
@@ -134,6 +137,12 @@ pub fn launch_disk_usage_global_eviction_task(
 
     info!("launching disk usage based eviction task");
 
+    // Don't wait for the next period when a write has already failed because the disk is full.
+    {
+        let state = Arc::clone(&state);
+        crate::virtual_file::set_out_of_space_callback(move || state.wakeup.notify_one());
+    }
+
     task_mgr::spawn(
         BACKGROUND_RUNTIME.handle(),
         TaskKind::DiskUsageEviction,
@@ -208,11 +217,12 @@ async fn disk_usage_eviction_task(
         .await;
 
         let sleep_until = start + task_config.period;
-        if tokio::time::timeout_at(sleep_until, cancel.cancelled())
-            .await
-            .is_ok()
-        {
-            break;
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = tokio::time::sleep_until(sleep_until) => {}
+            _ = state.wakeup.notified() => {
+                info!("starting next iteration early because a write ran out of disk space");
+            }
         }
     }
 }
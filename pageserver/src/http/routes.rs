@@ -54,7 +54,7 @@ use crate::{config::PageServerConf, tenant::mgr};
 use crate::{disk_usage_eviction_task, tenant};
 use pageserver_api::models::{
     StatusResponse, TenantConfigRequest, TenantCreateRequest, TenantCreateResponse, TenantInfo,
-    TimelineCreateRequest, TimelineGcRequest, TimelineInfo,
+    TimelineCreateRequest, TimelineGcRequest, TimelineInfo, TimelineStateFilter,
 };
 use utils::{
     auth::SwappableJwtAuth,
@@ -526,13 +526,17 @@ async fn timeline_list_handler(
         parse_query_param(&request, "include-non-incremental-logical-size")?;
     let force_await_initial_logical_size: Option<bool> =
         parse_query_param(&request, "force-await-initial-logical-size")?;
+    let state_filter: Option<TimelineStateFilter> = parse_query_param(&request, "state")?;
     check_permission(&request, Some(tenant_shard_id.tenant_id))?;
 
     let ctx = RequestContext::new(TaskKind::MgmtRequest, DownloadBehavior::Download);
 
     let response_data = async {
         let tenant = mgr::get_tenant(tenant_shard_id, true)?;
-        let timelines = tenant.list_timelines();
+        let mut timelines = tenant.list_timelines();
+        if let Some(state_filter) = state_filter {
+            timelines.retain(|timeline| state_filter.matches(&timeline.current_state()));
+        }
 
         let mut response_data = Vec::with_capacity(timelines.len());
         for timeline in timelines {
@@ -813,6 +817,36 @@ async fn tenant_reset_handler(
     json_response(StatusCode::OK, ())
 }
 
+async fn tenant_pagestream_drain_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_shard_id: TenantShardId = parse_request_param(&request, "tenant_shard_id")?;
+    check_permission(&request, Some(tenant_shard_id.tenant_id))?;
+
+    let reason: Option<String> = parse_query_param(&request, "reason")?;
+
+    let tenant = mgr::get_tenant(tenant_shard_id, true)?;
+    tenant.set_pagestream_drain(Some(
+        reason.unwrap_or_else(|| "pageserver maintenance".to_owned()),
+    ));
+
+    json_response(StatusCode::OK, ())
+}
+
+async fn tenant_pagestream_undrain_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_shard_id: TenantShardId = parse_request_param(&request, "tenant_shard_id")?;
+    check_permission(&request, Some(tenant_shard_id.tenant_id))?;
+
+    let tenant = mgr::get_tenant(tenant_shard_id, true)?;
+    tenant.set_pagestream_drain(None);
+
+    json_response(StatusCode::OK, ())
+}
+
 async fn tenant_load_handler(
     mut request: Request<Body>,
     _cancel: CancellationToken,
@@ -1907,6 +1941,12 @@ pub fn make_router(
         .post("/v1/tenant/:tenant_shard_id/reset", |r| {
             api_handler(r, tenant_reset_handler)
         })
+        .put("/v1/tenant/:tenant_shard_id/pagestream_drain", |r| {
+            api_handler(r, tenant_pagestream_drain_handler)
+        })
+        .delete("/v1/tenant/:tenant_shard_id/pagestream_drain", |r| {
+            api_handler(r, tenant_pagestream_undrain_handler)
+        })
         .post("/v1/tenant/:tenant_id/load", |r| {
             api_handler(r, tenant_load_handler)
         })
@@ -34,7 +34,7 @@ use postgres_ffi::waldecoder::WalStreamDecoder;
 use postgres_ffi::ControlFileData;
 use postgres_ffi::DBState_DB_SHUTDOWNED;
 use postgres_ffi::Oid;
-use postgres_ffi::XLogFileName;
+use postgres_ffi::{IsXLogFileName, XLogFileName, XLogFromFileName, XLogSegNo};
 use postgres_ffi::{BLCKSZ, WAL_SEGMENT_SIZE};
 use utils::lsn::Lsn;
 
@@ -425,16 +425,24 @@ pub async fn import_wal_from_tar(
 
             match header.entry_type() {
                 tokio_tar::EntryType::Regular => {
-                    // FIXME: assume postgresql tli 1 for now
-                    let expected_filename = XLogFileName(1, segno, WAL_SEGMENT_SIZE);
                     let file_name = file_path
                         .file_name()
                         .expect("missing wal filename")
                         .to_string_lossy();
-                    ensure!(expected_filename == file_name);
+                    check_wal_segment_name(&file_name, segno, last_lsn)?;
 
                     debug!("processing wal file {:?}", file_path);
-                    read_all_bytes(&mut entry).await?
+                    let bytes = read_all_bytes(&mut entry).await?;
+                    if bytes.len() != WAL_SEGMENT_SIZE {
+                        let segment_start = Lsn(segno * WAL_SEGMENT_SIZE as u64);
+                        bail!(
+                            "WAL is not contiguous: segment {file_name} is {} bytes instead of {WAL_SEGMENT_SIZE}, WAL between {} and {} is missing",
+                            bytes.len(),
+                            segment_start + bytes.len() as u64,
+                            segment_start + WAL_SEGMENT_SIZE as u64,
+                        );
+                    }
+                    bytes
                 }
                 tokio_tar::EntryType::Directory => {
                     debug!("directory {:?}", file_path);
@@ -455,7 +463,10 @@ pub async fn import_wal_from_tar(
         let mut modification = tline.begin_modification(last_lsn);
         let mut decoded = DecodedWALRecord::default();
         while last_lsn <= end_lsn {
-            if let Some((lsn, recdata)) = waldecoder.poll_decode()? {
+            let decoded_record = waldecoder.poll_decode().with_context(|| {
+                format!("failed to decode WAL after {last_lsn}, the WAL may not be contiguous")
+            })?;
+            if let Some((lsn, recdata)) = decoded_record {
                 walingest
                     .ingest_record(recdata, lsn, &mut modification, &mut decoded, ctx)
                     .await?;
@@ -463,6 +474,9 @@ pub async fn import_wal_from_tar(
                 last_lsn = lsn;
 
                 debug!("imported record at {} (end {})", lsn, end_lsn);
+            } else {
+                // Need the next segment to decode further
+                break;
             }
         }
 
@@ -488,6 +502,34 @@ pub async fn import_wal_from_tar(
     Ok(())
 }
 
+/// Check that a WAL segment file in an import archive is the next one we expect, so that
+/// missing segments are reported as a gap in the WAL instead of being silently skipped.
+fn check_wal_segment_name(file_name: &str, expected_segno: XLogSegNo, last_lsn: Lsn) -> Result<()> {
+    // FIXME: assume postgresql tli 1 for now
+    let expected_filename = XLogFileName(1, expected_segno, WAL_SEGMENT_SIZE);
+    if file_name == expected_filename {
+        return Ok(());
+    }
+    ensure!(
+        IsXLogFileName(file_name),
+        "unexpected file {file_name} in WAL tar archive, expected WAL segment {expected_filename}"
+    );
+    let (segno, tli) = XLogFromFileName(file_name, WAL_SEGMENT_SIZE);
+    ensure!(
+        tli == 1,
+        "WAL segment {file_name} is on timeline {tli}, only timeline 1 is supported"
+    );
+    ensure!(
+        segno > expected_segno,
+        "WAL segment {file_name} is out of order, expected {expected_filename}"
+    );
+    bail!(
+        "WAL is not contiguous: expected segment {expected_filename} but got {file_name}, WAL between {} and {} is missing",
+        last_lsn,
+        Lsn(segno * WAL_SEGMENT_SIZE as u64),
+    );
+}
+
 async fn import_file(
     modification: &mut DatadirModification<'_>,
     file_path: &Path,
@@ -695,3 +737,172 @@ pub async fn extract_tar_zst(
     archive.unpack(pgdata_path).await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tenant::harness::{TenantHarness, TIMELINE_ID};
+    use crate::DEFAULT_PG_VERSION;
+    use postgres_ffi::v14::xlog_utils::SIZEOF_CHECKPOINT;
+    use std::sync::Arc;
+
+    /// Build a basebackup tar archive of the given files, in order.
+    async fn basebackup_tar(files: &[(&str, Bytes)]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        for (path, content) in files {
+            let mut header = tokio_tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o600);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, &content[..])
+                .await
+                .unwrap();
+        }
+        builder.into_inner().await.unwrap()
+    }
+
+    /// Build a WAL tar archive with zero-filled segments of the given numbers and sizes.
+    async fn wal_tar(segments: &[(XLogSegNo, usize)]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        for &(segno, len) in segments {
+            let mut header = tokio_tar::Header::new_gnu();
+            header.set_size(len as u64);
+            header.set_mode(0o600);
+            header.set_cksum();
+            let path = format!("pg_wal/{}", XLogFileName(1, segno, WAL_SEGMENT_SIZE));
+            builder
+                .append_data(&mut header, path, &vec![0u8; len][..])
+                .await
+                .unwrap();
+        }
+        builder.into_inner().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn import_wal_detects_gaps() -> Result<()> {
+        let (tenant, ctx) = TenantHarness::create("import_wal_detects_gaps")?
+            .load()
+            .await;
+        let tline = tenant
+            .create_test_timeline(TIMELINE_ID, Lsn(8), DEFAULT_PG_VERSION, &ctx)
+            .await?;
+        let start_lsn = Lsn(0x10);
+        let mut m = tline.begin_modification(start_lsn);
+        m.put_checkpoint(Bytes::from_static(&[0u8; SIZEOF_CHECKPOINT]))?;
+        m.commit(&ctx).await?;
+        let end_lsn = Lsn(3 * WAL_SEGMENT_SIZE as u64);
+
+        // The first segment is missing
+        let tar = wal_tar(&[(1, WAL_SEGMENT_SIZE)]).await;
+        let err = import_wal_from_tar(&tline, &mut &tar[..], start_lsn, end_lsn, &ctx)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "WAL is not contiguous: expected segment 000000010000000000000000 but got 000000010000000000000001, WAL between 0/10 and 0/1000000 is missing"
+        );
+
+        // The first segment is truncated
+        let tar = wal_tar(&[(0, 8192)]).await;
+        let err = import_wal_from_tar(&tline, &mut &tar[..], start_lsn, end_lsn, &ctx)
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{err:#}").contains("WAL between 0/2000 and 0/1000000 is missing"),
+            "{err:#}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn import_basebackup_into_empty_timeline() -> Result<()> {
+        let (tenant, ctx) = TenantHarness::create("import_basebackup_into_empty_timeline")?
+            .load()
+            .await;
+        let base_lsn = Lsn(0x20);
+        let uninit = tenant
+            .create_empty_timeline(TIMELINE_ID, base_lsn, DEFAULT_PG_VERSION, &ctx)
+            .await?;
+        let tline = Arc::clone(uninit.raw_timeline()?);
+
+        let relmap = Bytes::from(vec![0u8; postgres_ffi::v14::bindings::SIZEOF_RELMAPFILE]);
+        let page = Bytes::from(vec![1u8; BLCKSZ as usize]);
+        let files = [
+            ("global/pg_control", ControlFileData::default().encode()),
+            ("global/pg_filenode.map", relmap.clone()),
+            ("base/16384/pg_filenode.map", relmap),
+            ("base/16384/1000", page.clone()),
+        ];
+
+        // The timeline was created for a different LSN
+        let err = tline
+            .prepare_basebackup_import(Lsn(0x30), DEFAULT_PG_VERSION)
+            .await
+            .err()
+            .expect("import at a different LSN should be refused");
+        assert!(
+            err.to_string().contains("does not start from the same lsn"),
+            "{err:#}"
+        );
+
+        // An import that fails before any data reaches the timeline leaves it empty, so that
+        // the import can be retried.
+        let tar = basebackup_tar(&files[1..]).await;
+        let err = tline
+            .prepare_basebackup_import(base_lsn, DEFAULT_PG_VERSION)
+            .await?
+            .import_from_tar(&mut &tar[..], &ctx)
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{err:#}").contains("pg_control file not found"),
+            "{err:#}"
+        );
+        assert!(tline.is_empty().await);
+        assert!(!tline.is_broken());
+
+        // Only one import at a time
+        let tar = basebackup_tar(&files).await;
+        let import = tline
+            .prepare_basebackup_import(base_lsn, DEFAULT_PG_VERSION)
+            .await?;
+        let err = tline
+            .prepare_basebackup_import(base_lsn, DEFAULT_PG_VERSION)
+            .await
+            .err()
+            .expect("concurrent import should be refused");
+        assert!(
+            err.to_string().contains("another import is in progress"),
+            "{err:#}"
+        );
+        import.import_from_tar(&mut &tar[..], &ctx).await?;
+
+        let rel = RelTag {
+            spcnode: pg_constants::DEFAULTTABLESPACE_OID,
+            dbnode: 16384,
+            relnode: 1000,
+            forknum: MAIN_FORKNUM,
+        };
+        assert_eq!(
+            tline
+                .get_rel_page_at_lsn(rel, 0, Version::Lsn(base_lsn), false, &ctx)
+                .await?,
+            page
+        );
+
+        // The timeline is not empty anymore
+        let err = tline
+            .prepare_basebackup_import(base_lsn, DEFAULT_PG_VERSION)
+            .await
+            .err()
+            .expect("import into a non-empty timeline should be refused");
+        assert!(err.to_string().contains("is not empty"), "{err:#}");
+
+        // The imported data made the timeline valid
+        uninit.finish_creation()?;
+
+        Ok(())
+    }
+}
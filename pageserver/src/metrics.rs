@@ -1,11 +1,11 @@
 use enum_map::EnumMap;
 use metrics::metric_vec_duration::DurationResultObserver;
 use metrics::{
-    register_counter_vec, register_gauge_vec, register_histogram, register_histogram_vec,
-    register_int_counter, register_int_counter_pair_vec, register_int_counter_vec,
-    register_int_gauge, register_int_gauge_vec, register_uint_gauge, register_uint_gauge_vec,
-    Counter, CounterVec, GaugeVec, Histogram, HistogramVec, IntCounter, IntCounterPairVec,
-    IntCounterVec, IntGauge, IntGaugeVec, UIntGauge, UIntGaugeVec,
+    register_counter, register_counter_vec, register_gauge_vec, register_histogram,
+    register_histogram_vec, register_int_counter, register_int_counter_pair_vec,
+    register_int_counter_vec, register_int_gauge, register_int_gauge_vec, register_uint_gauge,
+    register_uint_gauge_vec, Counter, CounterVec, GaugeVec, Histogram, HistogramVec, IntCounter,
+    IntCounterPairVec, IntCounterVec, IntGauge, IntGaugeVec, UIntGauge, UIntGaugeVec,
 };
 use once_cell::sync::Lazy;
 use pageserver_api::shard::TenantShardId;
@@ -880,6 +880,8 @@ pub(crate) enum StorageIoOperation {
     Seek,
     Fsync,
     Metadata,
+    Fadvise,
+    Fallocate,
 }
 
 impl StorageIoOperation {
@@ -894,6 +896,8 @@ impl StorageIoOperation {
             StorageIoOperation::Seek => "seek",
             StorageIoOperation::Fsync => "fsync",
             StorageIoOperation::Metadata => "metadata",
+            StorageIoOperation::Fadvise => "fadvise",
+            StorageIoOperation::Fallocate => "fallocate",
         }
     }
 }
@@ -952,12 +956,21 @@ pub(crate) mod virtual_file_descriptor_cache {
         .unwrap()
     });
 
-    // SIZE_CURRENT: derive it like so:
-    // ```
-    // sum (pageserver_io_operations_seconds_count{operation=~"^(open|open-after-replace)$")
-    // -ignoring(operation)
-    // sum(pageserver_io_operations_seconds_count{operation=~"^(close|close-by-replace)$"}
-    // ```
+    pub(crate) static SIZE_CURRENT: Lazy<UIntGauge> = Lazy::new(|| {
+        register_uint_gauge!(
+            "pageserver_virtual_file_descriptor_cache_size_current",
+            "Number of file descriptors currently open in the cache."
+        )
+        .unwrap()
+    });
+
+    pub(crate) static SWEEP_EXHAUSTED: Lazy<IntCounter> = Lazy::new(|| {
+        register_int_counter!(
+            "pageserver_virtual_file_descriptor_cache_sweep_exhausted",
+            "Number of times the search for a file descriptor to evict found no idle slot, and waited for a busy one."
+        )
+        .expect("failed to define a metric")
+    });
 }
 
 #[derive(Debug)]
@@ -976,12 +989,20 @@ impl GlobalAndPerTimelineHistogram {
 struct GlobalAndPerTimelineHistogramTimer<'a> {
     h: &'a GlobalAndPerTimelineHistogram,
     start: std::time::Instant,
+    /// The SLO breach counter to increment if the timed operation takes longer than the
+    /// threshold.
+    slo: Option<(&'a IntCounter, Duration)>,
 }
 
 impl<'a> Drop for GlobalAndPerTimelineHistogramTimer<'a> {
     fn drop(&mut self) {
         let elapsed = self.start.elapsed();
         self.h.observe(elapsed.as_secs_f64());
+        if let Some((breaches, threshold)) = self.slo {
+            if elapsed > threshold {
+                breaches.inc();
+            }
+        }
     }
 }
 
@@ -1000,13 +1021,35 @@ pub enum SmgrQueryType {
     GetRelSize,
     GetPageAtLsn,
     GetDbSize,
+    GetPageLastModified,
+    // The part of the above that is spent waiting for the requested LSN to arrive.
+    GetRelExistsWaitLsn,
+    GetRelSizeWaitLsn,
+    GetPageAtLsnWaitLsn,
+    GetDbSizeWaitLsn,
+    GetPageLastModifiedWaitLsn,
+    /// The part of [`SmgrQueryType::GetPageAtLsn`] that is spent reconstructing the page,
+    /// after the requested LSN has arrived.
+    GetPageAtLsnReconstruct,
 }
 
 #[derive(Debug)]
 pub(crate) struct SmgrQueryTimePerTimeline {
     metrics: [GlobalAndPerTimelineHistogram; SmgrQueryType::COUNT],
+    slo_breaches: [IntCounter; SmgrQueryType::COUNT],
+    slo_threshold: Duration,
 }
 
+static SMGR_SLO_BREACHES_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_smgr_slo_breaches_total",
+        "Number of smgr queries that took longer than page_service_slo_threshold, \
+         by query type and tenant/timeline.",
+        &["smgr_query_type", "tenant_id", "timeline_id"]
+    )
+    .expect("failed to define a metric")
+});
+
 static SMGR_QUERY_TIME_PER_TENANT_TIMELINE: Lazy<HistogramVec> = Lazy::new(|| {
     register_histogram_vec!(
         "pageserver_smgr_query_seconds",
@@ -1078,7 +1121,11 @@ static SMGR_QUERY_TIME_GLOBAL: Lazy<HistogramVec> = Lazy::new(|| {
 });
 
 impl SmgrQueryTimePerTimeline {
-    pub(crate) fn new(tenant_id: &TenantId, timeline_id: &TimelineId) -> Self {
+    pub(crate) fn new(
+        tenant_id: &TenantId,
+        timeline_id: &TimelineId,
+        slo_threshold: Duration,
+    ) -> Self {
         let tenant_id = tenant_id.to_string();
         let timeline_id = timeline_id.to_string();
         let metrics = std::array::from_fn(|i| {
@@ -1094,13 +1141,34 @@ impl SmgrQueryTimePerTimeline {
                 per_tenant_timeline,
             }
         });
-        Self { metrics }
+        let slo_breaches = std::array::from_fn(|i| {
+            let op = SmgrQueryType::from_repr(i).unwrap();
+            SMGR_SLO_BREACHES_TOTAL
+                .get_metric_with_label_values(&[op.into(), &tenant_id, &timeline_id])
+                .unwrap()
+        });
+        Self {
+            metrics,
+            slo_breaches,
+            slo_threshold,
+        }
     }
     pub(crate) fn start_timer(&self, op: SmgrQueryType) -> impl Drop + '_ {
         let metric = &self.metrics[op as usize];
         GlobalAndPerTimelineHistogramTimer {
             h: metric,
             start: std::time::Instant::now(),
+            slo: None,
+        }
+    }
+    /// Like [`Self::start_timer`], but for a whole request: if it takes longer than the SLO
+    /// threshold, that also counts as an SLO breach.
+    pub(crate) fn start_request_timer(&self, op: SmgrQueryType) -> impl Drop + '_ {
+        let metric = &self.metrics[op as usize];
+        GlobalAndPerTimelineHistogramTimer {
+            h: metric,
+            start: std::time::Instant::now(),
+            slo: Some((&self.slo_breaches[op as usize], self.slo_threshold)),
         }
     }
 }
@@ -1114,11 +1182,21 @@ mod smgr_query_time_tests {
     #[test]
     fn op_label_name() {
         use super::SmgrQueryType::*;
-        let expect: [(super::SmgrQueryType, &'static str); 4] = [
+        let expect: [(super::SmgrQueryType, &'static str); 11] = [
             (GetRelExists, "get_rel_exists"),
             (GetRelSize, "get_rel_size"),
             (GetPageAtLsn, "get_page_at_lsn"),
             (GetDbSize, "get_db_size"),
+            (GetPageLastModified, "get_page_last_modified"),
+            (GetRelExistsWaitLsn, "get_rel_exists_wait_lsn"),
+            (GetRelSizeWaitLsn, "get_rel_size_wait_lsn"),
+            (GetPageAtLsnWaitLsn, "get_page_at_lsn_wait_lsn"),
+            (GetDbSizeWaitLsn, "get_db_size_wait_lsn"),
+            (
+                GetPageLastModifiedWaitLsn,
+                "get_page_last_modified_wait_lsn",
+            ),
+            (GetPageAtLsnReconstruct, "get_page_at_lsn_reconstruct"),
         ];
         for (op, expect) in expect {
             let actual: &'static str = op.into();
@@ -1133,7 +1211,11 @@ mod smgr_query_time_tests {
         for op in &ops {
             let tenant_id = TenantId::generate();
             let timeline_id = TimelineId::generate();
-            let metrics = super::SmgrQueryTimePerTimeline::new(&tenant_id, &timeline_id);
+            let metrics = super::SmgrQueryTimePerTimeline::new(
+                &tenant_id,
+                &timeline_id,
+                std::time::Duration::from_secs(3600),
+            );
 
             let get_counts = || {
                 let global: u64 = ops
@@ -1162,6 +1244,36 @@ mod smgr_query_time_tests {
             assert!(post_global > pre_global);
         }
     }
+
+    #[test]
+    fn slo_breaches() {
+        let tenant_id = TenantId::generate();
+        let timeline_id = TimelineId::generate();
+        let op = super::SmgrQueryType::GetPageAtLsn;
+
+        let metrics = super::SmgrQueryTimePerTimeline::new(
+            &tenant_id,
+            &timeline_id,
+            std::time::Duration::from_millis(50),
+        );
+        let breaches = || metrics.slo_breaches[op as usize].get();
+
+        // Fast enough
+        drop(metrics.start_request_timer(op));
+        assert_eq!(breaches(), 0);
+
+        // Too slow
+        let timer = metrics.start_request_timer(op);
+        std::thread::sleep(std::time::Duration::from_millis(60));
+        drop(timer);
+        assert_eq!(breaches(), 1);
+
+        // Parts of a request don't count on their own
+        let timer = metrics.start_timer(op);
+        std::thread::sleep(std::time::Duration::from_millis(60));
+        drop(timer);
+        assert_eq!(breaches(), 1);
+    }
 }
 
 // keep in sync with control plane Go code so that we can validate
@@ -1188,6 +1300,31 @@ pub(crate) static BASEBACKUP_QUERY_TIME: Lazy<BasebackupQueryTime> = Lazy::new(|
     })
 });
 
+pub(crate) static BASEBACKUP_QUEUED: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "pageserver_basebackup_queued",
+        "Number of basebackup queries waiting for their turn to run, see concurrent_basebackups",
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static BASEBACKUP_THROTTLED_SECONDS: Lazy<Counter> = Lazy::new(|| {
+    register_counter!(
+        "pageserver_basebackup_throttled_seconds_total",
+        "Total time that sending basebackups was paused by basebackup_bandwidth_limit",
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static IMPORT_COPYIN_ABORTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_import_copyin_aborted_total",
+        "Number of basebackup and WAL imports whose COPY IN stream was cut short, by cause",
+        &["cause"]
+    )
+    .expect("failed to define a metric")
+});
+
 impl DurationResultObserver for BasebackupQueryTime {
     fn observe_result<T, E>(&self, res: &Result<T, E>, duration: std::time::Duration) {
         let label_value = if res.is_ok() { "ok" } else { "error" };
@@ -1205,6 +1342,40 @@ pub(crate) static LIVE_CONNECTIONS_COUNT: Lazy<IntGaugeVec> = Lazy::new(|| {
     .expect("failed to define a metric")
 });
 
+pub(crate) static PAGESTREAM_CONNECTIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "pageserver_pagestream_connections",
+        "Number of active pagestream connections, by tenant and timeline",
+        &["tenant_id", "timeline_id"]
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static PAGE_SERVICE_ACCEPT_QUEUE_DEPTH: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "pageserver_page_service_accept_queue_depth",
+        "Number of libpq connections waiting to be accepted, as of the last accept",
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static PAGE_SERVICE_FD_EXHAUSTION_TOTAL: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_page_service_fd_exhaustion_total",
+        "Number of times the libpq listener could not accept a connection because the process \
+         or the system ran out of file descriptors",
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static PAGE_SERVICE_THROTTLED_SECONDS: Lazy<Counter> = Lazy::new(|| {
+    register_counter!(
+        "pageserver_page_service_throttled_seconds_total",
+        "Total time that page_service requests were delayed by the per-tenant rate limit",
+    )
+    .expect("failed to define a metric")
+});
+
 // remote storage metrics
 
 /// NB: increment _after_ recording the current value into [`REMOTE_TIMELINE_CLIENT_CALLS_STARTED_HIST`].
@@ -1879,7 +2050,11 @@ impl Drop for TimelineMetrics {
                 tenant_id,
                 timeline_id,
             ]);
+            let _ =
+                SMGR_SLO_BREACHES_TOTAL.remove_label_values(&[op.into(), tenant_id, timeline_id]);
         }
+
+        let _ = PAGESTREAM_CONNECTIONS.remove_label_values(&[tenant_id, timeline_id]);
     }
 }
 
@@ -2304,6 +2479,7 @@ pub fn preinitialize_metrics() {
         &WALRECEIVER_BROKER_UPDATES,
         &WALRECEIVER_CANDIDATES_ADDED,
         &WALRECEIVER_CANDIDATES_REMOVED,
+        &PAGE_SERVICE_FD_EXHAUSTION_TOTAL,
     ]
     .into_iter()
     .for_each(|c| {
@@ -6,32 +6,47 @@
 // commands are supported now:
 //     *status* -- show actual info about this pageserver,
 //     *pagestream* -- enter mode where smgr and pageserver talk with their
-//  custom protocol.
+//  custom protocol. With `--pipeline=N`, up to N requests are processed
+//  concurrently.
 //
 
+use ::metrics::launch_timestamp::LaunchTimestamp;
 use anyhow::Context;
 use async_compression::tokio::write::GzipEncoder;
 use bytes::Buf;
 use bytes::Bytes;
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+use futures::stream::FuturesOrdered;
+use futures::FutureExt;
 use futures::Stream;
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use pageserver_api::key::Key;
 use pageserver_api::models::TenantState;
 use pageserver_api::models::{
     PagestreamBeMessage, PagestreamDbSizeRequest, PagestreamDbSizeResponse,
     PagestreamErrorResponse, PagestreamExistsRequest, PagestreamExistsResponse,
-    PagestreamFeMessage, PagestreamGetPageRequest, PagestreamGetPageResponse,
-    PagestreamNblocksRequest, PagestreamNblocksResponse,
+    PagestreamFeMessage, PagestreamGetPageLastModifiedRequest,
+    PagestreamGetPageLastModifiedResponse, PagestreamGetPageRequest, PagestreamGetPageResponse,
+    PagestreamGoAwayResponse, PagestreamHandshakeRequest, PagestreamHandshakeResponse,
+    PagestreamNblocksRequest, PagestreamNblocksResponse, PagestreamProtocolVersion,
 };
+use pageserver_api::reltag::{BlockNumber, RelTag};
+use pageserver_api::shard::{ShardNumber, TenantShardId};
 use postgres_backend::{self, is_expected_io_error, AuthType, PostgresBackend, QueryError};
 use pq_proto::framed::ConnectionError;
 use pq_proto::FeStartupPacket;
-use pq_proto::{BeMessage, FeMessage, RowDescriptor};
+use pq_proto::{BeMessage, CancelKeyData, FeMessage, RowDescriptor};
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::io;
 use std::net::TcpListener;
+use std::ops::Range;
 use std::pin::pin;
 use std::str;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize};
+use std::sync::{Arc, Weak};
 use std::time::Duration;
 use tokio::io::AsyncWriteExt;
 use tokio::io::{AsyncRead, AsyncWrite};
@@ -42,38 +57,54 @@ use tracing::*;
 use utils::id::ConnectionId;
 use utils::{
     auth::{Claims, Scope, SwappableJwtAuth},
+    backoff,
+    bin_ser::BeSer,
     id::{TenantId, TimelineId},
     lsn::Lsn,
     simple_rcu::RcuReadGuard,
+    sync::gate::GateGuard,
+    tcp_listener,
 };
 
-use crate::auth::check_permission;
+use crate::auth::{check_permission, check_timeline_permission};
 use crate::basebackup;
-use crate::config::PageServerConf;
-use crate::context::{DownloadBehavior, RequestContext};
+use crate::config::{PageServerConf, PageServiceTunables};
+use crate::context::{DownloadBehavior, RequestContext, RequestContextBuilder};
 use crate::import_datadir::import_wal_from_tar;
 use crate::metrics;
 use crate::metrics::LIVE_CONNECTIONS_COUNT;
-use crate::pgdatadir_mapping::{rel_block_to_key, Version};
+use crate::pgdatadir_mapping::{rel_block_to_key, LsnForTimestamp, Version};
 use crate::task_mgr;
 use crate::task_mgr::TaskKind;
 use crate::tenant::debug_assert_current_span_has_tenant_and_timeline_id;
 use crate::tenant::mgr;
 use crate::tenant::mgr::get_active_tenant_with_timeout;
 use crate::tenant::mgr::GetActiveTenantError;
+use crate::tenant::mgr::GetTenantError;
 use crate::tenant::mgr::ShardSelector;
-use crate::tenant::timeline::WaitLsnError;
+use crate::tenant::pausable_failpoint;
+use crate::tenant::secondary;
+use crate::tenant::timeline::uninit::UninitializedTimeline;
+use crate::tenant::timeline::{BasebackupImport, WaitLsnError};
 use crate::tenant::GetTimelineError;
 use crate::tenant::PageReconstructError;
+use crate::tenant::Tenant;
 use crate::tenant::Timeline;
 use crate::trace::Tracer;
+use crate::walrecord::NeonWalRecord;
 
-use postgres_ffi::pg_constants::DEFAULTTABLESPACE_OID;
 use postgres_ffi::BLCKSZ;
 
-// How long we may wait for a [`TenantSlot::InProgress`]` and/or a [`Tenant`] which
-// is not yet in state [`TenantState::Active`].
-const ACTIVE_TENANT_TIMEOUT: Duration = Duration::from_millis(30000);
+/// Upper bound for the number of requests that a pagestream client may have in flight, see
+/// the `--pipeline` option of the `pagestream` command.
+const MAX_PAGESTREAM_PIPELINE_DEPTH: usize = 32;
+
+/// With `--progress`, the import commands send a notice each time this many more bytes of the
+/// COPY IN stream have been imported.
+const IMPORT_PROGRESS_INTERVAL_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Upper bound for the number of rows that the `debug_page_history` command returns.
+const DEBUG_PAGE_HISTORY_MAX_ROWS: usize = 1000;
 
 /// Read the end of a tar archive.
 ///
@@ -125,20 +156,27 @@ async fn read_tar_eof(mut reader: (impl AsyncRead + Unpin)) -> anyhow::Result<()
 ///
 /// Listens for connections, and launches a new handler task for each.
 ///
+#[allow(clippy::too_many_arguments)]
 pub async fn libpq_listener_main(
     conf: &'static PageServerConf,
+    launch_ts: &'static LaunchTimestamp,
     broker_client: storage_broker::BrokerClientChannel,
     auth: Option<Arc<SwappableJwtAuth>>,
     listener: TcpListener,
     auth_type: AuthType,
+    tls_config: Option<Arc<rustls::ServerConfig>>,
     listener_ctx: RequestContext,
     cancel: CancellationToken,
 ) -> anyhow::Result<()> {
     listener.set_nonblocking(true)?;
     let tokio_listener = tokio::net::TcpListener::from_std(listener)?;
 
+    // Number of accept() calls in a row that failed, to back off if the failure persists, e.g.
+    // when we are out of file descriptors.
+    let mut consecutive_accept_errors: u32 = 0;
+
     // Wait for a new connection to arrive, or for server shutdown.
-    while let Some(res) = tokio::select! {
+    while let Some(mut res) = tokio::select! {
         biased;
 
         _ = cancel.cancelled() => {
@@ -150,41 +188,93 @@ pub async fn libpq_listener_main(
             Some(res)
         }
     } {
-        match res {
-            Ok((socket, peer_addr)) => {
-                // Connection established. Spawn a new task to handle it.
-                debug!("accepted connection from {}", peer_addr);
-                let local_auth = auth.clone();
-
-                let connection_ctx = listener_ctx
-                    .detached_child(TaskKind::PageRequestHandler, DownloadBehavior::Download);
-
-                // PageRequestHandler tasks are not associated with any particular
-                // timeline in the task manager. In practice most connections will
-                // only deal with a particular timeline, but we don't know which one
-                // yet.
-                task_mgr::spawn(
-                    &tokio::runtime::Handle::current(),
-                    TaskKind::PageRequestHandler,
-                    None,
-                    None,
-                    "serving compute connection task",
-                    false,
-                    page_service_conn_main(
-                        conf,
-                        broker_client.clone(),
-                        local_auth,
-                        socket,
-                        auth_type,
-                        connection_ctx,
-                    ),
-                );
+        // Accept the connections that are already waiting in the backlog, up to the batch size,
+        // without going through the select above for each of them.
+        let mut accepted = 0;
+        loop {
+            match res {
+                Ok((socket, peer_addr)) => {
+                    consecutive_accept_errors = 0;
+
+                    // Connection established. Spawn a new task to handle it.
+                    debug!("accepted connection from {}", peer_addr);
+                    let local_auth = auth.clone();
+
+                    let connection_ctx = listener_ctx
+                        .detached_child(TaskKind::PageRequestHandler, DownloadBehavior::Download);
+
+                    // PageRequestHandler tasks are not associated with any particular
+                    // timeline in the task manager. In practice most connections will
+                    // only deal with a particular timeline, but we don't know which one
+                    // yet.
+                    task_mgr::spawn(
+                        &tokio::runtime::Handle::current(),
+                        TaskKind::PageRequestHandler,
+                        None,
+                        None,
+                        "serving compute connection task",
+                        false,
+                        page_service_conn_main(
+                            conf,
+                            launch_ts,
+                            broker_client.clone(),
+                            local_auth,
+                            socket,
+                            auth_type,
+                            tls_config.clone(),
+                            connection_ctx,
+                        ),
+                    );
+                }
+                Err(err) => {
+                    // accept() failed. Log the error, and loop back to retry on next connection.
+                    consecutive_accept_errors += 1;
+                    let (backoff_attempt, max_backoff_seconds) = if is_fd_exhaustion(&err) {
+                        // Retrying is pointless until some file descriptors are closed, e.g. by
+                        // connections ending or the VirtualFile cache evicting open files.
+                        metrics::PAGE_SERVICE_FD_EXHAUSTION_TOTAL.inc();
+                        warn!(
+                            consecutive_accept_errors,
+                            "accept() failed, out of file descriptors: {err}"
+                        );
+                        (
+                            consecutive_accept_errors,
+                            ACCEPT_FD_EXHAUSTION_MAX_BACKOFF_SECONDS,
+                        )
+                    } else {
+                        // Retry the first failure right away, but back off if it keeps failing,
+                        // so that a persistent error doesn't make us spin.
+                        error!(consecutive_accept_errors, "accept() failed: {:?}", err);
+                        (
+                            consecutive_accept_errors - 1,
+                            backoff::DEFAULT_MAX_BACKOFF_SECONDS,
+                        )
+                    };
+                    backoff::exponential_backoff(
+                        backoff_attempt,
+                        backoff::DEFAULT_BASE_BACKOFF_SECONDS,
+                        max_backoff_seconds,
+                        &cancel,
+                    )
+                    .await;
+                    break;
+                }
             }
-            Err(err) => {
-                // accept() failed. Log the error, and loop back to retry on next connection.
-                error!("accept() failed: {:?}", err);
+
+            accepted += 1;
+            if accepted >= conf.page_service_accept_batch_size.get() || cancel.is_cancelled() {
+                break;
+            }
+            // Polling accept() once only returns a connection if one is already waiting.
+            match tokio_listener.accept().now_or_never() {
+                Some(next) => res = next,
+                None => break,
             }
         }
+
+        if let Ok(depth) = tcp_listener::accept_queue_len(&tokio_listener) {
+            metrics::PAGE_SERVICE_ACCEPT_QUEUE_DEPTH.set(depth as i64);
+        }
     }
 
     debug!("page_service loop terminated");
@@ -192,13 +282,230 @@ pub async fn libpq_listener_main(
     Ok(())
 }
 
+/// Wait until the timeline asks its pagestream connections to end, see
+/// [`Timeline::set_pagestream_drain`], and return the reason.
+async fn pagestream_drain_requested(
+    drain: &mut tokio::sync::watch::Receiver<Option<String>>,
+) -> String {
+    loop {
+        if let Some(reason) = drain.borrow_and_update().clone() {
+            return reason;
+        }
+        if drain.changed().await.is_err() {
+            // The timeline is gone, and there is nothing left to drain.
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Upper bound of the delay between accept() attempts while out of file descriptors.
+const ACCEPT_FD_EXHAUSTION_MAX_BACKOFF_SECONDS: f64 = 10.0;
+
+/// Did the accept() fail because the process (EMFILE) or the system (ENFILE) ran out of file
+/// descriptors?
+fn is_fd_exhaustion(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(errno) if errno == nix::errno::Errno::EMFILE as i32
+            || errno == nix::errno::Errno::ENFILE as i32
+    )
+}
+
+/// A per-connection setting that can be changed with the `set` command, or given as a startup
+/// option.
+#[derive(Debug, PartialEq, Eq)]
+enum ConnectionSetting {
+    /// `pagestream_effective_lsn`: include the LSN at which a page was read in GetPage
+    /// responses.
+    PagestreamEffectiveLsn(bool),
+    /// `pagestream_correlation_ids`: follow each response to a pagestream request with the
+    /// number of the request on this connection, see
+    /// [`PagestreamBeMessage::serialize_with_correlation_id`].
+    PagestreamCorrelationIds(bool),
+}
+
+impl ConnectionSetting {
+    /// Parse the value of setting `name`. Settings that we don't know are `None`, for
+    /// compatibility with clients that set Postgres settings on connect.
+    fn parse(name: &str, value: &str) -> anyhow::Result<Option<ConnectionSetting>> {
+        let setting = match name.to_ascii_lowercase().as_str() {
+            "pagestream_effective_lsn" => ConnectionSetting::PagestreamEffectiveLsn(
+                value
+                    .parse()
+                    .with_context(|| format!("Failed to parse {value} as {name}"))?,
+            ),
+            "pagestream_correlation_ids" => ConnectionSetting::PagestreamCorrelationIds(
+                value
+                    .parse()
+                    .with_context(|| format!("Failed to parse {value} as {name}"))?,
+            ),
+            _ => return Ok(None),
+        };
+        Ok(Some(setting))
+    }
+
+    /// Parse a `SET [SESSION] name {TO | =} value` command. Commands with a different syntax,
+    /// like `SET TIME ZONE`, are `None` like unknown settings.
+    fn parse_set_command(query: &str) -> anyhow::Result<Option<ConnectionSetting>> {
+        let mut words = query
+            .trim()
+            .trim_end_matches(';')
+            .split_whitespace()
+            .peekable();
+        if !words
+            .next()
+            .is_some_and(|word| word.eq_ignore_ascii_case("set"))
+        {
+            return Ok(None);
+        }
+        words.next_if(|word| word.eq_ignore_ascii_case("session"));
+        let Some(name_and_rest) = words.next() else {
+            return Ok(None);
+        };
+        // `name=value` and `name= value` don't need spaces around the `=`
+        let (name, value) = match name_and_rest.split_once('=') {
+            Some((name, "")) => (name, words.next()),
+            Some((name, value)) => (name, Some(value)),
+            None => match words.next() {
+                Some(to) if to.eq_ignore_ascii_case("to") || to == "=" => {
+                    (name_and_rest, words.next())
+                }
+                Some(to) => match to.strip_prefix('=') {
+                    Some(value) if !value.is_empty() => (name_and_rest, Some(value)),
+                    _ => return Ok(None),
+                },
+                None => return Ok(None),
+            },
+        };
+        let Some(value) = value else {
+            return Ok(None);
+        };
+        if words.next().is_some() {
+            return Ok(None);
+        }
+        let value = value.trim_matches('\'');
+        Self::parse(name, value)
+    }
+}
+
+/// Parse the value of the `--rel-filter` option of the `basebackup` command: a comma-separated
+/// list of database OIDs.
+fn parse_rel_filter(value: &str) -> anyhow::Result<Vec<u32>> {
+    value
+        .split(',')
+        .map(|oid| match oid.parse::<u32>() {
+            Ok(oid) if oid != 0 => Ok(oid),
+            _ => Err(anyhow::anyhow!(
+                "Invalid database OID '{oid}' in --rel-filter"
+            )),
+        })
+        .collect()
+}
+
+/// Resolve the destination of the `basebackup_to_file` command.
+///
+/// The client only names the file: it always goes into `basebackups_dir`, so that the command
+/// can't be used to write anywhere else on the node.
+fn basebackup_file_path(
+    basebackups_dir: &Utf8Path,
+    file_name: &str,
+) -> anyhow::Result<Utf8PathBuf> {
+    let mut components = Utf8Path::new(file_name).components();
+    match (components.next(), components.next()) {
+        (Some(Utf8Component::Normal(file_name)), None) => Ok(basebackups_dir.join(file_name)),
+        _ => anyhow::bail!("'{file_name}' is not a plain file name"),
+    }
+}
+
+/// A pagestream connection, counted against the connection limit of a tenant, see
+/// [`PageServerConf::page_service_tenant_max_connections`]. The connection stops counting when
+/// this is dropped.
+struct PagestreamConnectionPermit<'a> {
+    connections: &'a AtomicUsize,
+}
+
+impl<'a> PagestreamConnectionPermit<'a> {
+    /// Count a new connection in `connections`, unless there are `max_connections` already.
+    /// 0 means unlimited.
+    fn acquire(connections: &'a AtomicUsize, max_connections: usize) -> Option<Self> {
+        let permit = PagestreamConnectionPermit { connections };
+        let previous = connections.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        (max_connections == 0 || previous < max_connections).then_some(permit)
+    }
+}
+
+impl Drop for PagestreamConnectionPermit<'_> {
+    fn drop(&mut self) {
+        self.connections
+            .fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+/// Run a pagestream request handler, failing it with [`PageStreamError::DeadlineExceeded`] if
+/// it doesn't complete by the deadline that the client set on the request.
+async fn with_deadline<F>(
+    deadline: Option<tokio::time::Instant>,
+    handler: F,
+) -> Result<PagestreamBeMessage, PageStreamError>
+where
+    F: std::future::Future<Output = Result<PagestreamBeMessage, PageStreamError>>,
+{
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, handler)
+            .await
+            .unwrap_or(Err(PageStreamError::DeadlineExceeded)),
+        None => handler.await,
+    }
+}
+
+/// Short name of a WAL record variant, for the output of the `debug_page_history` command.
+fn wal_record_kind(rec: &NeonWalRecord) -> &'static str {
+    match rec {
+        NeonWalRecord::Postgres {
+            will_init: true, ..
+        } => "postgres_init",
+        NeonWalRecord::Postgres {
+            will_init: false, ..
+        } => "postgres",
+        NeonWalRecord::ClearVisibilityMapFlags { .. } => "clear_visibility_map_flags",
+        NeonWalRecord::ClogSetCommitted { .. } => "clog_set_committed",
+        NeonWalRecord::ClogSetAborted { .. } => "clog_set_aborted",
+        NeonWalRecord::MultixactOffsetCreate { .. } => "multixact_offset_create",
+        NeonWalRecord::MultixactMembersCreate { .. } => "multixact_members_create",
+    }
+}
+
+/// Parse a relation in the format that [`RelTag`] is displayed in, i.e.
+/// `<spcnode>/<dbnode>/<relnode>[_fsm|_vm|_init]`.
+fn parse_rel_tag(s: &str) -> anyhow::Result<RelTag> {
+    let mut parts = s.splitn(3, '/');
+    let (Some(spcnode), Some(dbnode), Some(rest)) = (parts.next(), parts.next(), parts.next())
+    else {
+        anyhow::bail!("expected <spcnode>/<dbnode>/<relnode>[_fork]");
+    };
+    let (relnode, forkname) = match rest.split_once('_') {
+        Some((relnode, forkname)) => (relnode, Some(forkname)),
+        None => (rest, None),
+    };
+    Ok(RelTag {
+        forknum: postgres_ffi::relfile_utils::forkname_to_number(forkname)
+            .map_err(|_| anyhow::anyhow!("unknown fork name in {s}"))?,
+        spcnode: spcnode.parse().context("parse spcnode")?,
+        dbnode: dbnode.parse().context("parse dbnode")?,
+        relnode: relnode.parse().context("parse relnode")?,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
 #[instrument(skip_all, fields(peer_addr))]
 async fn page_service_conn_main(
     conf: &'static PageServerConf,
+    launch_ts: &'static LaunchTimestamp,
     broker_client: storage_broker::BrokerClientChannel,
     auth: Option<Arc<SwappableJwtAuth>>,
     socket: tokio::net::TcpStream,
     auth_type: AuthType,
+    tls_config: Option<Arc<rustls::ServerConfig>>,
     connection_ctx: RequestContext,
 ) -> anyhow::Result<()> {
     // Immediately increment the gauge, then create a job to decrement it on task exit.
@@ -217,14 +524,16 @@ async fn page_service_conn_main(
     let peer_addr = socket.peer_addr().context("get peer address")?;
     tracing::Span::current().record("peer_addr", field::display(peer_addr));
 
-    // setup read timeout of 10 minutes. the timeout is rather arbitrary for requirements:
-    // - long enough for most valid compute connections
-    // - less than infinite to stop us from "leaking" connections to long-gone computes
+    // The connection keeps the tunables that are current now, even if they are reloaded
+    // while it runs.
+    let tunables = PageServiceTunables::current(conf);
+
+    // setup read timeout, see [`PageServerConf::page_service_connection_timeout`].
     //
     // no write timeout is used, because the kernel is assumed to error writes after some time.
     let mut socket = tokio_io_timeout::TimeoutReader::new(socket);
 
-    let default_timeout_ms = 10 * 60 * 1000; // 10 minutes by default
+    let default_timeout_ms = tunables.connection_timeout.as_millis() as u64;
     let socket_timeout_ms = (|| {
         fail::fail_point!("simulated-bad-compute-connection", |avg_timeout_ms| {
             // Exponential distribution for simulating
@@ -251,8 +560,17 @@ async fn page_service_conn_main(
     // and create a child per-query context when it invokes process_query.
     // But it's in a shared crate, so, we store connection_ctx inside PageServerHandler
     // and create the per-query context in process_query ourselves.
-    let mut conn_handler = PageServerHandler::new(conf, broker_client, auth, connection_ctx);
-    let pgbackend = PostgresBackend::new_from_io(socket, peer_addr, auth_type, None)?;
+    let mut conn_handler = PageServerHandler::new(
+        conf,
+        tunables,
+        launch_ts,
+        broker_client,
+        auth,
+        connection_ctx,
+    );
+    // If TLS is configured, the client may upgrade the connection with an SSLRequest
+    // during startup; plaintext connections are still accepted.
+    let pgbackend = PostgresBackend::new_from_io(socket, peer_addr, auth_type, tls_config)?;
 
     match pgbackend
         .run(&mut conn_handler, task_mgr::shutdown_watcher)
@@ -274,17 +592,85 @@ async fn page_service_conn_main(
     }
 }
 
+/// The in-flight requests of the open page_service connections, by the key data that was
+/// sent to their clients in BackendKeyData.
+///
+/// A client cancels its in-flight requests like a Postgres client cancels a query: it opens
+/// a new connection and sends a CancelRequest with the key data, instead of a startup
+/// message. The new connection looks up the key data here. The key data is random, and only
+/// valid while the connection it was given out for is open.
+static REQUEST_CANCEL_KEYS: Lazy<
+    std::sync::Mutex<HashMap<CancelKeyData, Arc<RequestCancellation>>>,
+> = Lazy::new(Default::default);
+
+/// Cancellation of the requests in flight on a connection, see [`REQUEST_CANCEL_KEYS`].
+#[derive(Default)]
+struct RequestCancellation {
+    current: std::sync::Mutex<CancellationToken>,
+}
+
+impl RequestCancellation {
+    /// The token for a request that starts now.
+    fn token(&self) -> CancellationToken {
+        self.current.lock().unwrap().clone()
+    }
+
+    /// Cancel the requests that are in flight. Requests that start later are not affected.
+    fn cancel(&self) {
+        std::mem::take(&mut *self.current.lock().unwrap()).cancel();
+    }
+}
+
 struct PageServerHandler {
-    _conf: &'static PageServerConf,
+    conf: &'static PageServerConf,
+    /// The hot-reloadable settings, as they were when the connection was accepted.
+    tunables: Arc<PageServiceTunables>,
+    launch_ts: &'static LaunchTimestamp,
     broker_client: storage_broker::BrokerClientChannel,
     auth: Option<Arc<SwappableJwtAuth>>,
     claims: Option<Claims>,
 
+    /// Whether the client asked for the LSN at which a page was read to be included in
+    /// GetPage responses, with the `pagestream_effective_lsn=true` startup option.
+    send_effective_lsn: bool,
+
+    /// Whether the client asked for each response to a pagestream request to be followed by
+    /// the correlation id of the request, with the `pagestream_correlation_ids=true` option.
+    send_correlation_ids: bool,
+
+    /// Number of pagestream requests received on this connection. The n-th request has
+    /// correlation id n.
+    pagestream_requests: AtomicU64,
+
+    /// Pagestream protocol version that was negotiated with the client, see
+    /// [`PagestreamHandshakeRequest`].
+    protocol_version: PagestreamProtocolVersion,
+
+    /// Timelines of other shards of the tenant on this pageserver, that served GetPage
+    /// requests of this connection for keys that the connection's shard doesn't hold.
+    /// Populated lazily, see [`Self::cached_shard_timeline`].
+    shard_timelines: std::sync::Mutex<HashMap<ShardNumber, Weak<Timeline>>>,
+
     /// The context created for the lifetime of the connection
     /// services by this PageServerHandler.
     /// For each query received over the connection,
     /// `process_query` creates a child context from this one.
     connection_ctx: RequestContext,
+
+    /// Cancels the in-flight pagestream requests when the client sends a CancelRequest.
+    request_cancellation: Arc<RequestCancellation>,
+
+    /// The key data under which [`Self::request_cancellation`] is registered in
+    /// [`REQUEST_CANCEL_KEYS`], once it has been sent to the client.
+    cancel_key: Option<CancelKeyData>,
+}
+
+impl Drop for PageServerHandler {
+    fn drop(&mut self) {
+        if let Some(key) = self.cancel_key.take() {
+            REQUEST_CANCEL_KEYS.lock().unwrap().remove(&key);
+        }
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -314,6 +700,14 @@ enum PageStreamError {
     /// Request asked for something that doesn't make sense, like an invalid LSN
     #[error("Bad request: {0}")]
     BadRequest(std::borrow::Cow<'static, str>),
+
+    /// The client cancelled the request with a CancelRequest
+    #[error("Request cancelled by the client")]
+    Cancelled,
+
+    /// The request could not be answered by the deadline that the client set on it
+    #[error("Deadline exceeded")]
+    DeadlineExceeded,
 }
 
 impl From<PageReconstructError> for PageStreamError {
@@ -325,6 +719,80 @@ impl From<PageReconstructError> for PageStreamError {
     }
 }
 
+/// Why a COPY IN from the client ended before CopyDone, see [`PageServerHandler::copyin_stream`].
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+enum CopyInTermination {
+    /// The client sent a Terminate message.
+    #[error("client terminated connection with Terminate message during COPY")]
+    ClientTerminated,
+
+    /// The client closed the connection.
+    #[error("client closed connection during COPY")]
+    ClientClosed,
+
+    /// We were instructed to shutdown while receiving the data.
+    #[error("pageserver is shutting down")]
+    Shutdown,
+}
+
+impl From<CopyInTermination> for io::Error {
+    fn from(value: CopyInTermination) -> Self {
+        let kind = match value {
+            CopyInTermination::ClientTerminated | CopyInTermination::ClientClosed => {
+                io::ErrorKind::ConnectionReset
+            }
+            CopyInTermination::Shutdown => io::ErrorKind::Interrupted,
+        };
+        io::Error::new(kind, value)
+    }
+}
+
+impl CopyInTermination {
+    fn pg_error_code(&self) -> &'static [u8; 5] {
+        match self {
+            CopyInTermination::ClientTerminated | CopyInTermination::ClientClosed => {
+                QueryError::Disconnected(ConnectionError::Io(io::ErrorKind::ConnectionReset.into()))
+                    .pg_error_code()
+            }
+            CopyInTermination::Shutdown => QueryError::Shutdown.pg_error_code(),
+        }
+    }
+
+    /// Find the cause of a COPY IN termination in an error returned by a consumer of
+    /// [`PageServerHandler::copyin_stream`]. The consumer, e.g. the tar reader, may have
+    /// wrapped the `io::Error` of the stream into errors of its own.
+    fn find(err: &anyhow::Error) -> Option<CopyInTermination> {
+        err.chain().find_map(|e| {
+            e.downcast_ref::<io::Error>()
+                .and_then(|io_error| io_error.get_ref())
+                .and_then(|inner| inner.downcast_ref::<CopyInTermination>())
+                .copied()
+        })
+    }
+
+    /// Convert the error of a failed import into the [`QueryError`] to return, logging and
+    /// counting imports that were cut short by the client or by shutdown.
+    fn import_error(err: anyhow::Error) -> QueryError {
+        match Self::find(&err) {
+            Some(CopyInTermination::Shutdown) => {
+                info!("pageserver shutting down, import aborted");
+                metrics::IMPORT_COPYIN_ABORTED
+                    .with_label_values(&["shutdown"])
+                    .inc();
+                QueryError::Shutdown
+            }
+            Some(termination) => {
+                warn!("client aborted import: {termination}");
+                metrics::IMPORT_COPYIN_ABORTED
+                    .with_label_values(&["client"])
+                    .inc();
+                QueryError::Disconnected(ConnectionError::Io(termination.into()))
+            }
+            None => QueryError::Other(err),
+        }
+    }
+}
+
 impl From<GetActiveTimelineError> for PageStreamError {
     fn from(value: GetActiveTimelineError) -> Self {
         match value {
@@ -348,16 +816,34 @@ impl From<WaitLsnError> for PageStreamError {
 impl PageServerHandler {
     pub fn new(
         conf: &'static PageServerConf,
+        tunables: Arc<PageServiceTunables>,
+        launch_ts: &'static LaunchTimestamp,
         broker_client: storage_broker::BrokerClientChannel,
         auth: Option<Arc<SwappableJwtAuth>>,
         connection_ctx: RequestContext,
     ) -> Self {
         PageServerHandler {
-            _conf: conf,
+            conf,
+            tunables,
+            launch_ts,
             broker_client,
             auth,
             claims: None,
+            send_effective_lsn: false,
+            send_correlation_ids: false,
+            pagestream_requests: AtomicU64::new(0),
+            protocol_version: PagestreamProtocolVersion::V1,
+            shard_timelines: std::sync::Mutex::new(HashMap::new()),
             connection_ctx,
+            request_cancellation: Arc::default(),
+            cancel_key: None,
+        }
+    }
+
+    fn apply_setting(&mut self, setting: ConnectionSetting) {
+        match setting {
+            ConnectionSetting::PagestreamEffectiveLsn(value) => self.send_effective_lsn = value,
+            ConnectionSetting::PagestreamCorrelationIds(value) => self.send_correlation_ids = value,
         }
     }
 
@@ -365,6 +851,9 @@ impl PageServerHandler {
     /// this rather than naked flush() in order to shut down promptly.  Without this, we would
     /// block shutdown of a tenant if a postgres client was failing to consume bytes we send
     /// in the flush.
+    ///
+    /// If [`PageServiceTunables::flush_timeout`] is set, a flush that takes longer fails
+    /// with a timeout error, which tears down the connection.
     async fn flush_cancellable<IO>(
         &self,
         pgb: &mut PostgresBackend<IO>,
@@ -373,8 +862,20 @@ impl PageServerHandler {
     where
         IO: AsyncRead + AsyncWrite + Send + Sync + Unpin,
     {
+        let flush = async {
+            match self.tunables.flush_timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, pgb.flush()).await {
+                    Ok(flush_r) => flush_r,
+                    Err(_) => Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("flush to client did not complete within {timeout:?}"),
+                    )),
+                },
+                None => pgb.flush().await,
+            }
+        };
         tokio::select!(
-            flush_r = pgb.flush() => {
+            flush_r = flush => {
                 Ok(flush_r?)
             },
             _ = cancel.cancelled() => {
@@ -383,23 +884,45 @@ impl PageServerHandler {
         )
     }
 
+    /// Turn the CopyData messages of a COPY IN into a stream of bytes.
+    ///
+    /// If the stream ends before the client sent CopyDone because the client went away or
+    /// because we are shutting down, the `io::Error` it yields carries a [`CopyInTermination`].
+    ///
+    /// With `progress_every`, a NoticeResponse with the number of bytes consumed so far is sent
+    /// to the client each time at least that many more bytes have been consumed.
     fn copyin_stream<'a, IO>(
-        &'a self,
         pgb: &'a mut PostgresBackend<IO>,
         cancel: &'a CancellationToken,
+        progress_every: Option<u64>,
     ) -> impl Stream<Item = io::Result<Bytes>> + 'a
     where
         IO: AsyncRead + AsyncWrite + Send + Sync + Unpin,
     {
         async_stream::try_stream! {
+            let mut consumed = 0u64;
+            let mut reported = 0u64;
             loop {
+                // The consumer only asks for more once it has consumed everything we yielded.
+                if let Some(progress_every) = progress_every {
+                    if consumed - reported >= progress_every {
+                        reported = consumed;
+                        let notice = format!("imported {consumed} bytes");
+                        pgb.write_message_noflush(&BeMessage::NoticeResponse(&notice)).map_err(|e| e.into_io_error())?;
+                        tokio::select! {
+                            biased;
+
+                            _ = cancel.cancelled() => Err(CopyInTermination::Shutdown)?,
+                            res = pgb.flush() => res?,
+                        }
+                    }
+                }
+
                 let msg = tokio::select! {
                     biased;
 
                     _ = cancel.cancelled() => {
                         // We were requested to shut down.
-                        let msg = "pageserver is shutting down";
-                        let _ = pgb.write_message_noflush(&BeMessage::ErrorResponse(msg, None));
                         Err(QueryError::Shutdown)
                     }
 
@@ -413,11 +936,10 @@ impl PageServerHandler {
                             FeMessage::CopyDone => { break },
                             FeMessage::Sync => continue,
                             FeMessage::Terminate => {
-                                let msg = "client terminated connection with Terminate message during COPY";
-                                let query_error = QueryError::Disconnected(ConnectionError::Io(io::Error::new(io::ErrorKind::ConnectionReset, msg)));
+                                let termination = CopyInTermination::ClientTerminated;
                                 // error can't happen here, ErrorResponse serialization should be always ok
-                                pgb.write_message_noflush(&BeMessage::ErrorResponse(msg, Some(query_error.pg_error_code()))).map_err(|e| e.into_io_error())?;
-                                Err(io::Error::new(io::ErrorKind::ConnectionReset, msg))?;
+                                pgb.write_message_noflush(&BeMessage::ErrorResponse(&termination.to_string(), Some(termination.pg_error_code()))).map_err(|e| e.into_io_error())?;
+                                Err(termination)?;
                                 break;
                             }
                             m => {
@@ -429,15 +951,25 @@ impl PageServerHandler {
                             }
                         };
 
+                        consumed += copy_data_bytes.len() as u64;
                         yield copy_data_bytes;
                     }
                     Ok(None) => {
-                        let msg = "client closed connection during COPY";
-                        let query_error = QueryError::Disconnected(ConnectionError::Io(io::Error::new(io::ErrorKind::ConnectionReset, msg)));
+                        let termination = CopyInTermination::ClientClosed;
                         // error can't happen here, ErrorResponse serialization should be always ok
-                        pgb.write_message_noflush(&BeMessage::ErrorResponse(msg, Some(query_error.pg_error_code()))).map_err(|e| e.into_io_error())?;
-                        self.flush_cancellable(pgb, cancel).await.map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-                        Err(io::Error::new(io::ErrorKind::ConnectionReset, msg))?;
+                        pgb.write_message_noflush(&BeMessage::ErrorResponse(&termination.to_string(), Some(termination.pg_error_code()))).map_err(|e| e.into_io_error())?;
+                        // The client is gone, so failing to deliver the error is expected and
+                        // must not mask why the COPY ended.
+                        tokio::select! {
+                            _ = pgb.flush() => {}
+                            _ = cancel.cancelled() => Err(CopyInTermination::Shutdown)?,
+                        }
+                        Err(termination)?;
+                    }
+                    Err(QueryError::Shutdown) => {
+                        let termination = CopyInTermination::Shutdown;
+                        let _ = pgb.write_message_noflush(&BeMessage::ErrorResponse(&termination.to_string(), Some(termination.pg_error_code())));
+                        Err(termination)?;
                     }
                     Err(QueryError::Disconnected(ConnectionError::Io(io_error))) => {
                         Err(io_error)?;
@@ -450,12 +982,13 @@ impl PageServerHandler {
         }
     }
 
-    #[instrument(skip_all)]
+    #[instrument(skip_all, fields(%pipeline_depth))]
     async fn handle_pagerequests<IO>(
-        &self,
+        &mut self,
         pgb: &mut PostgresBackend<IO>,
         tenant_id: TenantId,
         timeline_id: TimelineId,
+        pipeline_depth: usize,
         ctx: RequestContext,
     ) -> Result<(), QueryError>
     where
@@ -470,7 +1003,7 @@ impl PageServerHandler {
         let tenant = mgr::get_active_tenant_with_timeout(
             tenant_id,
             ShardSelector::First,
-            ACTIVE_TENANT_TIMEOUT,
+            self.tunables.active_tenant_timeout,
             &task_mgr::shutdown_token(),
         )
         .await?;
@@ -497,14 +1030,89 @@ impl PageServerHandler {
         // to cancellation.
         let _timeline_guard = timeline.gate.enter().map_err(|_| QueryError::Shutdown)?;
 
+        // Count the connection against the tenant's limit, and reject it before switching to
+        // COPYBOTH if it's one too many. The connections are counted by the shard that we looked
+        // up above, which is the same for all connections to the tenant.
+        let max_connections = self.tunables.tenant_max_connections;
+        let Some(_connection_permit) =
+            PagestreamConnectionPermit::acquire(&tenant.pagestream_connections, max_connections)
+        else {
+            return Err(QueryError::Other(anyhow::anyhow!(
+                "too many pagestream connections to tenant {tenant_id}, the limit is {max_connections}"
+            )));
+        };
+
+        // Count the connection for as long as this handler runs, whichever way it exits.
+        let connections_gauge = metrics::PAGESTREAM_CONNECTIONS
+            .with_label_values(&[&tenant_id.to_string(), &timeline_id.to_string()]);
+        connections_gauge.inc();
+        scopeguard::defer! {
+            connections_gauge.dec();
+        }
+
         // switch client to COPYBOTH
         pgb.write_message_noflush(&BeMessage::CopyBothResponse)?;
         self.flush_cancellable(pgb, &timeline.cancel).await?;
 
-        let metrics = metrics::SmgrQueryTimePerTimeline::new(&tenant_id, &timeline_id);
+        // The first message may be a protocol version handshake. Clients that don't send one
+        // speak the original protocol, and their first message is already a request.
+        self.protocol_version = PagestreamProtocolVersion::V1;
+        self.shard_timelines.get_mut().unwrap().clear();
+        let mut first_request = None;
+        tokio::select! {
+            biased;
+
+            _ = timeline.cancel.cancelled() => {
+                info!("shutdown request received in page handler");
+                return Err(QueryError::Shutdown)
+            }
+
+            msg = pgb.read_message() => {
+                match msg? {
+                    Some(FeMessage::CopyData(bytes))
+                        if PagestreamHandshakeRequest::is_handshake(&bytes) =>
+                    {
+                        self.negotiate_protocol_version(pgb, &timeline, bytes).await?;
+                    }
+                    Some(FeMessage::CopyData(bytes)) => first_request = Some(bytes),
+                    Some(FeMessage::Terminate) => return Ok(()),
+                    Some(m) => {
+                        return Err(QueryError::Other(anyhow::anyhow!(
+                            "unexpected message: {m:?} during COPY"
+                        )));
+                    }
+                    None => return Ok(()), // client disconnected
+                }
+            }
+        }
+        let protocol_version = self.protocol_version;
+
+        let metrics = metrics::SmgrQueryTimePerTimeline::new(
+            &tenant_id,
+            &timeline_id,
+            self.tunables.slo_threshold,
+        );
+
+        // Up to `pipeline_depth` requests are processed concurrently. The responses are sent
+        // in the order in which the requests arrived, because that is how the client matches
+        // them up with its requests.
+        let mut in_flight = FuturesOrdered::new();
+
+        // Once the timeline asks us to drain the connection, we stop reading requests, answer
+        // the ones in flight and then close the connection.
+        let mut drain = timeline.subscribe_for_pagestream_drain();
+        let mut draining = None;
 
         loop {
-            let msg = tokio::select! {
+            if let Some(reason) = draining.take() {
+                if in_flight.is_empty() {
+                    self.go_away(pgb, &timeline, reason).await?;
+                    break;
+                }
+                draining = Some(reason);
+            }
+
+            tokio::select! {
                 biased;
 
                 _ = timeline.cancel.cancelled() => {
@@ -513,115 +1121,284 @@ impl PageServerHandler {
                     return Err(QueryError::Shutdown)
                 }
 
-                msg = pgb.read_message() => { msg }
-            };
+                reason = pagestream_drain_requested(&mut drain), if draining.is_none() => {
+                    info!(%reason, "draining pagestream connection");
+                    draining = Some(reason);
+                }
 
-            let copy_data_bytes = match msg? {
-                Some(FeMessage::CopyData(bytes)) => bytes,
-                Some(FeMessage::Terminate) => break,
-                Some(m) => {
-                    return Err(QueryError::Other(anyhow::anyhow!(
-                        "unexpected message: {m:?} during COPY"
-                    )));
+                Some((response, span, correlation_id)) = in_flight.next(), if !in_flight.is_empty() => {
+                    self.send_pagestream_response(pgb, &timeline, response, span, correlation_id)
+                        .await?;
                 }
-                None => break, // client disconnected
-            };
 
-            trace!("query: {copy_data_bytes:?}");
+                msg = async {
+                    match first_request.take() {
+                        Some(bytes) => Ok(Some(FeMessage::CopyData(bytes))),
+                        None => pgb.read_message().await,
+                    }
+                }, if in_flight.len() < pipeline_depth && draining.is_none() => {
+                    let copy_data_bytes = match msg? {
+                        Some(FeMessage::CopyData(bytes)) => bytes,
+                        Some(FeMessage::Terminate) => break,
+                        Some(m) => {
+                            return Err(QueryError::Other(anyhow::anyhow!(
+                                "unexpected message: {m:?} during COPY"
+                            )));
+                        }
+                        None => break, // client disconnected
+                    };
+
+                    trace!("query: {copy_data_bytes:?}");
+
+                    // Trace request if needed
+                    if let Some(t) = tracer.as_mut() {
+                        t.trace(&copy_data_bytes)
+                    }
 
-            // Trace request if needed
-            if let Some(t) = tracer.as_mut() {
-                t.trace(&copy_data_bytes)
+                    let neon_fe_msg = PagestreamFeMessage::parse_versioned(
+                        &mut copy_data_bytes.reader(),
+                        protocol_version,
+                    )?;
+                    let correlation_id = self
+                        .pagestream_requests
+                        .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+                        + 1;
+
+                    // Every request gets its own context with a unique ID, so that all the work done
+                    // on its behalf can be correlated in the logs. The client can cancel it
+                    // with a CancelRequest.
+                    let ctx = RequestContextBuilder::extend(&ctx.attached_request_child())
+                        .cancel(self.request_cancellation.token())
+                        .build();
+
+                    in_flight.push_back(
+                        self.handle_pagestream_request(
+                            &tenant,
+                            &timeline,
+                            &metrics,
+                            neon_fe_msg,
+                            ctx,
+                        )
+                        .map(move |(response, span)| (response, span, correlation_id)),
+                    );
+                }
             }
+        }
+        Ok(())
+    }
+
+    /// End a drained pagestream connection: clients that understand it are told to reconnect
+    /// with a GoAway message, the others just see the connection close.
+    async fn go_away<IO>(
+        &self,
+        pgb: &mut PostgresBackend<IO>,
+        timeline: &Timeline,
+        reason: String,
+    ) -> Result<(), QueryError>
+    where
+        IO: AsyncRead + AsyncWrite + Send + Sync + Unpin,
+    {
+        if self.protocol_version >= PagestreamProtocolVersion::V3 {
+            pgb.write_message_noflush(&BeMessage::CopyData(
+                &PagestreamBeMessage::GoAway(PagestreamGoAwayResponse { reason }).serialize(),
+            ))?;
+            self.flush_cancellable(pgb, &timeline.cancel).await?;
+        }
+        Ok(())
+    }
+
+    /// Reply to a protocol version handshake with the newest version that both we and the
+    /// client speak, and remember it for parsing the client's requests.
+    async fn negotiate_protocol_version<IO>(
+        &mut self,
+        pgb: &mut PostgresBackend<IO>,
+        timeline: &Timeline,
+        handshake: Bytes,
+    ) -> Result<(), QueryError>
+    where
+        IO: AsyncRead + AsyncWrite + Send + Sync + Unpin,
+    {
+        let handshake = PagestreamHandshakeRequest::parse(&mut handshake.reader())?;
+        let Some(version) = PagestreamProtocolVersion::negotiate(handshake.supported_versions)
+        else {
+            let message = format!(
+                "no supported pagestream protocol version in {:#x}",
+                handshake.supported_versions
+            );
+            pgb.write_message_noflush(&BeMessage::CopyData(
+                &PagestreamBeMessage::Error(PagestreamErrorResponse {
+                    message: message.clone(),
+                })
+                .serialize(),
+            ))?;
+            self.flush_cancellable(pgb, &timeline.cancel).await?;
+            return Err(QueryError::Other(anyhow::anyhow!(message)));
+        };
+        debug!(?version, "negotiated pagestream protocol version");
+        self.protocol_version = version;
 
-            let neon_fe_msg = PagestreamFeMessage::parse(&mut copy_data_bytes.reader())?;
+        pgb.write_message_noflush(&BeMessage::CopyData(
+            &PagestreamBeMessage::Handshake(PagestreamHandshakeResponse { version }).serialize(),
+        ))?;
+        self.flush_cancellable(pgb, &timeline.cancel).await?;
+        Ok(())
+    }
 
-            // TODO: We could create a new per-request context here, with unique ID.
-            // Currently we use the same per-timeline context for all requests
+    /// Process a single pagestream request, returning the response along with the span
+    /// in which it was processed.
+    async fn handle_pagestream_request(
+        &self,
+        tenant: &Tenant,
+        timeline: &Timeline,
+        metrics: &metrics::SmgrQueryTimePerTimeline,
+        neon_fe_msg: PagestreamFeMessage,
+        ctx: RequestContext,
+    ) -> (Result<PagestreamBeMessage, PageStreamError>, tracing::Span) {
+        // The deadline counts from now, so time spent in the throttle counts against it.
+        let deadline = neon_fe_msg
+            .deadline()
+            .map(|deadline| tokio::time::Instant::now() + deadline);
+
+        // Apply the tenant's rate limit before doing any work for the request.
+        let throttled = tenant.pagestream_throttle.throttle().await;
+        if !throttled.is_zero() {
+            metrics::PAGE_SERVICE_THROTTLED_SECONDS.inc_by(throttled.as_secs_f64());
+        }
 
-            let (response, span) = match neon_fe_msg {
-                PagestreamFeMessage::Exists(req) => {
-                    let _timer = metrics.start_timer(metrics::SmgrQueryType::GetRelExists);
-                    let span = tracing::info_span!("handle_get_rel_exists_request", rel = %req.rel, req_lsn = %req.lsn);
-                    (
-                        self.handle_get_rel_exists_request(&timeline, &req, &ctx)
-                            .instrument(span.clone())
-                            .await,
-                        span,
+        let request_id = ctx.request_id().expect("set by attached_request_child");
+
+        match neon_fe_msg {
+            PagestreamFeMessage::Exists(req) => {
+                let _timer = metrics.start_request_timer(metrics::SmgrQueryType::GetRelExists);
+                let span = tracing::info_span!("handle_get_rel_exists_request", request_id = %request_id, rel = %req.rel, req_lsn = %req.lsn);
+                (
+                    with_deadline(
+                        deadline,
+                        self.handle_get_rel_exists_request(timeline, &req, metrics, &ctx)
+                            .instrument(span.clone()),
                     )
-                }
-                PagestreamFeMessage::Nblocks(req) => {
-                    let _timer = metrics.start_timer(metrics::SmgrQueryType::GetRelSize);
-                    let span = tracing::info_span!("handle_get_nblocks_request", rel = %req.rel, req_lsn = %req.lsn);
-                    (
-                        self.handle_get_nblocks_request(&timeline, &req, &ctx)
-                            .instrument(span.clone())
-                            .await,
-                        span,
+                    .await,
+                    span,
+                )
+            }
+            PagestreamFeMessage::Nblocks(req) => {
+                let _timer = metrics.start_request_timer(metrics::SmgrQueryType::GetRelSize);
+                let span = tracing::info_span!("handle_get_nblocks_request", request_id = %request_id, rel = %req.rel, req_lsn = %req.lsn);
+                (
+                    with_deadline(
+                        deadline,
+                        self.handle_get_nblocks_request(timeline, &req, metrics, &ctx)
+                            .instrument(span.clone()),
                     )
-                }
-                PagestreamFeMessage::GetPage(req) => {
-                    let _timer = metrics.start_timer(metrics::SmgrQueryType::GetPageAtLsn);
-                    let span = tracing::info_span!("handle_get_page_at_lsn_request", rel = %req.rel, blkno = %req.blkno, req_lsn = %req.lsn);
-                    (
-                        self.handle_get_page_at_lsn_request(&timeline, &req, &ctx)
-                            .instrument(span.clone())
-                            .await,
-                        span,
+                    .await,
+                    span,
+                )
+            }
+            PagestreamFeMessage::GetPage(req) => {
+                let _timer = metrics.start_request_timer(metrics::SmgrQueryType::GetPageAtLsn);
+                let span = tracing::info_span!("handle_get_page_at_lsn_request", request_id = %request_id, rel = %req.rel, blkno = %req.blkno, req_lsn = %req.lsn);
+                (
+                    with_deadline(
+                        deadline,
+                        self.handle_get_page_at_lsn_request(timeline, &req, metrics, &ctx)
+                            .instrument(span.clone()),
                     )
-                }
-                PagestreamFeMessage::DbSize(req) => {
-                    let _timer = metrics.start_timer(metrics::SmgrQueryType::GetDbSize);
-                    let span = tracing::info_span!("handle_db_size_request", dbnode = %req.dbnode, req_lsn = %req.lsn);
-                    (
-                        self.handle_db_size_request(&timeline, &req, &ctx)
-                            .instrument(span.clone())
-                            .await,
-                        span,
+                    .await,
+                    span,
+                )
+            }
+            PagestreamFeMessage::DbSize(req) => {
+                let _timer = metrics.start_request_timer(metrics::SmgrQueryType::GetDbSize);
+                let span = tracing::info_span!("handle_db_size_request", request_id = %request_id, dbnode = %req.dbnode, req_lsn = %req.lsn);
+                (
+                    with_deadline(
+                        deadline,
+                        self.handle_db_size_request(timeline, &req, metrics, &ctx)
+                            .instrument(span.clone()),
                     )
-                }
-            };
+                    .await,
+                    span,
+                )
+            }
+            PagestreamFeMessage::GetPageLastModified(req) => {
+                let _timer =
+                    metrics.start_request_timer(metrics::SmgrQueryType::GetPageLastModified);
+                let span = tracing::info_span!("handle_get_page_last_modified_request", request_id = %request_id, rel = %req.rel, blkno = %req.blkno, req_lsn = %req.lsn);
+                (
+                    with_deadline(
+                        deadline,
+                        self.handle_get_page_last_modified_request(timeline, &req, metrics, &ctx)
+                            .instrument(span.clone()),
+                    )
+                    .await,
+                    span,
+                )
+            }
+        }
+    }
 
-            match response {
-                Err(PageStreamError::Shutdown) => {
-                    // If we fail to fulfil a request during shutdown, which may be _because_ of
-                    // shutdown, then do not send the error to the client.  Instead just drop the
-                    // connection.
-                    span.in_scope(|| info!("dropping connection due to shutdown"));
-                    return Err(QueryError::Shutdown);
-                }
-                Err(PageStreamError::Reconnect(reason)) => {
-                    span.in_scope(|| info!("handler requested reconnect: {reason}"));
-                    return Err(QueryError::Reconnect);
-                }
-                Err(e) if timeline.cancel.is_cancelled() || timeline.is_stopping() => {
-                    // This branch accomodates code within request handlers that returns an anyhow::Error instead of a clean
-                    // shutdown error, this may be buried inside a PageReconstructError::Other for example.
-                    //
-                    // Requests may fail as soon as we are Stopping, even if the Timeline's cancellation token wasn't fired yet,
-                    // because wait_lsn etc will drop out
-                    // is_stopping(): [`Timeline::flush_and_shutdown`] has entered
-                    // is_canceled(): [`Timeline::shutdown`]` has entered
-                    span.in_scope(|| info!("dropped error response during shutdown: {e:#}"));
-                    return Err(QueryError::Shutdown);
-                }
-                r => {
-                    let response_msg = r.unwrap_or_else(|e| {
-                        // print the all details to the log with {:#}, but for the client the
-                        // error message is enough.  Do not log if shutting down, as the anyhow::Error
-                        // here includes cancellation which is not an error.
+    /// Send the response to a pagestream request to the client, or decide to drop the
+    /// connection instead.
+    async fn send_pagestream_response<IO>(
+        &self,
+        pgb: &mut PostgresBackend<IO>,
+        timeline: &Timeline,
+        response: Result<PagestreamBeMessage, PageStreamError>,
+        span: tracing::Span,
+        correlation_id: u64,
+    ) -> Result<(), QueryError>
+    where
+        IO: AsyncRead + AsyncWrite + Send + Sync + Unpin,
+    {
+        match response {
+            Err(PageStreamError::Shutdown) => {
+                // If we fail to fulfil a request during shutdown, which may be _because_ of
+                // shutdown, then do not send the error to the client.  Instead just drop the
+                // connection.
+                span.in_scope(|| info!("dropping connection due to shutdown"));
+                Err(QueryError::Shutdown)
+            }
+            Err(PageStreamError::Reconnect(reason)) => {
+                span.in_scope(|| info!("handler requested reconnect: {reason}"));
+                Err(QueryError::Reconnect)
+            }
+            Err(e) if timeline.cancel.is_cancelled() || timeline.is_stopping() => {
+                // This branch accomodates code within request handlers that returns an anyhow::Error instead of a clean
+                // shutdown error, this may be buried inside a PageReconstructError::Other for example.
+                //
+                // Requests may fail as soon as we are Stopping, even if the Timeline's cancellation token wasn't fired yet,
+                // because wait_lsn etc will drop out
+                // is_stopping(): [`Timeline::flush_and_shutdown`] has entered
+                // is_canceled(): [`Timeline::shutdown`]` has entered
+                span.in_scope(|| info!("dropped error response during shutdown: {e:#}"));
+                Err(QueryError::Shutdown)
+            }
+            r => {
+                let response_msg = r.unwrap_or_else(|e| {
+                    // print the all details to the log with {:#}, but for the client the
+                    // error message is enough.  Do not log if shutting down, as the anyhow::Error
+                    // here includes cancellation which is not an error.
+                    if matches!(e, PageStreamError::Cancelled) {
+                        span.in_scope(|| info!("request cancelled by the client"));
+                    } else if matches!(e, PageStreamError::DeadlineExceeded) {
+                        span.in_scope(|| info!("request missed the deadline set by the client"));
+                    } else {
                         span.in_scope(|| error!("error reading relation or page version: {:#}", e));
-                        PagestreamBeMessage::Error(PagestreamErrorResponse {
-                            message: e.to_string(),
-                        })
-                    });
+                    }
+                    PagestreamBeMessage::Error(PagestreamErrorResponse {
+                        message: e.to_string(),
+                    })
+                });
 
-                    pgb.write_message_noflush(&BeMessage::CopyData(&response_msg.serialize()))?;
-                    self.flush_cancellable(pgb, &timeline.cancel).await?;
-                }
+                let response_bytes = if self.send_correlation_ids {
+                    response_msg.serialize_with_correlation_id(correlation_id)
+                } else {
+                    response_msg.serialize()
+                };
+                pgb.write_message_noflush(&BeMessage::CopyData(&response_bytes))?;
+                self.flush_cancellable(pgb, &timeline.cancel).await
             }
         }
-        Ok(())
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -634,6 +1411,8 @@ impl PageServerHandler {
         base_lsn: Lsn,
         _end_lsn: Lsn,
         pg_version: u32,
+        into_existing: bool,
+        progress: bool,
         ctx: RequestContext,
     ) -> Result<(), QueryError>
     where
@@ -641,18 +1420,39 @@ impl PageServerHandler {
     {
         debug_assert_current_span_has_tenant_and_timeline_id();
 
-        // Create empty timeline
-        info!("creating new timeline");
         let tenant = get_active_tenant_with_timeout(
             tenant_id,
             ShardSelector::Zero,
-            ACTIVE_TENANT_TIMEOUT,
+            self.tunables.active_tenant_timeout,
             &task_mgr::shutdown_token(),
         )
         .await?;
-        let timeline = tenant
-            .create_empty_timeline(timeline_id, base_lsn, pg_version, &ctx)
-            .await?;
+
+        enum ImportTarget<'t> {
+            New(UninitializedTimeline<'t>),
+            Existing(BasebackupImport),
+        }
+        let target = if into_existing {
+            // Import into a timeline that was created empty before, e.g. by an earlier
+            // attempt of this import that failed.
+            info!("importing into existing timeline");
+            let timeline = tenant
+                .get_timeline(timeline_id, true)
+                .map_err(|e| QueryError::NotFound(format!("{e}").into()))?;
+            ImportTarget::Existing(
+                timeline
+                    .prepare_basebackup_import(base_lsn, pg_version)
+                    .await?,
+            )
+        } else {
+            // Create empty timeline
+            info!("creating new timeline");
+            ImportTarget::New(
+                tenant
+                    .create_empty_timeline(timeline_id, base_lsn, pg_version, &ctx)
+                    .await?,
+            )
+        };
 
         // TODO mark timeline as not ready until it reaches end_lsn.
         // We might have some wal to import as well, and we should prevent compute
@@ -669,18 +1469,34 @@ impl PageServerHandler {
         pgb.write_message_noflush(&BeMessage::CopyInResponse)?;
         self.flush_cancellable(pgb, &tenant.cancel).await?;
 
-        let mut copyin_reader = pin!(StreamReader::new(self.copyin_stream(pgb, &tenant.cancel)));
-        timeline
-            .import_basebackup_from_tar(
-                &mut copyin_reader,
-                base_lsn,
-                self.broker_client.clone(),
-                &ctx,
-            )
-            .await?;
+        let progress_every = progress.then_some(IMPORT_PROGRESS_INTERVAL_BYTES);
+        let mut copyin_reader = pin!(StreamReader::new(Self::copyin_stream(
+            pgb,
+            &tenant.cancel,
+            progress_every
+        )));
+        async {
+            match target {
+                ImportTarget::New(timeline) => {
+                    timeline
+                        .import_basebackup_from_tar(
+                            &mut copyin_reader,
+                            base_lsn,
+                            self.broker_client.clone(),
+                            &ctx,
+                        )
+                        .await?;
+                }
+                ImportTarget::Existing(import) => {
+                    import.import_from_tar(&mut copyin_reader, &ctx).await?;
+                }
+            }
 
-        // Read the end of the tar archive.
-        read_tar_eof(copyin_reader).await?;
+            // Read the end of the tar archive.
+            read_tar_eof(copyin_reader).await
+        }
+        .await
+        .map_err(CopyInTermination::import_error)?;
 
         // TODO check checksum
         // Meanwhile you can verify client-side by taking fullbackup
@@ -692,6 +1508,7 @@ impl PageServerHandler {
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     #[instrument(skip_all, fields(%start_lsn, %end_lsn))]
     async fn handle_import_wal<IO>(
         &self,
@@ -700,6 +1517,7 @@ impl PageServerHandler {
         timeline_id: TimelineId,
         start_lsn: Lsn,
         end_lsn: Lsn,
+        progress: bool,
         ctx: RequestContext,
     ) -> Result<(), QueryError>
     where
@@ -724,12 +1542,21 @@ impl PageServerHandler {
         info!("importing wal");
         pgb.write_message_noflush(&BeMessage::CopyInResponse)?;
         self.flush_cancellable(pgb, &timeline.cancel).await?;
-        let mut copyin_reader = pin!(StreamReader::new(self.copyin_stream(pgb, &timeline.cancel)));
-        import_wal_from_tar(&timeline, &mut copyin_reader, start_lsn, end_lsn, &ctx).await?;
-        info!("wal import complete");
-
-        // Read the end of the tar archive.
-        read_tar_eof(copyin_reader).await?;
+        let progress_every = progress.then_some(IMPORT_PROGRESS_INTERVAL_BYTES);
+        let mut copyin_reader = pin!(StreamReader::new(Self::copyin_stream(
+            pgb,
+            &timeline.cancel,
+            progress_every
+        )));
+        async {
+            import_wal_from_tar(&timeline, &mut copyin_reader, start_lsn, end_lsn, &ctx).await?;
+            info!("wal import complete");
+
+            // Read the end of the tar archive.
+            read_tar_eof(copyin_reader).await
+        }
+        .await
+        .map_err(CopyInTermination::import_error)?;
 
         // TODO Does it make sense to overshoot?
         if timeline.get_last_record_lsn() < end_lsn {
@@ -789,7 +1616,7 @@ impl PageServerHandler {
             if lsn <= last_record_lsn {
                 lsn = last_record_lsn;
             } else {
-                timeline.wait_lsn(lsn, ctx).await?;
+                Self::wait_lsn_cancellable(timeline, lsn, ctx).await?;
                 // Since we waited for 'lsn' to arrive, that is now the last
                 // record LSN. (Or close enough for our purposes; the
                 // last-record LSN can advance immediately after we return
@@ -801,7 +1628,7 @@ impl PageServerHandler {
                     "invalid LSN(0) in request".into(),
                 ));
             }
-            timeline.wait_lsn(lsn, ctx).await?;
+            Self::wait_lsn_cancellable(timeline, lsn, ctx).await?;
         }
 
         if lsn < **latest_gc_cutoff_lsn {
@@ -813,16 +1640,31 @@ impl PageServerHandler {
         Ok(lsn)
     }
 
+    /// Wait for `lsn` to arrive, unless the client cancels the request first.
+    async fn wait_lsn_cancellable(
+        timeline: &Timeline,
+        lsn: Lsn,
+        ctx: &RequestContext,
+    ) -> Result<(), PageStreamError> {
+        tokio::select! {
+            res = timeline.wait_lsn(lsn, ctx) => Ok(res?),
+            _ = ctx.cancelled() => Err(PageStreamError::Cancelled),
+        }
+    }
+
     async fn handle_get_rel_exists_request(
         &self,
         timeline: &Timeline,
         req: &PagestreamExistsRequest,
+        metrics: &metrics::SmgrQueryTimePerTimeline,
         ctx: &RequestContext,
     ) -> Result<PagestreamBeMessage, PageStreamError> {
         let latest_gc_cutoff_lsn = timeline.get_latest_gc_cutoff_lsn();
-        let lsn =
+        let lsn = {
+            let _timer = metrics.start_timer(metrics::SmgrQueryType::GetRelExistsWaitLsn);
             Self::wait_or_get_last_lsn(timeline, req.lsn, req.latest, &latest_gc_cutoff_lsn, ctx)
-                .await?;
+                .await?
+        };
 
         let exists = timeline
             .get_rel_exists(req.rel, Version::Lsn(lsn), req.latest, ctx)
@@ -837,12 +1679,15 @@ impl PageServerHandler {
         &self,
         timeline: &Timeline,
         req: &PagestreamNblocksRequest,
+        metrics: &metrics::SmgrQueryTimePerTimeline,
         ctx: &RequestContext,
     ) -> Result<PagestreamBeMessage, PageStreamError> {
         let latest_gc_cutoff_lsn = timeline.get_latest_gc_cutoff_lsn();
-        let lsn =
+        let lsn = {
+            let _timer = metrics.start_timer(metrics::SmgrQueryType::GetRelSizeWaitLsn);
             Self::wait_or_get_last_lsn(timeline, req.lsn, req.latest, &latest_gc_cutoff_lsn, ctx)
-                .await?;
+                .await?
+        };
 
         let n_blocks = timeline
             .get_rel_size(req.rel, Version::Lsn(lsn), req.latest, ctx)
@@ -857,22 +1702,33 @@ impl PageServerHandler {
         &self,
         timeline: &Timeline,
         req: &PagestreamDbSizeRequest,
+        metrics: &metrics::SmgrQueryTimePerTimeline,
         ctx: &RequestContext,
     ) -> Result<PagestreamBeMessage, PageStreamError> {
         let latest_gc_cutoff_lsn = timeline.get_latest_gc_cutoff_lsn();
-        let lsn =
+        let lsn = {
+            let _timer = metrics.start_timer(metrics::SmgrQueryType::GetDbSizeWaitLsn);
             Self::wait_or_get_last_lsn(timeline, req.lsn, req.latest, &latest_gc_cutoff_lsn, ctx)
-                .await?;
+                .await?
+        };
 
-        let total_blocks = timeline
-            .get_db_size(
-                DEFAULTTABLESPACE_OID,
-                req.dbnode,
-                Version::Lsn(lsn),
-                req.latest,
-                ctx,
-            )
-            .await?;
+        let spcnodes = if req.spcnode == PagestreamDbSizeRequest::ALL_TABLESPACES {
+            timeline
+                .list_dbdirs(lsn, ctx)
+                .await?
+                .into_keys()
+                .filter_map(|(spcnode, dbnode)| (dbnode == req.dbnode).then_some(spcnode))
+                .collect()
+        } else {
+            vec![req.spcnode]
+        };
+
+        let mut total_blocks = 0;
+        for spcnode in spcnodes {
+            total_blocks += timeline
+                .get_db_size(spcnode, req.dbnode, Version::Lsn(lsn), req.latest, ctx)
+                .await?;
+        }
         let db_size = total_blocks as i64 * BLCKSZ as i64;
 
         Ok(PagestreamBeMessage::DbSize(PagestreamDbSizeResponse {
@@ -884,18 +1740,46 @@ impl PageServerHandler {
         &self,
         timeline: &Timeline,
         req: &PagestreamGetPageRequest,
+        metrics: &metrics::SmgrQueryTimePerTimeline,
         ctx: &RequestContext,
     ) -> Result<PagestreamBeMessage, PageStreamError> {
+        let started_at = std::time::Instant::now();
         let latest_gc_cutoff_lsn = timeline.get_latest_gc_cutoff_lsn();
-        let lsn =
+        // Only non-latest requests for an LSN that hasn't arrived yet actually wait.
+        let waited_for_lsn = !req.latest && req.lsn > timeline.get_last_record_lsn();
+        let lsn = {
+            let _timer = metrics.start_timer(metrics::SmgrQueryType::GetPageAtLsnWaitLsn);
             Self::wait_or_get_last_lsn(timeline, req.lsn, req.latest, &latest_gc_cutoff_lsn, ctx)
-                .await?;
-        let page = timeline
-            .get_rel_page_at_lsn(req.rel, req.blkno, Version::Lsn(lsn), req.latest, ctx)
-            .await?;
+                .await?
+        };
+        let wait_lsn_elapsed = started_at.elapsed();
+        let page = {
+            let _timer = metrics.start_timer(metrics::SmgrQueryType::GetPageAtLsnReconstruct);
+            timeline
+                .get_rel_page_at_lsn(req.rel, req.blkno, Version::Lsn(lsn), req.latest, ctx)
+                .await?
+        };
+        let elapsed = started_at.elapsed();
+
+        if elapsed > self.tunables.slow_getpage_threshold {
+            warn!(
+                effective_lsn = %lsn,
+                waited_for_lsn,
+                shard_number = timeline.tenant_shard_id.shard_number.0,
+                rel = %req.rel,
+                blkno = req.blkno,
+                wait_lsn_millis = wait_lsn_elapsed.as_millis(),
+                reconstruct_millis = (elapsed - wait_lsn_elapsed).as_millis(),
+                elapsed_millis = elapsed.as_millis(),
+                "slow getpage request"
+            );
+        }
 
         Ok(PagestreamBeMessage::GetPage(PagestreamGetPageResponse {
             page,
+            effective_lsn: (self.send_effective_lsn
+                || self.protocol_version >= PagestreamProtocolVersion::V2)
+                .then_some(lsn),
         }))
     }
 
@@ -903,56 +1787,191 @@ impl PageServerHandler {
         &self,
         timeline: &Timeline,
         req: &PagestreamGetPageRequest,
+        metrics: &metrics::SmgrQueryTimePerTimeline,
         ctx: &RequestContext,
     ) -> Result<PagestreamBeMessage, PageStreamError> {
+        // Lets tests hold a request in flight.
+        pausable_failpoint!("pagestream-get-page-pausable");
+
         let key = rel_block_to_key(req.rel, req.blkno);
-        if timeline.get_shard_identity().is_key_local(&key) {
-            self.do_handle_get_page_at_lsn_request(timeline, req, ctx)
-                .await
-        } else {
-            // The Tenant shard we looked up at connection start does not hold this particular
-            // key: look for other shards in this tenant.  This scenario occurs if a pageserver
-            // has multiple shards for the same tenant.
-            //
-            // TODO: optimize this (https://github.com/neondatabase/neon/pull/6037)
-            let timeline = match self
+        let key_range = key..key.next();
+        match self.shard_timeline_for_keys(timeline, &key_range).await? {
+            None => {
+                self.do_handle_get_page_at_lsn_request(timeline, req, metrics, ctx)
+                    .await
+            }
+            Some((timeline, _timeline_guard)) => {
+                self.do_handle_get_page_at_lsn_request(&timeline, req, metrics, ctx)
+                    .await
+            }
+        }
+    }
+
+    async fn do_handle_get_page_last_modified_request(
+        &self,
+        timeline: &Timeline,
+        req: &PagestreamGetPageLastModifiedRequest,
+        metrics: &metrics::SmgrQueryTimePerTimeline,
+        ctx: &RequestContext,
+    ) -> Result<PagestreamBeMessage, PageStreamError> {
+        let latest_gc_cutoff_lsn = timeline.get_latest_gc_cutoff_lsn();
+        let lsn = {
+            let _timer = metrics.start_timer(metrics::SmgrQueryType::GetPageLastModifiedWaitLsn);
+            Self::wait_or_get_last_lsn(timeline, req.lsn, req.latest, &latest_gc_cutoff_lsn, ctx)
+                .await?
+        };
+        let last_modified_lsn = timeline
+            .get_rel_page_last_modified_lsn(req.rel, req.blkno, lsn, req.latest, ctx)
+            .await?;
+
+        Ok(PagestreamBeMessage::GetPageLastModified(
+            PagestreamGetPageLastModifiedResponse { last_modified_lsn },
+        ))
+    }
+
+    async fn handle_get_page_last_modified_request(
+        &self,
+        timeline: &Timeline,
+        req: &PagestreamGetPageLastModifiedRequest,
+        metrics: &metrics::SmgrQueryTimePerTimeline,
+        ctx: &RequestContext,
+    ) -> Result<PagestreamBeMessage, PageStreamError> {
+        let key = rel_block_to_key(req.rel, req.blkno);
+        let key_range = key..key.next();
+        match self.shard_timeline_for_keys(timeline, &key_range).await? {
+            None => {
+                self.do_handle_get_page_last_modified_request(timeline, req, metrics, ctx)
+                    .await
+            }
+            Some((timeline, _timeline_guard)) => {
+                self.do_handle_get_page_last_modified_request(&timeline, req, metrics, ctx)
+                    .await
+            }
+        }
+    }
+
+    /// Resolve the shard that holds the block keys in `keys`, contiguous blocks of one
+    /// relation: `None` if it's the shard of the connection's `timeline`, which is the common
+    /// case and costs one locality check per stripe of blocks rather than one per block.
+    /// Otherwise, the keys must all be on one other shard, whose Timeline is returned along
+    /// with a guard of its gate, to hold for the duration of the request. (The gate of the
+    /// connection's timeline is already held over the whole connection.)
+    ///
+    /// Callers with keys that may be spread over several shards must split them up by shard
+    /// first.
+    async fn shard_timeline_for_keys(
+        &self,
+        timeline: &Timeline,
+        keys: &Range<Key>,
+    ) -> Result<Option<(Arc<Timeline>, GateGuard)>, PageStreamError> {
+        if timeline.get_shard_identity().is_key_range_local(keys) {
+            return Ok(None);
+        }
+        let timeline = self
+            .get_shard_timeline_for_key(timeline, &keys.start)
+            .await?;
+        let timeline_guard = timeline
+            .gate
+            .enter()
+            .map_err(|_| PageStreamError::Shutdown)?;
+        Ok(Some((timeline, timeline_guard)))
+    }
+
+    /// Find the Timeline of the shard that holds `key`, for a key that the shard we looked up at
+    /// connection start doesn't hold.
+    async fn get_shard_timeline_for_key(
+        &self,
+        timeline: &Timeline,
+        key: &Key,
+    ) -> Result<Arc<Timeline>, PageStreamError> {
+        // The Tenant shard we looked up at connection start does not hold this particular
+        // key: look for other shards in this tenant.  This scenario occurs if a pageserver
+        // has multiple shards for the same tenant. Shards that we found before are cached,
+        // to avoid resolving the tenant again for every request.
+        let shard_number = timeline.get_shard_identity().get_shard_number(key);
+        let shard_timeline = match self.cached_shard_timeline(timeline, shard_number, key) {
+            Some(t) => Ok(t),
+            None => self
                 .get_active_tenant_timeline(
                     timeline.tenant_shard_id.tenant_id,
                     timeline.timeline_id,
-                    ShardSelector::Page(key),
+                    ShardSelector::Page(*key),
                 )
                 .await
-            {
-                Ok(t) => t,
-                Err(GetActiveTimelineError::Tenant(GetActiveTenantError::NotFound(_))) => {
-                    // We already know this tenant exists in general, because we resolved it at
-                    // start of connection.  Getting a NotFound here indicates that the shard containing
-                    // the requested page is not present on this node: the client's knowledge of shard->pageserver
-                    // mapping is out of date.
-                    tracing::info!("Page request routed to wrong shard: my identity {:?}, should go to shard {}, key {}",
-                        timeline.get_shard_identity(), timeline.get_shard_identity().get_shard_number(&key).0, key);
-                    // Closing the connection by returning ``::Reconnect` has the side effect of rate-limiting above message, via
-                    // client's reconnect backoff, as well as hopefully prompting the client to load its updated configuration
-                    // and talk to a different pageserver.
-                    return Err(PageStreamError::Reconnect(
-                        "getpage@lsn request routed to wrong shard".into(),
-                    ));
-                }
-                Err(e) => return Err(e.into()),
-            };
-
-            // Take a GateGuard for the duration of this request.  If we were using our main Timeline object,
-            // the GateGuard was already held over the whole connection.
-            let _timeline_guard = timeline
-                .gate
-                .enter()
-                .map_err(|_| PageStreamError::Shutdown)?;
+                .map(|t| {
+                    self.shard_timelines
+                        .lock()
+                        .unwrap()
+                        .insert(shard_number, Arc::downgrade(&t));
+                    t
+                }),
+        };
+        match shard_timeline {
+            Ok(t) => Ok(t),
+            Err(GetActiveTimelineError::Tenant(GetActiveTenantError::NotFound(_))) => {
+                // We already know this tenant exists in general, because we resolved it at
+                // start of connection.  Getting a NotFound here indicates that the shard containing
+                // the requested page is not present on this node: the client's knowledge of shard->pageserver
+                // mapping is out of date.
+                tracing::info!("Page request routed to wrong shard: my identity {:?}, should go to shard {}, key {}",
+                    timeline.get_shard_identity(), shard_number.0, key);
+                // Closing the connection by returning ``::Reconnect` has the side effect of rate-limiting above message, via
+                // client's reconnect backoff, as well as hopefully prompting the client to load its updated configuration
+                // and talk to a different pageserver.
+                Err(PageStreamError::Reconnect(
+                    "getpage@lsn request routed to wrong shard".into(),
+                ))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
 
-            self.do_handle_get_page_at_lsn_request(&timeline, req, ctx)
-                .await
+    /// Look up the Timeline of the shard with the given number in [`Self::shard_timelines`].
+    ///
+    /// Entries are only used while they're still valid for `key`: after a shard split, or when
+    /// the shard was detached, its old Timeline is shut down, and the entry is dropped so that
+    /// the caller resolves the shard again.
+    fn cached_shard_timeline(
+        &self,
+        connection_timeline: &Timeline,
+        shard_number: ShardNumber,
+        key: &Key,
+    ) -> Option<Arc<Timeline>> {
+        let mut shard_timelines = self.shard_timelines.lock().unwrap();
+        let timeline = shard_timelines.get(&shard_number)?.upgrade();
+        match timeline {
+            Some(timeline)
+                if !timeline.cancel.is_cancelled()
+                    && timeline.timeline_id == connection_timeline.timeline_id
+                    && timeline.tenant_shard_id.tenant_id
+                        == connection_timeline.tenant_shard_id.tenant_id
+                    && timeline.get_shard_identity().is_key_local(key) =>
+            {
+                Some(timeline)
+            }
+            _ => {
+                shard_timelines.remove(&shard_number);
+                None
+            }
         }
     }
 
+    /// `include_slots` controls whether replication slot state is included in the backup, see
+    /// [`basebackup::send_basebackup_tarball`].
+    ///
+    /// If `wait_timeout` is set, waiting for the requested LSN to arrive is bounded by it
+    /// (in addition to the usual `wait_lsn_timeout`). When the wait times out, the request
+    /// fails, unless `best_effort` is set: then the backup is taken at the last record LSN
+    /// that is available on the timeline instead.
+    ///
+    /// `range_start` is the number of bytes at the start of the (possibly compressed) tarball
+    /// that are not sent, see [`basebackup::SkipPrefix`]. If it is set, even to zero, the
+    /// tarball is reproducible, so that the transfer can be resumed if it is interrupted.
+    ///
+    /// The backup is taken from shard zero of the tenant, unless `shard` selects another shard.
+    ///
+    /// `rel_filter` restricts the backup to the databases with these OIDs, see
+    /// [`basebackup::send_basebackup_tarball`].
     #[allow(clippy::too_many_arguments)]
     #[instrument(skip_all, fields(?lsn, ?prev_lsn, %full_backup))]
     async fn handle_basebackup_request<IO>(
@@ -964,6 +1983,13 @@ impl PageServerHandler {
         prev_lsn: Option<Lsn>,
         full_backup: bool,
         gzip: bool,
+        include_slots: bool,
+        manifest: bool,
+        wait_timeout: Option<Duration>,
+        best_effort: bool,
+        range_start: Option<u64>,
+        shard: Option<ShardNumber>,
+        rel_filter: Option<&[u32]>,
         ctx: RequestContext,
     ) -> anyhow::Result<()>
     where
@@ -974,72 +2000,160 @@ impl PageServerHandler {
         let started = std::time::Instant::now();
 
         // check that the timeline exists
-        let timeline = self
-            .get_active_tenant_timeline(tenant_id, timeline_id, ShardSelector::Zero)
-            .await?;
+        let selector = match shard {
+            Some(number) => ShardSelector::Known(number),
+            None => ShardSelector::Zero,
+        };
+        let timeline = match self
+            .get_active_tenant_timeline(tenant_id, timeline_id, selector)
+            .await
+        {
+            Ok(timeline) => timeline,
+            Err(GetActiveTimelineError::Tenant(GetActiveTenantError::NotFound(
+                GetTenantError::NotFound(_),
+            ))) if shard.is_some() => {
+                anyhow::bail!(
+                    "shard {} of tenant {tenant_id} is not attached to this pageserver",
+                    shard.unwrap().0
+                );
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        // Limit the number of concurrent basebackups, so that many computes starting at
+        // once don't saturate the node. The rest wait here for their turn.
+        let _permit = {
+            metrics::BASEBACKUP_QUEUED.inc();
+            scopeguard::defer! {
+                metrics::BASEBACKUP_QUEUED.dec();
+            }
+            tokio::select! {
+                biased;
+
+                _ = timeline.cancel.cancelled() => {
+                    return Err(QueryError::Shutdown.into());
+                }
+
+                permit = self.conf.concurrent_basebackups.inner().acquire() => {
+                    permit.expect("semaphore is never closed")
+                }
+            }
+        };
+
         let latest_gc_cutoff_lsn = timeline.get_latest_gc_cutoff_lsn();
-        if let Some(lsn) = lsn {
+        let lsn = if let Some(lsn) = lsn {
             // Backup was requested at a particular LSN. Wait for it to arrive.
             info!("waiting for {}", lsn);
-            timeline.wait_lsn(lsn, &ctx).await?;
+            let wait_result = match wait_timeout {
+                Some(wait_timeout) => {
+                    tokio::time::timeout(wait_timeout, timeline.wait_lsn(lsn, &ctx))
+                        .await
+                        .unwrap_or_else(|_| {
+                            Err(WaitLsnError::Timeout(format!(
+                                "Timed out after {} while waiting for WAL record at LSN {} to arrive, last_record_lsn {}",
+                                humantime::format_duration(wait_timeout),
+                                lsn,
+                                timeline.get_last_record_lsn(),
+                            )))
+                        })
+                }
+                None => timeline.wait_lsn(lsn, &ctx).await,
+            };
+            let lsn = match wait_result {
+                Ok(()) => lsn,
+                Err(WaitLsnError::Timeout(reason)) if best_effort => {
+                    let last_record_lsn = timeline.get_last_record_lsn();
+                    warn!("{reason}, taking best-effort basebackup at {last_record_lsn} instead");
+                    last_record_lsn
+                }
+                Err(e) => return Err(e.into()),
+            };
             timeline
                 .check_lsn_is_in_scope(lsn, &latest_gc_cutoff_lsn)
                 .context("invalid basebackup lsn")?;
-        }
+            Some(lsn)
+        } else {
+            None
+        };
 
         let lsn_awaited_after = started.elapsed();
 
+        // An unknown database fails the request here, rather than in the middle of the COPY.
+        if let Some(dbnodes) = rel_filter {
+            let backup_lsn = lsn.unwrap_or_else(|| timeline.get_last_record_lsn());
+            basebackup::check_rel_filter(&timeline, backup_lsn, dbnodes, &ctx).await?;
+        }
+
         // switch client to COPYOUT
         pgb.write_message_noflush(&BeMessage::CopyOutResponse)?;
         self.flush_cancellable(pgb, &timeline.cancel).await?;
 
         // Send a tarball of the latest layer on the timeline. Compress if not
         // fullbackup. TODO Compress in that case too (tests need to be updated)
+        let writer = basebackup::RateLimited::new(
+            pgb.copyout_writer(),
+            self.tunables.basebackup_bandwidth_limit,
+            timeline.cancel.clone(),
+        );
+        let reproducible = range_start.is_some();
+        let range_start = range_start.unwrap_or(0);
+        let mut writer = basebackup::SkipPrefix::new(writer, range_start);
         if full_backup {
-            let mut writer = pgb.copyout_writer();
             basebackup::send_basebackup_tarball(
                 &mut writer,
                 &timeline,
                 lsn,
                 prev_lsn,
                 full_backup,
+                include_slots,
+                manifest,
+                rel_filter,
+                reproducible,
+                &ctx,
+            )
+            .await?;
+        } else if gzip {
+            let mut encoder = GzipEncoder::with_quality(
+                &mut writer,
+                // NOTE using fast compression because it's on the critical path
+                //      for compute startup. For an empty database, we get
+                //      <100KB with this method. The Level::Best compression method
+                //      gives us <20KB, but maybe we should add basebackup caching
+                //      on compute shutdown first.
+                async_compression::Level::Fastest,
+            );
+            basebackup::send_basebackup_tarball(
+                &mut encoder,
+                &timeline,
+                lsn,
+                prev_lsn,
+                full_backup,
+                include_slots,
+                manifest,
+                rel_filter,
+                reproducible,
                 &ctx,
             )
             .await?;
+            // shutdown the encoder to ensure the gzip footer is written
+            encoder.shutdown().await?;
         } else {
-            let mut writer = pgb.copyout_writer();
-            if gzip {
-                let mut encoder = GzipEncoder::with_quality(
-                    writer,
-                    // NOTE using fast compression because it's on the critical path
-                    //      for compute startup. For an empty database, we get
-                    //      <100KB with this method. The Level::Best compression method
-                    //      gives us <20KB, but maybe we should add basebackup caching
-                    //      on compute shutdown first.
-                    async_compression::Level::Fastest,
-                );
-                basebackup::send_basebackup_tarball(
-                    &mut encoder,
-                    &timeline,
-                    lsn,
-                    prev_lsn,
-                    full_backup,
-                    &ctx,
-                )
-                .await?;
-                // shutdown the encoder to ensure the gzip footer is written
-                encoder.shutdown().await?;
-            } else {
-                basebackup::send_basebackup_tarball(
-                    &mut writer,
-                    &timeline,
-                    lsn,
-                    prev_lsn,
-                    full_backup,
-                    &ctx,
-                )
-                .await?;
-            }
+            basebackup::send_basebackup_tarball(
+                &mut writer,
+                &timeline,
+                lsn,
+                prev_lsn,
+                full_backup,
+                include_slots,
+                manifest,
+                rel_filter,
+                reproducible,
+                &ctx,
+            )
+            .await?;
+        }
+        if writer.remaining_skip() > 0 {
+            anyhow::bail!("range start {range_start} is beyond the end of the basebackup");
         }
 
         pgb.write_message_noflush(&BeMessage::CopyDone)?;
@@ -1059,6 +2173,151 @@ impl PageServerHandler {
         Ok(())
     }
 
+    /// Send just the pg_control file of the timeline at the given LSN, as a single CopyData
+    /// message. This is much cheaper than a basebackup for callers that only need the
+    /// system identifier or the checkpoint location.
+    #[instrument(skip_all, fields(?lsn))]
+    async fn handle_get_controlfile_request<IO>(
+        &mut self,
+        pgb: &mut PostgresBackend<IO>,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        lsn: Option<Lsn>,
+        ctx: RequestContext,
+    ) -> anyhow::Result<()>
+    where
+        IO: AsyncRead + AsyncWrite + Send + Sync + Unpin,
+    {
+        debug_assert_current_span_has_tenant_and_timeline_id();
+
+        let timeline = self
+            .get_active_tenant_timeline(tenant_id, timeline_id, ShardSelector::Zero)
+            .await?;
+        let latest_gc_cutoff_lsn = timeline.get_latest_gc_cutoff_lsn();
+        // Without an explicit LSN, use the latest one, like a GetPage request with `latest` set.
+        let lsn = Self::wait_or_get_last_lsn(
+            &timeline,
+            lsn.unwrap_or(Lsn(0)),
+            lsn.is_none(),
+            &latest_gc_cutoff_lsn,
+            &ctx,
+        )
+        .await?;
+
+        let control_file = timeline.get_control_file(lsn, &ctx).await?;
+
+        pgb.write_message_noflush(&BeMessage::CopyOutResponse)?;
+        pgb.write_message_noflush(&BeMessage::CopyData(&control_file))?;
+        pgb.write_message_noflush(&BeMessage::CopyDone)?;
+        self.flush_cancellable(pgb, &timeline.cancel).await?;
+
+        Ok(())
+    }
+
+    /// Reads a single page for debugging. The LSN is either given explicitly, resolved from a
+    /// point in time, or the latest one if neither is given.
+    #[allow(clippy::too_many_arguments)]
+    async fn handle_debug_getpage_request(
+        &self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        rel: RelTag,
+        blkno: BlockNumber,
+        lsn: Option<Lsn>,
+        timestamp: Option<std::time::SystemTime>,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<(Lsn, Bytes)> {
+        debug_assert_current_span_has_tenant_and_timeline_id();
+
+        let lsn = match timestamp {
+            Some(timestamp) => {
+                // Requires SLRU contents, which are only stored on shard zero
+                let timeline = self
+                    .get_active_tenant_timeline(tenant_id, timeline_id, ShardSelector::Zero)
+                    .await?;
+                let result = timeline
+                    .find_lsn_for_timestamp(
+                        postgres_ffi::to_pg_timestamp(timestamp),
+                        &timeline.cancel,
+                        ctx,
+                    )
+                    .await
+                    .context("find lsn for timestamp")?;
+                match result {
+                    LsnForTimestamp::Present(lsn) | LsnForTimestamp::Future(lsn) => Some(lsn),
+                    LsnForTimestamp::Past(lsn) => anyhow::bail!(
+                        "requested time predates the retained history of the timeline, which starts at LSN {lsn}"
+                    ),
+                    LsnForTimestamp::NoData(_) => {
+                        anyhow::bail!("no commit timestamps found to resolve the requested time")
+                    }
+                }
+            }
+            None => lsn,
+        };
+
+        let key = rel_block_to_key(rel, blkno);
+        let timeline = self
+            .get_active_tenant_timeline(tenant_id, timeline_id, ShardSelector::Page(key))
+            .await?;
+        let latest_gc_cutoff_lsn = timeline.get_latest_gc_cutoff_lsn();
+        let lsn = Self::wait_or_get_last_lsn(
+            &timeline,
+            lsn.unwrap_or(Lsn(0)),
+            lsn.is_none(),
+            &latest_gc_cutoff_lsn,
+            ctx,
+        )
+        .await?;
+        let page = timeline
+            .get_rel_page_at_lsn(rel, blkno, Version::Lsn(lsn), false, ctx)
+            .await?;
+
+        Ok((lsn, page))
+    }
+
+    /// Collect the base image and WAL records that contribute to a page at the given LSN, as
+    /// `(lsn, kind, size)` rows in replay order. Only the first [`DEBUG_PAGE_HISTORY_MAX_ROWS`]
+    /// rows are returned, along with the total number of entries in the history.
+    async fn handle_debug_page_history_request(
+        &self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        rel: RelTag,
+        blkno: BlockNumber,
+        lsn: Lsn,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<(Vec<(Lsn, &'static str, u64)>, usize)> {
+        debug_assert_current_span_has_tenant_and_timeline_id();
+
+        let key = rel_block_to_key(rel, blkno);
+        let timeline = self
+            .get_active_tenant_timeline(tenant_id, timeline_id, ShardSelector::Page(key))
+            .await?;
+        let latest_gc_cutoff_lsn = timeline.get_latest_gc_cutoff_lsn();
+        let lsn =
+            Self::wait_or_get_last_lsn(&timeline, lsn, false, &latest_gc_cutoff_lsn, ctx).await?;
+
+        let history = timeline.get_reconstruct_history(key, lsn, ctx).await?;
+
+        let entries = history.img.is_some() as usize + history.records.len();
+        let mut rows = Vec::with_capacity(entries.min(DEBUG_PAGE_HISTORY_MAX_ROWS));
+        if let Some((img_lsn, img)) = &history.img {
+            rows.push((*img_lsn, "image", img.len() as u64));
+        }
+        for (rec_lsn, rec) in &history.records {
+            if rows.len() == DEBUG_PAGE_HISTORY_MAX_ROWS {
+                break;
+            }
+            let size = match rec {
+                NeonWalRecord::Postgres { rec, .. } => rec.len() as u64,
+                rec => rec.serialized_size().context("serialize wal record")?,
+            };
+            rows.push((*rec_lsn, wal_record_kind(rec), size));
+        }
+        Ok((rows, entries))
+    }
+
     // when accessing management api supply None as an argument
     // when using to authorize tenant pass corresponding tenant id
     fn check_permission(&self, tenant_id: Option<TenantId>) -> Result<(), QueryError> {
@@ -1076,6 +2335,25 @@ impl PageServerHandler {
         check_permission(claims, tenant_id).map_err(|e| QueryError::Unauthorized(e.0))
     }
 
+    /// Like [`Self::check_permission`], for commands that only access a single timeline,
+    /// which connections authenticated with a timeline scoped token may also run.
+    fn check_timeline_permission(
+        &self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+    ) -> Result<(), QueryError> {
+        if self.auth.is_none() {
+            // auth is set to Trust, nothing to check so just return ok
+            return Ok(());
+        }
+        let claims = self
+            .claims
+            .as_ref()
+            .expect("claims presence already checked");
+        check_timeline_permission(claims, tenant_id, timeline_id)
+            .map_err(|e| QueryError::Unauthorized(e.0))
+    }
+
     /// Shorthand for getting a reference to a Timeline of an Active tenant.
     async fn get_active_tenant_timeline(
         &self,
@@ -1086,7 +2364,7 @@ impl PageServerHandler {
         let tenant = get_active_tenant_with_timeout(
             tenant_id,
             selector,
-            ACTIVE_TENANT_TIMEOUT,
+            self.tunables.active_tenant_timeout,
             &task_mgr::shutdown_token(),
         )
         .await
@@ -1120,21 +2398,64 @@ where
                 "jwt token scope is Tenant, but tenant id is missing".into(),
             ));
         }
+        if matches!(data.claims.scope, Scope::Timeline)
+            && (data.claims.tenant_id.is_none() || data.claims.timeline_id.is_none())
+        {
+            return Err(QueryError::Unauthorized(
+                "jwt token scope is Timeline, but tenant id or timeline id is missing".into(),
+            ));
+        }
 
         debug!(
-            "jwt scope check succeeded for scope: {:#?} by tenant id: {:?}",
-            data.claims.scope, data.claims.tenant_id,
+            "jwt scope check succeeded for scope: {:#?} by tenant id: {:?}, timeline id: {:?}",
+            data.claims.scope, data.claims.tenant_id, data.claims.timeline_id,
         );
 
         self.claims = Some(data.claims);
         Ok(())
     }
 
+    fn backend_key_data(&mut self) -> Option<CancelKeyData> {
+        let mut keys = REQUEST_CANCEL_KEYS.lock().unwrap();
+        let key = loop {
+            let key = rand::random();
+            if !keys.contains_key(&key) {
+                break key;
+            }
+        };
+        keys.insert(key, Arc::clone(&self.request_cancellation));
+        self.cancel_key = Some(key);
+        Some(key)
+    }
+
+    fn process_cancel_request(&mut self, key: &CancelKeyData) -> Result<(), QueryError> {
+        let cancellation = REQUEST_CANCEL_KEYS.lock().unwrap().get(key).cloned();
+        match cancellation {
+            Some(cancellation) => {
+                info!("cancelling in-flight requests of connection {key}");
+                cancellation.cancel();
+            }
+            None => info!("ignoring CancelRequest for unknown connection {key}"),
+        }
+        Ok(())
+    }
+
     fn startup(
         &mut self,
         _pgb: &mut PostgresBackend<IO>,
-        _sm: &FeStartupPacket,
+        sm: &FeStartupPacket,
     ) -> Result<(), QueryError> {
+        if let FeStartupPacket::StartupMessage { params, .. } = sm {
+            if let Some(options) = params.options_raw() {
+                for opt in options {
+                    if let Some((name, value)) = opt.split_once('=') {
+                        if let Some(setting) = ConnectionSetting::parse(name, value)? {
+                            self.apply_setting(setting);
+                        }
+                    }
+                }
+            }
+        }
         Ok(())
     }
 
@@ -1151,14 +2472,470 @@ where
 
         let ctx = self.connection_ctx.attached_child();
         debug!("process query {query_string:?}");
-        if query_string.starts_with("pagestream ") {
+        if query_string == "ping" {
+            // Liveness probe for load balancers: doesn't look at any tenant, so it keeps
+            // working however busy or broken the tenants are.
+            self.check_permission(None)?;
+
+            pgb.write_message_noflush(&BeMessage::RowDescription(&[
+                RowDescriptor::int8_col(b"uptime_seconds"),
+                RowDescriptor::text_col(b"current_time"),
+            ]))?
+            .write_message_noflush(&BeMessage::DataRow(&[
+                Some(self.launch_ts.elapsed().as_secs().to_string().as_bytes()),
+                Some(
+                    humantime::format_rfc3339_millis(std::time::SystemTime::now())
+                        .to_string()
+                        .as_bytes(),
+                ),
+            ]))?
+            .write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+        } else if query_string.starts_with("pagestream ") {
             let (_, params_raw) = query_string.split_at("pagestream ".len());
             let params = params_raw.split(' ').collect::<Vec<_>>();
+            if params.len() < 2 {
+                return Err(QueryError::Other(anyhow::anyhow!(
+                    "invalid param number for pagestream command"
+                )));
+            }
+            let tenant_id = TenantId::from_str(params[0])
+                .with_context(|| format!("Failed to parse tenant id from {}", params[0]))?;
+            let timeline_id = TimelineId::from_str(params[1])
+                .with_context(|| format!("Failed to parse timeline id from {}", params[1]))?;
+
+            let mut pipeline_depth = 1;
+            for (i, param) in params.iter().enumerate().skip(2) {
+                if let Some(value) = param.strip_prefix("--pipeline=") {
+                    pipeline_depth = value
+                        .parse::<usize>()
+                        .with_context(|| format!("Failed to parse pipeline depth from {value}"))?;
+                    if !(1..=MAX_PAGESTREAM_PIPELINE_DEPTH).contains(&pipeline_depth) {
+                        return Err(QueryError::Other(anyhow::anyhow!(
+                            "pipeline depth must be between 1 and {MAX_PAGESTREAM_PIPELINE_DEPTH}, got {pipeline_depth}"
+                        )));
+                    }
+                } else {
+                    return Err(QueryError::Other(anyhow::anyhow!(
+                        "Parameter in position {i} unknown {param}",
+                    )));
+                }
+            }
+
+            tracing::Span::current()
+                .record("tenant_id", field::display(tenant_id))
+                .record("timeline_id", field::display(timeline_id));
+
+            self.check_timeline_permission(tenant_id, timeline_id)?;
+
+            self.handle_pagerequests(pgb, tenant_id, timeline_id, pipeline_depth, ctx)
+                .await?;
+        } else if query_string.starts_with("basebackup_size ") {
+            // basebackup_size <tenant_id> <timeline_id> <lsn> [--full] [--no-slots]
+            //
+            // Estimate the size of the uncompressed tarball that basebackup (or fullbackup,
+            // with --full) would send, without sending it.
+            let (_, params_raw) = query_string.split_at("basebackup_size ".len());
+            let params = params_raw.split_whitespace().collect::<Vec<_>>();
+            if params.len() < 3 {
+                return Err(QueryError::Other(anyhow::anyhow!(
+                    "invalid param number for basebackup_size command"
+                )));
+            }
+            let tenant_id = TenantId::from_str(params[0])
+                .with_context(|| format!("Failed to parse tenant id from {}", params[0]))?;
+            let timeline_id = TimelineId::from_str(params[1])
+                .with_context(|| format!("Failed to parse timeline id from {}", params[1]))?;
+            let lsn = Lsn::from_str(params[2])
+                .with_context(|| format!("Failed to parse Lsn from {}", params[2]))?;
+
+            let mut full_backup = false;
+            let mut include_slots = true;
+            for (i, param) in params.iter().enumerate().skip(3) {
+                match *param {
+                    "--full" => full_backup = true,
+                    "--no-slots" => include_slots = false,
+                    "--with-slots" => include_slots = true,
+                    _ => {
+                        return Err(QueryError::Other(anyhow::anyhow!(
+                            "Parameter in position {i} unknown {param}",
+                        )))
+                    }
+                }
+            }
+
+            tracing::Span::current()
+                .record("tenant_id", field::display(tenant_id))
+                .record("timeline_id", field::display(timeline_id));
+
+            self.check_timeline_permission(tenant_id, timeline_id)?;
+            let timeline = self
+                .get_active_tenant_timeline(tenant_id, timeline_id, ShardSelector::Zero)
+                .await?;
+
+            timeline.wait_lsn(lsn, &ctx).await.map_err(|e| match e {
+                WaitLsnError::Shutdown => QueryError::Shutdown,
+                e => QueryError::Other(e.into()),
+            })?;
+            let latest_gc_cutoff_lsn = timeline.get_latest_gc_cutoff_lsn();
+            timeline
+                .check_lsn_is_in_scope(lsn, &latest_gc_cutoff_lsn)
+                .context("invalid basebackup lsn")?;
+
+            let size = basebackup::estimate_basebackup_tarball_size(
+                &timeline,
+                lsn,
+                full_backup,
+                include_slots,
+                &ctx,
+            )
+            .await?;
+
+            pgb.write_message_noflush(&BeMessage::RowDescription(&[RowDescriptor::int8_col(
+                b"size",
+            )]))?
+            .write_message_noflush(&BeMessage::DataRow(&[Some(size.to_string().as_bytes())]))?
+            .write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+        } else if query_string.starts_with("basebackup_to_file ") {
+            // basebackup_to_file <tenant_id> <timeline_id> <lsn> <file_name> [--full] [--no-slots]
+            //
+            // Write the uncompressed tarball that basebackup (or fullbackup, with --full) would
+            // send into a new file on the pageserver's disk instead, and return its size. The
+            // file goes into the basebackups directory of the pageserver workdir.
+            let (_, params_raw) = query_string.split_at("basebackup_to_file ".len());
+            let params = params_raw.split_whitespace().collect::<Vec<_>>();
+            if params.len() < 4 {
+                return Err(QueryError::Other(anyhow::anyhow!(
+                    "invalid param number for basebackup_to_file command"
+                )));
+            }
+            let tenant_id = TenantId::from_str(params[0])
+                .with_context(|| format!("Failed to parse tenant id from {}", params[0]))?;
+            let timeline_id = TimelineId::from_str(params[1])
+                .with_context(|| format!("Failed to parse timeline id from {}", params[1]))?;
+            let lsn = Lsn::from_str(params[2])
+                .with_context(|| format!("Failed to parse Lsn from {}", params[2]))?;
+
+            let mut full_backup = false;
+            let mut include_slots = true;
+            for (i, param) in params.iter().enumerate().skip(4) {
+                match *param {
+                    "--full" => full_backup = true,
+                    "--no-slots" => include_slots = false,
+                    "--with-slots" => include_slots = true,
+                    _ => {
+                        return Err(QueryError::Other(anyhow::anyhow!(
+                            "Parameter in position {i} unknown {param}",
+                        )))
+                    }
+                }
+            }
+
+            tracing::Span::current()
+                .record("tenant_id", field::display(tenant_id))
+                .record("timeline_id", field::display(timeline_id));
+
+            // This writes to the node's disk, so it's not for tenant-scoped tokens.
+            self.check_permission(None)?;
+            let basebackups_dir = self.conf.basebackups_path();
+            let path = basebackup_file_path(&basebackups_dir, params[3])?;
+
+            let timeline = self
+                .get_active_tenant_timeline(tenant_id, timeline_id, ShardSelector::Zero)
+                .await?;
+
+            let _permit = tokio::select! {
+                biased;
+
+                _ = timeline.cancel.cancelled() => {
+                    return Err(QueryError::Shutdown);
+                }
+
+                permit = self.conf.concurrent_basebackups.inner().acquire() => {
+                    permit.expect("semaphore is never closed")
+                }
+            };
+
+            timeline.wait_lsn(lsn, &ctx).await.map_err(|e| match e {
+                WaitLsnError::Shutdown => QueryError::Shutdown,
+                e => QueryError::Other(e.into()),
+            })?;
+            let latest_gc_cutoff_lsn = timeline.get_latest_gc_cutoff_lsn();
+            timeline
+                .check_lsn_is_in_scope(lsn, &latest_gc_cutoff_lsn)
+                .context("invalid basebackup lsn")?;
+
+            tokio::fs::create_dir_all(&basebackups_dir)
+                .await
+                .with_context(|| format!("create {basebackups_dir}"))?;
+            let size = basebackup::write_basebackup_tarball_to_file(
+                &path,
+                &timeline,
+                Some(lsn),
+                None,
+                full_backup,
+                include_slots,
+                &ctx,
+            )
+            .await?;
+            info!("wrote basebackup at {lsn} to {path}, {size} bytes");
+
+            pgb.write_message_noflush(&BeMessage::RowDescription(&[RowDescriptor::int8_col(
+                b"size",
+            )]))?
+            .write_message_noflush(&BeMessage::DataRow(&[Some(size.to_string().as_bytes())]))?
+            .write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+        } else if query_string.starts_with("basebackup_multi ") {
+            // basebackup_multi <tenant_id> <lsn> <timeline_id>[,<timeline_id>...] [--gzip] [--no-slots]
+            //
+            // Take basebackups of several timelines of a tenant at the same LSN, over one
+            // connection. The timelines are sent in the order in which they are listed. For
+            // each timeline, the response contains:
+            //
+            // - a NoticeResponse with the message "basebackup of timeline <timeline_id>", followed by
+            // - a CopyOutResponse, the tarball in CopyData messages, and CopyDone, like the
+            //   response to the basebackup command.
+            //
+            // After the last timeline, CommandComplete ends the response. If a basebackup fails,
+            // an ErrorResponse takes the place of the rest of the response.
+            let (_, params_raw) = query_string.split_at("basebackup_multi ".len());
+            let params = params_raw.split_whitespace().collect::<Vec<_>>();
+            if params.len() < 3 {
+                return Err(QueryError::Other(anyhow::anyhow!(
+                    "invalid param number for basebackup_multi command"
+                )));
+            }
+            let tenant_id = TenantId::from_str(params[0])
+                .with_context(|| format!("Failed to parse tenant id from {}", params[0]))?;
+            let lsn = Lsn::from_str(params[1])
+                .with_context(|| format!("Failed to parse Lsn from {}", params[1]))?;
+            let mut timeline_ids = Vec::new();
+            for timeline_id in params[2].split(',') {
+                let timeline_id = TimelineId::from_str(timeline_id)
+                    .with_context(|| format!("Failed to parse timeline id from {timeline_id}"))?;
+                if timeline_ids.contains(&timeline_id) {
+                    return Err(QueryError::Other(anyhow::anyhow!(
+                        "timeline {timeline_id} is listed more than once"
+                    )));
+                }
+                timeline_ids.push(timeline_id);
+            }
+
+            let mut gzip = false;
+            let mut include_slots = true;
+            for (i, param) in params.iter().enumerate().skip(3) {
+                match *param {
+                    "--gzip" => gzip = true,
+                    "--no-slots" => include_slots = false,
+                    "--with-slots" => include_slots = true,
+                    _ => {
+                        return Err(QueryError::Other(anyhow::anyhow!(
+                            "Parameter in position {i} unknown {param}",
+                        )))
+                    }
+                }
+            }
+
+            tracing::Span::current().record("tenant_id", field::display(tenant_id));
+
+            // Check all of them up front, rather than failing halfway through.
+            for &timeline_id in &timeline_ids {
+                self.check_timeline_permission(tenant_id, timeline_id)?;
+            }
+
+            for timeline_id in timeline_ids {
+                tracing::Span::current().record("timeline_id", field::display(timeline_id));
+
+                pgb.write_message_noflush(&BeMessage::NoticeResponse(&format!(
+                    "basebackup of timeline {timeline_id}"
+                )))?;
+                let ctx = ctx.attached_child();
+                ::metrics::metric_vec_duration::observe_async_block_duration_by_result(
+                    &*metrics::BASEBACKUP_QUERY_TIME,
+                    self.handle_basebackup_request(
+                        pgb,
+                        tenant_id,
+                        timeline_id,
+                        Some(lsn),
+                        None,
+                        false,
+                        gzip,
+                        include_slots,
+                        false,
+                        None,
+                        false,
+                        None,
+                        None,
+                        None,
+                        ctx,
+                    ),
+                )
+                .await?;
+            }
+            pgb.write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+        } else if query_string.starts_with("basebackup ") {
+            let (_, params_raw) = query_string.split_at("basebackup ".len());
+            let params = params_raw.split_whitespace().collect::<Vec<_>>();
+
+            if params.len() < 2 {
+                return Err(QueryError::Other(anyhow::anyhow!(
+                    "invalid param number for basebackup command"
+                )));
+            }
+
+            let tenant_id = TenantId::from_str(params[0])
+                .with_context(|| format!("Failed to parse tenant id from {}", params[0]))?;
+            let timeline_id = TimelineId::from_str(params[1])
+                .with_context(|| format!("Failed to parse timeline id from {}", params[1]))?;
+
+            tracing::Span::current()
+                .record("tenant_id", field::display(tenant_id))
+                .record("timeline_id", field::display(timeline_id));
+
+            self.check_timeline_permission(tenant_id, timeline_id)?;
+
+            let lsn = if params.len() >= 3 {
+                Some(
+                    Lsn::from_str(params[2])
+                        .with_context(|| format!("Failed to parse Lsn from {}", params[2]))?,
+                )
+            } else {
+                None
+            };
+
+            let mut prev_lsn = None;
+            let mut gzip = false;
+            let mut include_slots = true;
+            let mut manifest = false;
+            let mut wait_timeout = None;
+            let mut best_effort = false;
+            let mut range_start = None;
+            let mut shard = None;
+            let mut rel_filter = None;
+            for (i, param) in params.iter().enumerate().skip(3) {
+                if let Some(value) = param.strip_prefix("--prev-lsn=") {
+                    prev_lsn = Some(
+                        Lsn::from_str(value)
+                            .with_context(|| format!("Failed to parse prev Lsn from {value}"))?,
+                    );
+                } else if *param == "--gzip" {
+                    gzip = true;
+                } else if *param == "--no-slots" {
+                    include_slots = false;
+                } else if *param == "--with-slots" {
+                    include_slots = true;
+                } else if *param == "--manifest" {
+                    manifest = true;
+                } else if let Some(value) = param.strip_prefix("--wait-timeout=") {
+                    wait_timeout =
+                        Some(humantime::parse_duration(value).with_context(|| {
+                            format!("Failed to parse wait timeout from {value}")
+                        })?);
+                } else if *param == "--best-effort" {
+                    best_effort = true;
+                } else if let Some(value) = param.strip_prefix("--range-start=") {
+                    range_start =
+                        Some(value.parse().with_context(|| {
+                            format!("Failed to parse range start from {value}")
+                        })?);
+                } else if let Some(value) = param.strip_prefix("--shard=") {
+                    shard = Some(ShardNumber(value.parse().with_context(|| {
+                        format!("Failed to parse shard number from {value}")
+                    })?));
+                } else if let Some(value) = param.strip_prefix("--rel-filter=") {
+                    // Only the given databases, to restore part of a cluster from. That doesn't
+                    // give a runnable cluster, see `send_basebackup_tarball`.
+                    rel_filter = Some(parse_rel_filter(value)?);
+                } else {
+                    return Err(QueryError::Other(anyhow::anyhow!(
+                        "Parameter in position {i} unknown {param}",
+                    )));
+                }
+            }
+            // Resuming only makes sense if the tarball comes out the same as in the interrupted
+            // transfer, which requires a fixed LSN and prev LSN. A best-effort backup might be
+            // taken at a different LSN. Clients that want to be able to resume pass
+            // `--range-start=0` on the first attempt.
+            if range_start.is_some() && (lsn.is_none() || prev_lsn.is_none() || best_effort) {
+                return Err(QueryError::Other(anyhow::anyhow!(
+                    "--range-start requires an explicit lsn and --prev-lsn, and cannot be used with --best-effort"
+                )));
+            }
+
+            ::metrics::metric_vec_duration::observe_async_block_duration_by_result(
+                &*metrics::BASEBACKUP_QUERY_TIME,
+                async move {
+                    self.handle_basebackup_request(
+                        pgb,
+                        tenant_id,
+                        timeline_id,
+                        lsn,
+                        prev_lsn,
+                        false,
+                        gzip,
+                        include_slots,
+                        manifest,
+                        wait_timeout,
+                        best_effort,
+                        range_start,
+                        shard,
+                        rel_filter.as_deref(),
+                        ctx,
+                    )
+                    .await?;
+                    pgb.write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+                    anyhow::Ok(())
+                },
+            )
+            .await?;
+        }
+        // return pair of prev_lsn and last_lsn
+        else if query_string.starts_with("get_last_record_rlsn ") {
+            let (_, params_raw) = query_string.split_at("get_last_record_rlsn ".len());
+            let params = params_raw.split_whitespace().collect::<Vec<_>>();
+
+            if params.len() != 2 {
+                return Err(QueryError::Other(anyhow::anyhow!(
+                    "invalid param number for get_last_record_rlsn command"
+                )));
+            }
+
+            let tenant_id = TenantId::from_str(params[0])
+                .with_context(|| format!("Failed to parse tenant id from {}", params[0]))?;
+            let timeline_id = TimelineId::from_str(params[1])
+                .with_context(|| format!("Failed to parse timeline id from {}", params[1]))?;
+
+            tracing::Span::current()
+                .record("tenant_id", field::display(tenant_id))
+                .record("timeline_id", field::display(timeline_id));
+
+            self.check_timeline_permission(tenant_id, timeline_id)?;
+            let timeline = self
+                .get_active_tenant_timeline(tenant_id, timeline_id, ShardSelector::Zero)
+                .await?;
+
+            let end_of_timeline = timeline.get_last_record_rlsn();
+
+            pgb.write_message_noflush(&BeMessage::RowDescription(&[
+                RowDescriptor::text_col(b"prev_lsn"),
+                RowDescriptor::text_col(b"last_lsn"),
+            ]))?
+            .write_message_noflush(&BeMessage::DataRow(&[
+                Some(end_of_timeline.prev.to_string().as_bytes()),
+                Some(end_of_timeline.last.to_string().as_bytes()),
+            ]))?
+            .write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+        }
+        // return the LSNs below which history is, or may soon be, garbage collected
+        else if query_string.starts_with("gc_cutoff ") {
+            let (_, params_raw) = query_string.split_at("gc_cutoff ".len());
+            let params = params_raw.split_whitespace().collect::<Vec<_>>();
+
             if params.len() != 2 {
                 return Err(QueryError::Other(anyhow::anyhow!(
-                    "invalid param number for pagestream command"
+                    "invalid param number for gc_cutoff command"
                 )));
             }
+
             let tenant_id = TenantId::from_str(params[0])
                 .with_context(|| format!("Failed to parse tenant id from {}", params[0]))?;
             let timeline_id = TimelineId::from_str(params[1])
@@ -1168,17 +2945,32 @@ where
                 .record("tenant_id", field::display(tenant_id))
                 .record("timeline_id", field::display(timeline_id));
 
-            self.check_permission(Some(tenant_id))?;
-
-            self.handle_pagerequests(pgb, tenant_id, timeline_id, ctx)
+            self.check_timeline_permission(tenant_id, timeline_id)?;
+            let timeline = self
+                .get_active_tenant_timeline(tenant_id, timeline_id, ShardSelector::Zero)
                 .await?;
-        } else if query_string.starts_with("basebackup ") {
-            let (_, params_raw) = query_string.split_at("basebackup ".len());
+
+            let latest_gc_cutoff_lsn = *timeline.get_latest_gc_cutoff_lsn();
+            let pitr_cutoff_lsn = timeline.gc_info.read().unwrap().pitr_cutoff;
+
+            pgb.write_message_noflush(&BeMessage::RowDescription(&[
+                RowDescriptor::text_col(b"latest_gc_cutoff_lsn"),
+                RowDescriptor::text_col(b"pitr_cutoff_lsn"),
+            ]))?
+            .write_message_noflush(&BeMessage::DataRow(&[
+                Some(latest_gc_cutoff_lsn.to_string().as_bytes()),
+                Some(pitr_cutoff_lsn.to_string().as_bytes()),
+            ]))?
+            .write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+        }
+        // return the LSN that corresponds to the given point in time
+        else if query_string.starts_with("lsn_for_timestamp ") {
+            let (_, params_raw) = query_string.split_at("lsn_for_timestamp ".len());
             let params = params_raw.split_whitespace().collect::<Vec<_>>();
 
-            if params.len() < 2 {
+            if params.len() != 3 {
                 return Err(QueryError::Other(anyhow::anyhow!(
-                    "invalid param number for basebackup command"
+                    "invalid param number for lsn_for_timestamp command"
                 )));
             }
 
@@ -1186,63 +2978,58 @@ where
                 .with_context(|| format!("Failed to parse tenant id from {}", params[0]))?;
             let timeline_id = TimelineId::from_str(params[1])
                 .with_context(|| format!("Failed to parse timeline id from {}", params[1]))?;
+            let timestamp = humantime::parse_rfc3339(params[2])
+                .with_context(|| format!("Failed to parse timestamp from {}", params[2]))?;
 
             tracing::Span::current()
                 .record("tenant_id", field::display(tenant_id))
                 .record("timeline_id", field::display(timeline_id));
 
-            self.check_permission(Some(tenant_id))?;
+            self.check_timeline_permission(tenant_id, timeline_id)?;
+            // Requires SLRU contents, which are only stored on shard zero
+            let timeline = self
+                .get_active_tenant_timeline(tenant_id, timeline_id, ShardSelector::Zero)
+                .await?;
 
-            let lsn = if params.len() >= 3 {
-                Some(
-                    Lsn::from_str(params[2])
-                        .with_context(|| format!("Failed to parse Lsn from {}", params[2]))?,
+            let result = timeline
+                .find_lsn_for_timestamp(
+                    postgres_ffi::to_pg_timestamp(timestamp),
+                    &timeline.cancel,
+                    &ctx,
                 )
-            } else {
-                None
-            };
-
-            let gzip = if params.len() >= 4 {
-                if params[3] == "--gzip" {
-                    true
-                } else {
+                .await
+                .context("find lsn for timestamp")?;
+            let (lsn, kind) = match result {
+                LsnForTimestamp::Present(lsn) => (lsn, "present"),
+                LsnForTimestamp::Future(lsn) => (lsn, "future"),
+                LsnForTimestamp::NoData(lsn) => (lsn, "nodata"),
+                LsnForTimestamp::Past(lsn) => {
                     return Err(QueryError::Other(anyhow::anyhow!(
-                        "Parameter in position 3 unknown {}",
-                        params[3],
+                        "timestamp {} predates the retained history of the timeline, which starts at LSN {lsn}",
+                        params[2]
                     )));
                 }
-            } else {
-                false
             };
 
-            ::metrics::metric_vec_duration::observe_async_block_duration_by_result(
-                &*metrics::BASEBACKUP_QUERY_TIME,
-                async move {
-                    self.handle_basebackup_request(
-                        pgb,
-                        tenant_id,
-                        timeline_id,
-                        lsn,
-                        None,
-                        false,
-                        gzip,
-                        ctx,
-                    )
-                    .await?;
-                    pgb.write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
-                    anyhow::Ok(())
-                },
-            )
-            .await?;
+            pgb.write_message_noflush(&BeMessage::RowDescription(&[
+                RowDescriptor::text_col(b"lsn"),
+                RowDescriptor::text_col(b"kind"),
+            ]))?
+            .write_message_noflush(&BeMessage::DataRow(&[
+                Some(lsn.to_string().as_bytes()),
+                Some(kind.as_bytes()),
+            ]))?
+            .write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
         }
-        // return pair of prev_lsn and last_lsn
-        else if query_string.starts_with("get_last_record_rlsn ") {
-            let (_, params_raw) = query_string.split_at("get_last_record_rlsn ".len());
+        // return a single page, for debugging. The LSN can be given relative to the current
+        // time with `--ago=<duration>`, e.g. `--ago=10m`.
+        else if query_string.starts_with("debug_getpage ") {
+            let (_, params_raw) = query_string.split_at("debug_getpage ".len());
             let params = params_raw.split_whitespace().collect::<Vec<_>>();
 
-            if params.len() != 2 {
+            if params.len() < 4 || params.len() > 5 {
                 return Err(QueryError::Other(anyhow::anyhow!(
-                    "invalid param number for get_last_record_rlsn command"
+                    "invalid param number for debug_getpage command"
                 )));
             }
 
@@ -1250,28 +3037,147 @@ where
                 .with_context(|| format!("Failed to parse tenant id from {}", params[0]))?;
             let timeline_id = TimelineId::from_str(params[1])
                 .with_context(|| format!("Failed to parse timeline id from {}", params[1]))?;
+            let rel = parse_rel_tag(params[2])
+                .with_context(|| format!("Failed to parse relation from {}", params[2]))?;
+            let blkno = BlockNumber::from_str(params[3])
+                .with_context(|| format!("Failed to parse block number from {}", params[3]))?;
+
+            let mut lsn = None;
+            let mut timestamp = None;
+            if let Some(param) = params.get(4) {
+                if let Some(ago) = param.strip_prefix("--ago=") {
+                    let ago = humantime::parse_duration(ago)
+                        .with_context(|| format!("Failed to parse duration from {ago}"))?;
+                    timestamp = Some(
+                        std::time::SystemTime::now()
+                            .checked_sub(ago)
+                            .ok_or_else(|| anyhow::anyhow!("duration {ago:?} is out of range"))?,
+                    );
+                } else {
+                    lsn = Some(
+                        Lsn::from_str(param)
+                            .with_context(|| format!("Failed to parse Lsn from {param}"))?,
+                    );
+                }
+            }
 
             tracing::Span::current()
                 .record("tenant_id", field::display(tenant_id))
                 .record("timeline_id", field::display(timeline_id));
 
-            self.check_permission(Some(tenant_id))?;
-            let timeline = self
-                .get_active_tenant_timeline(tenant_id, timeline_id, ShardSelector::Zero)
-                .await?;
+            // Reads arbitrary data, so this is for administrators only
+            self.check_permission(None)?;
 
-            let end_of_timeline = timeline.get_last_record_rlsn();
+            let (lsn, page) = self
+                .handle_debug_getpage_request(
+                    tenant_id,
+                    timeline_id,
+                    rel,
+                    blkno,
+                    lsn,
+                    timestamp,
+                    &ctx,
+                )
+                .await?;
 
             pgb.write_message_noflush(&BeMessage::RowDescription(&[
-                RowDescriptor::text_col(b"prev_lsn"),
-                RowDescriptor::text_col(b"last_lsn"),
+                RowDescriptor::text_col(b"lsn"),
+                RowDescriptor::text_col(b"page"),
             ]))?
             .write_message_noflush(&BeMessage::DataRow(&[
-                Some(end_of_timeline.prev.to_string().as_bytes()),
-                Some(end_of_timeline.last.to_string().as_bytes()),
+                Some(lsn.to_string().as_bytes()),
+                Some(hex::encode(&page).as_bytes()),
             ]))?
             .write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
         }
+        // return the image and WAL records that the page is reconstructed from, for debugging
+        else if query_string.starts_with("debug_page_history ") {
+            let (_, params_raw) = query_string.split_at("debug_page_history ".len());
+            let params = params_raw.split_whitespace().collect::<Vec<_>>();
+
+            if params.len() != 5 {
+                return Err(QueryError::Other(anyhow::anyhow!(
+                    "invalid param number for debug_page_history command"
+                )));
+            }
+
+            let tenant_id = TenantId::from_str(params[0])
+                .with_context(|| format!("Failed to parse tenant id from {}", params[0]))?;
+            let timeline_id = TimelineId::from_str(params[1])
+                .with_context(|| format!("Failed to parse timeline id from {}", params[1]))?;
+            let rel = parse_rel_tag(params[2])
+                .with_context(|| format!("Failed to parse relation from {}", params[2]))?;
+            let blkno = BlockNumber::from_str(params[3])
+                .with_context(|| format!("Failed to parse block number from {}", params[3]))?;
+            let lsn = Lsn::from_str(params[4])
+                .with_context(|| format!("Failed to parse Lsn from {}", params[4]))?;
+
+            tracing::Span::current()
+                .record("tenant_id", field::display(tenant_id))
+                .record("timeline_id", field::display(timeline_id));
+
+            // Exposes WAL contents, so this is for administrators only
+            self.check_permission(None)?;
+
+            let (rows, entries) = self
+                .handle_debug_page_history_request(tenant_id, timeline_id, rel, blkno, lsn, &ctx)
+                .await?;
+
+            if entries > rows.len() {
+                pgb.write_message_noflush(&BeMessage::NoticeResponse(&format!(
+                    "page history has {entries} entries, only the oldest {} are returned",
+                    rows.len()
+                )))?;
+            }
+            pgb.write_message_noflush(&BeMessage::RowDescription(&[
+                RowDescriptor::text_col(b"lsn"),
+                RowDescriptor::text_col(b"kind"),
+                RowDescriptor::int8_col(b"size"),
+            ]))?;
+            for (lsn, kind, size) in &rows {
+                pgb.write_message_noflush(&BeMessage::DataRow(&[
+                    Some(lsn.to_string().as_bytes()),
+                    Some(kind.as_bytes()),
+                    Some(size.to_string().as_bytes()),
+                ]))?;
+            }
+            pgb.write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+        }
+        // return the pg_control file at the given LSN
+        else if query_string.starts_with("get_controlfile ") {
+            let (_, params_raw) = query_string.split_at("get_controlfile ".len());
+            let params = params_raw.split_whitespace().collect::<Vec<_>>();
+
+            if params.len() < 2 || params.len() > 3 {
+                return Err(QueryError::Other(anyhow::anyhow!(
+                    "invalid param number for get_controlfile command"
+                )));
+            }
+
+            let tenant_id = TenantId::from_str(params[0])
+                .with_context(|| format!("Failed to parse tenant id from {}", params[0]))?;
+            let timeline_id = TimelineId::from_str(params[1])
+                .with_context(|| format!("Failed to parse timeline id from {}", params[1]))?;
+
+            tracing::Span::current()
+                .record("tenant_id", field::display(tenant_id))
+                .record("timeline_id", field::display(timeline_id));
+
+            self.check_timeline_permission(tenant_id, timeline_id)?;
+
+            let lsn = if params.len() > 2 {
+                Some(
+                    Lsn::from_str(params[2])
+                        .with_context(|| format!("Failed to parse Lsn from {}", params[2]))?,
+                )
+            } else {
+                None
+            };
+
+            self.handle_get_controlfile_request(pgb, tenant_id, timeline_id, lsn, ctx)
+                .await?;
+            pgb.write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+        }
         // same as basebackup, but result includes relational data as well
         else if query_string.starts_with("fullbackup ") {
             let (_, params_raw) = query_string.split_at("fullbackup ".len());
@@ -1310,7 +3216,7 @@ where
                 None
             };
 
-            self.check_permission(Some(tenant_id))?;
+            self.check_timeline_permission(tenant_id, timeline_id)?;
 
             // Check that the timeline exists
             self.handle_basebackup_request(
@@ -1321,6 +3227,15 @@ where
                 prev_lsn,
                 true,
                 false,
+                // A full backup is a complete export of the timeline at the given LSN and
+                // prev_lsn, so it always carries the replication slots along.
+                true,
+                false,
+                None,
+                false,
+                None,
+                None,
+                None,
                 ctx,
             )
             .await?;
@@ -1337,13 +3252,32 @@ where
             // 2. Run:
             // cat my_backup/base.tar | psql -h $PAGESERVER \
             //     -c "import basebackup $TENANT $TIMELINE $START_LSN $END_LSN $PG_VERSION"
+            //
+            // With `--into-existing` after the parameters, the data is imported into an
+            // existing empty timeline that starts at $START_LSN, instead of a new one.
+            //
+            // With `--progress`, a notice with the number of bytes imported so far is sent
+            // periodically during the import.
             let (_, params_raw) = query_string.split_at("import basebackup ".len());
             let params = params_raw.split_whitespace().collect::<Vec<_>>();
-            if params.len() != 5 {
+            if params.len() < 5 {
                 return Err(QueryError::Other(anyhow::anyhow!(
                     "invalid param number for import basebackup command"
                 )));
             }
+            let mut into_existing = false;
+            let mut progress = false;
+            for (i, param) in params.iter().enumerate().skip(5) {
+                match *param {
+                    "--into-existing" => into_existing = true,
+                    "--progress" => progress = true,
+                    _ => {
+                        return Err(QueryError::Other(anyhow::anyhow!(
+                            "Parameter in position {i} unknown {param}",
+                        )))
+                    }
+                }
+            }
             let tenant_id = TenantId::from_str(params[0])
                 .with_context(|| format!("Failed to parse tenant id from {}", params[0]))?;
             let timeline_id = TimelineId::from_str(params[1])
@@ -1369,6 +3303,8 @@ where
                     base_lsn,
                     end_lsn,
                     pg_version,
+                    into_existing,
+                    progress,
                     ctx,
                 )
                 .await
@@ -1387,13 +3323,26 @@ where
             //
             // Files are scheduled to be persisted to remote storage, and the
             // caller should poll the http api to check when that is done.
+            //
+            // Takes `--progress` like import basebackup.
             let (_, params_raw) = query_string.split_at("import wal ".len());
             let params = params_raw.split_whitespace().collect::<Vec<_>>();
-            if params.len() != 4 {
+            if params.len() < 4 {
                 return Err(QueryError::Other(anyhow::anyhow!(
                     "invalid param number for import wal command"
                 )));
             }
+            let mut progress = false;
+            for (i, param) in params.iter().enumerate().skip(4) {
+                match *param {
+                    "--progress" => progress = true,
+                    _ => {
+                        return Err(QueryError::Other(anyhow::anyhow!(
+                            "Parameter in position {i} unknown {param}",
+                        )))
+                    }
+                }
+            }
             let tenant_id = TenantId::from_str(params[0])
                 .with_context(|| format!("Failed to parse tenant id from {}", params[0]))?;
             let timeline_id = TimelineId::from_str(params[1])
@@ -1407,10 +3356,18 @@ where
                 .record("tenant_id", field::display(tenant_id))
                 .record("timeline_id", field::display(timeline_id));
 
-            self.check_permission(Some(tenant_id))?;
+            self.check_timeline_permission(tenant_id, timeline_id)?;
 
             match self
-                .handle_import_wal(pgb, tenant_id, timeline_id, start_lsn, end_lsn, ctx)
+                .handle_import_wal(
+                    pgb,
+                    tenant_id,
+                    timeline_id,
+                    start_lsn,
+                    end_lsn,
+                    progress,
+                    ctx,
+                )
                 .await
             {
                 Ok(()) => pgb.write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?,
@@ -1423,14 +3380,19 @@ where
                 }
             };
         } else if query_string.to_ascii_lowercase().starts_with("set ") {
-            // important because psycopg2 executes "SET datestyle TO 'ISO'"
-            // on connect
+            // Settings that we know are applied to the connection, see [`ConnectionSetting`].
+            // Others are accepted and ignored, which is important because psycopg2 executes
+            // "SET datestyle TO 'ISO'" on connect.
+            if let Some(setting) = ConnectionSetting::parse_set_command(query_string)? {
+                info!("applying {setting:?}");
+                self.apply_setting(setting);
+            }
             pgb.write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
         } else if query_string.starts_with("show ") {
-            // show <tenant_id>
+            // show <tenant_id> [--no-wait] [--json]
             let (_, params_raw) = query_string.split_at("show ".len());
             let params = params_raw.split(' ').collect::<Vec<_>>();
-            if params.len() != 1 {
+            if params.is_empty() || params.len() > 3 {
                 return Err(QueryError::Other(anyhow::anyhow!(
                     "invalid param number for config command"
                 )));
@@ -1438,17 +3400,76 @@ where
             let tenant_id = TenantId::from_str(params[0])
                 .with_context(|| format!("Failed to parse tenant id from {}", params[0]))?;
 
+            let mut no_wait = false;
+            let mut json = false;
+            for param in params.iter().skip(1) {
+                match *param {
+                    "--no-wait" => no_wait = true,
+                    "--json" => json = true,
+                    _ => {
+                        return Err(QueryError::Other(anyhow::anyhow!(
+                            "Unknown show parameter {param}"
+                        )))
+                    }
+                }
+            }
+
             tracing::Span::current().record("tenant_id", field::display(tenant_id));
 
             self.check_permission(Some(tenant_id))?;
 
+            if no_wait {
+                // Report the tenant's state right away, instead of waiting for it to
+                // become active.
+                let state = mgr::get_tenant_state(tenant_id, ShardSelector::Zero)
+                    .map_err(|e| QueryError::NotFound(format!("{e}").into()))?;
+                let state: &'static str = (&state).into();
+                if json {
+                    let json = serde_json::json!({ "state": state }).to_string();
+                    pgb.write_message_noflush(&BeMessage::RowDescription(&[
+                        RowDescriptor::text_col(b"json"),
+                    ]))?
+                    .write_message_noflush(&BeMessage::DataRow(&[Some(json.as_bytes())]))?;
+                } else {
+                    pgb.write_message_noflush(&BeMessage::RowDescription(&[
+                        RowDescriptor::text_col(b"state"),
+                    ]))?
+                    .write_message_noflush(&BeMessage::DataRow(&[Some(state.as_bytes())]))?;
+                }
+                pgb.write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+                return Ok(());
+            }
+
             let tenant = get_active_tenant_with_timeout(
                 tenant_id,
                 ShardSelector::Zero,
-                ACTIVE_TENANT_TIMEOUT,
+                self.tunables.active_tenant_timeout,
                 &task_mgr::shutdown_token(),
             )
             .await?;
+
+            if json {
+                // Same values as the columns below, durations are in seconds.
+                let json = serde_json::json!({
+                    "checkpoint_distance": tenant.get_checkpoint_distance(),
+                    "checkpoint_timeout": tenant.get_checkpoint_timeout().as_secs(),
+                    "compaction_target_size": tenant.get_compaction_target_size(),
+                    "compaction_period": tenant.get_compaction_period().as_secs(),
+                    "compaction_threshold": tenant.get_compaction_threshold(),
+                    "gc_horizon": tenant.get_gc_horizon(),
+                    "gc_period": tenant.get_gc_period().as_secs(),
+                    "image_creation_threshold": tenant.get_image_creation_threshold(),
+                    "pitr_interval": tenant.get_pitr_interval().as_secs(),
+                })
+                .to_string();
+                pgb.write_message_noflush(&BeMessage::RowDescription(&[RowDescriptor::text_col(
+                    b"json",
+                )]))?
+                .write_message_noflush(&BeMessage::DataRow(&[Some(json.as_bytes())]))?
+                .write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+                return Ok(());
+            }
+
             pgb.write_message_noflush(&BeMessage::RowDescription(&[
                 RowDescriptor::int8_col(b"checkpoint_distance"),
                 RowDescriptor::int8_col(b"checkpoint_timeout"),
@@ -1484,6 +3505,81 @@ where
                 Some(tenant.get_pitr_interval().as_secs().to_string().as_bytes()),
             ]))?
             .write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+        } else if query_string.starts_with("get_heatmap ") {
+            // get_heatmap <tenant_shard_id>
+            let (_, params_raw) = query_string.split_at("get_heatmap ".len());
+            let params = params_raw.split_whitespace().collect::<Vec<_>>();
+            if params.len() != 1 {
+                return Err(QueryError::Other(anyhow::anyhow!(
+                    "invalid param number for get_heatmap command"
+                )));
+            }
+            let tenant_shard_id = TenantShardId::from_str(params[0])
+                .with_context(|| format!("Failed to parse tenant shard id from {}", params[0]))?;
+
+            tracing::Span::current().record("tenant_id", field::display(tenant_shard_id.tenant_id));
+
+            self.check_permission(None)?;
+
+            let tenant = get_active_tenant_with_timeout(
+                tenant_shard_id.tenant_id,
+                ShardSelector::Known(tenant_shard_id.shard_number),
+                self.tunables.active_tenant_timeout,
+                &task_mgr::shutdown_token(),
+            )
+            .await?;
+            let Some(heatmap) = secondary::serialize_tenant_heatmap(&tenant).await? else {
+                return Err(QueryError::Other(anyhow::anyhow!(
+                    "heatmap of tenant {tenant_shard_id} is not available yet, a timeline is not ready"
+                )));
+            };
+
+            pgb.write_message_noflush(&BeMessage::CopyOutResponse)?;
+            pgb.write_message_noflush(&BeMessage::CopyData(&heatmap))?;
+            pgb.write_message_noflush(&BeMessage::CopyDone)?;
+            pgb.write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+        } else if query_string.starts_with("list_shards ") {
+            // list_shards <tenant_id>
+            let (_, params_raw) = query_string.split_at("list_shards ".len());
+            let params = params_raw.split_whitespace().collect::<Vec<_>>();
+            if params.len() != 1 {
+                return Err(QueryError::Other(anyhow::anyhow!(
+                    "invalid param number for list_shards command"
+                )));
+            }
+            let tenant_id = TenantId::from_str(params[0])
+                .with_context(|| format!("Failed to parse tenant id from {}", params[0]))?;
+
+            tracing::Span::current().record("tenant_id", field::display(tenant_id));
+
+            self.check_permission(Some(tenant_id))?;
+
+            let shards = mgr::list_tenant_shards(tenant_id)
+                .map_err(|e| QueryError::Other(anyhow::anyhow!(e)))?;
+            if shards.is_empty() {
+                return Err(QueryError::NotFound(
+                    format!("no shards of tenant {tenant_id} on this pageserver").into(),
+                ));
+            }
+
+            pgb.write_message_noflush(&BeMessage::RowDescription(&[
+                RowDescriptor::text_col(b"tenant_shard_id"),
+                RowDescriptor::int8_col(b"shard_number"),
+                RowDescriptor::int8_col(b"shard_count"),
+                RowDescriptor::text_col(b"state"),
+            ]))?;
+            for (tenant_shard_id, state) in &shards {
+                let state: &'static str = state.into();
+                pgb.write_message_noflush(&BeMessage::DataRow(&[
+                    Some(tenant_shard_id.to_string().as_bytes()),
+                    Some(tenant_shard_id.shard_number.0.to_string().as_bytes()),
+                    Some(tenant_shard_id.shard_count.0.to_string().as_bytes()),
+                    Some(state.as_bytes()),
+                ]))?;
+            }
+            pgb.write_message_noflush(&BeMessage::CommandComplete(
+                format!("SELECT {}", shards.len()).as_bytes(),
+            ))?;
         } else {
             return Err(QueryError::Other(anyhow::anyhow!(
                 "unknown command {query_string}"
@@ -1526,3 +3622,277 @@ impl From<GetActiveTimelineError> for QueryError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, DuplexStream};
+
+    fn copyin_backend() -> (PostgresBackend<DuplexStream>, DuplexStream) {
+        let (client, server) = tokio::io::duplex(1024);
+        let pgb = PostgresBackend::new_from_io(
+            server,
+            "127.0.0.1:5432".parse().unwrap(),
+            AuthType::Trust,
+            None,
+        )
+        .unwrap();
+        (pgb, client)
+    }
+
+    /// Read the COPY IN stream to its end like the import handlers do, and return the
+    /// termination cause found in the error.
+    async fn copyin_termination(
+        pgb: &mut PostgresBackend<DuplexStream>,
+        cancel: &CancellationToken,
+    ) -> Option<CopyInTermination> {
+        let mut reader = pin!(StreamReader::new(PageServerHandler::copyin_stream(
+            pgb, cancel, None
+        )));
+        let mut buf = Vec::new();
+        let err = reader.read_to_end(&mut buf).await.unwrap_err();
+        assert_eq!(buf, [1, 2, 3]);
+        CopyInTermination::find(&anyhow::Error::new(err).context("import failed"))
+    }
+
+    #[test]
+    fn basebackup_file_path_stays_in_basebackups_dir() {
+        let dir = Utf8Path::new("/data/pageserver/basebackups");
+        assert_eq!(
+            basebackup_file_path(dir, "base.tar").unwrap(),
+            dir.join("base.tar")
+        );
+
+        for file_name in [
+            "",
+            ".",
+            "..",
+            "../base.tar",
+            "./base.tar",
+            "backups/base.tar",
+            "/tmp/base.tar",
+        ] {
+            basebackup_file_path(dir, file_name).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn pagestream_connection_limit() {
+        let connections = AtomicUsize::new(0);
+        let first = PagestreamConnectionPermit::acquire(&connections, 2).unwrap();
+        let _second = PagestreamConnectionPermit::acquire(&connections, 2).unwrap();
+
+        // A connection beyond the limit is rejected, and doesn't count
+        assert!(PagestreamConnectionPermit::acquire(&connections, 2).is_none());
+        assert_eq!(connections.load(std::sync::atomic::Ordering::Relaxed), 2);
+
+        // until another one ends
+        drop(first);
+        let _third = PagestreamConnectionPermit::acquire(&connections, 2).unwrap();
+        assert!(PagestreamConnectionPermit::acquire(&connections, 2).is_none());
+
+        // 0 is unlimited
+        let unlimited: Vec<_> = (0..10)
+            .map(|_| PagestreamConnectionPermit::acquire(&connections, 0).unwrap())
+            .collect();
+        assert_eq!(connections.load(std::sync::atomic::Ordering::Relaxed), 12);
+        drop(unlimited);
+        assert_eq!(connections.load(std::sync::atomic::Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn parse_rel_filter_oids() {
+        assert_eq!(parse_rel_filter("5").unwrap(), vec![5]);
+        assert_eq!(parse_rel_filter("5,16384").unwrap(), vec![5, 16384]);
+
+        parse_rel_filter("").unwrap_err();
+        parse_rel_filter("5,").unwrap_err();
+        parse_rel_filter("0").unwrap_err();
+        parse_rel_filter("-1").unwrap_err();
+        parse_rel_filter("postgres").unwrap_err();
+    }
+
+    #[test]
+    fn parse_rel_tag_roundtrip() {
+        for rel in [
+            RelTag {
+                forknum: 0,
+                spcnode: 1663,
+                dbnode: 5,
+                relnode: 16384,
+            },
+            RelTag {
+                forknum: 2,
+                spcnode: 1664,
+                dbnode: 0,
+                relnode: 1262,
+            },
+        ] {
+            assert_eq!(parse_rel_tag(&rel.to_string()).unwrap(), rel);
+        }
+
+        parse_rel_tag("1663/5").unwrap_err();
+        parse_rel_tag("1663/5/16384_foo").unwrap_err();
+        parse_rel_tag("1663/x/16384").unwrap_err();
+    }
+
+    // CopyData with a 3 byte payload
+    const COPY_DATA: [u8; 8] = [b'd', 0, 0, 0, 7, 1, 2, 3];
+    const TERMINATE: [u8; 5] = [b'X', 0, 0, 0, 4];
+    const COPY_DONE: [u8; 5] = [b'c', 0, 0, 0, 4];
+
+    #[tokio::test]
+    async fn copyin_client_terminate() {
+        let (mut pgb, mut client) = copyin_backend();
+        client.write_all(&COPY_DATA).await.unwrap();
+        client.write_all(&TERMINATE).await.unwrap();
+
+        let cancel = CancellationToken::new();
+        let termination = copyin_termination(&mut pgb, &cancel).await;
+        assert_eq!(termination, Some(CopyInTermination::ClientTerminated));
+    }
+
+    #[tokio::test]
+    async fn copyin_client_closed() {
+        let (mut pgb, mut client) = copyin_backend();
+        client.write_all(&COPY_DATA).await.unwrap();
+        drop(client);
+
+        let cancel = CancellationToken::new();
+        let termination = copyin_termination(&mut pgb, &cancel).await;
+        assert_eq!(termination, Some(CopyInTermination::ClientClosed));
+    }
+
+    #[tokio::test]
+    async fn copyin_shutdown() {
+        let (mut pgb, mut client) = copyin_backend();
+        client.write_all(&COPY_DATA).await.unwrap();
+
+        let cancel = CancellationToken::new();
+        let mut reader = pin!(StreamReader::new(PageServerHandler::copyin_stream(
+            &mut pgb, &cancel, None
+        )));
+        let mut buf = [0u8; 3];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, [1, 2, 3]);
+
+        // The client is still connected, but we are shutting down
+        cancel.cancel();
+        let err = reader.read_u8().await.unwrap_err();
+        let err = anyhow::Error::new(err).context("import failed");
+        assert_eq!(
+            CopyInTermination::find(&err),
+            Some(CopyInTermination::Shutdown)
+        );
+        assert!(matches!(
+            CopyInTermination::import_error(err),
+            QueryError::Shutdown
+        ));
+    }
+
+    #[tokio::test]
+    async fn copyin_progress() {
+        let (mut pgb, mut client) = copyin_backend();
+        for _ in 0..3 {
+            client.write_all(&COPY_DATA).await.unwrap();
+        }
+        client.write_all(&COPY_DONE).await.unwrap();
+
+        let cancel = CancellationToken::new();
+        let mut reader = pin!(StreamReader::new(PageServerHandler::copyin_stream(
+            &mut pgb,
+            &cancel,
+            Some(5)
+        )));
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, [1, 2, 3, 1, 2, 3, 1, 2, 3]);
+
+        // One notice, after the second CopyData. The third one doesn't make another 5 bytes.
+        let mut notices = vec![0u8; 1024];
+        let n = client.read(&mut notices).await.unwrap();
+        let notices = String::from_utf8_lossy(&notices[..n]);
+        assert_eq!(notices.matches("imported").count(), 1);
+        assert!(notices.contains("imported 6 bytes"), "{notices}");
+    }
+
+    #[tokio::test]
+    async fn request_cancellation() {
+        let cancellation = RequestCancellation::default();
+        let ctx = RequestContext::new(TaskKind::PageRequestHandler, DownloadBehavior::Download);
+        let in_flight = RequestContextBuilder::extend(&ctx.attached_request_child())
+            .cancel(cancellation.token())
+            .build();
+        let child = in_flight.attached_child();
+
+        cancellation.cancel();
+        in_flight.cancelled().await;
+        child.cancelled().await;
+
+        // Requests that start after the CancelRequest are not cancelled
+        let later = RequestContextBuilder::extend(&ctx.attached_request_child())
+            .cancel(cancellation.token())
+            .build();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), later.cancelled())
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn set_command() {
+        use ConnectionSetting::{PagestreamCorrelationIds, PagestreamEffectiveLsn};
+        for (query, expected) in [
+            (
+                "SET pagestream_effective_lsn TO true",
+                Some(PagestreamEffectiveLsn(true)),
+            ),
+            (
+                "set pagestream_effective_lsn = 'false';",
+                Some(PagestreamEffectiveLsn(false)),
+            ),
+            (
+                "SET SESSION pagestream_effective_lsn=true",
+                Some(PagestreamEffectiveLsn(true)),
+            ),
+            (
+                "SET Pagestream_Effective_Lsn =false",
+                Some(PagestreamEffectiveLsn(false)),
+            ),
+            (
+                "SET pagestream_correlation_ids TO true",
+                Some(PagestreamCorrelationIds(true)),
+            ),
+            // Unknown settings and other syntax are accepted, and ignored
+            ("SET datestyle TO 'ISO'", None),
+            ("SET TIME ZONE 'UTC'", None),
+            ("SET pagestream_effective_lsn", None),
+        ] {
+            assert_eq!(
+                ConnectionSetting::parse_set_command(query).unwrap(),
+                expected,
+                "{query}"
+            );
+        }
+
+        // But a setting that we know must have a valid value
+        ConnectionSetting::parse_set_command("SET pagestream_effective_lsn TO maybe").unwrap_err();
+    }
+
+    #[test]
+    fn accept_fd_exhaustion() {
+        for (errno, expected) in [
+            (nix::errno::Errno::EMFILE, true),
+            (nix::errno::Errno::ENFILE, true),
+            (nix::errno::Errno::ECONNABORTED, false),
+        ] {
+            let err = io::Error::from_raw_os_error(errno as i32);
+            assert_eq!(is_fd_exhaustion(&err), expected, "{errno}");
+        }
+        assert!(!is_fd_exhaustion(&io::Error::new(
+            io::ErrorKind::Other,
+            "not an OS error"
+        )));
+    }
+}
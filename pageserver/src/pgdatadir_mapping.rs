@@ -187,6 +187,34 @@ impl Timeline {
         version.get(self, key, ctx).await
     }
 
+    /// Look up the LSN at which a relation page was last modified, at or below `lsn`. See
+    /// [`Timeline::get_last_modified_lsn`]. Pages beyond the end of the relation were never
+    /// written, which is reported as [`Lsn::INVALID`].
+    pub(crate) async fn get_rel_page_last_modified_lsn(
+        &self,
+        tag: RelTag,
+        blknum: BlockNumber,
+        lsn: Lsn,
+        latest: bool,
+        ctx: &RequestContext,
+    ) -> Result<Lsn, PageReconstructError> {
+        if tag.relnode == 0 {
+            return Err(PageReconstructError::Other(
+                RelationError::InvalidRelnode.into(),
+            ));
+        }
+
+        let nblocks = self
+            .get_rel_size(tag, Version::Lsn(lsn), latest, ctx)
+            .await?;
+        if blknum >= nblocks {
+            return Ok(Lsn::INVALID);
+        }
+
+        let key = rel_block_to_key(tag, blknum);
+        self.get_last_modified_lsn(key, lsn, ctx).await
+    }
+
     // Get size of a database in blocks
     pub(crate) async fn get_db_size(
         &self,
@@ -58,7 +58,7 @@ use self::timeline::uninit::UninitializedTimeline;
 use self::timeline::EvictionTaskTenantState;
 use self::timeline::TimelineResources;
 use self::timeline::WaitLsnError;
-use crate::config::PageServerConf;
+use crate::config::{PageServerConf, PageServiceTunables};
 use crate::context::{DownloadBehavior, RequestContext};
 use crate::deletion_queue::DeletionQueueClient;
 use crate::deletion_queue::DeletionQueueError;
@@ -91,6 +91,7 @@ use std::io;
 use std::ops::Bound::Included;
 use std::process::Stdio;
 use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::sync::{Mutex, RwLock};
@@ -139,6 +140,7 @@ macro_rules! pausable_failpoint {
         }
     };
 }
+pub(crate) use pausable_failpoint;
 
 pub mod blob_io;
 pub mod block_io;
@@ -158,6 +160,7 @@ pub mod delete;
 pub mod mgr;
 pub mod secondary;
 pub mod tasks;
+pub(crate) mod throttle;
 pub mod upload_queue;
 
 pub(crate) mod timeline;
@@ -302,6 +305,15 @@ pub struct Tenant {
     // Users of the Tenant such as the page service must take this Gate to avoid
     // trying to use a Tenant which is shutting down.
     pub(crate) gate: Gate,
+
+    /// Rate limit for the page_service requests of this tenant, see
+    /// [`PageServerConf::page_service_tenant_rate_limit`]. Updated when the configuration is
+    /// reloaded, see [`PageServiceTunables`].
+    pub(crate) pagestream_throttle: throttle::Throttle,
+
+    /// Number of open pagestream connections that started at this tenant shard, see
+    /// [`PageServerConf::page_service_tenant_max_connections`].
+    pub(crate) pagestream_connections: AtomicUsize,
 }
 
 impl std::fmt::Debug for Tenant {
@@ -1560,6 +1572,14 @@ impl Tenant {
             .collect()
     }
 
+    /// Drain the pagestream connections of all the tenant's timelines, see
+    /// [`Timeline::set_pagestream_drain`]. Timelines created later are not drained.
+    pub(crate) fn set_pagestream_drain(&self, reason: Option<String>) {
+        for timeline in self.list_timelines() {
+            timeline.set_pagestream_drain(reason.clone());
+        }
+    }
+
     pub fn list_timeline_ids(&self) -> Vec<TimelineId> {
         self.timelines.lock().unwrap().keys().cloned().collect()
     }
@@ -2643,6 +2663,10 @@ impl Tenant {
             delete_progress: Arc::new(tokio::sync::Mutex::new(DeleteTenantFlow::default())),
             cancel: CancellationToken::default(),
             gate: Gate::new(format!("Tenant<{tenant_shard_id}>")),
+            pagestream_throttle: throttle::Throttle::new(
+                PageServiceTunables::current(conf).tenant_rate_limit,
+            ),
+            pagestream_connections: AtomicUsize::new(0),
         }
     }
 
@@ -4129,6 +4153,43 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_get_last_modified_lsn() -> anyhow::Result<()> {
+        let (tenant, ctx) = TenantHarness::create("test_get_last_modified_lsn")?
+            .load()
+            .await;
+        let tline = tenant
+            .create_test_timeline(TIMELINE_ID, Lsn(0x08), DEFAULT_PG_VERSION, &ctx)
+            .await?;
+
+        for lsn in [Lsn(0x10), Lsn(0x20)] {
+            let writer = tline.writer().await;
+            writer
+                .put(
+                    *TEST_KEY,
+                    lsn,
+                    &Value::Image(TEST_IMG(&format!("foo at {lsn}"))),
+                    &ctx,
+                )
+                .await?;
+            writer.finish_write(lsn);
+        }
+
+        for (lsn, expected) in [
+            (Lsn(0x10), Lsn(0x10)),
+            (Lsn(0x1f), Lsn(0x10)),
+            (Lsn(0x20), Lsn(0x20)),
+        ] {
+            assert_eq!(
+                tline.get_last_modified_lsn(*TEST_KEY, lsn, &ctx).await?,
+                expected,
+                "at {lsn}"
+            );
+        }
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn no_duplicate_timelines() -> anyhow::Result<()> {
         let (tenant, ctx) = TenantHarness::create("no_duplicate_timelines")?
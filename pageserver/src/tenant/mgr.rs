@@ -22,7 +22,7 @@ use tracing::*;
 use remote_storage::GenericRemoteStorage;
 use utils::crashsafe;
 
-use crate::config::PageServerConf;
+use crate::config::{PageServerConf, PageServiceTunables};
 use crate::context::{DownloadBehavior, RequestContext};
 use crate::control_plane_client::{
     ControlPlaneClient, ControlPlaneGenerationsApi, RetryForeverError,
@@ -114,6 +114,8 @@ pub(crate) enum ShardSelector {
     First,
     /// Pick the shard that holds this key
     Page(Key),
+    /// Pick the shard with this number, if it is present.
+    Known(ShardNumber),
 }
 
 impl TenantsMap {
@@ -152,6 +154,9 @@ impl TenantsMap {
                         ShardSelector::Zero if slot.0.shard_number == ShardNumber(0) => {
                             return Some(*slot.0)
                         }
+                        ShardSelector::Known(number) if slot.0.shard_number == number => {
+                            return Some(*slot.0)
+                        }
                         ShardSelector::Page(key) => {
                             // First slot we see for this tenant, calculate the expected shard number
                             // for the key: we will use this for checking if this and subsequent
@@ -1164,6 +1169,24 @@ impl TenantManager {
                 .collect(),
         }
     }
+
+    /// Apply reloaded [`PageServiceTunables`] to the tenants that are attached already. Tenants
+    /// that are attached later pick up the current values when they are created.
+    pub fn apply_page_service_tunables(&self, tunables: &PageServiceTunables) {
+        let locked = self.tenants.read().unwrap();
+        let map = match &*locked {
+            TenantsMap::Initializing => return,
+            TenantsMap::Open(m) | TenantsMap::ShuttingDown(m) => m,
+        };
+        for slot in map.values() {
+            if let Some(tenant) = slot.get_attached() {
+                tenant
+                    .pagestream_throttle
+                    .set_rate(tunables.tenant_rate_limit);
+            }
+        }
+    }
+
     // Do some synchronous work for all tenant slots in Secondary state.  The provided
     // callback should be small and fast, as it will be called inside the global
     // TenantsMap lock.
@@ -1333,6 +1356,49 @@ pub(crate) enum GetActiveTenantError {
     WillNotBecomeActive(TenantState),
 }
 
+/// Gets the current state of a tenant's attached shard, without waiting for it to become
+/// active.
+///
+/// This method is cancel-safe.
+pub(crate) fn get_tenant_state(
+    tenant_id: TenantId,
+    shard_selector: ShardSelector,
+) -> Result<TenantState, GetTenantError> {
+    let locked = TENANTS.read().unwrap();
+
+    let tenant_shard_id = locked
+        .resolve_attached_shard(&tenant_id, shard_selector)
+        .ok_or(GetTenantError::NotFound(tenant_id))?;
+
+    match tenant_map_peek_slot(&locked, &tenant_shard_id, TenantSlotPeekMode::Read)? {
+        Some(TenantSlot::Attached(tenant)) => Ok(tenant.current_state()),
+        Some(TenantSlot::InProgress(_)) | Some(TenantSlot::Secondary(_)) => {
+            Err(GetTenantError::NotActive(tenant_id))
+        }
+        None => Err(GetTenantError::NotFound(tenant_id)),
+    }
+}
+
+/// List the shards of a tenant that are attached to this pageserver, with their current states.
+/// Secondary and in-progress slots are skipped, as they have no [`TenantState`].
+///
+/// This method is cancel-safe.
+pub(crate) fn list_tenant_shards(
+    tenant_id: TenantId,
+) -> Result<Vec<(TenantShardId, TenantState)>, TenantMapListError> {
+    let locked = TENANTS.read().unwrap();
+    let m = match &*locked {
+        TenantsMap::Initializing => return Err(TenantMapListError::Initializing),
+        TenantsMap::Open(m) | TenantsMap::ShuttingDown(m) => m,
+    };
+    Ok(m.range(TenantShardId::tenant_range(tenant_id))
+        .filter_map(|(id, slot)| match slot {
+            TenantSlot::Attached(tenant) => Some((*id, tenant.current_state())),
+            TenantSlot::Secondary(_) | TenantSlot::InProgress(_) => None,
+        })
+        .collect())
+}
+
 /// Get a [`Tenant`] in its active state. If the tenant_id is currently in [`TenantSlot::InProgress`]
 /// state, then wait for up to `timeout`.  If the [`Tenant`] is not currently in [`TenantState::Active`],
 /// then wait for up to `timeout` (minus however long we waited for the slot).
@@ -9,10 +9,10 @@ use crate::task_mgr::{self, TaskKind, BACKGROUND_RUNTIME};
 
 use self::{
     downloader::{downloader_task, SecondaryDetail},
-    heatmap_uploader::heatmap_uploader_task,
+    heatmap_uploader::{generate_tenant_heatmap, heatmap_uploader_task},
 };
 
-use super::{config::SecondaryLocationConfig, mgr::TenantManager};
+use super::{config::SecondaryLocationConfig, mgr::TenantManager, Tenant};
 
 use pageserver_api::shard::TenantShardId;
 use remote_storage::GenericRemoteStorage;
@@ -20,6 +20,21 @@ use remote_storage::GenericRemoteStorage;
 use tokio_util::sync::CancellationToken;
 use utils::{completion::Barrier, sync::gate::Gate};
 
+/// Serialize the current heatmap of an attached tenant, in the same format as the heatmaps that
+/// are uploaded for secondary locations. `None` if it can't be generated yet because a timeline
+/// isn't ready.
+pub(crate) async fn serialize_tenant_heatmap(tenant: &Tenant) -> anyhow::Result<Option<Vec<u8>>> {
+    let generation = tenant.get_generation();
+    if generation.is_none() {
+        // Generation::none is not serializable
+        anyhow::bail!("tenant has no generation, so it has no heatmap");
+    }
+    match generate_tenant_heatmap(tenant, generation).await {
+        Some(heatmap) => Ok(Some(serde_json::to_vec(&heatmap)?)),
+        None => Ok(None),
+    }
+}
+
 enum DownloadCommand {
     Download(TenantShardId),
 }
@@ -29,7 +29,7 @@ use super::{
 };
 use tokio_util::sync::CancellationToken;
 use tracing::{info_span, instrument, Instrument};
-use utils::{backoff, completion::Barrier, yielding_loop::yielding_loop};
+use utils::{backoff, completion::Barrier, generation::Generation, yielding_loop::yielding_loop};
 
 use super::{heatmap::HeatMapTenant, UploadCommand};
 
@@ -347,6 +347,29 @@ enum UploadHeatmapError {
     Upload(#[from] anyhow::Error),
 }
 
+/// Generate the heatmap of a tenant from the layers of its timelines. `None` if a timeline
+/// isn't ready to generate one yet.
+pub(super) async fn generate_tenant_heatmap(
+    tenant: &Tenant,
+    generation: Generation,
+) -> Option<HeatMapTenant> {
+    let mut heatmap = HeatMapTenant {
+        timelines: Vec::new(),
+        generation,
+    };
+    let timelines = tenant.timelines.lock().unwrap().clone();
+
+    for (timeline_id, timeline) in timelines {
+        let Some(heatmap_timeline) = timeline.generate_heatmap().await else {
+            tracing::debug!("Timeline {timeline_id} is not ready to generate a heatmap");
+            return None;
+        };
+        heatmap.timelines.push(heatmap_timeline);
+    }
+
+    Some(heatmap)
+}
+
 /// The inner upload operation.  This will skip if `last_digest` is Some and matches the digest
 /// of the object we would have uploaded.
 async fn upload_tenant_heatmap(
@@ -365,12 +388,6 @@ async fn upload_tenant_heatmap(
         return Ok(UploadHeatmapOutcome::Skipped);
     }
 
-    let mut heatmap = HeatMapTenant {
-        timelines: Vec::new(),
-        generation,
-    };
-    let timelines = tenant.timelines.lock().unwrap().clone();
-
     let tenant_cancel = tenant.cancel.clone();
 
     // Ensure that Tenant::shutdown waits for any upload in flight: this is needed because otherwise
@@ -384,20 +401,10 @@ async fn upload_tenant_heatmap(
         }
     };
 
-    for (timeline_id, timeline) in timelines {
-        let heatmap_timeline = timeline.generate_heatmap().await;
-        match heatmap_timeline {
-            None => {
-                tracing::debug!(
-                    "Skipping heatmap upload because timeline {timeline_id} is not ready"
-                );
-                return Ok(UploadHeatmapOutcome::Skipped);
-            }
-            Some(heatmap_timeline) => {
-                heatmap.timelines.push(heatmap_timeline);
-            }
-        }
-    }
+    let Some(heatmap) = generate_tenant_heatmap(tenant, generation).await else {
+        tracing::debug!("Skipping heatmap upload because a timeline is not ready");
+        return Ok(UploadHeatmapOutcome::Skipped);
+    };
 
     // Serialize the heatmap
     let bytes = serde_json::to_vec(&heatmap).map_err(|e| anyhow::anyhow!(e))?;
@@ -619,7 +619,7 @@ impl Drop for DeltaLayerWriter {
             // We want to remove the virtual file here, so it's fine to not
             // having completely flushed unwritten data.
             let vfile = inner.blob_writer.into_inner_no_flush();
-            vfile.remove();
+            vfile.remove_blocking();
         }
     }
 }
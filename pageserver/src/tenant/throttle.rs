@@ -0,0 +1,146 @@
+//! A token bucket rate limiter for the page_service requests of a tenant.
+//!
+//! Each request takes one token from the bucket. Tokens are refilled continuously at the
+//! configured rate, and the bucket holds at most one second worth of tokens, which is the
+//! burst that a tenant may issue after having been idle.
+//!
+//! Requests that exceed the rate are not rejected, but delayed until a token is available.
+//! A request reserves its token before waiting, so concurrent requests are served in the
+//! order in which they arrived.
+
+use std::time::{Duration, Instant};
+
+pub(crate) struct Throttle {
+    state: std::sync::Mutex<State>,
+}
+
+struct State {
+    /// Tokens per second, `None` if throttling is disabled.
+    rate: Option<f64>,
+    /// Number of tokens in the bucket. Goes negative when requests have reserved tokens
+    /// that are not refilled yet.
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Throttle {
+    /// Create a throttle that allows `rate_per_sec` requests per second. A rate of 0 disables
+    /// throttling.
+    pub(crate) fn new(rate_per_sec: u64) -> Self {
+        let rate = Self::rate(rate_per_sec);
+        Throttle {
+            state: std::sync::Mutex::new(State {
+                rate,
+                tokens: rate.unwrap_or(0.0),
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    fn rate(rate_per_sec: u64) -> Option<f64> {
+        (rate_per_sec > 0).then_some(rate_per_sec as f64)
+    }
+
+    /// Change the rate, e.g. after the configuration was reloaded. Requests that are already
+    /// waiting keep their delay, the bucket is refilled at the new rate from now on.
+    pub(crate) fn set_rate(&self, rate_per_sec: u64) {
+        let rate = Self::rate(rate_per_sec);
+        let mut state = self.state.lock().unwrap();
+        if state.rate == rate {
+            return;
+        }
+        let now = Instant::now();
+        if let Some(old_rate) = state.rate {
+            let elapsed = now.saturating_duration_since(state.last_refill);
+            state.tokens = (state.tokens + elapsed.as_secs_f64() * old_rate).min(old_rate);
+        }
+        state.tokens = match rate {
+            // A bucket that was unlimited before starts out full.
+            Some(rate) if state.rate.is_none() => rate,
+            Some(rate) => state.tokens.min(rate),
+            None => 0.0,
+        };
+        state.last_refill = now;
+        state.rate = rate;
+    }
+
+    /// Take a token from the bucket, waiting until it is available.
+    ///
+    /// Returns how long the caller was delayed.
+    pub(crate) async fn throttle(&self) -> Duration {
+        let Some(delay) = self.reserve(Instant::now()) else {
+            return Duration::ZERO;
+        };
+        tokio::time::sleep(delay).await;
+        delay
+    }
+
+    /// Take a token, returning how long the caller needs to wait before it may proceed.
+    fn reserve(&self, now: Instant) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap();
+        let rate = state.rate?;
+        let elapsed = now.saturating_duration_since(state.last_refill);
+        state.tokens = (state.tokens + elapsed.as_secs_f64() * rate).min(rate);
+        state.last_refill = now;
+        state.tokens -= 1.0;
+        if state.tokens >= 0.0 {
+            None
+        } else {
+            Some(Duration::from_secs_f64(-state.tokens / rate))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlimited() {
+        let throttle = Throttle::new(0);
+        let now = Instant::now();
+        for _ in 0..10_000 {
+            assert_eq!(throttle.reserve(now), None);
+        }
+    }
+
+    #[test]
+    fn burst_then_steady_rate() {
+        let throttle = Throttle::new(10);
+        let now = Instant::now();
+
+        // A full bucket allows a burst of one second worth of requests.
+        for _ in 0..10 {
+            assert_eq!(throttle.reserve(now), None);
+        }
+
+        // Subsequent requests are queued up behind each other.
+        let first = throttle.reserve(now).unwrap();
+        let second = throttle.reserve(now).unwrap();
+        assert!((first.as_secs_f64() - 0.1).abs() < 1e-6, "{first:?}");
+        assert!((second.as_secs_f64() - 0.2).abs() < 1e-6, "{second:?}");
+
+        // Once the backlog has been refilled, requests pass again.
+        let later = now + Duration::from_millis(300);
+        assert_eq!(throttle.reserve(later), None);
+    }
+
+    #[test]
+    fn change_rate() {
+        let throttle = Throttle::new(0);
+
+        // Enabling the throttle starts out with a full bucket.
+        throttle.set_rate(10);
+        let now = Instant::now();
+        for _ in 0..10 {
+            assert_eq!(throttle.reserve(now), None);
+        }
+        assert!(throttle.reserve(now).is_some());
+
+        // Disabling it lets everything through right away.
+        throttle.set_rate(0);
+        for _ in 0..10_000 {
+            assert_eq!(throttle.reserve(now), None);
+        }
+    }
+}
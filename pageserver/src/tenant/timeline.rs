@@ -29,7 +29,7 @@ use tokio::{
 };
 use tokio_util::sync::CancellationToken;
 use tracing::*;
-use utils::sync::gate::Gate;
+use utils::sync::gate::{Gate, GateGuard};
 
 use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::ops::{Deref, Range};
@@ -293,6 +293,10 @@ pub struct Timeline {
     pub last_received_wal: Mutex<Option<WalReceiverInfo>>,
     pub walreceiver: Mutex<Option<WalReceiver>>,
 
+    /// Held while a basebackup is imported into the timeline, see
+    /// [`Timeline::prepare_basebackup_import`].
+    import_lock: Arc<tokio::sync::Mutex<()>>,
+
     /// Relation size cache
     pub rel_size_cache: RwLock<HashMap<RelTag, (Lsn, BlockNumber)>>,
 
@@ -317,6 +321,10 @@ pub struct Timeline {
     /// to the timeline should drop out when this token fires.
     pub(crate) cancel: CancellationToken,
 
+    /// If set, pagestream connections to this timeline should end, for the given reason. See
+    /// [`Self::set_pagestream_drain`].
+    pagestream_drain: watch::Sender<Option<String>>,
+
     /// Make sure we only have one running compaction at a time in tests.
     ///
     /// Must only be taken in two places:
@@ -564,6 +572,80 @@ impl Timeline {
         res
     }
 
+    /// Look up the LSN of the newest version of the given key at or below `lsn`, without
+    /// reconstructing the value.
+    ///
+    /// That is the LSN of the newest WAL record for the key, or of the newest image if there is
+    /// no WAL record after it. Images are stored at the LSN at which they were created, e.g. by
+    /// compaction, so the result can be later than the WAL record that really changed the key.
+    ///
+    /// # Cancel-Safety
+    ///
+    /// This method is cancellation-safe.
+    pub(crate) async fn get_last_modified_lsn(
+        &self,
+        key: Key,
+        lsn: Lsn,
+        ctx: &RequestContext,
+    ) -> Result<Lsn, PageReconstructError> {
+        if !lsn.is_valid() {
+            return Err(PageReconstructError::Other(anyhow::anyhow!("Invalid LSN")));
+        }
+        debug_assert!(!self.shard_identity.is_key_disposable(&key));
+
+        // Don't consult the page cache: the LSN of a cached page is the LSN at which it was
+        // requested, not the one at which it was modified.
+        let mut reconstruct_state = ValueReconstructState {
+            records: Vec::new(),
+            img: None,
+        };
+        self.get_reconstruct_data(key, lsn, &mut reconstruct_state, ctx)
+            .await?;
+
+        reconstruct_state
+            .records
+            .iter()
+            .map(|(record_lsn, _)| *record_lsn)
+            .chain(reconstruct_state.img.as_ref().map(|(img_lsn, _)| *img_lsn))
+            .max()
+            .ok_or_else(|| {
+                PageReconstructError::Other(anyhow::anyhow!(
+                    "no versions of key {key} found at or below {lsn}"
+                ))
+            })
+    }
+
+    /// Collect the base image and WAL records that [`Self::get`] would use to reconstruct the
+    /// given key at `lsn`, without performing the WAL redo. For debugging.
+    ///
+    /// The records are returned in replay order, oldest first. The page cache is not consulted,
+    /// so the base image, if any, is the one stored in the layers.
+    ///
+    /// # Cancel-Safety
+    ///
+    /// This method is cancellation-safe.
+    pub(crate) async fn get_reconstruct_history(
+        &self,
+        key: Key,
+        lsn: Lsn,
+        ctx: &RequestContext,
+    ) -> Result<ValueReconstructState, PageReconstructError> {
+        if !lsn.is_valid() {
+            return Err(PageReconstructError::Other(anyhow::anyhow!("Invalid LSN")));
+        }
+        debug_assert!(!self.shard_identity.is_key_disposable(&key));
+
+        let mut reconstruct_state = ValueReconstructState {
+            records: Vec::new(),
+            img: None,
+        };
+        self.get_reconstruct_data(key, lsn, &mut reconstruct_state, ctx)
+            .await?;
+        reconstruct_state.records.reverse();
+
+        Ok(reconstruct_state)
+    }
+
     /// Get last or prev record separately. Same as get_last_record_rlsn().last/prev.
     pub fn get_last_record_lsn(&self) -> Lsn {
         self.last_record_lsn.load().last
@@ -1057,6 +1139,18 @@ impl Timeline {
         self.state.subscribe()
     }
 
+    /// Ask the computes that stream pages from this timeline to reconnect, e.g. before the
+    /// tenant is moved to another pageserver: page_service finishes the requests it has
+    /// received, says goodbye to clients that understand it and closes the connections, also
+    /// new ones. Lasts until called again with `None`.
+    pub(crate) fn set_pagestream_drain(&self, reason: Option<String>) {
+        self.pagestream_drain.send_replace(reason);
+    }
+
+    pub(crate) fn subscribe_for_pagestream_drain(&self) -> watch::Receiver<Option<String>> {
+        self.pagestream_drain.subscribe()
+    }
+
     pub async fn wait_to_become_active(
         &self,
         _ctx: &RequestContext, // Prepare for use by cancellation
@@ -1082,6 +1176,74 @@ impl Timeline {
         }
     }
 
+    /// Does the timeline hold no data at all, neither in memory nor in layer files? That is the
+    /// case for timelines that were created empty and that nothing was imported into yet.
+    pub(crate) async fn is_empty(&self) -> bool {
+        let guard = self.layers.read().await;
+        let layer_map = guard.layer_map();
+        layer_map.open_layer.is_none()
+            && layer_map.frozen_layers.is_empty()
+            && layer_map.iter_historic_layers().next().is_none()
+    }
+
+    /// Prepare an import of a basebackup into this timeline, which must have been created empty
+    /// at `base_lsn` and must not have been written to since.
+    ///
+    /// The returned [`BasebackupImport`] keeps other imports out until it is dropped, so that the
+    /// timeline cannot stop being empty between these checks and the import. The WAL receiver is
+    /// the only other writer, so the import is refused while it runs.
+    pub(crate) async fn prepare_basebackup_import(
+        self: &Arc<Self>,
+        base_lsn: Lsn,
+        pg_version: u32,
+    ) -> anyhow::Result<BasebackupImport> {
+        let import_guard = Arc::clone(&self.import_lock)
+            .try_lock_owned()
+            .map_err(|_| {
+                anyhow!(
+                    "Cannot import basebackup into timeline {} because another import is in progress",
+                    self.timeline_id
+                )
+            })?;
+        // Block timeline shutdown until the import is complete.
+        let gate_guard = self.gate.enter().map_err(|_| {
+            anyhow!(
+                "Cannot import basebackup into timeline {} because it is shutting down",
+                self.timeline_id
+            )
+        })?;
+
+        ensure!(
+            self.pg_version == pg_version,
+            "Cannot import basebackup of PostgreSQL {pg_version} into timeline {} of PostgreSQL {}",
+            self.timeline_id,
+            self.pg_version
+        );
+        ensure!(
+            self.walreceiver.lock().unwrap().is_none(),
+            "Cannot import basebackup into timeline {} because it is receiving WAL",
+            self.timeline_id
+        );
+        ensure!(
+            self.initdb_lsn == base_lsn,
+            "Cannot import basebackup from Lsn {base_lsn} because timeline {} does not start from the same lsn: {}",
+            self.timeline_id,
+            self.initdb_lsn
+        );
+        ensure!(
+            self.is_empty().await,
+            "Cannot import basebackup into timeline {} because it is not empty",
+            self.timeline_id
+        );
+
+        Ok(BasebackupImport {
+            timeline: Arc::clone(self),
+            base_lsn,
+            _import_guard: import_guard,
+            _gate_guard: gate_guard,
+        })
+    }
+
     pub async fn layer_map_info(&self, reset: LayerAccessStatsReset) -> LayerMapInfo {
         let guard = self.layers.read().await;
         let layer_map = guard.layer_map();
@@ -1286,6 +1448,7 @@ impl Timeline {
 
                 walredo_mgr,
                 walreceiver: Mutex::new(None),
+                import_lock: Arc::new(tokio::sync::Mutex::new(())),
 
                 remote_client: resources.remote_client.map(Arc::new),
 
@@ -1355,6 +1518,7 @@ impl Timeline {
 
                 cancel,
                 gate: Gate::new(format!("Timeline<{tenant_shard_id}/{timeline_id}>")),
+                pagestream_drain: watch::channel(None).0,
 
                 compaction_lock: tokio::sync::Mutex::default(),
                 gc_lock: tokio::sync::Mutex::default(),
@@ -4423,6 +4587,47 @@ impl Timeline {
     }
 }
 
+/// An import of a basebackup into an empty timeline, see [`Timeline::prepare_basebackup_import`].
+pub(crate) struct BasebackupImport {
+    timeline: Arc<Timeline>,
+    base_lsn: Lsn,
+    _import_guard: tokio::sync::OwnedMutexGuard<()>,
+    _gate_guard: GateGuard,
+}
+
+impl BasebackupImport {
+    /// Import the basebackup tarball read from `reader`, and flush it to disk.
+    ///
+    /// Data that has reached the timeline cannot be taken back, so if the import fails after
+    /// that, the timeline is marked broken and has to be deleted and created again. If the import
+    /// fails before, the timeline stays empty and the import can be retried.
+    pub(crate) async fn import_from_tar(
+        self,
+        reader: &mut (impl tokio::io::AsyncRead + Send + Sync + Unpin),
+        ctx: &RequestContext,
+    ) -> anyhow::Result<()> {
+        let timeline = &self.timeline;
+        let res = async {
+            crate::import_datadir::import_basebackup_from_tar(timeline, reader, self.base_lsn, ctx)
+                .await
+                .context("Failed to import basebackup")?;
+            timeline.maybe_spawn_flush_loop();
+            timeline
+                .freeze_and_flush()
+                .await
+                .context("Failed to flush after basebackup import")
+        }
+        .await;
+
+        if let Err(e) = &res {
+            if !timeline.is_empty().await {
+                timeline.set_broken(format!("basebackup import failed: {e:#}"));
+            }
+        }
+        res
+    }
+}
+
 pub(crate) struct DiskUsageEvictionInfo {
     /// Timeline's largest layer (remote or resident)
     pub max_layer_size: Option<u64>,
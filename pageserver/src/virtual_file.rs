@@ -12,16 +12,25 @@
 //!
 use crate::metrics::{StorageIoOperation, STORAGE_IO_SIZE, STORAGE_IO_TIME_METRIC};
 use crate::tenant::TENANTS_SEGMENT_NAME;
+use arc_swap::ArcSwap;
 use camino::{Utf8Path, Utf8PathBuf};
 use once_cell::sync::OnceCell;
 use std::fs::{self, File, OpenOptions};
 use std::io::{Error, ErrorKind, Seek, SeekFrom};
+use std::num::NonZeroUsize;
 use std::os::unix::fs::FileExt;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
 use utils::fs_ext;
 
+mod buf_reader;
+#[cfg(any(test, feature = "testing"))]
+pub mod in_memory;
+
+pub use buf_reader::{VirtualFileBufReader, DEFAULT_BUF_READER_CAPACITY};
+
 ///
 /// A virtual file descriptor. You can use this just like std::fs::File, but internally
 /// the underlying file is closed if the system is low on file descriptors,
@@ -39,28 +48,111 @@ use utils::fs_ext;
 ///
 #[derive(Debug)]
 pub struct VirtualFile {
-    /// Lazy handle to the global file descriptor cache. The slot that this points to
-    /// might contain our File, or it may be empty, or it may contain a File that
-    /// belongs to a different VirtualFile.
-    handle: RwLock<SlotHandle>,
+    backend: Backend,
 
     /// Current file position
     pos: u64,
 
-    /// File path and options to use to open it.
-    ///
-    /// Note: this only contains the options needed to re-open it. For example,
-    /// if a new file is created, we only pass the create flag when it's initially
-    /// opened, in the VirtualFile::create() function, and strip the flag before
-    /// storing it here.
     pub path: Utf8PathBuf,
-    open_options: OpenOptions,
 
     // These are strings becase we only use them for metrics, and those expect strings.
     // It makes no sense for us to constantly turn the `TimelineId` and `TenantId` into
     // strings.
     tenant_id: String,
     timeline_id: String,
+
+    /// Running CRC32C of the bytes written, if enabled with [`VirtualFile::enable_checksum`].
+    checksum: Option<AtomicU32>,
+}
+
+/// Where the contents of a [`VirtualFile`] are kept.
+#[derive(Debug)]
+enum Backend {
+    /// A file on the local filesystem, opened through the global file descriptor cache.
+    FdCache {
+        /// Lazy handle to the global file descriptor cache. The slot that this points to
+        /// might contain our File, or it may be empty, or it may contain a File that
+        /// belongs to a different VirtualFile.
+        handle: RwLock<SlotHandle>,
+
+        /// Options to use to re-open the file at `path`.
+        ///
+        /// Note: this only contains the options needed to re-open it. For example,
+        /// if a new file is created, we only pass the create flag when it's initially
+        /// opened, in the VirtualFile::create() function, and strip the flag before
+        /// storing it here.
+        open_options: OpenOptions,
+        /// The flags that `open_options` opens the file with, see [`VirtualFile::reopen_flags`].
+        reopen_flags: ReopenFlags,
+    },
+    /// A file that only exists in memory, see [`in_memory`].
+    #[cfg(any(test, feature = "testing"))]
+    InMemory(in_memory::InMemoryFile),
+}
+
+/// Maximum number of fsyncs that [`VirtualFile::sync_all_batch`] has in flight at a time.
+const SYNC_ALL_BATCH_CONCURRENCY: usize = 16;
+
+/// Error of [`VirtualFile::sync_all_batch`], listing the files that could not be synced.
+#[derive(Debug, thiserror::Error)]
+#[error("failed to fsync {} files, first {}: {}", .errors.len(), .errors[0].0, .errors[0].1)]
+pub struct SyncAllBatchError {
+    pub errors: Vec<(Utf8PathBuf, Error)>,
+}
+
+/// Error of [`VirtualFile::read_exact_at_counted`]: reading stopped after `nread` of the
+/// `requested` bytes.
+#[derive(Debug, thiserror::Error)]
+#[error("read {nread} of {requested} bytes: {source}")]
+pub struct PartialReadError {
+    pub nread: usize,
+    pub requested: usize,
+    #[source]
+    pub source: Error,
+}
+
+/// Error of [`VirtualFile::read_at_cancellable`].
+#[derive(Debug, thiserror::Error)]
+pub enum CancellableReadError {
+    /// The cancellation token fired before the read completed.
+    #[error("read cancelled")]
+    Cancelled,
+    #[error(transparent)]
+    Io(#[from] Error),
+}
+
+/// The access mode and status flags that a [`VirtualFile`] is opened with, when it's
+/// re-opened after its descriptor was evicted from the cache. Unlike [`OpenOptions`], these
+/// can be inspected.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct ReopenFlags {
+    pub read: bool,
+    pub write: bool,
+    pub append: bool,
+    /// `O_DIRECT`, see [`VirtualFile::open_direct`]. Always false on platforms without it.
+    pub direct: bool,
+}
+
+impl ReopenFlags {
+    /// The flags of an open file, as the kernel reports them. The flags that only matter when
+    /// opening the file, like `O_CREAT` and `O_TRUNC`, are not reported, and are not used to
+    /// re-open it either.
+    fn of(file: &File) -> Result<Self, Error> {
+        use nix::fcntl::{fcntl, FcntlArg, OFlag};
+        use std::os::fd::AsRawFd;
+        let flags = OFlag::from_bits_truncate(fcntl(file.as_raw_fd(), FcntlArg::F_GETFL)?);
+        let access_mode = flags & OFlag::O_ACCMODE;
+        #[cfg(target_os = "linux")]
+        let direct = flags.contains(OFlag::O_DIRECT);
+        #[cfg(not(target_os = "linux"))]
+        let direct = false;
+        Ok(ReopenFlags {
+            read: access_mode == OFlag::O_RDONLY || access_mode == OFlag::O_RDWR,
+            write: access_mode == OFlag::O_WRONLY || access_mode == OFlag::O_RDWR,
+            append: flags.contains(OFlag::O_APPEND),
+            direct,
+        })
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -81,14 +173,47 @@ struct SlotHandle {
 ///
 /// OPEN_FILES starts in uninitialized state, and it's initialized by
 /// the virtual_file::init() function. It must be called exactly once at page
-/// server startup.
+/// server startup. The number of slots can be increased later with
+/// virtual_file::resize().
 static OPEN_FILES: OnceCell<OpenFiles> = OnceCell::new();
 
 struct OpenFiles {
-    slots: &'static [Slot],
+    /// The slots themselves are leaked and never move or go away, so a slot can be
+    /// referenced with a 'static lifetime, and a SlotHandle's index stays valid forever.
+    ///
+    /// Growing the array swaps in a new, longer list of references that starts with the
+    /// existing slots. Readers just load the current list without locking; one that still
+    /// sees the old list merely doesn't use the new slots yet. Concurrent resizes are
+    /// serialized by `resize_lock`.
+    slots: ArcSwap<Vec<&'static Slot>>,
+    resize_lock: std::sync::Mutex<()>,
 
     /// clock arm for the clock algorithm
     next: AtomicUsize,
+
+    /// How many times the clock sweep goes around the array before it waits for a slot, see
+    /// [`set_sweep_rounds`].
+    sweep_rounds: AtomicUsize,
+
+    /// Number of slots that currently hold an open file. Published as the SIZE_CURRENT
+    /// metric whenever it changes.
+    num_open: AtomicUsize,
+
+    /// Number of files closed to make room for another one, since startup.
+    evictions: AtomicU64,
+
+    /// Number of times a file had to be opened again because it had been evicted, since
+    /// startup.
+    reopens: AtomicU64,
+}
+
+/// A snapshot of the state of the virtual file descriptor cache, see [`open_files_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenFilesStats {
+    pub total_slots: usize,
+    pub occupied_slots: usize,
+    pub evictions: u64,
+    pub reopens: u64,
 }
 
 struct Slot {
@@ -116,25 +241,27 @@ impl OpenFiles {
         //
         // Run the clock algorithm to find a slot to replace.
         //
-        let num_slots = self.slots.len();
+        let slots = self.slots.load_full();
+        let num_slots = slots.len();
+        let sweep_rounds = self.sweep_rounds.load(Ordering::Relaxed);
         let mut retries = 0;
         let mut slot;
         let mut slot_guard;
         let index;
         loop {
             let next = self.next.fetch_add(1, Ordering::AcqRel) % num_slots;
-            slot = &self.slots[next];
+            slot = slots[next];
 
             // If the recently_used flag on this slot is set, continue the clock
             // sweep. Otherwise try to use this slot. If we cannot acquire the
             // lock, also continue the clock sweep.
             //
             // We only continue in this manner for a while, though. If we loop
-            // through the array twice without finding a victim, just pick the
-            // next slot and wait until we can reuse it. This way, we avoid
-            // spinning in the extreme case that all the slots are busy with an
-            // I/O operation.
-            if retries < num_slots * 2 {
+            // through the array `sweep_rounds` times without finding a victim,
+            // just pick the next slot and wait until we can reuse it. This way,
+            // we avoid spinning in the extreme case that all the slots are busy
+            // with an I/O operation.
+            if retries < num_slots * sweep_rounds {
                 if !slot.recently_used.swap(false, Ordering::Release) {
                     if let Ok(guard) = slot.inner.try_write() {
                         slot_guard = guard;
@@ -144,6 +271,7 @@ impl OpenFiles {
                 }
                 retries += 1;
             } else {
+                crate::metrics::virtual_file_descriptor_cache::SWEEP_EXHAUSTED.inc();
                 slot_guard = slot.inner.write().await;
                 index = next;
                 break;
@@ -160,6 +288,8 @@ impl OpenFiles {
             STORAGE_IO_TIME_METRIC
                 .get(StorageIoOperation::CloseByReplace)
                 .observe_closure_duration(|| drop(old_file));
+            self.file_closed();
+            self.evictions.fetch_add(1, Ordering::Relaxed);
         }
 
         // Prepare the slot for reuse and return it
@@ -173,6 +303,29 @@ impl OpenFiles {
             slot_guard,
         )
     }
+
+    /// Store a newly opened file in a slot returned by `find_victim_slot`.
+    fn store_file(&self, slot_guard: &mut SlotInner, file: File) {
+        let old_file = slot_guard.file.replace(file);
+        debug_assert!(old_file.is_none(), "find_victim_slot empties the slot");
+        let num_open = self.num_open.fetch_add(1, Ordering::Relaxed) + 1;
+        crate::metrics::virtual_file_descriptor_cache::SIZE_CURRENT.set(num_open as u64);
+    }
+
+    fn stats(&self) -> OpenFilesStats {
+        OpenFilesStats {
+            total_slots: self.slots.load().len(),
+            occupied_slots: self.num_open.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            reopens: self.reopens.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Account for a file that was taken out of its slot and closed.
+    fn file_closed(&self) {
+        let num_open = self.num_open.fetch_sub(1, Ordering::Relaxed) - 1;
+        crate::metrics::virtual_file_descriptor_cache::SIZE_CURRENT.set(num_open as u64);
+    }
 }
 
 /// Identify error types that should alwways terminate the process.  Other
@@ -210,15 +363,97 @@ pub(crate) fn is_fatal_io_error(e: &std::io::Error) -> bool {
     }
 }
 
+/// Does the error tell that the filesystem is full?
+pub(crate) fn is_out_of_space(e: &std::io::Error) -> bool {
+    e.raw_os_error() == Some(nix::errno::Errno::ENOSPC as i32)
+}
+
+type OutOfSpaceCallback = Box<dyn Fn() + Send + Sync>;
+
+static OUT_OF_SPACE_CALLBACK: std::sync::RwLock<Option<OutOfSpaceCallback>> =
+    std::sync::RwLock::new(None);
+
+/// Set the function that [`MaybeFatalIo::maybe_fatal_err`] calls when a write fails because
+/// the filesystem is full, e.g. to kick off eviction to free up space. Replaces any
+/// previously set callback.
+pub(crate) fn set_out_of_space_callback(callback: impl Fn() + Send + Sync + 'static) {
+    *OUT_OF_SPACE_CALLBACK.write().unwrap() = Some(Box::new(callback));
+}
+
+fn on_out_of_space() {
+    if let Some(callback) = OUT_OF_SPACE_CALLBACK.read().unwrap().as_ref() {
+        callback();
+    }
+}
+
 /// Call this when the local filesystem gives us an error with an external
 /// cause: this includes EIO, EROFS, and EACCESS: all these indicate either
 /// bad storage or bad configuration, and we can't fix that from inside
 /// a running process.
 pub(crate) fn on_fatal_io_error(e: &std::io::Error, context: &str) -> ! {
     tracing::error!("Fatal I/O error: {e}: {context})");
+    FATAL_IO_ERROR_HOOKS.run(FATAL_IO_ERROR_HOOKS_DEADLINE);
     std::process::abort();
 }
 
+/// How long the hooks registered with [`register_fatal_io_error_hook`] may take in total,
+/// before we abort anyway.
+const FATAL_IO_ERROR_HOOKS_DEADLINE: std::time::Duration = std::time::Duration::from_secs(5);
+
+type FatalIoErrorHook = std::sync::Arc<dyn Fn() + Send + Sync>;
+
+/// The hooks that [`on_fatal_io_error`] runs, see [`register_fatal_io_error_hook`]. Tests use
+/// their own instance, so that their hooks don't outlive them.
+struct FatalIoErrorHooks {
+    hooks: std::sync::Mutex<Vec<FatalIoErrorHook>>,
+    /// Set once the hooks have started running. If a hook runs into a fatal I/O error itself,
+    /// we end up here again: skip the hooks then, and abort right away.
+    running: AtomicBool,
+}
+
+static FATAL_IO_ERROR_HOOKS: FatalIoErrorHooks = FatalIoErrorHooks::new();
+
+impl FatalIoErrorHooks {
+    const fn new() -> Self {
+        FatalIoErrorHooks {
+            hooks: std::sync::Mutex::new(Vec::new()),
+            running: AtomicBool::new(false),
+        }
+    }
+
+    fn register(&self, hook: FatalIoErrorHook) {
+        self.hooks.lock().unwrap().push(hook);
+    }
+
+    fn run(&self, deadline: std::time::Duration) {
+        if self.running.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let hooks = self.hooks.lock().unwrap().clone();
+
+        // Run the hooks on a separate thread, so that a hook that hangs can't prevent the abort.
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        let spawned = std::thread::Builder::new()
+            .name("fatal I/O error hooks".to_string())
+            .spawn(move || {
+                for hook in hooks {
+                    let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(&*hook));
+                }
+                let _ = done_tx.send(());
+            });
+        if spawned.is_ok() {
+            let _ = done_rx.recv_timeout(deadline);
+        }
+    }
+}
+
+/// Register a "last breath" action, like flushing logs, for [`on_fatal_io_error`] to run
+/// before it terminates the process. Hooks are best-effort: they run in the order in which
+/// they were registered, until they are done or the deadline passes, whichever is first.
+pub fn register_fatal_io_error_hook(hook: impl Fn() + Send + Sync + 'static) {
+    FATAL_IO_ERROR_HOOKS.register(std::sync::Arc::new(hook));
+}
+
 pub(crate) trait MaybeFatalIo<T> {
     fn maybe_fatal_err(self, context: &str) -> std::io::Result<T>;
     fn fatal_err(self, context: &str) -> T;
@@ -228,12 +463,16 @@ impl<T> MaybeFatalIo<T> for std::io::Result<T> {
     /// Terminate the process if the result is an error of a fatal type, else pass it through
     ///
     /// This is appropriate for writes, where we typically want to die on EIO/ACCES etc, but
-    /// not on ENOSPC.
+    /// not on ENOSPC. On ENOSPC, the callback set with [`set_out_of_space_callback`] is
+    /// called before the error is returned.
     fn maybe_fatal_err(self, context: &str) -> std::io::Result<T> {
         if let Err(e) = &self {
             if is_fatal_io_error(e) {
                 on_fatal_io_error(e, context);
             }
+            if is_out_of_space(e) {
+                on_out_of_space();
+            }
         }
         self
     }
@@ -280,6 +519,27 @@ impl VirtualFile {
         Self::open_with_options(path, OpenOptions::new().read(true)).await
     }
 
+    /// Open a file in read-only mode, bypassing the OS page cache with `O_DIRECT`.
+    ///
+    /// This is meant for large sequential scans, like compaction reading whole layer
+    /// files, which would otherwise evict more useful pages from the page cache.
+    ///
+    /// With `O_DIRECT`, the memory address of the buffer, the file offset and the length
+    /// of every read must be multiples of the logical block size of the underlying device,
+    /// typically 512 bytes or 4 KiB. Reads that violate this fail with EINVAL. Reading
+    /// whole `PAGE_SZ` blocks at block boundaries into page-aligned buffers is always fine.
+    /// Some filesystems, like tmpfs, don't support `O_DIRECT` at all and fail the open.
+    ///
+    /// The flag is part of the stored open options, so it also applies when the file is
+    /// re-opened after its descriptor was evicted from the cache. On platforms without
+    /// `O_DIRECT`, this is the same as [`VirtualFile::open`].
+    pub async fn open_direct(path: &Utf8Path) -> Result<VirtualFile, std::io::Error> {
+        let mut open_options = OpenOptions::new();
+        open_options.read(true);
+        set_direct_io(&mut open_options);
+        Self::open_with_options(path, &open_options).await
+    }
+
     /// Create a new file for writing. If the file exists, it will be truncated.
     /// Like File::create.
     pub async fn create(path: &Utf8Path) -> Result<VirtualFile, std::io::Error> {
@@ -299,18 +559,9 @@ impl VirtualFile {
         path: &Utf8Path,
         open_options: &OpenOptions,
     ) -> Result<VirtualFile, std::io::Error> {
-        let path_str = path.to_string();
-        let parts = path_str.split('/').collect::<Vec<&str>>();
-        let tenant_id;
-        let timeline_id;
-        if parts.len() > 5 && parts[parts.len() - 5] == TENANTS_SEGMENT_NAME {
-            tenant_id = parts[parts.len() - 4].to_string();
-            timeline_id = parts[parts.len() - 2].to_string();
-        } else {
-            tenant_id = "*".to_string();
-            timeline_id = "*".to_string();
-        }
-        let (handle, mut slot_guard) = get_open_files().find_victim_slot().await;
+        let (tenant_id, timeline_id) = metric_labels(path);
+        let open_files = get_open_files();
+        let (handle, mut slot_guard) = open_files.find_victim_slot().await;
 
         // NB: there is also StorageIoOperation::OpenAfterReplace which is for the case
         // where our caller doesn't get to use the returned VirtualFile before its
@@ -326,24 +577,91 @@ impl VirtualFile {
         reopen_options.create(false);
         reopen_options.create_new(false);
         reopen_options.truncate(false);
+        let reopen_flags = ReopenFlags::of(&file)?;
 
         let vfile = VirtualFile {
-            handle: RwLock::new(handle),
+            backend: Backend::FdCache {
+                handle: RwLock::new(handle),
+                open_options: reopen_options,
+                reopen_flags,
+            },
             pos: 0,
             path: path.to_path_buf(),
-            open_options: reopen_options,
             tenant_id,
             timeline_id,
+            checksum: None,
         };
 
         // TODO: Under pressure, it's likely the slot will get re-used and
         // the underlying file closed before they get around to using it.
         // => https://github.com/neondatabase/neon/issues/6065
-        slot_guard.file.replace(file);
+        open_files.store_file(&mut slot_guard, file);
 
         Ok(vfile)
     }
 
+    /// Open a file that only exists in memory, see [`in_memory`]. It's used like any other
+    /// `VirtualFile`, but never touches the disk or the file descriptor cache.
+    #[cfg(any(test, feature = "testing"))]
+    pub async fn open_in_memory(
+        path: &Utf8Path,
+        open_options: &in_memory::OpenOptions,
+    ) -> Result<VirtualFile, std::io::Error> {
+        let (tenant_id, timeline_id) = metric_labels(path);
+        let file = in_memory::InMemoryFile::open_with_options(path, open_options)?;
+        Ok(VirtualFile {
+            backend: Backend::InMemory(file),
+            pos: 0,
+            path: path.to_path_buf(),
+            tenant_id,
+            timeline_id,
+            checksum: None,
+        })
+    }
+
+    /// Like [`VirtualFile::create`], for a file that only exists in memory.
+    #[cfg(any(test, feature = "testing"))]
+    pub async fn create_in_memory(path: &Utf8Path) -> Result<VirtualFile, std::io::Error> {
+        Self::open_in_memory(
+            path,
+            OpenOptions::new().write(true).create(true).truncate(true),
+        )
+        .await
+    }
+
+    /// The flags that the file is opened with when it's re-opened after its descriptor was
+    /// evicted from the cache: the flags of the `OpenOptions` it was opened with, minus the
+    /// ones that only matter for the first open, like `create` and `truncate`.
+    pub fn reopen_flags(&self) -> ReopenFlags {
+        match &self.backend {
+            Backend::FdCache { reopen_flags, .. } => *reopen_flags,
+            #[cfg(any(test, feature = "testing"))]
+            Backend::InMemory(file) => ReopenFlags {
+                read: file.readable,
+                write: file.writable,
+                ..ReopenFlags::default()
+            },
+        }
+    }
+
+    /// Rename the file to `new_path`, and keep track of it there: if its descriptor gets
+    /// evicted from the cache, it's re-opened from `new_path`.
+    ///
+    /// Renaming the file out of band, e.g. with `std::fs::rename`, leaves the VirtualFile
+    /// pointing to the old path, which fails to open on the next re-open.
+    pub async fn rename_to(&mut self, new_path: &Utf8Path) -> Result<(), Error> {
+        // With `&mut self`, nothing can re-open the file between the rename and the update
+        // of the path.
+        match &self.backend {
+            Backend::FdCache { .. } => tokio::fs::rename(&self.path, new_path).await?,
+            #[cfg(any(test, feature = "testing"))]
+            Backend::InMemory(_) => in_memory::InMemoryFile::rename(&self.path, new_path)?,
+        }
+        self.path = new_path.to_path_buf();
+        (self.tenant_id, self.timeline_id) = metric_labels(new_path);
+        Ok(())
+    }
+
     /// Writes a file to the specified `final_path` in a crash safe fasion
     ///
     /// The file is first written to the specified tmp_path, and in a second
@@ -386,26 +704,152 @@ impl VirtualFile {
         Ok(())
     }
 
+    /// Start maintaining a CRC32C of all the bytes written to the file from now on, in the
+    /// order in which they are written. For a file that is written sequentially from the
+    /// start, like with [`VirtualFile::write_all`], this is the checksum of its contents.
+    pub fn enable_checksum(&mut self) {
+        self.checksum = Some(AtomicU32::new(0));
+    }
+
+    /// Stop maintaining the checksum, and return it. Returns `None` if checksumming was not
+    /// enabled.
+    pub fn finish_checksum(&mut self) -> Option<u32> {
+        self.checksum.take().map(AtomicU32::into_inner)
+    }
+
     /// Call File::sync_all() on the underlying File.
     pub async fn sync_all(&self) -> Result<(), Error> {
+        #[cfg(any(test, feature = "testing"))]
+        if let Backend::InMemory(_) = self.backend {
+            return Ok(());
+        }
         with_file!(self, StorageIoOperation::Fsync, |file| file
             .as_ref()
             .sync_all())
     }
 
+    /// Call File::sync_all() on all the given files, with up to [`SYNC_ALL_BATCH_CONCURRENCY`]
+    /// of them in flight at a time, which takes less wall clock time than syncing them one by
+    /// one.
+    ///
+    /// Like for any write, an error that [`is_fatal_io_error`] terminates the process. All
+    /// the other errors are returned together.
+    pub async fn sync_all_batch(files: &[&VirtualFile]) -> Result<(), SyncAllBatchError> {
+        let semaphore = tokio::sync::Semaphore::new(SYNC_ALL_BATCH_CONCURRENCY);
+        let results = futures::future::join_all(files.iter().map(|file| async {
+            let _permit = semaphore
+                .acquire()
+                .await
+                .expect("the semaphore is never closed");
+            file.sync_all_on_blocking_thread().await
+        }))
+        .await;
+
+        let errors = files
+            .iter()
+            .zip(results)
+            .filter_map(|(file, result)| {
+                result
+                    .maybe_fatal_err(&format!("fsync {}", file.path))
+                    .err()
+                    .map(|e| (file.path.clone(), e))
+            })
+            .collect::<Vec<_>>();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(SyncAllBatchError { errors })
+        }
+    }
+
+    /// Like [`VirtualFile::sync_all`], but the fsync runs on a blocking thread, so that several
+    /// of them can be in progress at the same time.
+    async fn sync_all_on_blocking_thread(&self) -> Result<(), Error> {
+        #[cfg(any(test, feature = "testing"))]
+        if let Backend::InMemory(_) = self.backend {
+            return Ok(());
+        }
+        // A duplicate of the descriptor refers to the same open file, so syncing it syncs
+        // ours. Unlike the descriptor in the slot, it can be moved to the blocking thread.
+        let file = self.lock_file().await?.as_ref().try_clone()?;
+        observe_duration!(
+            StorageIoOperation::Fsync,
+            tokio::task::spawn_blocking(move || file.sync_all())
+                .await
+                .unwrap_or_else(|e| Err(Error::new(ErrorKind::Other, e)))
+        )
+    }
+
+    /// Not supported for in-memory files, which have no `fs::Metadata`.
     pub async fn metadata(&self) -> Result<fs::Metadata, Error> {
+        #[cfg(any(test, feature = "testing"))]
+        if let Backend::InMemory(_) = self.backend {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "in-memory files have no metadata",
+            ));
+        }
         with_file!(self, StorageIoOperation::Metadata, |file| file
             .as_ref()
             .metadata())
     }
 
+    /// Tell the kernel that we won't need the given range of the file again soon, so that
+    /// it can drop the range from the page cache. Useful after consuming a part of a file in
+    /// a large sequential scan. It's only a hint: on platforms without `posix_fadvise`, this
+    /// does nothing.
+    pub async fn advise_dontneed(&self, offset: u64, len: u64) -> Result<(), Error> {
+        #[cfg(any(test, feature = "testing"))]
+        if let Backend::InMemory(_) = self.backend {
+            return Ok(());
+        }
+        with_file!(self, StorageIoOperation::Fadvise, |file| fadvise_dontneed(
+            file.as_ref(),
+            offset,
+            len
+        ))
+    }
+
+    /// Allocate disk space for the first `len` bytes of the file, extending it to `len` bytes
+    /// if it's shorter, for writers that know the size of the file up front. This avoids
+    /// fragmentation, and makes the write fail right away if the disk is full, instead of
+    /// in the middle of writing the contents.
+    ///
+    /// Where `fallocate` is not supported, by the platform or the filesystem, this does
+    /// nothing: the space is then allocated as the file is written.
+    pub async fn preallocate(&self, len: u64) -> Result<(), Error> {
+        #[cfg(any(test, feature = "testing"))]
+        if let Backend::InMemory(file) = &self.backend {
+            return file.preallocate(len);
+        }
+        with_file!(self, StorageIoOperation::Fallocate, |file| fallocate(
+            file.as_ref(),
+            len
+        ))
+        .maybe_fatal_err("preallocate")
+    }
+
     /// Helper function internal to `VirtualFile` that looks up the underlying File,
     /// opens it and evicts some other File if necessary. The passed parameter is
     /// assumed to be a function available for the physical `File`.
     ///
     /// We are doing it via a macro as Rust doesn't support async closures that
     /// take on parameters with lifetimes.
-    async fn lock_file(&self) -> Result<FileGuard<'_>, Error> {
+    ///
+    /// Must not be called for in-memory files, which have no file descriptor.
+    ///
+    /// The slots of the cache live forever, so the guard can outlive `self`, e.g. to be moved
+    /// to a blocking thread.
+    async fn lock_file(&self) -> Result<FileGuard<'static>, Error> {
+        let (handle_lock, open_options) = match &self.backend {
+            Backend::FdCache {
+                handle,
+                open_options,
+                ..
+            } => (handle, open_options),
+            #[cfg(any(test, feature = "testing"))]
+            Backend::InMemory(_) => unreachable!("in-memory files have no file descriptor"),
+        };
         let open_files = get_open_files();
 
         let mut handle_guard = {
@@ -415,11 +859,11 @@ impl VirtualFile {
             // We only need to hold the handle lock while we read the current handle. If
             // another thread closes the file and recycles the slot for a different file,
             // we will notice that the handle we read is no longer valid and retry.
-            let mut handle = *self.handle.read().await;
+            let mut handle = *handle_lock.read().await;
             loop {
                 // Check if the slot contains our File
                 {
-                    let slot = &open_files.slots[handle.index];
+                    let slot = open_files.slot(handle.index);
                     let slot_guard = slot.inner.read().await;
                     if slot_guard.tag == handle.tag && slot_guard.file.is_some() {
                         // Found a cached file descriptor.
@@ -431,7 +875,7 @@ impl VirtualFile {
                 // The slot didn't contain our File. We will have to open it ourselves,
                 // but before that, grab a write lock on handle in the VirtualFile, so
                 // that no other thread will try to concurrently open the same file.
-                let handle_guard = self.handle.write().await;
+                let handle_guard = handle_lock.write().await;
 
                 // If another thread changed the handle while we were not holding the lock,
                 // then the handle might now be valid again. Loop back to retry.
@@ -453,12 +897,13 @@ impl VirtualFile {
         // of the virtual file descriptor cache.
         let file = observe_duration!(
             StorageIoOperation::OpenAfterReplace,
-            self.open_options.open(&self.path)
+            open_options.open(&self.path)
         )?;
+        open_files.reopens.fetch_add(1, Ordering::Relaxed);
 
         // Store the File in the slot and update the handle in the VirtualFile
         // to point to it.
-        slot_guard.file.replace(file);
+        open_files.store_file(&mut slot_guard, file);
 
         *handle_guard = handle;
 
@@ -467,8 +912,30 @@ impl VirtualFile {
         });
     }
 
-    pub fn remove(self) {
+    /// Close the file and delete it.
+    ///
+    /// Errors are returned, including the file being gone already, e.g. because something else
+    /// removed it concurrently. Callers that don't mind that can use
+    /// `.or_else(fs_ext::ignore_not_found)`.
+    pub async fn remove(self) -> Result<(), Error> {
         let path = self.path.clone();
+        #[cfg(any(test, feature = "testing"))]
+        if let Backend::InMemory(_) = self.backend {
+            return in_memory::InMemoryFile::remove(&path);
+        }
+        drop(self);
+        tokio::fs::remove_file(path).await
+    }
+
+    /// Like [`Self::remove`], but blocking, for where we can't await, like in `Drop` impls.
+    /// Panics if the file can't be removed.
+    pub fn remove_blocking(self) {
+        let path = self.path.clone();
+        #[cfg(any(test, feature = "testing"))]
+        if let Backend::InMemory(_) = self.backend {
+            in_memory::InMemoryFile::remove(&path).expect("failed to remove the virtual file");
+            return;
+        }
         drop(self);
         std::fs::remove_file(path).expect("failed to remove the virtual file");
     }
@@ -479,9 +946,24 @@ impl VirtualFile {
                 self.pos = offset;
             }
             SeekFrom::End(offset) => {
-                self.pos = with_file!(self, StorageIoOperation::Seek, |file| file
-                    .as_ref()
-                    .seek(SeekFrom::End(offset)))?
+                self.pos = match &self.backend {
+                    Backend::FdCache { .. } => {
+                        with_file!(self, StorageIoOperation::Seek, |file| file
+                            .as_ref()
+                            .seek(SeekFrom::End(offset)))?
+                    }
+                    #[cfg(any(test, feature = "testing"))]
+                    Backend::InMemory(file) => {
+                        let pos = file.size() as i128 + offset as i128;
+                        if pos < 0 {
+                            return Err(Error::new(
+                                ErrorKind::InvalidInput,
+                                "offset would be negative",
+                            ));
+                        }
+                        pos as u64
+                    }
+                }
             }
             SeekFrom::Current(offset) => {
                 let pos = self.pos as i128 + offset as i128;
@@ -500,22 +982,45 @@ impl VirtualFile {
         Ok(self.pos)
     }
 
+    pub async fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> Result<(), Error> {
+        self.read_exact_at_counted(buf, offset)
+            .await
+            .map_err(|e| e.source)
+    }
+
+    /// Like [`Self::read_exact_at`], but if the buffer cannot be filled, the error also
+    /// tells how many bytes were read before giving up, e.g. because the file is truncated.
     // Copied from https://doc.rust-lang.org/1.72.0/src/std/os/unix/fs.rs.html#117-135
-    pub async fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> Result<(), Error> {
+    pub async fn read_exact_at_counted(
+        &self,
+        mut buf: &mut [u8],
+        mut offset: u64,
+    ) -> Result<(), PartialReadError> {
+        let requested = buf.len();
         while !buf.is_empty() {
             match self.read_at(buf, offset).await {
                 Ok(0) => {
-                    return Err(Error::new(
-                        std::io::ErrorKind::UnexpectedEof,
-                        "failed to fill whole buffer",
-                    ))
+                    return Err(PartialReadError {
+                        nread: requested - buf.len(),
+                        requested,
+                        source: Error::new(
+                            std::io::ErrorKind::UnexpectedEof,
+                            "failed to fill whole buffer",
+                        ),
+                    })
                 }
                 Ok(n) => {
                     buf = &mut buf[n..];
                     offset += n as u64;
                 }
                 Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {}
-                Err(e) => return Err(e),
+                Err(e) => {
+                    return Err(PartialReadError {
+                        nread: requested - buf.len(),
+                        requested,
+                        source: e,
+                    })
+                }
             }
         }
         Ok(())
@@ -569,9 +1074,13 @@ impl VirtualFile {
     }
 
     pub async fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize, Error> {
-        let result = with_file!(self, StorageIoOperation::Read, |file| file
-            .as_ref()
-            .read_at(buf, offset));
+        let result = match &self.backend {
+            Backend::FdCache { .. } => with_file!(self, StorageIoOperation::Read, |file| file
+                .as_ref()
+                .read_at(buf, offset)),
+            #[cfg(any(test, feature = "testing"))]
+            Backend::InMemory(file) => file.read_at(buf, offset),
+        };
         if let Ok(size) = result {
             STORAGE_IO_SIZE
                 .with_label_values(&["read", &self.tenant_id, &self.timeline_id])
@@ -580,19 +1089,143 @@ impl VirtualFile {
         result
     }
 
+    /// Like [`Self::read_at`], but gives up with [`CancellableReadError::Cancelled`] when
+    /// `cancel` fires, so that e.g. shutdown doesn't have to wait for a read that is stuck on
+    /// stalled storage.
+    ///
+    /// The read is done on a blocking thread, into a temporary buffer, through the descriptor in
+    /// the file's slot of the cache. Cancelling also stops waiting for the slot, but otherwise
+    /// only stops waiting for the read: the syscall keeps going in the background and its
+    /// result is discarded. The blocking thread stays busy, and the slot stays locked, until it
+    /// returns.
+    pub async fn read_at_cancellable(
+        &self,
+        buf: &mut [u8],
+        offset: u64,
+        cancel: &CancellationToken,
+    ) -> Result<usize, CancellableReadError> {
+        if cancel.is_cancelled() {
+            return Err(CancellableReadError::Cancelled);
+        }
+        #[cfg(any(test, feature = "testing"))]
+        if let Backend::InMemory(_) = self.backend {
+            return Ok(self.read_at(buf, offset).await?);
+        }
+        let len = buf.len();
+        let read = async {
+            // The guard keeps the descriptor from being closed while the blocking thread uses
+            // it, even if we stop waiting for the read.
+            let file = self.lock_file().await?;
+            observe_duration!(
+                StorageIoOperation::Read,
+                tokio::task::spawn_blocking(move || {
+                    let mut tmp = vec![0; len];
+                    let n = file.as_ref().read_at(&mut tmp, offset)?;
+                    tmp.truncate(n);
+                    Ok::<_, Error>(tmp)
+                })
+                .await
+                .unwrap_or_else(|e| Err(Error::new(ErrorKind::Other, e)))
+            )
+        };
+        let tmp = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => return Err(CancellableReadError::Cancelled),
+            tmp = read => tmp?,
+        };
+        buf[..tmp.len()].copy_from_slice(&tmp);
+        STORAGE_IO_SIZE
+            .with_label_values(&["read", &self.tenant_id, &self.timeline_id])
+            .add(tmp.len() as i64);
+        Ok(tmp.len())
+    }
+
     async fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize, Error> {
-        let result = with_file!(self, StorageIoOperation::Write, |file| file
-            .as_ref()
-            .write_at(buf, offset));
+        let result = match &self.backend {
+            Backend::FdCache { .. } => with_file!(self, StorageIoOperation::Write, |file| file
+                .as_ref()
+                .write_at(buf, offset)),
+            #[cfg(any(test, feature = "testing"))]
+            Backend::InMemory(file) => file.write_at(buf, offset),
+        };
         if let Ok(size) = result {
             STORAGE_IO_SIZE
                 .with_label_values(&["write", &self.tenant_id, &self.timeline_id])
                 .add(size as i64);
+            if let Some(checksum) = &self.checksum {
+                let written = &buf[..size];
+                checksum
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |crc| {
+                        Some(crc32c::crc32c_append(crc, written))
+                    })
+                    .expect("closure always returns Some");
+            }
         }
         result
     }
 }
 
+/// The tenant and timeline ids in the metrics of the file at `path`, or `*` if it doesn't
+/// belong to a timeline.
+fn metric_labels(path: &Utf8Path) -> (String, String) {
+    let path_str = path.to_string();
+    let parts = path_str.split('/').collect::<Vec<&str>>();
+    if parts.len() > 5 && parts[parts.len() - 5] == TENANTS_SEGMENT_NAME {
+        (
+            parts[parts.len() - 4].to_string(),
+            parts[parts.len() - 2].to_string(),
+        )
+    } else {
+        ("*".to_string(), "*".to_string())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_direct_io(open_options: &mut OpenOptions) {
+    use std::os::unix::fs::OpenOptionsExt;
+    open_options.custom_flags(nix::libc::O_DIRECT);
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_direct_io(_open_options: &mut OpenOptions) {}
+
+#[cfg(target_os = "linux")]
+fn fadvise_dontneed(file: &File, offset: u64, len: u64) -> Result<(), Error> {
+    use nix::fcntl::{posix_fadvise, PosixFadviseAdvice};
+    use std::os::fd::AsRawFd;
+    let offset = i64::try_from(offset).map_err(|_| Error::from(ErrorKind::InvalidInput))?;
+    let len = i64::try_from(len).map_err(|_| Error::from(ErrorKind::InvalidInput))?;
+    posix_fadvise(
+        file.as_raw_fd(),
+        offset,
+        len,
+        PosixFadviseAdvice::POSIX_FADV_DONTNEED,
+    )
+    .map_err(Error::from)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn fadvise_dontneed(_file: &File, _offset: u64, _len: u64) -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn fallocate(file: &File, len: u64) -> Result<(), Error> {
+    use nix::fcntl::FallocateFlags;
+    use std::os::fd::AsRawFd;
+    let len = i64::try_from(len).map_err(|_| Error::from(ErrorKind::InvalidInput))?;
+    match nix::fcntl::fallocate(file.as_raw_fd(), FallocateFlags::empty(), 0, len) {
+        Ok(()) => Ok(()),
+        Err(nix::errno::Errno::EOPNOTSUPP) => Ok(()),
+        Err(e) => Err(Error::from(e)),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn fallocate(_file: &File, _len: u64) -> Result<(), Error> {
+    Ok(())
+}
+
 struct FileGuard<'a> {
     slot_guard: RwLockReadGuard<'a, SlotInner>,
 }
@@ -618,6 +1251,33 @@ impl VirtualFile {
         Ok(std::sync::Arc::new(buf).into())
     }
 
+    /// Close the file descriptor as if the clock algorithm had picked its slot for another
+    /// file, so that the next access goes through the reopen path in `lock_file`. Returns
+    /// false if the file was not open.
+    pub(crate) async fn force_evict(&self) -> bool {
+        let Backend::FdCache { handle, .. } = &self.backend else {
+            return false;
+        };
+        let open_files = get_open_files();
+        let handle = *handle.read().await;
+        let slot = open_files.slot(handle.index);
+        let mut slot_guard = slot.inner.write().await;
+        if slot_guard.tag != handle.tag {
+            return false;
+        }
+        let Some(file) = slot_guard.file.take() else {
+            return false;
+        };
+        drop(file);
+        open_files.file_closed();
+        open_files.evictions.fetch_add(1, Ordering::Relaxed);
+
+        // Same as when the slot is reused by find_victim_slot
+        slot_guard.tag += 1;
+        slot.recently_used.store(false, Ordering::Relaxed);
+        true
+    }
+
     async fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<(), Error> {
         loop {
             let mut tmp = [0; 128];
@@ -637,7 +1297,11 @@ impl VirtualFile {
 impl Drop for VirtualFile {
     /// If a VirtualFile is dropped, close the underlying file if it was open.
     fn drop(&mut self) {
-        let handle = self.handle.get_mut();
+        let handle = match &mut self.backend {
+            Backend::FdCache { handle, .. } => handle.get_mut(),
+            #[cfg(any(test, feature = "testing"))]
+            Backend::InMemory(_) => return,
+        };
 
         fn clean_slot(slot: &Slot, mut slot_guard: RwLockWriteGuard<'_, SlotInner>, tag: u64) {
             if slot_guard.tag == tag {
@@ -648,6 +1312,7 @@ impl Drop for VirtualFile {
                     STORAGE_IO_TIME_METRIC
                         .get(StorageIoOperation::Close)
                         .observe_closure_duration(|| drop(fd));
+                    get_open_files().file_closed();
                 }
             }
         }
@@ -662,7 +1327,7 @@ impl Drop for VirtualFile {
         // is still occupied by our file, there should be no access from
         // other I/O operations; the only other possible place to lock
         // the slot is the lock algorithm looking for free slots.
-        let slot = &get_open_files().slots[handle.index];
+        let slot = get_open_files().slot(handle.index);
         if let Ok(slot_guard) = slot.inner.try_write() {
             clean_slot(slot, slot_guard, handle.tag);
         } else {
@@ -677,7 +1342,21 @@ impl Drop for VirtualFile {
 
 impl OpenFiles {
     fn new(num_slots: usize) -> OpenFiles {
-        let mut slots = Box::new(Vec::with_capacity(num_slots));
+        OpenFiles {
+            next: AtomicUsize::new(0),
+            sweep_rounds: AtomicUsize::new(
+                crate::config::defaults::DEFAULT_VIRTUAL_FILE_SWEEP_ROUNDS,
+            ),
+            slots: ArcSwap::from_pointee(Self::new_slots(num_slots)),
+            resize_lock: std::sync::Mutex::new(()),
+            num_open: AtomicUsize::new(0),
+            evictions: AtomicU64::new(0),
+            reopens: AtomicU64::new(0),
+        }
+    }
+
+    fn new_slots(num_slots: usize) -> Vec<&'static Slot> {
+        let mut slots = Vec::with_capacity(num_slots);
         for _ in 0..num_slots {
             let slot = Slot {
                 recently_used: AtomicBool::new(false),
@@ -685,11 +1364,30 @@ impl OpenFiles {
             };
             slots.push(slot);
         }
+        Box::leak(slots.into_boxed_slice()).iter().collect()
+    }
 
-        OpenFiles {
-            next: AtomicUsize::new(0),
-            slots: Box::leak(slots),
+    fn slot(&self, index: usize) -> &'static Slot {
+        self.slots.load()[index]
+    }
+
+    /// Grow the array to `new_num_slots` slots. The new slots start out empty.
+    fn resize(&self, new_num_slots: usize) -> anyhow::Result<()> {
+        let _resize_guard = self.resize_lock.lock().unwrap();
+        let slots = self.slots.load_full();
+        anyhow::ensure!(
+            new_num_slots >= slots.len(),
+            "cannot shrink the virtual file descriptor cache from {} to {new_num_slots} slots",
+            slots.len()
+        );
+        if new_num_slots == slots.len() {
+            return Ok(());
         }
+        let mut new_slots = Vec::with_capacity(new_num_slots);
+        new_slots.extend_from_slice(&slots);
+        new_slots.extend(Self::new_slots(new_num_slots - slots.len()));
+        self.slots.store(std::sync::Arc::new(new_slots));
+        Ok(())
     }
 }
 
@@ -704,6 +1402,35 @@ pub fn init(num_slots: usize) {
     crate::metrics::virtual_file_descriptor_cache::SIZE_MAX.set(num_slots as u64);
 }
 
+///
+/// Grow the number of file descriptors that the virtual file module may keep
+/// open to `new_num_slots`. Shrinking is not supported.
+///
+pub fn resize(new_num_slots: usize) -> anyhow::Result<()> {
+    get_open_files().resize(new_num_slots)?;
+    crate::metrics::virtual_file_descriptor_cache::SIZE_MAX.set(new_num_slots as u64);
+    Ok(())
+}
+
+///
+/// Set how many times the search for a slot to evict may go around the whole array,
+/// skipping the slots that were recently used or are locked, before it picks the next slot
+/// and waits for its lock. Lower values put a tighter bound on the search with many slots,
+/// higher ones make waiting less likely when many files are in use at the same time. How
+/// often the search comes up empty is counted in the
+/// `pageserver_virtual_file_descriptor_cache_sweep_exhausted` metric.
+///
+pub fn set_sweep_rounds(sweep_rounds: NonZeroUsize) {
+    get_open_files()
+        .sweep_rounds
+        .store(sweep_rounds.get(), Ordering::Relaxed);
+}
+
+/// The state of the virtual file descriptor cache, for tests and for tuning its size.
+pub fn open_files_stats() -> OpenFilesStats {
+    get_open_files().stats()
+}
+
 const TEST_MAX_FILE_DESCRIPTORS: usize = 10;
 
 // Get a handle to the global slots array.
@@ -820,6 +1547,54 @@ mod tests {
         .await
     }
 
+    #[tokio::test]
+    async fn test_in_memory_files() -> Result<(), Error> {
+        // The in-memory files of all tests share one namespace, so use paths unique to the test
+        // like for real files, even though nothing is created on disk.
+        let testdir = crate::config::PageServerConf::test_repo_dir("in_memory_files");
+        let path_a = testdir.join("file_a");
+
+        let mut file_a = VirtualFile::create_in_memory(&path_a).await?;
+        file_a.write_all(b"foobar").await?;
+        // cannot read from a file opened in write-only mode
+        let _ = file_a.read_to_end(&mut Vec::new()).await.unwrap_err();
+
+        // Close the file and re-open for reading
+        let mut file_a =
+            VirtualFile::open_in_memory(&path_a, in_memory::OpenOptions::new().read(true)).await?;
+        // cannot write to a file opened in read-only mode
+        let _ = file_a.write_all(b"bar").await.unwrap_err();
+
+        let mut buf = Vec::new();
+        file_a.read_to_end(&mut buf).await?;
+        assert_eq!(buf, b"foobar");
+
+        assert_eq!(file_a.seek(SeekFrom::End(-3)).await?, 3);
+        let mut buf = [0u8; 3];
+        file_a.read_exact_at(&mut buf, 1).await?;
+        assert_eq!(&buf, b"oob");
+        let err = file_a.read_exact_at(&mut buf, 4).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+        // Nothing reached the disk
+        assert!(!path_a.exists());
+
+        let path_b = testdir.join("file_b");
+        file_a.rename_to(&path_b).await?;
+        let err = VirtualFile::open_in_memory(&path_a, in_memory::OpenOptions::new().read(true))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+
+        file_a.remove().await?;
+        let err = VirtualFile::open_in_memory(&path_b, in_memory::OpenOptions::new().read(true))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+
+        Ok(())
+    }
+
     async fn test_files<OF, FT>(testname: &str, openfunc: OF) -> Result<(), Error>
     where
         OF: Fn(Utf8PathBuf, OpenOptions) -> FT,
@@ -899,6 +1674,7 @@ mod tests {
         //
         // leave file_a positioned at offset 1 before we start
         assert_eq!(file_a.seek(SeekFrom::Start(1)).await?, 1);
+        let stats_before = open_files_stats();
 
         let mut vfiles = Vec::new();
         for _ in 0..100 {
@@ -910,10 +1686,26 @@ mod tests {
 
         // make sure we opened enough files to definitely cause evictions.
         assert!(vfiles.len() > TEST_MAX_FILE_DESCRIPTORS * 2);
+        let is_virtual = matches!(file_a, MaybeVirtualFile::VirtualFile(_));
+        if is_virtual {
+            // Other tests use the cache concurrently, so the counters may have grown more
+            let stats = open_files_stats();
+            assert_eq!(stats.total_slots, TEST_MAX_FILE_DESCRIPTORS);
+            assert!(stats.occupied_slots <= stats.total_slots, "{stats:?}");
+            assert!(
+                stats.evictions - stats_before.evictions
+                    >= (vfiles.len() - TEST_MAX_FILE_DESCRIPTORS) as u64,
+                "{stats_before:?} {stats:?}"
+            );
+        }
 
         // The underlying file descriptor for 'file_a' should be closed now. Try to read
         // from it again. We left the file positioned at offset 1 above.
+        let reopens_before = open_files_stats().reopens;
         assert_eq!("oobar", file_a.read_string().await?);
+        if is_virtual {
+            assert!(open_files_stats().reopens > reopens_before);
+        }
 
         // Check that all the other FDs still work too. Use them in random order for
         // good measure.
@@ -982,6 +1774,433 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_out_of_space_callback() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        set_out_of_space_callback({
+            let calls = Arc::clone(&calls);
+            move || {
+                calls.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+
+        let enospc = Error::from_raw_os_error(nix::errno::Errno::ENOSPC as i32);
+        assert!(is_out_of_space(&enospc));
+        assert!(!is_fatal_io_error(&enospc));
+        let res: std::io::Result<()> = Err(enospc);
+        res.maybe_fatal_err("test").unwrap_err();
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+        // Other non-fatal errors don't trigger the callback
+        let einval = Error::from_raw_os_error(nix::errno::Errno::EINVAL as i32);
+        assert!(!is_out_of_space(&einval));
+        let res: std::io::Result<()> = Err(einval);
+        res.maybe_fatal_err("test").unwrap_err();
+        Ok::<_, Error>(()).maybe_fatal_err("test").unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_fatal_io_error_hooks() {
+        let hooks = FatalIoErrorHooks::new();
+        let runs = Arc::new(AtomicUsize::new(0));
+        hooks.register(Arc::new(|| panic!("hook failed")));
+        hooks.register(Arc::new({
+            let runs = Arc::clone(&runs);
+            move || {
+                runs.fetch_add(1, Ordering::Relaxed);
+            }
+        }));
+        hooks.register(Arc::new(|| {
+            std::thread::sleep(std::time::Duration::from_secs(3600))
+        }));
+
+        // A hook that fails doesn't stop the others, and one that hangs doesn't keep us
+        // from terminating.
+        let started = std::time::Instant::now();
+        hooks.run(std::time::Duration::from_secs(1));
+        assert_eq!(runs.load(Ordering::Relaxed), 1);
+        assert!(started.elapsed() < std::time::Duration::from_secs(60));
+
+        // The hooks only run once, even if one of them runs into a fatal error
+        hooks.run(std::time::Duration::from_secs(1));
+        assert_eq!(runs.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sync_all_batch() -> Result<(), Error> {
+        let testdir = crate::config::PageServerConf::test_repo_dir("test_sync_all_batch");
+        std::fs::create_dir_all(&testdir)?;
+
+        // More files than the fd cache and the batch concurrency can hold at once
+        let mut files = Vec::new();
+        for i in 0..SYNC_ALL_BATCH_CONCURRENCY * 2 {
+            let mut file = VirtualFile::create(&testdir.join(format!("file_{i}"))).await?;
+            file.write_all(format!("contents {i}").as_bytes()).await?;
+            files.push(file);
+        }
+        VirtualFile::sync_all_batch(&files.iter().collect::<Vec<_>>())
+            .await
+            .unwrap();
+        for (i, file) in files.iter().enumerate() {
+            assert_eq!(
+                std::fs::read(&file.path)?,
+                format!("contents {i}").as_bytes()
+            );
+        }
+
+        // /dev/null doesn't support fsync: the error is reported for it only
+        let devnull = VirtualFile::open(Utf8Path::new("/dev/null")).await?;
+        let err = VirtualFile::sync_all_batch(&[&files[0], &devnull, &files[1]])
+            .await
+            .unwrap_err();
+        assert_eq!(err.errors.len(), 1);
+        assert_eq!(err.errors[0].0, "/dev/null");
+        assert_eq!(
+            err.errors[0].1.raw_os_error(),
+            Some(nix::errno::Errno::EINVAL as i32)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_checksum() -> Result<(), Error> {
+        let testdir = crate::config::PageServerConf::test_repo_dir("test_write_checksum");
+        std::fs::create_dir_all(&testdir)?;
+
+        let path = testdir.join("file");
+        let mut file = VirtualFile::create(&path).await?;
+        file.enable_checksum();
+        file.write_all(b"foo").await?;
+        file.write_all_at(b"bar", 3).await?;
+        file.seek(SeekFrom::Start(6)).await?;
+        file.write_all(b"baz").await?;
+        let checksum = file.finish_checksum();
+        drop(file);
+
+        // read back and compare
+        let contents = std::fs::read(&path)?;
+        assert_eq!(contents, b"foobarbaz");
+        assert_eq!(checksum, Some(crc32c::crc32c(&contents)));
+
+        // without enabling it, there is no checksum
+        let mut file = VirtualFile::create(&path).await?;
+        file.write_all(b"foo").await?;
+        assert_eq!(file.finish_checksum(), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_exact_at_counted() -> Result<(), Error> {
+        let testdir = crate::config::PageServerConf::test_repo_dir("test_read_exact_at_counted");
+        std::fs::create_dir_all(&testdir)?;
+
+        let path = testdir.join("file");
+        std::fs::write(&path, b"foobar")?;
+        let file = VirtualFile::open(&path).await?;
+
+        let mut buf = [0u8; 4];
+        file.read_exact_at_counted(&mut buf, 1).await.unwrap();
+        assert_eq!(&buf, b"ooba");
+
+        // Only 2 bytes are left after offset 4
+        let err = file.read_exact_at_counted(&mut buf, 4).await.unwrap_err();
+        assert_eq!(err.nread, 2);
+        assert_eq!(err.requested, 4);
+        assert_eq!(err.source.kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(&buf[..2], b"ar");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_at_cancellable() -> Result<(), Error> {
+        let testdir = crate::config::PageServerConf::test_repo_dir("test_read_at_cancellable");
+        std::fs::create_dir_all(&testdir)?;
+
+        let path = testdir.join("file");
+        std::fs::write(&path, b"foobar")?;
+        let file = VirtualFile::open(&path).await?;
+
+        let cancel = CancellationToken::new();
+        let mut buf = [0u8; 4];
+        let n = file
+            .read_at_cancellable(&mut buf, 1, &cancel)
+            .await
+            .unwrap();
+        assert_eq!(&buf[..n], &b"ooba"[..n]);
+
+        // Reads past the end of the file return 0 bytes, like read_at
+        let n = file
+            .read_at_cancellable(&mut buf, 6, &cancel)
+            .await
+            .unwrap();
+        assert_eq!(n, 0);
+
+        // Lock the file's slot, like a concurrent eviction would, so that the read gets stuck,
+        // and cancel it midway.
+        let Backend::FdCache { handle, .. } = &file.backend else {
+            unreachable!("the file is on disk");
+        };
+        let handle = *handle.read().await;
+        let slot_guard = get_open_files().slot(handle.index).inner.write().await;
+        let read = file.read_at_cancellable(&mut buf, 0, &cancel);
+        tokio::pin!(read);
+        tokio::time::timeout(std::time::Duration::from_millis(100), &mut read)
+            .await
+            .unwrap_err();
+        cancel.cancel();
+        let err = read.await.unwrap_err();
+        assert!(matches!(err, CancellableReadError::Cancelled), "{err}");
+        drop(slot_guard);
+
+        // The file is still readable
+        let n = file
+            .read_at_cancellable(&mut buf, 1, &CancellationToken::new())
+            .await
+            .unwrap();
+        assert_eq!(&buf[..n], &b"ooba"[..n]);
+
+        // A read that is cancelled before it starts doesn't even look at the file
+        let err = file
+            .read_at_cancellable(&mut buf, 0, &cancel)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CancellableReadError::Cancelled), "{err}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remove() -> Result<(), Error> {
+        let testdir = crate::config::PageServerConf::test_repo_dir("test_remove");
+        std::fs::create_dir_all(&testdir)?;
+
+        let path = testdir.join("file");
+        let file = VirtualFile::create(&path).await?;
+        let same_file = VirtualFile::open(&path).await?;
+        file.remove().await?;
+        assert!(!path.exists());
+
+        // Removing a file that is already gone fails, unless the caller ignores that
+        let err = same_file.remove().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        let file = VirtualFile::create(&path).await?;
+        std::fs::remove_file(&path)?;
+        file.remove().await.or_else(fs_ext::ignore_not_found)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_preallocate() -> Result<(), Error> {
+        let testdir = crate::config::PageServerConf::test_repo_dir("test_preallocate");
+        std::fs::create_dir_all(&testdir)?;
+
+        let path = testdir.join("file");
+        let mut file = VirtualFile::create(&path).await?;
+        file.preallocate(8192).await?;
+        assert_eq!(file.metadata().await?.len(), 8192);
+
+        // Writing within the preallocated space doesn't change the size
+        file.write_all(b"foobar").await?;
+        file.write_all_at(b"baz", 8189).await?;
+        assert_eq!(file.metadata().await?.len(), 8192);
+        drop(file);
+
+        let contents = std::fs::read(&path)?;
+        assert_eq!(&contents[..6], b"foobar");
+        assert!(contents[6..8189].iter().all(|b| *b == 0));
+        assert_eq!(&contents[8189..], b"baz");
+
+        // Preallocating less than the size of the file doesn't truncate it
+        let file = VirtualFile::open_with_options(&path, OpenOptions::new().write(true)).await?;
+        file.preallocate(10).await?;
+        assert_eq!(file.metadata().await?.len(), 8192);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rename_to() -> Result<(), Error> {
+        let testdir = crate::config::PageServerConf::test_repo_dir("test_rename_to");
+        std::fs::create_dir_all(&testdir)?;
+
+        let path = testdir.join("file");
+        let new_path = testdir.join("file.renamed");
+        std::fs::write(&path, b"foobar")?;
+        let mut file = VirtualFile::open(&path).await?;
+        file.rename_to(&new_path).await?;
+        assert_eq!(file.path, new_path);
+        assert!(!path.exists());
+
+        // Evict the file's descriptor by using more files than there are slots
+        let other_path = testdir.join("other");
+        std::fs::write(&other_path, b"other")?;
+        let mut others = Vec::new();
+        for _ in 0..TEST_MAX_FILE_DESCRIPTORS * 2 {
+            let other = VirtualFile::open(&other_path).await?;
+            let mut buf = [0u8; 5];
+            other.read_exact_at(&mut buf, 0).await?;
+            others.push(other);
+        }
+
+        // The file is re-opened from its new path
+        let mut buf = [0u8; 6];
+        file.read_exact_at(&mut buf, 0).await?;
+        assert_eq!(&buf, b"foobar");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reopen_flags() -> Result<(), Error> {
+        let testdir = crate::config::PageServerConf::test_repo_dir("test_reopen_flags");
+        std::fs::create_dir_all(&testdir)?;
+
+        let path = testdir.join("file");
+        let file = VirtualFile::create(&path).await?;
+        assert_eq!(
+            file.reopen_flags(),
+            ReopenFlags {
+                write: true,
+                ..Default::default()
+            }
+        );
+        drop(file);
+
+        let file = VirtualFile::open(&path).await?;
+        assert_eq!(
+            file.reopen_flags(),
+            ReopenFlags {
+                read: true,
+                ..Default::default()
+            }
+        );
+        drop(file);
+
+        let file = VirtualFile::open_with_options(
+            &path,
+            OpenOptions::new().read(true).append(true).create(true),
+        )
+        .await?;
+        assert_eq!(
+            file.reopen_flags(),
+            ReopenFlags {
+                read: true,
+                write: true,
+                append: true,
+                direct: false,
+            }
+        );
+        drop(file);
+
+        // Not all filesystems support O_DIRECT, like tmpfs
+        if let Ok(file) = VirtualFile::open_direct(&path).await {
+            assert_eq!(file.reopen_flags().direct, cfg!(target_os = "linux"));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_force_evict() -> Result<(), Error> {
+        let testdir = crate::config::PageServerConf::test_repo_dir("test_force_evict");
+        std::fs::create_dir_all(&testdir)?;
+
+        let path = testdir.join("file");
+        let file = VirtualFile::open_with_options(
+            &path,
+            OpenOptions::new().read(true).write(true).create(true),
+        )
+        .await?;
+        file.write_all_at(b"foobar", 0).await?;
+
+        // Other tests running concurrently may have evicted the file already, so the first
+        // eviction doesn't necessarily find it open. Either way, nothing is left to evict until
+        // the file is used again.
+        file.force_evict().await;
+        assert!(!file.force_evict().await);
+
+        // The file is reopened, with the same options
+        let mut buf = [0; 6];
+        file.read_exact_at(&mut buf, 0).await?;
+        assert_eq!(&buf, b"foobar");
+        file.force_evict().await;
+        file.write_all_at(b"baz", 3).await?;
+        file.read_exact_at(&mut buf, 0).await?;
+        assert_eq!(&buf, b"foobaz");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_advise_dontneed() -> Result<(), Error> {
+        let testdir = crate::config::PageServerConf::test_repo_dir("test_advise_dontneed");
+        std::fs::create_dir_all(&testdir)?;
+
+        let path = testdir.join("file");
+        std::fs::write(&path, b"foobar")?;
+
+        let mut file = MaybeVirtualFile::from(VirtualFile::open(&path).await?);
+        let MaybeVirtualFile::VirtualFile(vfile) = &file else {
+            unreachable!()
+        };
+        vfile.advise_dontneed(0, 3).await?;
+        // a zero length means "until the end of the file"
+        vfile.advise_dontneed(3, 0).await?;
+
+        // dropping the range from the page cache doesn't change what we read
+        assert_eq!("foobar", file.read_string().await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_open_files_resize() {
+        let open_files = OpenFiles::new(2);
+
+        // Occupy a slot, and remember its handle
+        let (handle, mut slot_guard) = open_files.find_victim_slot().await;
+        open_files.store_file(&mut slot_guard, File::open("/dev/null").unwrap());
+        drop(slot_guard);
+
+        open_files.resize(5).unwrap();
+        assert_eq!(open_files.slots.load().len(), 5);
+
+        // The existing slot kept its file
+        let slot_guard = open_files.slot(handle.index).inner.read().await;
+        assert_eq!(slot_guard.tag, handle.tag);
+        assert!(slot_guard.file.is_some());
+        drop(slot_guard);
+
+        // The new slots get used by the clock sweep
+        let mut indexes = std::collections::HashSet::new();
+        for _ in 0..10 {
+            let (handle, _slot_guard) = open_files.find_victim_slot().await;
+            indexes.insert(handle.index);
+        }
+        assert!(indexes.iter().any(|index| *index >= 2), "{indexes:?}");
+        assert!(indexes.iter().all(|index| *index < 5), "{indexes:?}");
+
+        // Resizing to the same size is a no-op, but shrinking is not allowed
+        open_files.resize(5).unwrap();
+        open_files.resize(4).unwrap_err();
+        assert_eq!(open_files.slots.load().len(), 5);
+
+        // The file was evicted by the clock sweep
+        assert_eq!(open_files.num_open.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_open_files_sweep_rounds() {
+        let open_files = OpenFiles::new(2);
+        open_files.sweep_rounds.store(1, Ordering::Relaxed);
+
+        // With a single sweep round, once all slots have been recently used, the sweep
+        // clears their flags and then waits for the next slot
+        let (first, slot_guard) = open_files.find_victim_slot().await;
+        drop(slot_guard);
+        let (second, slot_guard) = open_files.find_victim_slot().await;
+        drop(slot_guard);
+        let (third, _slot_guard) = open_files.find_victim_slot().await;
+        assert_ne!(first.index, second.index);
+        assert_eq!(first.index, third.index);
+        assert_eq!(first.tag + 1, third.tag);
+    }
+
     #[tokio::test]
     async fn test_atomic_overwrite_basic() {
         let testdir = crate::config::PageServerConf::test_repo_dir("test_atomic_overwrite_basic");
@@ -0,0 +1,167 @@
+//! A read-ahead buffer over a [`VirtualFile`], for sequential scans that do many small reads,
+//! like iterating over the entries of a layer file.
+//!
+//! Every [`VirtualFile::read_at`] call takes the lock of the file's slot in the descriptor
+//! cache, and may have to re-open the file. [`VirtualFileBufReader`] reads large chunks
+//! instead, and serves the small reads that follow from memory.
+use std::io::{Error, ErrorKind};
+
+use super::VirtualFile;
+
+/// Size of the chunks that [`VirtualFileBufReader::new`] reads.
+pub const DEFAULT_BUF_READER_CAPACITY: usize = 128 * 1024;
+
+/// A cursor over a [`VirtualFile`] that reads ahead, in chunks of a fixed size.
+///
+/// The buffer is not invalidated by writes to the file, so this is only meant for files that
+/// don't change while they are read, like layer files.
+pub struct VirtualFileBufReader<'a> {
+    file: &'a VirtualFile,
+    /// The contents of the file from `buf_offset` on. Only the first `buf_len` bytes are valid.
+    buf: Box<[u8]>,
+    buf_offset: u64,
+    buf_len: usize,
+    /// The offset in the file of the next read.
+    pos: u64,
+}
+
+impl<'a> VirtualFileBufReader<'a> {
+    /// A reader positioned at the start of `file`, reading [`DEFAULT_BUF_READER_CAPACITY`]
+    /// bytes at a time.
+    pub fn new(file: &'a VirtualFile) -> Self {
+        Self::with_capacity(DEFAULT_BUF_READER_CAPACITY, file)
+    }
+
+    /// A reader positioned at the start of `file`, reading `capacity` bytes at a time.
+    pub fn with_capacity(capacity: usize, file: &'a VirtualFile) -> Self {
+        assert!(capacity > 0, "the capacity must not be zero");
+        VirtualFileBufReader {
+            file,
+            buf: vec![0; capacity].into_boxed_slice(),
+            buf_offset: 0,
+            buf_len: 0,
+            pos: 0,
+        }
+    }
+
+    /// The offset in the file of the next read.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Continue reading at `offset`. The buffered data is discarded, unless `offset` is within
+    /// it.
+    pub fn seek(&mut self, offset: u64) {
+        self.pos = offset;
+        if self.buffered().is_empty() {
+            self.buf_len = 0;
+        }
+    }
+
+    /// Read up to `buf.len()` bytes at the current position, like [`std::io::Read::read`].
+    /// Returns 0 at the end of the file.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.buffered().is_empty() {
+            if buf.len() >= self.buf.len() {
+                // Buffering a read this large wouldn't save any calls.
+                let n = self.file.read_at(buf, self.pos).await?;
+                self.pos += n as u64;
+                return Ok(n);
+            }
+            self.fill().await?;
+        }
+        let buffered = self.buffered();
+        let n = buffered.len().min(buf.len());
+        buf[..n].copy_from_slice(&buffered[..n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    /// Fill `buf` from the current position, like [`std::io::Read::read_exact`].
+    pub async fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<(), Error> {
+        while !buf.is_empty() {
+            match self.read(buf).await {
+                Ok(0) => {
+                    return Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "failed to fill whole buffer",
+                    ))
+                }
+                Ok(n) => buf = &mut buf[n..],
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// The buffered bytes from the current position on, or nothing if the current position
+    /// is not within the buffer.
+    fn buffered(&self) -> &[u8] {
+        let buf_end = self.buf_offset + self.buf_len as u64;
+        if self.buf_offset <= self.pos && self.pos < buf_end {
+            &self.buf[(self.pos - self.buf_offset) as usize..self.buf_len]
+        } else {
+            &[]
+        }
+    }
+
+    /// Read the next chunk at the current position into the buffer. At the end of the file,
+    /// the buffer is left empty.
+    async fn fill(&mut self) -> Result<(), Error> {
+        self.buf_offset = self.pos;
+        self.buf_len = 0;
+        self.buf_len = self.file.read_at(&mut self.buf, self.pos).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_buf_reader() -> Result<(), Error> {
+        let testdir = crate::config::PageServerConf::test_repo_dir("test_buf_reader");
+        std::fs::create_dir_all(&testdir)?;
+
+        let path = testdir.join("file");
+        let contents = (0..100u8).collect::<Vec<_>>();
+        std::fs::write(&path, &contents)?;
+        let file = VirtualFile::open(&path).await?;
+        let mut reader = VirtualFileBufReader::with_capacity(16, &file);
+
+        // Small sequential reads, some crossing the chunk boundaries
+        let mut buf = [0u8; 7];
+        for i in 0..14 {
+            reader.read_exact(&mut buf).await?;
+            assert_eq!(&buf[..], &contents[i * 7..(i + 1) * 7]);
+        }
+        assert_eq!(reader.position(), 98);
+
+        // Only 2 bytes are left
+        let err = reader.read_exact(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(reader.read(&mut buf).await?, 0);
+
+        // Seeking back, outside of and within the buffered chunk
+        reader.seek(10);
+        reader.read_exact(&mut buf).await?;
+        assert_eq!(&buf[..], &contents[10..17]);
+        reader.seek(12);
+        reader.read_exact(&mut buf).await?;
+        assert_eq!(&buf[..], &contents[12..19]);
+
+        // Reads larger than the buffer bypass it
+        let mut large = [0u8; 40];
+        reader.seek(50);
+        reader.read_exact(&mut large).await?;
+        assert_eq!(&large[..], &contents[50..90]);
+        reader.read_exact(&mut buf[..3]).await?;
+        assert_eq!(&buf[..3], &contents[90..93]);
+        Ok(())
+    }
+}
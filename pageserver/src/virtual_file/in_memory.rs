@@ -0,0 +1,202 @@
+//! A pure in-memory backend of [`VirtualFile`](super::VirtualFile), for tests and benchmarks
+//! that exercise code doing file I/O but don't care about the disk.
+//!
+//! A `VirtualFile` opened with [`VirtualFile::open_in_memory`](super::VirtualFile::open_in_memory)
+//! is used through the same API as any other, with the same error behaviour where it matters
+//! (e.g. reading from a write-only file), but its contents are kept in a process-wide map keyed
+//! by path instead of on the local filesystem. Files survive being closed and re-opened, like
+//! real files do, until they are removed with [`VirtualFile::remove`](super::VirtualFile::remove).
+//!
+//! This is only compiled for tests and with the `testing` feature: production code always goes
+//! through the file descriptor cache.
+use camino::{Utf8Path, Utf8PathBuf};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::sync::{Arc, Mutex, RwLock};
+
+type FileContents = Arc<RwLock<Vec<u8>>>;
+
+/// All in-memory files of the process, keyed by path.
+static FILES: Lazy<Mutex<HashMap<Utf8PathBuf, FileContents>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Options for opening an in-memory file, with the same builder methods as
+/// [`std::fs::OpenOptions`].
+///
+/// Unlike `std::fs::OpenOptions`, the flags can be inspected, which is what allows the
+/// in-memory backend to honour them.
+#[derive(Debug, Clone, Default)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    create: bool,
+    create_new: bool,
+    truncate: bool,
+}
+
+impl OpenOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.create_new = create_new;
+        self
+    }
+
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+}
+
+/// The in-memory state of an open [`VirtualFile`](super::VirtualFile). The file position is
+/// kept by the `VirtualFile`, like for files on disk.
+#[derive(Debug)]
+pub(super) struct InMemoryFile {
+    contents: FileContents,
+    pub(super) readable: bool,
+    pub(super) writable: bool,
+}
+
+fn bad_file_descriptor() -> Error {
+    Error::from_raw_os_error(nix::errno::Errno::EBADF as i32)
+}
+
+fn not_found() -> Error {
+    Error::new(ErrorKind::NotFound, "no such file")
+}
+
+impl InMemoryFile {
+    pub(super) fn open_with_options(
+        path: &Utf8Path,
+        open_options: &OpenOptions,
+    ) -> Result<InMemoryFile, Error> {
+        let &OpenOptions {
+            read,
+            write,
+            create,
+            create_new,
+            truncate,
+        } = open_options;
+        if !read && !write {
+            return Err(Error::from_raw_os_error(nix::errno::Errno::EINVAL as i32));
+        }
+        if (create || create_new || truncate) && !write {
+            return Err(Error::from_raw_os_error(nix::errno::Errno::EINVAL as i32));
+        }
+
+        let contents = {
+            let mut files = FILES.lock().unwrap();
+            match files.get(path) {
+                Some(_) if create_new => {
+                    return Err(Error::new(ErrorKind::AlreadyExists, "file exists"));
+                }
+                Some(contents) => Arc::clone(contents),
+                None if create || create_new => {
+                    let contents = FileContents::default();
+                    files.insert(path.to_owned(), Arc::clone(&contents));
+                    contents
+                }
+                None => return Err(not_found()),
+            }
+        };
+        if truncate {
+            contents.write().unwrap().clear();
+        }
+
+        Ok(InMemoryFile {
+            contents,
+            readable: read,
+            writable: write,
+        })
+    }
+
+    /// Remove the file at `path`. Files that are still open keep working, like they would
+    /// after unlinking a real file.
+    pub(super) fn remove(path: &Utf8Path) -> Result<(), Error> {
+        FILES
+            .lock()
+            .unwrap()
+            .remove(path)
+            .map(drop)
+            .ok_or_else(not_found)
+    }
+
+    /// Move the file from `old_path` to `new_path`, replacing any file that was there.
+    pub(super) fn rename(old_path: &Utf8Path, new_path: &Utf8Path) -> Result<(), Error> {
+        let mut files = FILES.lock().unwrap();
+        let contents = files.remove(old_path).ok_or_else(not_found)?;
+        files.insert(new_path.to_owned(), contents);
+        Ok(())
+    }
+
+    /// Current length of the file.
+    pub(super) fn size(&self) -> u64 {
+        self.contents.read().unwrap().len() as u64
+    }
+
+    /// Extend the file with zeros to `len` bytes, if it's shorter.
+    pub(super) fn preallocate(&self, len: u64) -> Result<(), Error> {
+        if !self.writable {
+            return Err(bad_file_descriptor());
+        }
+        let len = usize::try_from(len)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "offset overflow"))?;
+        let mut contents = self.contents.write().unwrap();
+        if contents.len() < len {
+            contents.resize(len, 0);
+        }
+        Ok(())
+    }
+
+    pub(super) fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize, Error> {
+        if !self.readable {
+            return Err(bad_file_descriptor());
+        }
+        let contents = self.contents.read().unwrap();
+        let Ok(start) = usize::try_from(offset) else {
+            return Ok(0);
+        };
+        let Some(available) = contents.get(start..) else {
+            return Ok(0);
+        };
+        let n = buf.len().min(available.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        Ok(n)
+    }
+
+    /// Unlike a write to a real file, this always writes the whole buffer.
+    pub(super) fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize, Error> {
+        if !self.writable {
+            return Err(bad_file_descriptor());
+        }
+        let start = usize::try_from(offset)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "offset overflow"))?;
+        let end = start
+            .checked_add(buf.len())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "offset overflow"))?;
+        let mut contents = self.contents.write().unwrap();
+        if contents.len() < end {
+            contents.resize(end, 0);
+        }
+        contents[start..end].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+}
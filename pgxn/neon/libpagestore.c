@@ -58,6 +58,7 @@ char	   *neon_auth_token;
 
 int			readahead_buffer_size = 128;
 int			flush_every_n_requests = 8;
+int			neon_protocol_version = NEON_PROTOCOL_VERSION_MAX;
 
 static int n_reconnect_attempts = 0;
 static int max_reconnect_attempts = 60;
@@ -82,6 +83,8 @@ static char local_pageserver_connstring[MAX_PAGESERVER_CONNSTRING_SIZE];
 
 static bool pageserver_flush(void);
 static void pageserver_disconnect(void);
+static bool pageserver_negotiate_protocol_version(int elevel);
+static int	call_PQgetCopyData(char **buffer);
 
 static bool
 PagestoreShmemIsValid()
@@ -247,12 +250,88 @@ pageserver_connect(int elevel)
 		}
 	}
 
+	if (!pageserver_negotiate_protocol_version(elevel))
+	{
+		PQfinish(pageserver_conn);
+		pageserver_conn = NULL;
+		FreeWaitEventSet(pageserver_conn_wes);
+		pageserver_conn_wes = NULL;
+		return false;
+	}
+
 	neon_log(LOG, "libpagestore: connected to '%s'", page_server_connstring);
 
 	connected = true;
 	return true;
 }
 
+/*
+ * Agree on the pagestream protocol version with the pageserver: offer all the
+ * versions up to neon.protocol_version, and the pageserver picks the newest
+ * one that it speaks. Version 1 needs no handshake, which keeps us compatible
+ * with pageservers that don't know about it.
+ */
+static bool
+pageserver_negotiate_protocol_version(int elevel)
+{
+	StringInfoData req_buff;
+	StringInfoData resp_buff;
+	uint32		supported_versions = 0;
+	int			rc;
+	int			tag;
+
+	if (neon_protocol_version <= 1)
+		return true;
+
+	for (int version = NEON_PROTOCOL_VERSION_MIN; version <= neon_protocol_version; version++)
+		supported_versions |= 1 << version;
+
+	initStringInfo(&req_buff);
+	pq_sendbyte(&req_buff, T_NeonHandshakeRequest);
+	pq_sendint32(&req_buff, supported_versions);
+	rc = PQputCopyData(pageserver_conn, req_buff.data, req_buff.len);
+	pfree(req_buff.data);
+	if (rc <= 0 || PQflush(pageserver_conn) != 0)
+	{
+		char	   *msg = pchomp(PQerrorMessage(pageserver_conn));
+
+		neon_log(elevel, "could not send protocol version handshake to pageserver: %s", msg);
+		return false;
+	}
+
+	rc = call_PQgetCopyData(&resp_buff.data);
+	if (rc < 0)
+	{
+		char	   *msg = pchomp(PQerrorMessage(pageserver_conn));
+
+		neon_log(elevel, "could not receive protocol version handshake from pageserver: %s", msg);
+		return false;
+	}
+	resp_buff.len = rc;
+	resp_buff.cursor = 0;
+
+	tag = rc > 0 ? (unsigned char) resp_buff.data[0] : -1;
+	if (tag == T_NeonHandshakeResponse && rc == 2)
+	{
+		int			version = (unsigned char) resp_buff.data[1];
+
+		PQfreemem(resp_buff.data);
+		neon_log(PageStoreTrace, "negotiated pagestream protocol version %d", version);
+		return true;
+	}
+	else if (tag == T_NeonErrorResponse)
+	{
+		neon_log(elevel, "pageserver rejected protocol version handshake: %s",
+				 pnstrdup(resp_buff.data + 1, rc - 1));
+	}
+	else
+	{
+		neon_log(elevel, "unexpected response to protocol version handshake from pageserver, tag 0x%02x", tag);
+	}
+	PQfreemem(resp_buff.data);
+	return false;
+}
+
 /*
  * A wrapper around PQgetCopyData that checks for interrupts while sleeping.
  */
@@ -419,6 +498,20 @@ pageserver_receive(void)
 				neon_log(PageStoreTrace, "got response: %s", msg);
 				pfree(msg);
 			}
+
+			/*
+			 * The pageserver has answered all our requests that it read, and
+			 * asks us to send the rest on a new connection. Callers treat this
+			 * like a lost connection, and send the request again.
+			 */
+			if (resp->tag == T_NeonGoAwayResponse)
+			{
+				neon_log(LOG, "pageserver asked to reconnect: %s",
+						 ((NeonGoAwayResponse *) resp)->reason);
+				pfree(resp);
+				pageserver_disconnect();
+				resp = NULL;
+			}
 		}
 		else if (rc == -1)
 		{
@@ -606,6 +699,17 @@ pg_init_libpagestore(void)
 							PGC_USERSET,
 							0,
 							NULL, NULL, NULL);
+	DefineCustomIntVariable("neon.protocol_version",
+							"Newest version of the pagestream protocol to use",
+							"Version 1 is the only one that pageservers without "
+							"support for protocol version negotiation understand.",
+							&neon_protocol_version,
+							NEON_PROTOCOL_VERSION_MAX,
+							NEON_PROTOCOL_VERSION_MIN,
+							NEON_PROTOCOL_VERSION_MAX,
+							PGC_SIGHUP,
+							0,	/* no flags required */
+							NULL, NULL, NULL);
 	DefineCustomIntVariable("neon.readahead_buffer_size",
 							"number of prefetches to buffer",
 							"This buffer is used to hold and manage prefetched "
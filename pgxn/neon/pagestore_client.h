@@ -31,14 +31,28 @@ typedef enum
 	T_NeonGetPageRequest,
 	T_NeonDbSizeRequest,
 
+	/* protocol version negotiation, see pageserver_negotiate_protocol_version */
+	T_NeonHandshakeRequest = 10,
+
 	/* pagestore -> pagestore_client */
 	T_NeonExistsResponse = 100,
 	T_NeonNblocksResponse,
 	T_NeonGetPageResponse,
 	T_NeonErrorResponse,
 	T_NeonDbSizeResponse,
+	T_NeonHandshakeResponse,
+	T_NeonGoAwayResponse,
 } NeonMessageTag;
 
+/*
+ * Versions of the pagestream protocol. Without a handshake, the pageserver
+ * assumes version 1. Version 2 always sends the effective LSN in GetPage
+ * responses, and version 3 lets the pageserver end the connection with a
+ * GoAway message, asking us to reconnect.
+ */
+#define NEON_PROTOCOL_VERSION_MIN 1
+#define NEON_PROTOCOL_VERSION_MAX 3
+
 /* base struct for c-style inheritance */
 typedef struct
 {
@@ -133,6 +147,17 @@ typedef struct
 												 * message */
 } NeonErrorResponse;
 
+/*
+ * The last message on a connection that the pageserver is draining. All
+ * requests sent before it have been answered, the ones sent after it have not
+ * been read and need to be sent again on a new connection.
+ */
+typedef struct
+{
+	NeonMessageTag tag;
+	char		reason[FLEXIBLE_ARRAY_MEMBER]; /* null-terminated */
+} NeonGoAwayResponse;
+
 extern StringInfoData nm_pack_request(NeonRequest *msg);
 extern NeonResponse *nm_unpack_response(StringInfo s);
 extern char *nm_to_string(NeonMessage *msg);
@@ -158,6 +183,7 @@ extern int	readahead_buffer_size;
 extern char *neon_timeline;
 extern char *neon_tenant;
 extern int32 max_cluster_size;
+extern int	neon_protocol_version;
 
 extern const f_smgr *smgr_neon(BackendId backend, NRelFileInfo rinfo);
 extern void smgr_init_neon(void);
@@ -52,6 +52,7 @@
 #include "access/xlog_internal.h"
 #include "access/xlogutils.h"
 #include "catalog/pg_class.h"
+#include "catalog/pg_tablespace_d.h"
 #include "common/hashfn.h"
 #include "executor/instrument.h"
 #include "pgstat.h"
@@ -965,6 +966,8 @@ nm_pack_request(NeonRequest *msg)
 				pq_sendbyte(&s, msg_req->req.latest);
 				pq_sendint64(&s, msg_req->req.lsn);
 				pq_sendint32(&s, msg_req->dbNode);
+				/* optional in protocol version 1, required from version 2 */
+				pq_sendint32(&s, DEFAULTTABLESPACE_OID);
 
 				break;
 			}
@@ -989,6 +992,7 @@ nm_pack_request(NeonRequest *msg)
 		case T_NeonGetPageResponse:
 		case T_NeonErrorResponse:
 		case T_NeonDbSizeResponse:
+		case T_NeonGoAwayResponse:
 		default:
 			elog(ERROR, "unexpected neon message tag 0x%02x", msg->tag);
 			break;
@@ -1037,6 +1041,9 @@ nm_unpack_response(StringInfo s)
 				msg_resp->tag = tag;
 				/* XXX:	should be varlena */
 				memcpy(msg_resp->page, pq_getmsgbytes(s, BLCKSZ), BLCKSZ);
+				/* From protocol version 2, followed by the effective LSN */
+				if (s->cursor < s->len)
+					(void) pq_getmsgint64(s);
 				pq_getmsgend(s);
 
 				Assert(msg_resp->tag == T_NeonGetPageResponse);
@@ -1075,6 +1082,24 @@ nm_unpack_response(StringInfo s)
 				break;
 			}
 
+		case T_NeonGoAwayResponse:
+			{
+				NeonGoAwayResponse *msg_resp;
+				size_t		msglen;
+				const char *msgtext;
+
+				msgtext = pq_getmsgrawstring(s);
+				msglen = strlen(msgtext);
+
+				msg_resp = palloc0(sizeof(NeonGoAwayResponse) + msglen + 1);
+				msg_resp->tag = tag;
+				memcpy(msg_resp->reason, msgtext, msglen + 1);
+				pq_getmsgend(s);
+
+				resp = (NeonResponse *) msg_resp;
+				break;
+			}
+
 			/*
 			 * pagestore_client -> pagestore
 			 *
@@ -1209,6 +1234,16 @@ nm_to_string(NeonMessage *msg)
 
 				break;
 			}
+		case T_NeonGoAwayResponse:
+			{
+				NeonGoAwayResponse *msg_resp = (NeonGoAwayResponse *) msg;
+
+				/* FIXME: escape double-quotes in the reason */
+				appendStringInfoString(&s, "{\"type\": \"NeonGoAwayResponse\"");
+				appendStringInfo(&s, ", \"reason\": \"%s\"}", msg_resp->reason);
+				appendStringInfoChar(&s, '}');
+				break;
+			}
 
 		default:
 			appendStringInfo(&s, "{\"type\": \"unknown 0x%02x\"", msg->tag);
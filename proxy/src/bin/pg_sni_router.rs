@@ -172,7 +172,7 @@ async fn task_main(
 
                 info!(%peer_addr, "serving");
                 let mut ctx =
-                    RequestMonitoring::new(session_id, peer_addr.ip(), "sni_router", "sni");
+                    RequestMonitoring::new(session_id, peer_addr.ip(), "sni_router", "sni", None);
                 handle_client(
                     &mut ctx,
                     dest_suffix,
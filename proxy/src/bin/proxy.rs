@@ -47,6 +47,9 @@ struct ProxyCliArgs {
     /// Name of the region this proxy is deployed in
     #[clap(long, default_value_t = String::new())]
     region: String,
+    /// Name of the availability zone this proxy is deployed in, if known
+    #[clap(long)]
+    availability_zone: Option<String>,
     /// listen for incoming client connections on ip:port
     #[clap(short, long, default_value = "127.0.0.1:4432")]
     proxy: String,
@@ -393,6 +396,7 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
         endpoint_rps_limit,
         // TODO: add this argument
         region: args.region.clone(),
+        availability_zone: args.availability_zone.clone(),
     }));
 
     Ok(config)
@@ -7,11 +7,15 @@ use std::net::IpAddr;
 use tokio::sync::mpsc;
 use uuid::Uuid;
 
-use crate::{console::messages::MetricsAuxInfo, error::ErrorKind, metrics::LatencyTimer};
+use crate::{
+    console::messages::MetricsAuxInfo,
+    error::ErrorKind,
+    metrics::{LatencyTimer, PARQUET_UPLOAD_DROPPED_ROWS},
+};
 
 pub mod parquet;
 
-static LOG_CHAN: OnceCell<mpsc::WeakUnboundedSender<RequestMonitoring>> = OnceCell::new();
+static LOG_CHAN: OnceCell<mpsc::WeakSender<RequestMonitoring>> = OnceCell::new();
 
 #[derive(Clone)]
 /// Context data for a single request to connect to a database.
@@ -24,6 +28,7 @@ pub struct RequestMonitoring {
     pub protocol: &'static str,
     first_packet: chrono::DateTime<Utc>,
     region: &'static str,
+    availability_zone: Option<&'static str>,
 
     // filled in as they are discovered
     project: Option<SmolStr>,
@@ -35,7 +40,7 @@ pub struct RequestMonitoring {
 
     // extra
     // This sender is here to keep the request monitoring channel open while requests are taking place.
-    sender: Option<mpsc::UnboundedSender<RequestMonitoring>>,
+    sender: Option<mpsc::Sender<RequestMonitoring>>,
     pub latency_timer: LatencyTimer,
 }
 
@@ -45,6 +50,7 @@ impl RequestMonitoring {
         peer_addr: IpAddr,
         protocol: &'static str,
         region: &'static str,
+        availability_zone: Option<&'static str>,
     ) -> Self {
         Self {
             peer_addr,
@@ -52,6 +58,7 @@ impl RequestMonitoring {
             protocol,
             first_packet: Utc::now(),
             region,
+            availability_zone,
 
             project: None,
             branch: None,
@@ -67,7 +74,7 @@ impl RequestMonitoring {
 
     #[cfg(test)]
     pub fn test() -> Self {
-        RequestMonitoring::new(Uuid::now_v7(), [127, 0, 0, 1].into(), "test", "test")
+        RequestMonitoring::new(Uuid::now_v7(), [127, 0, 0, 1].into(), "test", "test", None)
     }
 
     pub fn console_application_name(&self) -> String {
@@ -98,7 +105,11 @@ impl RequestMonitoring {
 
     pub fn log(&mut self) {
         if let Some(tx) = self.sender.take() {
-            let _: Result<(), _> = tx.send(self.clone());
+            // Don't wait for the parquet worker: if it's behind, e.g. because uploads are slow,
+            // drop the row rather than hold up the request or let the rows pile up in memory.
+            if let Err(mpsc::error::TrySendError::Full(_)) = tx.try_send(self.clone()) {
+                PARQUET_UPLOAD_DROPPED_ROWS.inc();
+            }
         }
     }
 }
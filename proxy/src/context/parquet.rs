@@ -1,16 +1,22 @@
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 
 use anyhow::Context;
 use bytes::BytesMut;
+use camino::Utf8PathBuf;
 use futures::{Stream, StreamExt};
 use parquet::{
     basic::Compression,
     file::{
         metadata::RowGroupMetaDataPtr,
-        properties::{WriterProperties, WriterPropertiesPtr, DEFAULT_PAGE_SIZE},
+        properties::{EnabledStatistics, WriterProperties, WriterPropertiesPtr, DEFAULT_PAGE_SIZE},
         writer::SerializedFileWriter,
     },
+    format::KeyValue,
     record::RecordWriter,
+    schema::types::ColumnPath,
 };
 use remote_storage::{GenericRemoteStorage, RemotePath, RemoteStorageConfig};
 use tokio::{sync::mpsc, time};
@@ -19,6 +25,10 @@ use tracing::{debug, info, Span};
 use utils::backoff;
 
 use super::{RequestMonitoring, LOG_CHAN};
+use crate::metrics::PARQUET_UPLOAD_SKIPPED_DUPLICATES;
+
+mod spill;
+use spill::Spill;
 
 #[derive(clap::Args, Clone, Debug)]
 pub struct ParquetUploadArgs {
@@ -40,13 +50,48 @@ pub struct ParquetUploadArgs {
     #[clap(long, default_value_t = 100_000_000)]
     parquet_upload_size: i64,
 
-    /// How long to wait before forcing a file upload
-    #[clap(long, default_value = "20m", value_parser = humantime::parse_duration)]
+    /// How long to wait before forcing a file upload. Must not be zero.
+    #[clap(long, default_value = "20m", value_parser = nonzero_duration)]
     parquet_upload_maximum_duration: tokio::time::Duration,
 
+    /// How many rows to keep in memory at most. Half of them may be queued for the worker, the
+    /// other half buffered by it or already written to the file that is being built. When the
+    /// worker has its half, the file is uploaded right away and no new rows are taken until the
+    /// upload is done. Rows that don't fit in the queue meanwhile are dropped, so that memory
+    /// use stays bounded while uploads are slow.
+    #[clap(long, default_value_t = 1_000_000)]
+    parquet_upload_max_buffered_rows: usize,
+
+    /// How long to keep retrying the uploads of the remaining rows on shutdown. The rows that
+    /// aren't uploaded by then are lost, unless `parquet_durable_spill` is set.
+    #[clap(long, default_value = "10s", value_parser = humantime::parse_duration)]
+    parquet_upload_shutdown_grace_period: tokio::time::Duration,
+
     /// What level of compression to use
     #[clap(long, default_value_t = Compression::UNCOMPRESSED)]
     parquet_upload_compression: Compression,
+
+    /// CPU pressure (the share of time that tasks were stalled waiting for the CPU over the
+    /// last 10 seconds, in percent, from /proc/pressure/cpu) above which files are written with
+    /// `parquet_upload_cpu_pressure_compression` instead, to leave the CPU to request serving.
+    /// Not checked if unset.
+    #[clap(long)]
+    parquet_upload_cpu_pressure_threshold: Option<f64>,
+
+    /// What level of compression to use while the CPU is under pressure
+    #[clap(long, default_value_t = Compression::UNCOMPRESSED)]
+    parquet_upload_cpu_pressure_compression: Compression,
+
+    /// Local file to record requests in until they are uploaded, so that they aren't lost if
+    /// the proxy crashes. The recorded requests are uploaded on the next start.
+    /// Requests are only kept in memory if unset.
+    #[clap(long)]
+    parquet_durable_spill: Option<Utf8PathBuf>,
+
+    /// How long to keep the uploaded files for. Older files are deleted from the storage
+    /// location periodically. Files are kept forever if unset.
+    #[clap(long, value_parser = humantime::parse_duration)]
+    parquet_retention: Option<tokio::time::Duration>,
 }
 
 /// Hack to avoid clap being smarter. If you don't use this type alias, clap assumes more about the optional state and you get
@@ -57,6 +102,12 @@ fn remote_storage_from_toml(s: &str) -> anyhow::Result<OptRemoteStorageConfig> {
     RemoteStorageConfig::from_toml(&s.parse()?)
 }
 
+fn nonzero_duration(s: &str) -> anyhow::Result<tokio::time::Duration> {
+    let duration = humantime::parse_duration(s)?;
+    anyhow::ensure!(!duration.is_zero(), "duration must not be zero");
+    Ok(duration)
+}
+
 // Occasional network issues and such can cause remote operations to fail, and
 // that's expected. If a upload fails, we log it at info-level, and retry.
 // But after FAILED_UPLOAD_WARN_THRESHOLD retries, we start to log it at WARN
@@ -65,6 +116,29 @@ fn remote_storage_from_toml(s: &str) -> anyhow::Result<OptRemoteStorageConfig> {
 pub(crate) const FAILED_UPLOAD_WARN_THRESHOLD: u32 = 3;
 pub(crate) const FAILED_UPLOAD_MAX_RETRIES: u32 = 10;
 
+/// Key of the sequence number of an uploaded file in its key-value metadata.
+const SEQUENCE_NUMBER_KEY: &str = "sequence_number";
+
+/// Key of the [`SCHEMA_VERSION`] of an uploaded file in its key-value metadata.
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+/// Version of the columns of [`RequestData`], for consumers to tell the files with different
+/// columns apart. Bump it whenever the columns change.
+///
+/// 1. The files without a version.
+/// 2. Added `availability_zone`.
+const SCHEMA_VERSION: u32 = 2;
+
+/// Keys of the [`RunSummary`] in the key-value metadata of the last file of a run.
+const TOTAL_ROWS_KEY: &str = "total_rows";
+const FIRST_TIMESTAMP_KEY: &str = "first_timestamp";
+const LAST_TIMESTAMP_KEY: &str = "last_timestamp";
+
+/// How often to delete expired files, if `parquet_retention` is set.
+const RETENTION_CHECK_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(60 * 60);
+
+/// How often to check the CPU pressure, if `parquet_upload_cpu_pressure_threshold` is set.
+const CPU_PRESSURE_CHECK_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(10);
+
 // the parquet crate leaves a lot to be desired...
 // what follows is an attempt to write parquet files with minimal allocs.
 // complication: parquet is a columnar format, while we want to write in as rows.
@@ -72,12 +146,42 @@ pub(crate) const FAILED_UPLOAD_MAX_RETRIES: u32 = 10;
 // * we batch up to 1024 rows, then flush them into a 'row group'
 // * after each rowgroup write, we check the length of the file and upload to s3 if large enough
 
+/// A timestamp without a timezone that is known to be in UTC.
+///
+/// The parquet derive macro doesn't like the timezones, so [`RequestData`] has to store
+/// timestamps as [`chrono::NaiveDateTime`]. This wrapper makes sure that they can only be
+/// created from UTC times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct UtcNaive(chrono::NaiveDateTime);
+
+impl UtcNaive {
+    fn from_utc(timestamp: chrono::DateTime<chrono::Utc>) -> Self {
+        Self(timestamp.naive_utc())
+    }
+
+    /// Called by the code generated by the derive macro to write the column.
+    fn timestamp_millis(&self) -> i64 {
+        self.0.timestamp_millis()
+    }
+
+    /// RFC 3339, with millisecond precision.
+    fn to_rfc3339(self) -> String {
+        self.0.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+    }
+}
+
+/// The derive macro picks the column type by the last segment of the field's type, so
+/// [`UtcNaive`] has to be named after the type it wraps to be written like it.
+mod utc {
+    pub(super) use super::UtcNaive as NaiveDateTime;
+}
+
 #[derive(parquet_derive::ParquetRecordWriter)]
 struct RequestData {
     region: &'static str,
+    availability_zone: Option<&'static str>,
     protocol: &'static str,
-    /// Must be UTC. The derive macro doesn't like the timezones
-    timestamp: chrono::NaiveDateTime,
+    timestamp: utc::NaiveDateTime,
     session_id: uuid::Uuid,
     peer_addr: String,
     username: Option<String>,
@@ -93,7 +197,7 @@ impl From<RequestMonitoring> for RequestData {
         Self {
             session_id: value.session_id,
             peer_addr: value.peer_addr.to_string(),
-            timestamp: value.first_packet.naive_utc(),
+            timestamp: UtcNaive::from_utc(value.first_packet),
             username: value.user.as_deref().map(String::from),
             application_name: value.application.as_deref().map(String::from),
             endpoint_id: value.endpoint_id.as_deref().map(String::from),
@@ -101,15 +205,67 @@ impl From<RequestMonitoring> for RequestData {
             branch: value.branch.as_deref().map(String::from),
             protocol: value.protocol,
             region: value.region,
+            availability_zone: value.availability_zone,
             error: value.error_kind.as_ref().map(|e| e.to_str()),
         }
     }
 }
 
+/// Totals over all the requests written by one run of the worker, i.e. since the process
+/// started, for consumers to reconcile the uploaded files against.
+///
+/// They are written into the key-value metadata of the last file of the run, when the worker
+/// shuts down. If all rows were already uploaded by then, the last file has no rows.
+#[derive(Default)]
+struct RunSummary {
+    total_rows: u64,
+    first_timestamp: Option<UtcNaive>,
+    last_timestamp: Option<UtcNaive>,
+}
+
+impl RunSummary {
+    fn record(&mut self, row: &RequestData) {
+        self.total_rows += 1;
+        self.first_timestamp = Some(match self.first_timestamp {
+            Some(first) => first.min(row.timestamp),
+            None => row.timestamp,
+        });
+        self.last_timestamp = Some(match self.last_timestamp {
+            Some(last) => last.max(row.timestamp),
+            None => row.timestamp,
+        });
+    }
+
+    fn key_value_metadata(&self) -> Vec<KeyValue> {
+        let mut kvs = vec![KeyValue::new(
+            TOTAL_ROWS_KEY.to_owned(),
+            self.total_rows.to_string(),
+        )];
+        if let Some(first) = self.first_timestamp {
+            kvs.push(KeyValue::new(
+                FIRST_TIMESTAMP_KEY.to_owned(),
+                first.to_rfc3339(),
+            ));
+        }
+        if let Some(last) = self.last_timestamp {
+            kvs.push(KeyValue::new(
+                LAST_TIMESTAMP_KEY.to_owned(),
+                last.to_rfc3339(),
+            ));
+        }
+        kvs
+    }
+}
+
 /// Parquet request context worker
 ///
 /// It listened on a channel for all completed requests, extracts the data and writes it into a parquet file,
 /// then uploads a completed batch to S3
+///
+/// The uploaded files are numbered, see [`upload_parquet`]. The numbering starts from zero whenever
+/// the process starts, so consumers that want to detect lost files need to combine the sequence
+/// number with the process start time, e.g. from the timestamps of the first requests in the files.
+/// The last file of a run also carries a [`RunSummary`].
 pub async fn worker(
     cancellation_token: CancellationToken,
     config: ParquetUploadArgs,
@@ -119,15 +275,38 @@ pub async fn worker(
         return Ok(());
     };
 
-    let (tx, mut rx) = mpsc::unbounded_channel();
+    let (queue_capacity, max_buffered_rows) =
+        split_row_budget(config.parquet_upload_max_buffered_rows);
+    let (tx, mut rx) = mpsc::channel(queue_capacity);
     LOG_CHAN.set(tx.downgrade()).unwrap();
 
+    let cpu_pressure = config
+        .parquet_upload_cpu_pressure_threshold
+        .map(|threshold| {
+            let active = Arc::new(AtomicBool::new(false));
+            tokio::spawn(watch_cpu_pressure(
+                threshold,
+                Arc::clone(&active),
+                cancellation_token.clone(),
+            ));
+            CpuPressure {
+                active,
+                propeties: Arc::new(writer_properties(
+                    config.parquet_upload_page_size,
+                    config.parquet_upload_cpu_pressure_compression,
+                )),
+            }
+        });
+
     // setup row stream that will close on cancellation
-    tokio::spawn(async move {
-        cancellation_token.cancelled().await;
-        // dropping this sender will cause the channel to close only once
-        // all the remaining inflight requests have been completed.
-        drop(tx);
+    tokio::spawn({
+        let cancellation_token = cancellation_token.clone();
+        async move {
+            cancellation_token.cancelled().await;
+            // dropping this sender will cause the channel to close only once
+            // all the remaining inflight requests have been completed.
+            drop(tx);
+        }
     });
     let rx = futures::stream::poll_fn(move |cx| rx.poll_recv(cx));
     let rx = rx.map(RequestData::from);
@@ -135,39 +314,233 @@ pub async fn worker(
     let storage =
         GenericRemoteStorage::from_config(&remote_storage_config).context("remote storage init")?;
 
-    let properties = WriterProperties::builder()
-        .set_data_page_size_limit(config.parquet_upload_page_size)
-        .set_compression(config.parquet_upload_compression);
+    if let Some(retention) = config.parquet_retention {
+        tokio::spawn(expire_files_periodically(
+            storage.clone(),
+            retention,
+            cancellation_token.clone(),
+        ));
+    }
 
     let parquet_config = ParquetConfig {
-        propeties: Arc::new(properties.build()),
+        propeties: Arc::new(writer_properties(
+            config.parquet_upload_page_size,
+            config.parquet_upload_compression,
+        )),
         rows_per_group: config.parquet_upload_row_group_size,
         file_size: config.parquet_upload_size,
+        max_buffered_rows,
+        shutdown_grace_period: config.parquet_upload_shutdown_grace_period,
         max_duration: config.parquet_upload_maximum_duration,
+        cpu_pressure,
+        durable_spill: config.parquet_durable_spill,
 
         #[cfg(any(test, feature = "testing"))]
         test_remote_failures: 0,
     };
 
-    worker_inner(storage, rx, parquet_config).await
+    worker_inner(storage, rx, parquet_config, cancellation_token).await
+}
+
+/// Split `--parquet-upload-max-buffered-rows` into the capacity of the queue of rows for the
+/// worker, and the number of rows that the worker buffers before it uploads.
+fn split_row_budget(max_rows: usize) -> (usize, usize) {
+    let queue_capacity = (max_rows / 2).max(1);
+    (
+        queue_capacity,
+        max_rows.saturating_sub(queue_capacity).max(1),
+    )
+}
+
+/// Deletes the uploaded files that are older than `retention` every
+/// [`RETENTION_CHECK_INTERVAL`], until cancelled.
+async fn expire_files_periodically(
+    storage: GenericRemoteStorage,
+    retention: tokio::time::Duration,
+    cancel: CancellationToken,
+) {
+    let mut interval = time::interval(RETENTION_CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => return,
+            _ = interval.tick() => {}
+        }
+        match delete_expired_files(&storage, retention, std::time::SystemTime::now()).await {
+            Ok(0) => {}
+            Ok(deleted) => info!(deleted, "deleted expired request parquet files"),
+            Err(e) => {
+                tracing::warn!("parquet request upload: failed to delete expired files: {e:#}")
+            }
+        }
+    }
+}
+
+/// Deletes the uploaded files that were created more than `retention` before `now`, and returns
+/// how many were deleted.
+///
+/// The creation time of a file is taken from the v7 UUID in its name (see [`upload_parquet`]),
+/// so that no per-file metadata requests are needed. Other files are left alone.
+async fn delete_expired_files(
+    storage: &GenericRemoteStorage,
+    retention: tokio::time::Duration,
+    now: std::time::SystemTime,
+) -> anyhow::Result<usize> {
+    let Some(cutoff) = now.checked_sub(retention) else {
+        return Ok(0);
+    };
+    let mut deleted = 0;
+    for path in storage.list_files(None).await.context("list files")? {
+        let Some(created) = path.object_name().and_then(upload_time) else {
+            continue;
+        };
+        if created < cutoff {
+            debug!(%path, "deleting expired request parquet file");
+            storage
+                .delete(&path)
+                .await
+                .with_context(|| format!("delete {path}"))?;
+            deleted += 1;
+        }
+    }
+    Ok(deleted)
+}
+
+/// The time at which a file named by [`upload_parquet`] was uploaded, from the v7 UUID at the
+/// end of its name.
+fn upload_time(name: &str) -> Option<std::time::SystemTime> {
+    let id = name
+        .strip_prefix("requests_")?
+        .strip_suffix(".parquet")?
+        .rsplit('_')
+        .next()?;
+    let (secs, nanos) = uuid::Uuid::parse_str(id).ok()?.get_timestamp()?.to_unix();
+    Some(std::time::UNIX_EPOCH + std::time::Duration::new(secs, nanos))
+}
+
+/// Properties of the uploaded files.
+///
+/// Queries of the request logs almost always filter by `endpoint_id`, so that column gets
+/// statistics and a Bloom filter, for readers to skip the row groups that don't contain the
+/// endpoint they're looking for.
+///
+/// All columns are dictionary encoded, which is the writer's default. That suits the long ids
+/// like `endpoint_id`, `project` and `branch`, which repeat across the requests of an endpoint,
+/// as well as the few distinct values of `region` or `protocol`.
+fn writer_properties(page_size: usize, compression: Compression) -> WriterProperties {
+    WriterProperties::builder()
+        .set_data_page_size_limit(page_size)
+        .set_compression(compression)
+        .set_column_statistics_enabled(ColumnPath::from("endpoint_id"), EnabledStatistics::Page)
+        .set_column_bloom_filter_enabled(ColumnPath::from("endpoint_id"), true)
+        .build()
+}
+
+/// Sets `active` whenever the CPU pressure is above `threshold`, until cancelled.
+async fn watch_cpu_pressure(threshold: f64, active: Arc<AtomicBool>, cancel: CancellationToken) {
+    let mut interval = time::interval(CPU_PRESSURE_CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => return,
+            _ = interval.tick() => {}
+        }
+        // A small file in procfs: reading it doesn't block.
+        let pressure = std::fs::read_to_string("/proc/pressure/cpu")
+            .context("read /proc/pressure/cpu")
+            .and_then(|contents| cpu_pressure_avg10(&contents).context("parse cpu pressure"));
+        let pressure = match pressure {
+            Ok(pressure) => pressure,
+            Err(e) => {
+                tracing::warn!("parquet request upload: not checking cpu pressure anymore: {e:#}");
+                return;
+            }
+        };
+
+        let under_pressure = pressure > threshold;
+        if active.swap(under_pressure, Ordering::Relaxed) != under_pressure {
+            info!(
+                pressure,
+                threshold, under_pressure, "cpu pressure changed, switching parquet compression"
+            );
+        }
+    }
+}
+
+/// Parse the `avg10` value of the `some` line of /proc/pressure/cpu, which looks like
+/// `some avg10=1.23 avg60=0.50 avg300=0.10 total=123456`.
+fn cpu_pressure_avg10(contents: &str) -> Option<f64> {
+    contents
+        .lines()
+        .find_map(|line| line.strip_prefix("some "))?
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("avg10="))?
+        .parse()
+        .ok()
+}
+
+/// Writer properties to use instead of the configured ones while the CPU is under pressure.
+struct CpuPressure {
+    active: Arc<AtomicBool>,
+    propeties: WriterPropertiesPtr,
 }
 
 struct ParquetConfig {
     propeties: WriterPropertiesPtr,
     rows_per_group: usize,
     file_size: i64,
+    max_buffered_rows: usize,
+    shutdown_grace_period: tokio::time::Duration,
 
     max_duration: tokio::time::Duration,
 
+    cpu_pressure: Option<CpuPressure>,
+
+    /// See [`spill`].
+    durable_spill: Option<Utf8PathBuf>,
+
     #[cfg(any(test, feature = "testing"))]
     test_remote_failures: u64,
 }
 
+impl ParquetConfig {
+    /// The properties to start the next file with. The compression that they select is
+    /// recorded in the metadata of every column chunk of the file.
+    fn next_file_properties(&self) -> WriterPropertiesPtr {
+        match &self.cpu_pressure {
+            Some(cpu_pressure) if cpu_pressure.active.load(Ordering::Relaxed) => {
+                cpu_pressure.propeties.clone()
+            }
+            _ => self.propeties.clone(),
+        }
+    }
+}
+
+/// Uploads the rows of `rx` until it ends. `rx` is expected to end soon after `cancel` is
+/// cancelled, and the remaining rows are then still uploaded, but the uploads are only retried
+/// for the shutdown grace period after that, so that a wedged remote storage can't hold up
+/// the shutdown.
 async fn worker_inner(
     storage: GenericRemoteStorage,
     rx: impl Stream<Item = RequestData>,
     config: ParquetConfig,
+    cancel: CancellationToken,
 ) -> anyhow::Result<()> {
+    let upload_cancel = CancellationToken::new();
+    let _upload_cancel_guard = upload_cancel.clone().drop_guard();
+    tokio::spawn({
+        let upload_cancel = upload_cancel.clone();
+        let grace_period = config.shutdown_grace_period;
+        async move {
+            tokio::select! {
+                _ = async {
+                    cancel.cancelled().await;
+                    time::sleep(grace_period).await;
+                } => upload_cancel.cancel(),
+                // The worker is done.
+                _ = upload_cancel.cancelled() => {}
+            }
+        }
+    });
+
     #[cfg(any(test, feature = "testing"))]
     let storage = if config.test_remote_failures > 0 {
         GenericRemoteStorage::unreliable_wrapper(storage, config.test_remote_failures)
@@ -175,30 +548,87 @@ async fn worker_inner(
         storage
     };
 
+    // Rows that were spilled by the previous run go first. They are already in the spill file.
+    let (mut spill, replayed) = match &config.durable_spill {
+        Some(path) => {
+            let (spill, replayed) = Spill::open(path).await?;
+            if !replayed.is_empty() {
+                info!(rows = replayed.len(), "replaying spilled requests");
+            }
+            (Some(spill), replayed)
+        }
+        None => (None, Vec::new()),
+    };
+    let rx = futures::stream::iter(replayed.into_iter().map(|row| (row, true)))
+        .chain(rx.map(|row| (row, false)));
     let mut rx = std::pin::pin!(rx);
 
     let mut rows = Vec::with_capacity(config.rows_per_group);
 
     let schema = rows.as_slice().schema()?;
     let file = BytesWriter::default();
-    let mut w = SerializedFileWriter::new(file, schema.clone(), config.propeties.clone())?;
+    let mut w = SerializedFileWriter::new(file, schema.clone(), config.next_file_properties())?;
 
     let mut last_upload = time::Instant::now();
+    let mut last_digest = None;
+    let mut seq = 0;
+    let mut summary = RunSummary::default();
+
+    // Without new rows nothing would wake the loop up, so check on a timer as well whether the
+    // buffered rows are due for upload. They are uploaded at most 2 * max_duration after the
+    // previous upload when the proxy is idle.
+    let mut upload_check = time::interval(config.max_duration);
+    upload_check.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
 
     let mut len = 0;
-    while let Some(row) = rx.next().await {
-        rows.push(row);
-        let force = last_upload.elapsed() > config.max_duration;
-        if rows.len() == config.rows_per_group || force {
+    // Rows received since the last upload, in `rows` or in the row groups of `w`.
+    let mut buffered_rows = 0;
+    loop {
+        tokio::select! {
+            row = rx.next() => {
+                let Some((row, spilled)) = row else { break };
+                if let Some(spill) = spill.as_mut().filter(|_| !spilled) {
+                    spill.append(&row).await?;
+                }
+                summary.record(&row);
+                rows.push(row);
+                buffered_rows += 1;
+            }
+            _ = upload_check.tick() => {
+                if rows.is_empty() && w.flushed_row_groups().is_empty() {
+                    continue;
+                }
+            }
+        }
+        // The upload is awaited before the next row is taken, which bounds the rows in memory.
+        let force = last_upload.elapsed() > config.max_duration
+            || buffered_rows >= config.max_buffered_rows;
+        if !rows.is_empty() && (rows.len() == config.rows_per_group || force) {
             let rg_meta;
             (rows, w, rg_meta) = flush_rows(rows, w).await?;
             len += rg_meta.compressed_size();
         }
         if len > config.file_size || force {
             last_upload = time::Instant::now();
-            let file = upload_parquet(w, len, &storage).await?;
-            w = SerializedFileWriter::new(file, schema.clone(), config.propeties.clone())?;
+            let file = upload_parquet(
+                w,
+                len,
+                seq,
+                None,
+                &mut last_digest,
+                &storage,
+                &upload_cancel,
+            )
+            .await?;
+            // All rows received so far are in the uploaded file: a file is only uploaded
+            // right after its buffered rows were flushed.
+            if let Some(spill) = &mut spill {
+                spill.truncate().await?;
+            }
+            seq += 1;
+            w = SerializedFileWriter::new(file, schema.clone(), config.next_file_properties())?;
             len = 0;
+            buffered_rows = 0;
         }
     }
 
@@ -208,8 +638,20 @@ async fn worker_inner(
         len += rg_meta.compressed_size();
     }
 
-    if !w.flushed_row_groups().is_empty() {
-        let _: BytesWriter = upload_parquet(w, len, &storage).await?;
+    if !w.flushed_row_groups().is_empty() || summary.total_rows > 0 {
+        let _: BytesWriter = upload_parquet(
+            w,
+            len,
+            seq,
+            Some(&summary),
+            &mut last_digest,
+            &storage,
+            &upload_cancel,
+        )
+        .await?;
+        if let Some(spill) = &mut spill {
+            spill.truncate().await?;
+        }
     }
 
     Ok(())
@@ -245,11 +687,40 @@ async fn flush_rows(
     Ok((rows, w, rg_meta))
 }
 
+/// Finish the file and upload it as `requests_{seq}_{uuid}.parquet`, with the sequence number
+/// zero-padded so that the files sort in upload order. The sequence number is also stored in
+/// the file's key-value metadata, under [`SEQUENCE_NUMBER_KEY`], along with the
+/// [`SCHEMA_VERSION`] and followed by the `summary`
+/// for the last file of the run.
+///
+/// As a guard against uploading the same rows twice, the upload is skipped if the file has the
+/// same rows as the previous one, which is tracked in `last_digest`. That can't happen unless
+/// there is a bug in the retry logic: the rows of different requests always differ.
+///
+/// The upload is retried until it succeeds or `cancel` is cancelled, which also interrupts
+/// an upload that is in progress.
 async fn upload_parquet(
-    w: SerializedFileWriter<BytesWriter>,
+    mut w: SerializedFileWriter<BytesWriter>,
     len: i64,
+    seq: u64,
+    summary: Option<&RunSummary>,
+    last_digest: &mut Option<md5::Digest>,
     storage: &GenericRemoteStorage,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<BytesWriter> {
+    let seq = format!("{seq:012}");
+    w.append_key_value_metadata(KeyValue::new(SEQUENCE_NUMBER_KEY.to_owned(), seq.clone()));
+    w.append_key_value_metadata(KeyValue::new(
+        SCHEMA_VERSION_KEY.to_owned(),
+        SCHEMA_VERSION.to_string(),
+    ));
+    for kv in summary
+        .iter()
+        .flat_map(|summary| summary.key_value_metadata())
+    {
+        w.append_key_value_metadata(kv);
+    }
+
     let len_uncompressed = w
         .flushed_row_groups()
         .iter()
@@ -264,35 +735,65 @@ async fn upload_parquet(
 
     let data = file.buf.split().freeze();
 
+    let digest = rows_digest(&data)?;
+    if metadata.num_rows > 0 && Some(digest) == *last_digest {
+        tracing::warn!(
+            %seq,
+            rows = metadata.num_rows,
+            "not uploading request parquet file with the same rows as the previous one"
+        );
+        PARQUET_UPLOAD_SKIPPED_DUPLICATES.inc();
+        return Ok(file);
+    }
+
     let compression = len as f64 / len_uncompressed as f64;
     let size = data.len();
     let id = uuid::Uuid::now_v7();
 
     info!(
         %id,
+        %seq,
         rows = metadata.num_rows,
         size, compression, "uploading request parquet file"
     );
 
-    let path = RemotePath::from_string(&format!("requests_{id}.parquet"))?;
+    let path = RemotePath::from_string(&format!("requests_{seq}_{id}.parquet"))?;
     backoff::retry(
         || async {
             let stream = futures::stream::once(futures::future::ready(Ok(data.clone())));
-            storage.upload(stream, data.len(), &path, None).await
+            tokio::select! {
+                res = storage.upload(stream, data.len(), &path, None) => res,
+                _ = cancel.cancelled() => Err(anyhow::anyhow!("Cancelled")),
+            }
         },
         |_e| false,
         FAILED_UPLOAD_WARN_THRESHOLD,
         FAILED_UPLOAD_MAX_RETRIES,
         "request_data_upload",
-        // we don't want cancellation to interrupt here, so we make a dummy cancel token
-        backoff::Cancel::new(CancellationToken::new(), || anyhow::anyhow!("Cancelled")),
+        backoff::Cancel::new(cancel.clone(), || anyhow::anyhow!("Cancelled")),
     )
     .await
     .context("request_data_upload")?;
+    *last_digest = Some(digest);
 
     Ok(file)
 }
 
+/// Digest of the row groups of a finished parquet file, leaving out the footer, which differs
+/// between files with the same rows because of the key-value metadata.
+fn rows_digest(data: &[u8]) -> anyhow::Result<md5::Digest> {
+    // The file ends with the footer, its length as a 4 byte little endian integer, and "PAR1".
+    let footer_len_at = data
+        .len()
+        .checked_sub(8)
+        .context("parquet file too short")?;
+    let footer_len = u32::from_le_bytes(data[footer_len_at..footer_len_at + 4].try_into()?);
+    let rows_end = footer_len_at
+        .checked_sub(footer_len as usize)
+        .context("parquet footer longer than the file")?;
+    Ok(md5::compute(&data[..rows_end]))
+}
+
 // why doesn't BytesMut impl io::Write?
 #[derive(Default)]
 struct BytesWriter {
@@ -312,19 +813,28 @@ impl std::io::Write for BytesWriter {
 
 #[cfg(test)]
 mod tests {
-    use std::{net::Ipv4Addr, num::NonZeroUsize, sync::Arc};
+    use std::{
+        collections::HashMap,
+        io::Write,
+        net::Ipv4Addr,
+        num::NonZeroUsize,
+        sync::{atomic::AtomicBool, Arc},
+    };
 
     use camino::Utf8Path;
+    use chrono::TimeZone;
     use clap::Parser;
     use futures::{Stream, StreamExt};
     use itertools::Itertools;
     use parquet::{
         basic::{Compression, ZstdLevel},
         file::{
-            properties::{WriterProperties, DEFAULT_PAGE_SIZE},
+            properties::{EnabledStatistics, WriterProperties, DEFAULT_PAGE_SIZE},
             reader::FileReader,
             serialized_reader::SerializedFileReader,
+            writer::SerializedFileWriter,
         },
+        record::{Field, RecordWriter, Row},
     };
     use rand::{rngs::StdRng, Rng, SeedableRng};
     use remote_storage::{
@@ -332,8 +842,14 @@ mod tests {
         DEFAULT_MAX_KEYS_PER_LIST_RESPONSE, DEFAULT_REMOTE_STORAGE_S3_CONCURRENCY_LIMIT,
     };
     use tokio::{sync::mpsc, time};
+    use tokio_util::sync::CancellationToken;
 
-    use super::{worker_inner, ParquetConfig, ParquetUploadArgs, RequestData};
+    use super::{
+        cpu_pressure_avg10, delete_expired_files, flush_rows, split_row_budget, upload_parquet,
+        worker_inner, writer_properties, BytesWriter, CpuPressure, ParquetConfig,
+        ParquetUploadArgs, RequestData, Spill, UtcNaive, FIRST_TIMESTAMP_KEY, LAST_TIMESTAMP_KEY,
+        SCHEMA_VERSION, SCHEMA_VERSION_KEY, SEQUENCE_NUMBER_KEY, TOTAL_ROWS_KEY,
+    };
 
     #[derive(Parser)]
     struct ProxyCliArgs {
@@ -356,6 +872,18 @@ mod tests {
             parquet_upload.parquet_upload_compression,
             Compression::UNCOMPRESSED
         );
+        assert_eq!(parquet_upload.parquet_upload_cpu_pressure_threshold, None);
+        assert_eq!(
+            parquet_upload.parquet_upload_cpu_pressure_compression,
+            Compression::UNCOMPRESSED
+        );
+        assert_eq!(parquet_upload.parquet_upload_max_buffered_rows, 1_000_000);
+        assert_eq!(
+            parquet_upload.parquet_upload_shutdown_grace_period,
+            time::Duration::from_secs(10)
+        );
+        assert_eq!(parquet_upload.parquet_durable_spill, None);
+        assert_eq!(parquet_upload.parquet_retention, None);
     }
 
     #[test]
@@ -374,6 +902,8 @@ mod tests {
             "10m",
             "--parquet-upload-compression",
             "zstd(5)",
+            "--parquet-upload-max-buffered-rows",
+            "5000",
         ]);
         assert_eq!(
             parquet_upload.parquet_upload_remote_storage,
@@ -402,16 +932,25 @@ mod tests {
             parquet_upload.parquet_upload_compression,
             Compression::ZSTD(ZstdLevel::try_new(5).unwrap())
         );
+        assert_eq!(parquet_upload.parquet_upload_max_buffered_rows, 5000);
+    }
+
+    #[test]
+    fn zero_maximum_duration_is_rejected() {
+        ProxyCliArgs::try_parse_from(["proxy", "--parquet-upload-maximum-duration", "0s"])
+            .err()
+            .expect("zero duration should be rejected");
     }
 
     fn generate_request_data(rng: &mut impl Rng) -> RequestData {
         RequestData {
             session_id: uuid::Builder::from_random_bytes(rng.gen()).into_uuid(),
             peer_addr: Ipv4Addr::from(rng.gen::<[u8; 4]>()).to_string(),
-            timestamp: chrono::NaiveDateTime::from_timestamp_millis(
-                rng.gen_range(1703862754..1803862754),
-            )
-            .unwrap(),
+            timestamp: UtcNaive::from_utc(
+                chrono::Utc
+                    .timestamp_millis_opt(rng.gen_range(1703862754..1803862754))
+                    .unwrap(),
+            ),
             application_name: Some("test".to_owned()),
             username: Some(hex::encode(rng.gen::<[u8; 4]>())),
             endpoint_id: Some(hex::encode(rng.gen::<[u8; 16]>())),
@@ -419,6 +958,9 @@ mod tests {
             branch: Some(hex::encode(rng.gen::<[u8; 16]>())),
             protocol: ["tcp", "ws", "http"][rng.gen_range(0..3)],
             region: "us-east-1",
+            availability_zone: ["us-east-1a", "us-east-1b"]
+                .get(rng.gen_range(0..3))
+                .copied(),
             error: None,
         }
     }
@@ -440,7 +982,9 @@ mod tests {
         };
         let storage = GenericRemoteStorage::from_config(&remote_storage_config).unwrap();
 
-        worker_inner(storage, rx, config).await.unwrap();
+        worker_inner(storage, rx, config, CancellationToken::new())
+            .await
+            .unwrap();
 
         let mut files = std::fs::read_dir(tmpdir.as_std_path())
             .unwrap()
@@ -467,6 +1011,50 @@ mod tests {
             .collect()
     }
 
+    /// Counts of the non-null values per column, over the rows of the files in `dir` that
+    /// match `filter`. For checking that optional columns are populated.
+    #[derive(Debug, Default)]
+    struct NonNullCounts {
+        rows: usize,
+        columns: HashMap<String, usize>,
+    }
+
+    impl NonNullCounts {
+        fn read(dir: &Utf8Path, filter: impl Fn(&Row) -> bool) -> Self {
+            let mut counts = NonNullCounts::default();
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let file = std::fs::File::open(entry.unwrap().path()).unwrap();
+                let reader = SerializedFileReader::new(file).unwrap();
+                for row in reader.get_row_iter(None).unwrap() {
+                    let row = row.unwrap();
+                    if !filter(&row) {
+                        continue;
+                    }
+                    counts.rows += 1;
+                    for (name, field) in row.get_column_iter() {
+                        let count = counts.columns.entry(name.clone()).or_default();
+                        if *field != Field::Null {
+                            *count += 1;
+                        }
+                    }
+                }
+            }
+            counts
+        }
+
+        fn get(&self, column: &str) -> usize {
+            *self
+                .columns
+                .get(column)
+                .unwrap_or_else(|| panic!("no column {column}"))
+        }
+    }
+
+    fn column_is(row: &Row, column: &str, value: &str) -> bool {
+        row.get_column_iter()
+            .any(|(name, field)| name == column && *field == Field::Str(value.to_owned()))
+    }
+
     #[tokio::test]
     async fn verify_parquet_no_compression() {
         let tmpdir = camino_tempfile::tempdir().unwrap();
@@ -475,7 +1063,11 @@ mod tests {
             propeties: Arc::new(WriterProperties::new()),
             rows_per_group: 2_000,
             file_size: 1_000_000,
+            max_buffered_rows: usize::MAX,
+            shutdown_grace_period: time::Duration::from_secs(10),
             max_duration: time::Duration::from_secs(20 * 60),
+            cpu_pressure: None,
+            durable_spill: None,
             test_remote_failures: 0,
         };
 
@@ -485,15 +1077,15 @@ mod tests {
         assert_eq!(
             file_stats,
             [
-                (1029153, 3, 6000),
-                (1029075, 3, 6000),
-                (1029216, 3, 6000),
-                (1029129, 3, 6000),
-                (1029250, 3, 6000),
-                (1029017, 3, 6000),
-                (1029175, 3, 6000),
-                (1029247, 3, 6000),
-                (343124, 1, 2000)
+                (1031302, 3, 6000),
+                (1031293, 3, 6000),
+                (1031406, 3, 6000),
+                (1031500, 3, 6000),
+                (1031428, 3, 6000),
+                (1031482, 3, 6000),
+                (1031420, 3, 6000),
+                (1031459, 3, 6000),
+                (344146, 1, 2000)
             ],
         );
 
@@ -512,7 +1104,11 @@ mod tests {
             ),
             rows_per_group: 2_000,
             file_size: 1_000_000,
+            max_buffered_rows: usize::MAX,
+            shutdown_grace_period: time::Duration::from_secs(10),
             max_duration: time::Duration::from_secs(20 * 60),
+            cpu_pressure: None,
+            durable_spill: None,
             test_remote_failures: 0,
         };
 
@@ -523,11 +1119,11 @@ mod tests {
         assert_eq!(
             file_stats,
             [
-                (1166201, 6, 12000),
-                (1163577, 6, 12000),
-                (1164641, 6, 12000),
-                (1168772, 6, 12000),
-                (196761, 1, 2000)
+                (1166380, 6, 12000),
+                (1170328, 6, 12000),
+                (1168481, 6, 12000),
+                (1166783, 6, 12000),
+                (192791, 1, 2000)
             ],
         );
 
@@ -548,7 +1144,11 @@ mod tests {
             ),
             rows_per_group: 2_000,
             file_size: 1_000_000,
+            max_buffered_rows: usize::MAX,
+            shutdown_grace_period: time::Duration::from_secs(10),
             max_duration: time::Duration::from_secs(20 * 60),
+            cpu_pressure: None,
+            durable_spill: None,
             test_remote_failures: 0,
         };
 
@@ -559,17 +1159,59 @@ mod tests {
         assert_eq!(
             file_stats,
             [
-                (1144934, 6, 12000),
-                (1144941, 6, 12000),
-                (1144735, 6, 12000),
-                (1144936, 6, 12000),
-                (191035, 1, 2000)
+                (1149641, 6, 12000),
+                (1149557, 6, 12000),
+                (1149795, 6, 12000),
+                (1149450, 6, 12000),
+                (191970, 1, 2000)
             ],
         );
 
         tmpdir.close().unwrap();
     }
 
+    #[tokio::test]
+    async fn verify_parquet_compression_levels() {
+        let mut bytes_per_row = Vec::new();
+        for compression in [
+            Compression::UNCOMPRESSED,
+            Compression::ZSTD(ZstdLevel::default()),
+            Compression::ZSTD(ZstdLevel::try_new(10).unwrap()),
+        ] {
+            let tmpdir = camino_tempfile::tempdir().unwrap();
+            let config = ParquetConfig {
+                propeties: Arc::new(
+                    WriterProperties::builder()
+                        .set_compression(compression)
+                        .build(),
+                ),
+                rows_per_group: 2_000,
+                file_size: 1_000_000,
+                max_buffered_rows: usize::MAX,
+                shutdown_grace_period: time::Duration::from_secs(10),
+                max_duration: time::Duration::from_secs(20 * 60),
+                cpu_pressure: None,
+                durable_spill: None,
+                test_remote_failures: 0,
+            };
+
+            let rx = random_stream(50_000);
+            let file_stats = run_test(tmpdir.path(), config, rx).await;
+            let bytes = file_stats.iter().map(|s| s.0).sum::<u64>();
+            let rows = file_stats.iter().map(|s| s.2).sum::<i64>();
+            assert_eq!(rows, 50_000);
+            bytes_per_row.push(bytes as f64 / rows as f64);
+
+            tmpdir.close().unwrap();
+        }
+
+        // the same rows take less space the stronger the compression
+        assert!(
+            bytes_per_row.windows(2).all(|w| w[0] > w[1]),
+            "{bytes_per_row:?}"
+        );
+    }
+
     #[tokio::test]
     async fn verify_parquet_unreliable_upload() {
         let tmpdir = camino_tempfile::tempdir().unwrap();
@@ -578,7 +1220,11 @@ mod tests {
             propeties: Arc::new(WriterProperties::new()),
             rows_per_group: 2_000,
             file_size: 1_000_000,
+            max_buffered_rows: usize::MAX,
+            shutdown_grace_period: time::Duration::from_secs(10),
             max_duration: time::Duration::from_secs(20 * 60),
+            cpu_pressure: None,
+            durable_spill: None,
             test_remote_failures: 2,
         };
 
@@ -588,21 +1234,642 @@ mod tests {
         assert_eq!(
             file_stats,
             [
-                (1029153, 3, 6000),
-                (1029075, 3, 6000),
-                (1029216, 3, 6000),
-                (1029129, 3, 6000),
-                (1029250, 3, 6000),
-                (1029017, 3, 6000),
-                (1029175, 3, 6000),
-                (1029247, 3, 6000),
-                (343124, 1, 2000)
+                (1031302, 3, 6000),
+                (1031293, 3, 6000),
+                (1031406, 3, 6000),
+                (1031500, 3, 6000),
+                (1031428, 3, 6000),
+                (1031482, 3, 6000),
+                (1031420, 3, 6000),
+                (1031459, 3, 6000),
+                (344146, 1, 2000)
             ],
         );
 
         tmpdir.close().unwrap();
     }
 
+    #[tokio::test]
+    async fn verify_parquet_upload_cancellation() {
+        let tmpdir = camino_tempfile::tempdir().unwrap();
+
+        // The uploads never succeed, so without cancellation the worker would go through the
+        // whole retry schedule, which takes over 15 seconds
+        let config = ParquetConfig {
+            propeties: Arc::new(WriterProperties::new()),
+            rows_per_group: 2_000,
+            file_size: 1_000_000,
+            max_buffered_rows: usize::MAX,
+            shutdown_grace_period: time::Duration::from_millis(500),
+            max_duration: time::Duration::from_secs(20 * 60),
+            cpu_pressure: None,
+            durable_spill: None,
+            test_remote_failures: 1_000,
+        };
+
+        let remote_storage_config = RemoteStorageConfig {
+            storage: RemoteStorageKind::LocalFs(tmpdir.path().to_path_buf()),
+        };
+        let storage = GenericRemoteStorage::from_config(&remote_storage_config).unwrap();
+        let cancel = CancellationToken::new();
+
+        let started_at = time::Instant::now();
+        let (res, ()) = tokio::join!(
+            worker_inner(storage, random_stream(1_000), config, cancel.clone()),
+            async {
+                // Cancel while the final upload is being retried
+                time::sleep(time::Duration::from_secs(1)).await;
+                cancel.cancel();
+            }
+        );
+        let elapsed = started_at.elapsed();
+
+        assert!(res.is_err());
+        assert!(elapsed < time::Duration::from_secs(5), "{elapsed:?}");
+        assert_eq!(std::fs::read_dir(tmpdir.path()).unwrap().count(), 0);
+
+        tmpdir.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_parquet_max_buffered_rows() {
+        let tmpdir = camino_tempfile::tempdir().unwrap();
+
+        // Files would be large, and uploads fail a few times before they succeed
+        let config = ParquetConfig {
+            propeties: Arc::new(WriterProperties::new()),
+            rows_per_group: 2_000,
+            file_size: 100_000_000,
+            max_buffered_rows: 5_000,
+            shutdown_grace_period: time::Duration::from_secs(10),
+            max_duration: time::Duration::from_secs(20 * 60),
+            cpu_pressure: None,
+            durable_spill: None,
+            test_remote_failures: 2,
+        };
+
+        let file_stats = run_test(tmpdir.path(), config, random_stream(50_000)).await;
+
+        // No file has more rows than the cap, and none were lost
+        assert!(file_stats.len() >= 10);
+        for (_, _, rows) in &file_stats {
+            assert!(*rows <= 5_000, "{file_stats:?}");
+        }
+        let total_rows: i64 = file_stats.iter().map(|(_, _, rows)| rows).sum();
+        assert_eq!(total_rows, 50_000);
+
+        tmpdir.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_parquet_max_buffered_rows_with_queue() {
+        let tmpdir = camino_tempfile::tempdir().unwrap();
+
+        let max_rows = 10_000;
+        let (queue_capacity, max_buffered_rows) = split_row_budget(max_rows);
+        let config = ParquetConfig {
+            propeties: Arc::new(WriterProperties::new()),
+            rows_per_group: 2_000,
+            file_size: 100_000_000,
+            max_buffered_rows,
+            shutdown_grace_period: time::Duration::from_secs(10),
+            max_duration: time::Duration::from_secs(20 * 60),
+            cpu_pressure: None,
+            durable_spill: None,
+            test_remote_failures: 2,
+        };
+
+        // Requests keep coming in while the uploads fail, like in the proxy.
+        let (tx, mut rx) = mpsc::channel(queue_capacity);
+        let producer = tokio::spawn(async move {
+            let mut rng = StdRng::from_seed([0x39; 32]);
+            let mut dropped = 0;
+            let mut max_queued = 0;
+            for _ in 0..50_000 {
+                if let Err(e) = tx.try_send(generate_request_data(&mut rng)) {
+                    assert!(matches!(e, mpsc::error::TrySendError::Full(_)));
+                    dropped += 1;
+                }
+                max_queued = max_queued.max(tx.max_capacity() - tx.capacity());
+                tokio::task::yield_now().await;
+            }
+            (dropped, max_queued)
+        });
+        let rx = futures::stream::poll_fn(move |cx| rx.poll_recv(cx));
+
+        let file_stats = run_test(tmpdir.path(), config, rx).await;
+        let (dropped, max_queued) = producer.await.unwrap();
+
+        // The rows of a file were all buffered at once, on top of the queued ones.
+        let max_file_rows = file_stats
+            .iter()
+            .map(|(_, _, rows)| *rows as usize)
+            .max()
+            .unwrap();
+        assert!(
+            max_file_rows + max_queued <= max_rows,
+            "{max_file_rows} + {max_queued} rows in memory"
+        );
+
+        // The rows that didn't fit were dropped, the others were all uploaded.
+        assert!(dropped > 0);
+        let total_rows: i64 = file_stats.iter().map(|(_, _, rows)| rows).sum();
+        assert_eq!(total_rows as usize + dropped, 50_000);
+
+        tmpdir.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_parquet_endpoint_id_bloom_filter() {
+        let tmpdir = camino_tempfile::tempdir().unwrap();
+
+        let config = ParquetConfig {
+            propeties: Arc::new(writer_properties(
+                DEFAULT_PAGE_SIZE,
+                Compression::UNCOMPRESSED,
+            )),
+            rows_per_group: 2_000,
+            file_size: 1_000_000,
+            max_buffered_rows: usize::MAX,
+            shutdown_grace_period: time::Duration::from_secs(10),
+            max_duration: time::Duration::from_secs(20 * 60),
+            cpu_pressure: None,
+            durable_spill: None,
+            test_remote_failures: 0,
+        };
+
+        let remote_storage_config = RemoteStorageConfig {
+            storage: RemoteStorageKind::LocalFs(tmpdir.path().to_path_buf()),
+        };
+        let storage = GenericRemoteStorage::from_config(&remote_storage_config).unwrap();
+        worker_inner(
+            storage,
+            random_stream(10_000),
+            config,
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+
+        let mut checked = 0;
+        for entry in std::fs::read_dir(tmpdir.path()).unwrap() {
+            let file = std::fs::File::open(entry.unwrap().path()).unwrap();
+            let reader = SerializedFileReader::new(file).unwrap();
+            for rg in reader.metadata().row_groups() {
+                for column in rg.columns() {
+                    let is_endpoint_id = column.column_path().string() == "endpoint_id";
+                    assert_eq!(
+                        column.bloom_filter_offset().is_some(),
+                        is_endpoint_id,
+                        "{}",
+                        column.column_path()
+                    );
+                    if is_endpoint_id {
+                        assert!(column.statistics().is_some());
+                        checked += 1;
+                    }
+                }
+            }
+        }
+        assert!(checked > 0);
+
+        tmpdir.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_parquet_dictionary_encoding() {
+        // Like real traffic, many requests go to the same few endpoints
+        let stream = || {
+            let mut rng = StdRng::from_seed([0x39; 32]);
+            let endpoints = (0..50)
+                .map(|_| [(); 3].map(|_| hex::encode(rng.gen::<[u8; 16]>())))
+                .collect_vec();
+            futures::stream::iter(
+                std::iter::repeat_with(move || {
+                    let mut row = generate_request_data(&mut rng);
+                    let [endpoint_id, project, branch] =
+                        endpoints[rng.gen_range(0..endpoints.len())].clone();
+                    row.endpoint_id = Some(endpoint_id);
+                    row.project = Some(project);
+                    row.branch = Some(branch);
+                    row
+                })
+                .take(20_000),
+            )
+        };
+        let config = |propeties| ParquetConfig {
+            propeties: Arc::new(propeties),
+            rows_per_group: 2_000,
+            file_size: 1_000_000,
+            max_buffered_rows: usize::MAX,
+            shutdown_grace_period: time::Duration::from_secs(10),
+            max_duration: time::Duration::from_secs(20 * 60),
+            cpu_pressure: None,
+            durable_spill: None,
+            test_remote_failures: 0,
+        };
+
+        let id_columns = ["endpoint_id", "project", "branch"];
+        let low_cardinality_columns = ["region", "protocol"];
+
+        let tmpdir = camino_tempfile::tempdir().unwrap();
+        let properties = writer_properties(DEFAULT_PAGE_SIZE, Compression::UNCOMPRESSED);
+        let with_dictionary = run_test(tmpdir.path(), config(properties), stream()).await;
+
+        // The columns are dictionary encoded without having to ask for it
+        for entry in std::fs::read_dir(tmpdir.path()).unwrap() {
+            let file = std::fs::File::open(entry.unwrap().path()).unwrap();
+            let reader = SerializedFileReader::new(file).unwrap();
+            for rg in reader.metadata().row_groups() {
+                for column in rg.columns() {
+                    let path = column.column_path().string();
+                    if id_columns.contains(&path.as_str())
+                        || low_cardinality_columns.contains(&path.as_str())
+                    {
+                        assert!(column.dictionary_page_offset().is_some(), "{path}");
+                    }
+                }
+            }
+        }
+        tmpdir.close().unwrap();
+
+        // The same properties, but without dictionaries for the ids
+        let tmpdir = camino_tempfile::tempdir().unwrap();
+        let mut properties = WriterProperties::builder()
+            .set_data_page_size_limit(DEFAULT_PAGE_SIZE)
+            .set_compression(Compression::UNCOMPRESSED)
+            .set_column_statistics_enabled("endpoint_id".into(), EnabledStatistics::Page)
+            .set_column_bloom_filter_enabled("endpoint_id".into(), true);
+        for column in id_columns {
+            properties = properties.set_column_dictionary_enabled(column.into(), false);
+        }
+        let without_dictionary =
+            run_test(tmpdir.path(), config(properties.build()), stream()).await;
+        tmpdir.close().unwrap();
+
+        let total_size = |stats: &[(u64, usize, i64)]| stats.iter().map(|s| s.0).sum::<u64>();
+        assert!(
+            total_size(&with_dictionary) < total_size(&without_dictionary),
+            "{with_dictionary:?} {without_dictionary:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_parquet_sequence_numbers() {
+        let tmpdir = camino_tempfile::tempdir().unwrap();
+
+        let config = ParquetConfig {
+            propeties: Arc::new(WriterProperties::new()),
+            rows_per_group: 2_000,
+            file_size: 1_000_000,
+            max_buffered_rows: usize::MAX,
+            shutdown_grace_period: time::Duration::from_secs(10),
+            max_duration: time::Duration::from_secs(20 * 60),
+            cpu_pressure: None,
+            durable_spill: None,
+            test_remote_failures: 0,
+        };
+
+        let remote_storage_config = RemoteStorageConfig {
+            storage: RemoteStorageKind::LocalFs(tmpdir.path().to_path_buf()),
+        };
+        let storage = GenericRemoteStorage::from_config(&remote_storage_config).unwrap();
+        worker_inner(
+            storage,
+            random_stream(20_000),
+            config,
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+
+        let files = std::fs::read_dir(tmpdir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .sorted()
+            .collect_vec();
+        assert!(files.len() > 1);
+
+        for (expected, path) in files.iter().enumerate() {
+            let expected = format!("{expected:012}");
+            let name = path.file_name().unwrap().to_str().unwrap();
+            assert!(name.starts_with(&format!("requests_{expected}_")), "{name}");
+
+            let reader = SerializedFileReader::new(std::fs::File::open(path).unwrap()).unwrap();
+            let seq = reader
+                .metadata()
+                .file_metadata()
+                .key_value_metadata()
+                .unwrap()
+                .iter()
+                .find(|kv| kv.key == SEQUENCE_NUMBER_KEY)
+                .and_then(|kv| kv.value.clone());
+            assert_eq!(seq, Some(expected));
+            let schema_version = reader
+                .metadata()
+                .file_metadata()
+                .key_value_metadata()
+                .unwrap()
+                .iter()
+                .find(|kv| kv.key == SCHEMA_VERSION_KEY)
+                .and_then(|kv| kv.value.clone());
+            assert_eq!(schema_version, Some(SCHEMA_VERSION.to_string()));
+        }
+
+        tmpdir.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_parquet_run_summary() {
+        let tmpdir = camino_tempfile::tempdir().unwrap();
+
+        let config = ParquetConfig {
+            propeties: Arc::new(WriterProperties::new()),
+            rows_per_group: 2_000,
+            file_size: 1_000_000,
+            max_buffered_rows: usize::MAX,
+            shutdown_grace_period: time::Duration::from_secs(10),
+            max_duration: time::Duration::from_secs(20 * 60),
+            cpu_pressure: None,
+            durable_spill: None,
+            test_remote_failures: 0,
+        };
+
+        let remote_storage_config = RemoteStorageConfig {
+            storage: RemoteStorageKind::LocalFs(tmpdir.path().to_path_buf()),
+        };
+        let storage = GenericRemoteStorage::from_config(&remote_storage_config).unwrap();
+        worker_inner(
+            storage,
+            random_stream(20_000),
+            config,
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+
+        let timestamps = random_stream(20_000)
+            .map(|row| row.timestamp)
+            .collect::<Vec<_>>()
+            .await;
+        let first = timestamps.iter().min().unwrap().to_rfc3339();
+        let last = timestamps.iter().max().unwrap().to_rfc3339();
+
+        let files = std::fs::read_dir(tmpdir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .sorted()
+            .collect_vec();
+        assert!(files.len() > 1);
+
+        for (i, path) in files.iter().enumerate() {
+            let reader = SerializedFileReader::new(std::fs::File::open(path).unwrap()).unwrap();
+            let kvs = reader
+                .metadata()
+                .file_metadata()
+                .key_value_metadata()
+                .unwrap()
+                .iter()
+                .map(|kv| (kv.key.as_str(), kv.value.as_deref().unwrap()))
+                .collect::<HashMap<_, _>>();
+
+            if i + 1 < files.len() {
+                assert!(!kvs.contains_key(TOTAL_ROWS_KEY), "{path:?}");
+                continue;
+            }
+            assert_eq!(kvs.get(TOTAL_ROWS_KEY), Some(&"20000"));
+            assert_eq!(kvs.get(FIRST_TIMESTAMP_KEY), Some(&first.as_str()));
+            assert_eq!(kvs.get(LAST_TIMESTAMP_KEY), Some(&last.as_str()));
+        }
+
+        tmpdir.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_parquet_durable_spill() {
+        let tmpdir = camino_tempfile::tempdir().unwrap();
+        let uploads = tmpdir.path().join("uploads");
+        std::fs::create_dir(&uploads).unwrap();
+        let spill_path = tmpdir.path().join("spill");
+
+        // A previous run recorded some rows and crashed in the middle of recording another one.
+        {
+            let (mut spill, replayed) = Spill::open(&spill_path).await.unwrap();
+            assert!(replayed.is_empty());
+            let mut rows = random_stream(100);
+            while let Some(row) = rows.next().await {
+                spill.append(&row).await.unwrap();
+            }
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&spill_path)
+            .unwrap();
+        file.write_all(b"{\"region\":\"us-").unwrap();
+        drop(file);
+
+        let config = ParquetConfig {
+            propeties: Arc::new(WriterProperties::new()),
+            rows_per_group: 2_000,
+            file_size: 1_000_000,
+            max_buffered_rows: usize::MAX,
+            shutdown_grace_period: time::Duration::from_secs(10),
+            max_duration: time::Duration::from_secs(20 * 60),
+            cpu_pressure: None,
+            durable_spill: Some(spill_path.clone()),
+            test_remote_failures: 0,
+        };
+        let file_stats = run_test(&uploads, config, random_stream(1000)).await;
+
+        // The spilled rows were uploaded along with the new ones
+        let total_rows: i64 = file_stats.iter().map(|(_, _, rows)| rows).sum();
+        assert_eq!(total_rows, 1100);
+        assert_eq!(std::fs::metadata(&spill_path).unwrap().len(), 0);
+
+        tmpdir.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_parquet_skip_duplicate_upload() {
+        let tmpdir = camino_tempfile::tempdir().unwrap();
+        let remote_storage_config = RemoteStorageConfig {
+            storage: RemoteStorageKind::LocalFs(tmpdir.path().to_path_buf()),
+        };
+        let storage = GenericRemoteStorage::from_config(&remote_storage_config).unwrap();
+
+        let write_file = |rows: Vec<RequestData>| async move {
+            let schema = rows.as_slice().schema().unwrap();
+            let w = SerializedFileWriter::new(
+                BytesWriter::default(),
+                schema,
+                Arc::new(WriterProperties::new()),
+            )
+            .unwrap();
+            let (_, w, rg_meta) = flush_rows(rows, w).await.unwrap();
+            (w, rg_meta.compressed_size())
+        };
+
+        // The same rows are flushed twice, like after a retry bug: only the first file is
+        // uploaded, even though the second one has a different sequence number.
+        let cancel = CancellationToken::new();
+        let mut last_digest = None;
+        for seq in 0..2 {
+            let rows = random_stream(100).collect::<Vec<_>>().await;
+            let (w, len) = write_file(rows).await;
+            upload_parquet(w, len, seq, None, &mut last_digest, &storage, &cancel)
+                .await
+                .unwrap();
+        }
+        assert_eq!(std::fs::read_dir(tmpdir.path()).unwrap().count(), 1);
+
+        // Different rows are uploaded
+        let rows = random_stream(101).collect::<Vec<_>>().await;
+        let (w, len) = write_file(rows).await;
+        upload_parquet(w, len, 2, None, &mut last_digest, &storage, &cancel)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_dir(tmpdir.path()).unwrap().count(), 2);
+
+        tmpdir.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_parquet_retention() {
+        let tmpdir = camino_tempfile::tempdir().unwrap();
+        let remote_storage_config = RemoteStorageConfig {
+            storage: RemoteStorageKind::LocalFs(tmpdir.path().to_path_buf()),
+        };
+        let storage = GenericRemoteStorage::from_config(&remote_storage_config).unwrap();
+
+        const NOW_SECS: u64 = 1_700_000_000;
+        let file_name = |age_secs: u64| {
+            let created = uuid::Timestamp::from_unix(uuid::NoContext, NOW_SECS - age_secs, 0);
+            format!("requests_{:012}_{}.parquet", 0, uuid::Uuid::new_v7(created))
+        };
+        let old = file_name(2 * 24 * 60 * 60);
+        let recent = file_name(60 * 60);
+        let unrelated = "unrelated.txt".to_owned();
+        for name in [&old, &recent, &unrelated] {
+            std::fs::write(tmpdir.path().join(name), b"data").unwrap();
+        }
+
+        let now = std::time::UNIX_EPOCH + time::Duration::from_secs(NOW_SECS);
+        let retention = time::Duration::from_secs(24 * 60 * 60);
+        let deleted = delete_expired_files(&storage, retention, now)
+            .await
+            .unwrap();
+
+        assert_eq!(deleted, 1);
+        assert!(!tmpdir.path().join(&old).exists());
+        assert!(tmpdir.path().join(&recent).exists());
+        assert!(tmpdir.path().join(&unrelated).exists());
+
+        tmpdir.close().unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_parquet_non_null_counts() {
+        let tmpdir = camino_tempfile::tempdir().unwrap();
+
+        let config = ParquetConfig {
+            propeties: Arc::new(WriterProperties::new()),
+            rows_per_group: 2_000,
+            file_size: 1_000_000,
+            max_buffered_rows: usize::MAX,
+            shutdown_grace_period: time::Duration::from_secs(10),
+            max_duration: time::Duration::from_secs(20 * 60),
+            cpu_pressure: None,
+            durable_spill: None,
+            test_remote_failures: 0,
+        };
+        run_test(tmpdir.path(), config, random_stream(5_000)).await;
+
+        let all = NonNullCounts::read(tmpdir.path(), |_| true);
+        assert_eq!(all.rows, 5_000);
+        for column in [
+            "region",
+            "protocol",
+            "timestamp",
+            "session_id",
+            "peer_addr",
+            "username",
+            "application_name",
+            "endpoint_id",
+            "project",
+            "branch",
+        ] {
+            assert_eq!(all.get(column), all.rows, "{column}");
+        }
+        assert_eq!(all.get("error"), 0);
+
+        let http = NonNullCounts::read(tmpdir.path(), |row| column_is(row, "protocol", "http"));
+        assert!(http.rows > 0);
+        assert_eq!(http.get("endpoint_id"), http.rows);
+
+        tmpdir.close().unwrap();
+    }
+
+    #[test]
+    fn parse_cpu_pressure() {
+        let contents = "some avg10=12.34 avg60=5.00 avg300=1.00 total=123456\n\
+                        full avg10=0.00 avg60=0.00 avg300=0.00 total=0\n";
+        assert_eq!(cpu_pressure_avg10(contents), Some(12.34));
+        assert_eq!(cpu_pressure_avg10("garbage"), None);
+    }
+
+    #[tokio::test]
+    async fn verify_parquet_cpu_pressure() {
+        for under_pressure in [false, true] {
+            let tmpdir = camino_tempfile::tempdir().unwrap();
+
+            let strong = Compression::ZSTD(ZstdLevel::try_new(10).unwrap());
+            let config = ParquetConfig {
+                propeties: Arc::new(WriterProperties::builder().set_compression(strong).build()),
+                rows_per_group: 2_000,
+                file_size: 1_000_000,
+                max_buffered_rows: usize::MAX,
+                shutdown_grace_period: time::Duration::from_secs(10),
+                max_duration: time::Duration::from_secs(20 * 60),
+                cpu_pressure: Some(CpuPressure {
+                    active: Arc::new(AtomicBool::new(under_pressure)),
+                    propeties: Arc::new(WriterProperties::new()),
+                }),
+                durable_spill: None,
+                test_remote_failures: 0,
+            };
+
+            let remote_storage_config = RemoteStorageConfig {
+                storage: RemoteStorageKind::LocalFs(tmpdir.path().to_path_buf()),
+            };
+            let storage = GenericRemoteStorage::from_config(&remote_storage_config).unwrap();
+            worker_inner(
+                storage,
+                random_stream(10_000),
+                config,
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+
+            let expected = if under_pressure {
+                Compression::UNCOMPRESSED
+            } else {
+                strong
+            };
+            for entry in std::fs::read_dir(tmpdir.path()).unwrap() {
+                let file = std::fs::File::open(entry.unwrap().path()).unwrap();
+                let reader = SerializedFileReader::new(file).unwrap();
+                for rg in reader.metadata().row_groups() {
+                    for column in rg.columns() {
+                        assert_eq!(column.compression(), expected);
+                    }
+                }
+            }
+
+            tmpdir.close().unwrap();
+        }
+    }
+
     #[tokio::test(start_paused = true)]
     async fn verify_parquet_regular_upload() {
         let tmpdir = camino_tempfile::tempdir().unwrap();
@@ -611,7 +1878,11 @@ mod tests {
             propeties: Arc::new(WriterProperties::new()),
             rows_per_group: 2_000,
             file_size: 1_000_000,
+            max_buffered_rows: usize::MAX,
+            shutdown_grace_period: time::Duration::from_secs(10),
             max_duration: time::Duration::from_secs(60),
+            cpu_pressure: None,
+            durable_spill: None,
             test_remote_failures: 2,
         };
 
@@ -633,9 +1904,50 @@ mod tests {
         // files are smaller than the size threshold, but they took too long to fill so were flushed early
         assert_eq!(
             file_stats,
-            [(515807, 2, 3001), (515585, 2, 3000), (515425, 2, 2999)],
+            [(517146, 2, 3001), (516923, 2, 3000), (516869, 2, 2999)],
         );
 
         tmpdir.close().unwrap();
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn verify_parquet_idle_upload() {
+        let tmpdir = camino_tempfile::tempdir().unwrap();
+
+        let config = ParquetConfig {
+            propeties: Arc::new(WriterProperties::new()),
+            rows_per_group: 2_000,
+            file_size: 1_000_000,
+            max_buffered_rows: usize::MAX,
+            shutdown_grace_period: time::Duration::from_secs(10),
+            max_duration: time::Duration::from_secs(60),
+            cpu_pressure: None,
+            durable_spill: None,
+            test_remote_failures: 0,
+        };
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let mut s = random_stream(10);
+            while let Some(r) = s.next().await {
+                tx.send(r).unwrap();
+            }
+            // no more rows, but keep the stream open for longer than max_duration
+            time::sleep(time::Duration::from_secs(300)).await;
+            drop(tx);
+        });
+
+        let rx = futures::stream::poll_fn(move |cx| rx.poll_recv(cx));
+        let file_stats = run_test(tmpdir.path(), config, rx).await;
+
+        // the rows were uploaded while the stream was idle, the last file only has the summary
+        let file_stats = file_stats
+            .into_iter()
+            .map(|(_, row_groups, rows)| (row_groups, rows))
+            .collect_vec();
+        assert_eq!(file_stats, [(1, 10), (0, 0)]);
+
+        tmpdir.close().unwrap();
+    }
 }
@@ -0,0 +1,176 @@
+//! Local spill file for the rows of the parquet upload worker that haven't been uploaded yet,
+//! so that they survive a crash or restart of the proxy.
+//!
+//! Rows are appended to the file as JSON lines when the worker receives them, and the file is
+//! truncated once they have all been uploaded. On startup, the rows that are still in the file
+//! are replayed: they are uploaded along with the new ones.
+//!
+//! Appends are not fsynced, to keep the cost per request low: the rows survive the proxy
+//! crashing, but not the machine.
+
+use std::collections::HashSet;
+
+use anyhow::Context;
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::warn;
+
+use super::{RequestData, UtcNaive};
+
+/// A [`RequestData`] as it's stored in the spill file.
+#[derive(Serialize, Deserialize)]
+struct SpilledRow {
+    region: String,
+    availability_zone: Option<String>,
+    protocol: String,
+    timestamp_millis: i64,
+    session_id: uuid::Uuid,
+    peer_addr: String,
+    username: Option<String>,
+    application_name: Option<String>,
+    endpoint_id: Option<String>,
+    project: Option<String>,
+    branch: Option<String>,
+    error: Option<String>,
+}
+
+impl From<&RequestData> for SpilledRow {
+    fn from(row: &RequestData) -> Self {
+        Self {
+            region: row.region.to_owned(),
+            availability_zone: row.availability_zone.map(str::to_owned),
+            protocol: row.protocol.to_owned(),
+            timestamp_millis: row.timestamp.timestamp_millis(),
+            session_id: row.session_id,
+            peer_addr: row.peer_addr.clone(),
+            username: row.username.clone(),
+            application_name: row.application_name.clone(),
+            endpoint_id: row.endpoint_id.clone(),
+            project: row.project.clone(),
+            branch: row.branch.clone(),
+            error: row.error.map(str::to_owned),
+        }
+    }
+}
+
+impl SpilledRow {
+    /// `RequestData` has `&'static str`s for the columns with few distinct values. They are
+    /// leaked once per distinct value, through `interned`.
+    fn into_request_data(
+        self,
+        interned: &mut HashSet<&'static str>,
+    ) -> anyhow::Result<RequestData> {
+        let mut intern = |s: String| -> &'static str {
+            match interned.get(s.as_str()).copied() {
+                Some(s) => s,
+                None => {
+                    let s: &'static str = Box::leak(s.into_boxed_str());
+                    interned.insert(s);
+                    s
+                }
+            }
+        };
+        let timestamp = chrono::NaiveDateTime::from_timestamp_millis(self.timestamp_millis)
+            .context("timestamp out of range")?;
+        Ok(RequestData {
+            region: intern(self.region),
+            availability_zone: self.availability_zone.map(&mut intern),
+            protocol: intern(self.protocol),
+            timestamp: UtcNaive(timestamp),
+            session_id: self.session_id,
+            peer_addr: self.peer_addr,
+            username: self.username,
+            application_name: self.application_name,
+            endpoint_id: self.endpoint_id,
+            project: self.project,
+            branch: self.branch,
+            error: self.error.map(intern),
+        })
+    }
+}
+
+pub(super) struct Spill {
+    path: Utf8PathBuf,
+    file: File,
+}
+
+impl Spill {
+    /// Open the spill file at `path`, creating it if it doesn't exist, and read the rows that
+    /// the previous run left in it.
+    ///
+    /// A crash in the middle of an append leaves an incomplete last line behind, which is
+    /// dropped: the request it was for had not been recorded yet.
+    pub(super) async fn open(path: &Utf8Path) -> anyhow::Result<(Spill, Vec<RequestData>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .await
+            .with_context(|| format!("open spill file {path}"))?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)
+            .await
+            .with_context(|| format!("read spill file {path}"))?;
+
+        let mut rows = Vec::new();
+        let mut interned = HashSet::new();
+        let mut complete_len = 0;
+        while let Some(newline) = contents[complete_len..].iter().position(|b| *b == b'\n') {
+            let line = &contents[complete_len..complete_len + newline];
+            complete_len += newline + 1;
+            let row = serde_json::from_slice::<SpilledRow>(line)
+                .map_err(anyhow::Error::from)
+                .and_then(|row| row.into_request_data(&mut interned));
+            match row {
+                Ok(row) => rows.push(row),
+                Err(e) => warn!("skipping malformed row in spill file {path}: {e:#}"),
+            }
+        }
+        if complete_len < contents.len() {
+            warn!(
+                "dropping incomplete last row of spill file {path}, {} bytes",
+                contents.len() - complete_len
+            );
+            file.set_len(complete_len as u64)
+                .await
+                .with_context(|| format!("truncate spill file {path}"))?;
+        }
+
+        let spill = Spill {
+            path: path.to_owned(),
+            file,
+        };
+        Ok((spill, rows))
+    }
+
+    /// Record a row that is about to be buffered for upload. Returns once the row has been
+    /// written to the file.
+    pub(super) async fn append(&mut self, row: &RequestData) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(&SpilledRow::from(row))?;
+        line.push(b'\n');
+        // In one write, so that a crash can only leave the last line incomplete.
+        self.file
+            .write_all(&line)
+            .await
+            .with_context(|| format!("append to spill file {}", self.path))?;
+        self.file
+            .flush()
+            .await
+            .with_context(|| format!("append to spill file {}", self.path))
+    }
+
+    /// Forget all the recorded rows, once they have been uploaded.
+    pub(super) async fn truncate(&mut self) -> anyhow::Result<()> {
+        self.file
+            .set_len(0)
+            .await
+            .with_context(|| format!("truncate spill file {}", self.path))?;
+        self.file
+            .sync_all()
+            .await
+            .with_context(|| format!("truncate spill file {}", self.path))
+    }
+}
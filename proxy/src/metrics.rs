@@ -3,8 +3,8 @@ use ::metrics::{
     IntCounterPairVec, IntCounterVec,
 };
 use prometheus::{
-    register_histogram, register_histogram_vec, register_int_gauge_vec, Histogram, HistogramVec,
-    IntGaugeVec,
+    register_histogram, register_histogram_vec, register_int_counter, register_int_gauge_vec,
+    Histogram, HistogramVec, IntCounter, IntGaugeVec,
 };
 
 use once_cell::sync::Lazy;
@@ -229,6 +229,22 @@ pub static NUM_BYTES_PROXIED_COUNTER: Lazy<IntCounterVec> = Lazy::new(|| {
     .unwrap()
 });
 
+pub static PARQUET_UPLOAD_SKIPPED_DUPLICATES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "proxy_parquet_upload_skipped_duplicate_total",
+        "Number of request parquet files that were not uploaded because they had the same rows as the previous file.",
+    )
+    .unwrap()
+});
+
+pub static PARQUET_UPLOAD_DROPPED_ROWS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "proxy_parquet_upload_dropped_rows_total",
+        "Number of requests that were not logged to parquet because the worker was too far behind.",
+    )
+    .unwrap()
+});
+
 pub const fn bool_to_str(x: bool) -> &'static str {
     if x {
         "true"
@@ -92,7 +92,13 @@ pub async fn task_main(
                     bail!("missing required client IP");
                 }
 
-                let mut ctx = RequestMonitoring::new(session_id, peer_addr, "tcp", &config.region);
+                let mut ctx = RequestMonitoring::new(
+                    session_id,
+                    peer_addr,
+                    "tcp",
+                    &config.region,
+                    config.availability_zone.as_deref(),
+                );
 
                 socket
                     .inner
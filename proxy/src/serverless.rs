@@ -222,7 +222,13 @@ async fn request_handler(
 
         ws_connections.spawn(
             async move {
-                let mut ctx = RequestMonitoring::new(session_id, peer_addr, "ws", &config.region);
+                let mut ctx = RequestMonitoring::new(
+                    session_id,
+                    peer_addr,
+                    "ws",
+                    &config.region,
+                    config.availability_zone.as_deref(),
+                );
 
                 if let Err(e) = websocket::serve_websocket(
                     config,
@@ -243,7 +249,13 @@ async fn request_handler(
         // Return the response so the spawned future can continue.
         Ok(response)
     } else if request.uri().path() == "/sql" && request.method() == Method::POST {
-        let mut ctx = RequestMonitoring::new(session_id, peer_addr, "http", &config.region);
+        let mut ctx = RequestMonitoring::new(
+            session_id,
+            peer_addr,
+            "http",
+            &config.region,
+            config.availability_zone.as_deref(),
+        );
 
         sql_over_http::handle(
             tls,
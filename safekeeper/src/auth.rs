@@ -12,6 +12,9 @@ pub fn check_permission(claims: &Claims, tenant_id: Option<TenantId>) -> Result<
             }
             Ok(())
         }
+        (Scope::Timeline, _) => Err(AuthError(
+            "Timeline scope makes no sense for Safekeeper".into(),
+        )),
         (Scope::PageServerApi, _) => Err(AuthError(
             "PageServerApi scope makes no sense for Safekeeper".into(),
         )),
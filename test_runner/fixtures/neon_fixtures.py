@@ -402,6 +402,12 @@ class AuthKeys:
     def generate_tenant_token(self, tenant_id: TenantId) -> str:
         return self.generate_token(scope="tenant", tenant_id=str(tenant_id))
 
+    # generate token giving access to only one timeline of a tenant
+    def generate_timeline_token(self, tenant_id: TenantId, timeline_id: TimelineId) -> str:
+        return self.generate_token(
+            scope="timeline", tenant_id=str(tenant_id), timeline_id=str(timeline_id)
+        )
+
 
 class NeonEnvBuilder:
     """
@@ -277,6 +277,20 @@ class PageserverHttpClient(requests.Session):
         res = self.post(f"http://localhost:{self.port}/v1/tenant/{tenant_id}/reset", params=params)
         self.verbose_error(res)
 
+    def tenant_pagestream_drain(self, tenant_id: TenantId, reason: Optional[str] = None):
+        params = {}
+        if reason is not None:
+            params["reason"] = reason
+
+        res = self.put(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/pagestream_drain", params=params
+        )
+        self.verbose_error(res)
+
+    def tenant_pagestream_undrain(self, tenant_id: TenantId):
+        res = self.delete(f"http://localhost:{self.port}/v1/tenant/{tenant_id}/pagestream_drain")
+        self.verbose_error(res)
+
     def tenant_location_conf(
         self, tenant_id: TenantId, location_conf=dict[str, Any], flush_ms=None
     ):
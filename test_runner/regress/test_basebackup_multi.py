@@ -0,0 +1,94 @@
+import io
+import socket
+import struct
+import tarfile
+from typing import List, Tuple
+
+from fixtures.neon_fixtures import NeonEnv
+from fixtures.types import Lsn
+
+
+def recv_exact(sock: socket.socket, n: int) -> bytes:
+    buf = b""
+    while len(buf) < n:
+        chunk = sock.recv(n - len(buf))
+        assert chunk, "connection closed"
+        buf += chunk
+    return buf
+
+
+def read_message(sock: socket.socket) -> Tuple[bytes, bytes]:
+    tag = recv_exact(sock, 1)
+    (length,) = struct.unpack("!I", recv_exact(sock, 4))
+    return tag, recv_exact(sock, length - 4)
+
+
+def simple_query(port: int, query: str) -> List[Tuple[bytes, bytes]]:
+    """
+    Run a query over the simple query protocol, and return the messages of the response
+    before ReadyForQuery. psycopg2 and psql can't show us the messages of a response with
+    more than one COPY, so speak the protocol directly.
+    """
+    with socket.create_connection(("localhost", port)) as sock:
+        params = b"user\x00cloud_admin\x00\x00"
+        sock.sendall(struct.pack("!II", 8 + len(params), 196608) + params)
+        while True:
+            tag, body = read_message(sock)
+            assert tag != b"E", f"startup failed: {body!r}"
+            if tag == b"Z":
+                break
+
+        query_bytes = query.encode() + b"\x00"
+        sock.sendall(b"Q" + struct.pack("!I", 4 + len(query_bytes)) + query_bytes)
+        messages = []
+        while True:
+            tag, body = read_message(sock)
+            if tag == b"Z":
+                return messages
+            messages.append((tag, body))
+
+
+def notice_message(body: bytes) -> str:
+    fields = {field[:1]: field[1:] for field in body.split(b"\x00") if field}
+    return fields[b"M"].decode()
+
+
+#
+# Test the framing of the response to 'basebackup_multi': for each timeline, a notice that
+# names it, followed by the tarball in a COPY of its own.
+#
+def test_basebackup_multi(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    ps_http = env.pageserver.http_client()
+
+    parent = env.neon_cli.create_branch("test_basebackup_multi", "empty")
+    child = env.neon_cli.create_branch("test_basebackup_multi_child", "test_basebackup_multi")
+    lsn = Lsn(ps_http.timeline_detail(env.initial_tenant, child)["last_record_lsn"])
+
+    timelines = [child, parent]
+    messages = simple_query(
+        env.pageserver.service_port.pg,
+        f"basebackup_multi {env.initial_tenant} {lsn} {','.join(str(t) for t in timelines)}",
+    )
+
+    tarballs = []
+    for timeline in timelines:
+        tag, body = messages.pop(0)
+        assert tag == b"N"
+        assert notice_message(body) == f"basebackup of timeline {timeline}"
+
+        assert messages.pop(0)[0] == b"H"
+        tarball = b""
+        while messages[0][0] == b"d":
+            tarball += messages.pop(0)[1]
+        assert messages.pop(0)[0] == b"c"
+        tarballs.append(tarball)
+
+    assert [tag for tag, _ in messages] == [b"C"]
+
+    # Each COPY holds a complete tarball of its timeline
+    for tarball in tarballs:
+        with tarfile.open(fileobj=io.BytesIO(tarball)) as tar:
+            names = tar.getnames()
+            assert "global/pg_control" in names
+            assert "zenith.signal" in names
@@ -0,0 +1,77 @@
+import threading
+
+from fixtures.log_helper import log
+from fixtures.neon_fixtures import Endpoint, NeonEnv
+from fixtures.utils import wait_until
+
+
+def compute_log_contains(endpoint: Endpoint, pattern: str) -> bool:
+    with (endpoint.endpoint_path() / "compute.log").open("r") as f:
+        return any(pattern in line for line in f)
+
+
+#
+# Test that draining a pagestream connection answers the requests that are in
+# flight, and that the compute reconnects when it gets the GoAway message that
+# ends the connection.
+#
+def test_pagestream_drain(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    ps_http = env.pageserver.http_client()
+
+    env.neon_cli.create_branch("test_pagestream_drain", "empty")
+    endpoint = env.endpoints.create_start("test_pagestream_drain")
+    endpoint.safe_psql_many(
+        [
+            "CREATE EXTENSION neon_test_utils",
+            "CREATE TABLE t (id integer)",
+            "INSERT INTO t SELECT generate_series(1, 10000)",
+        ]
+    )
+
+    pg_conn = endpoint.connect()
+    cur = pg_conn.cursor()
+    # Make the next scan of the table read its pages from the pageserver
+    cur.execute("SELECT clear_buffer_cache()")
+
+    # Hold the GetPage requests of the scan in flight
+    ps_http.configure_failpoints(("pagestream-get-page-pausable", "pause"))
+    result = []
+
+    def scan_table():
+        cur.execute("SELECT count(*) FROM t")
+        result.append(cur.fetchone()[0])
+
+    scan = threading.Thread(target=scan_table)
+    scan.start()
+
+    def paused():
+        assert env.pageserver.log_contains("at failpoint pagestream-get-page-pausable")
+
+    wait_until(20, 0.5, paused)
+
+    # Drain the connection while the requests are in flight. The connection keeps
+    # draining once it has started, so undrain right away to let the compute
+    # reconnect.
+    ps_http.tenant_pagestream_drain(env.initial_tenant, reason="test drain")
+
+    def draining():
+        assert env.pageserver.log_contains("draining pagestream connection")
+
+    wait_until(20, 0.5, draining)
+    ps_http.tenant_pagestream_undrain(env.initial_tenant)
+
+    # The requests in flight are answered, and the rest of the scan goes through
+    # a new connection.
+    ps_http.configure_failpoints(("pagestream-get-page-pausable", "off"))
+    scan.join(timeout=60)
+    assert not scan.is_alive()
+    assert result == [10000]
+
+    def reconnected():
+        assert compute_log_contains(endpoint, "pageserver asked to reconnect: test drain")
+
+    wait_until(20, 0.5, reconnected)
+    log.info("compute reconnected after GoAway")
+
+    assert endpoint.safe_psql("SELECT count(*) FROM t")[0][0] == 10000